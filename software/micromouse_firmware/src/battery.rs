@@ -1,12 +1,15 @@
 use stm32f4xx_hal::stm32 as stm32f405;
 
-const DEAD_VOLTAGE: u16 = 2100;
+use micromouse_logic::hardware::battery::{raw_to_volts, BatteryConfig, Cutoff};
+
 const DEAD_TIME: u32 = 5000;
 
 pub struct Battery {
     adc: stm32f405::ADC1,
+    config: BatteryConfig,
     last_alive: Option<u32>,
     last_update: Option<u32>,
+    cutoff: Cutoff,
 }
 
 impl Battery {
@@ -14,6 +17,7 @@ impl Battery {
         rcc: &stm32f405::RCC,
         gpiob: &stm32f405::GPIOB,
         adc: stm32f405::ADC1,
+        config: BatteryConfig,
     ) -> Battery {
         rcc.apb2enr.modify(|_, w| w.adc1en().set_bit());
         rcc.ahb1enr.write(|w| w.gpioben().set_bit());
@@ -28,8 +32,10 @@ impl Battery {
 
         Battery {
             adc,
+            config,
             last_alive: None,
             last_update: None,
+            cutoff: Cutoff::new(),
         }
     }
 
@@ -39,11 +45,20 @@ impl Battery {
         raw
     }
 
+    /// The pack voltage, in volts
+    pub fn volts(&self) -> f32 {
+        raw_to_volts(&self.config, self.raw())
+    }
+
     pub fn update(&mut self, now: u32) {
-        if self.raw() > DEAD_VOLTAGE {
+        let volts = self.volts();
+
+        if volts > self.config.dead_volts {
             self.last_alive = Some(now);
         }
 
+        self.cutoff.update(volts, now);
+
         self.last_update = Some(now);
     }
 
@@ -53,4 +68,9 @@ impl Battery {
             _ => true,
         }
     }
+
+    /// The pack got low enough that the motors have to stay off until reset
+    pub fn is_cut_off(&self) -> bool {
+        self.cutoff.is_tripped()
+    }
 }
@@ -0,0 +1,74 @@
+use stm32f4xx_hal::stm32 as stm32f405;
+
+use micromouse_logic::hardware::tone::{Tone, ToneQueue};
+
+/// The timer counts at 1MHz, so the period of a tone is easy to find
+const TIMER_FREQ: u32 = 1_000_000;
+
+/// A piezo buzzer on PA2, driven by timer 9 channel 1
+pub struct Buzzer {
+    timer: stm32f405::TIM9,
+    queue: ToneQueue,
+}
+
+impl Buzzer {
+    pub fn setup(
+        rcc: &stm32f405::RCC,
+        timer: stm32f405::TIM9,
+        gpio: &stm32f405::GPIOA,
+    ) -> Buzzer {
+        // Enable clock for gpio a
+        rcc.ahb1enr.modify(|_, w| w.gpioaen().set_bit());
+
+        // Enable clock for timer 9
+        rcc.apb2enr.modify(|_, w| w.tim9en().set_bit());
+
+        // Set pin to alternate function
+        gpio.moder.modify(|_, w| w.moder2().alternate());
+
+        // Set the alternate function to timer 9 channel 1
+        gpio.afrl.modify(|_, w| w.afrl2().af3());
+
+        // setup the timer, 168MHz / 168 = 1MHz
+        timer.psc.write(|w| w.psc().bits(167u16));
+        timer.cr1.write(|w| w.arpe().set_bit());
+        timer.arr.write(|w| w.arr().bits(1000u16));
+        timer.ccr1.write(|w| w.ccr().bits(0u16));
+        timer
+            .ccmr1_output()
+            .write(|w| w.oc1m().bits(0b110).oc1pe().set_bit());
+        timer.ccer.write(|w| w.cc1e().set_bit());
+        timer.cr1.modify(|_, w| w.cen().set_bit());
+
+        Buzzer {
+            timer,
+            queue: ToneQueue::new(),
+        }
+    }
+
+    /// Queue up a tone. It will play after any tones already queued, as `update` is called.
+    pub fn beep(&mut self, freq_hz: u16, duration_ms: u16) {
+        self.queue.push(Tone {
+            freq_hz,
+            duration_ms,
+        });
+    }
+
+    /// Start and stop tones. Call this every time through the main loop.
+    pub fn update(&mut self, now: u32) {
+        if let Some(freq_hz) = self.queue.update(now) {
+            self.set_frequency(freq_hz);
+        }
+    }
+
+    fn set_frequency(&mut self, freq_hz: u16) {
+        if freq_hz == 0 {
+            self.timer.ccr1.write(|w| w.ccr().bits(0u16));
+        } else {
+            // A square wave sounds the loudest on a piezo
+            let period = (TIMER_FREQ / freq_hz as u32).min(core::u16::MAX as u32) as u16;
+            self.timer.arr.write(|w| w.arr().bits(period - 1));
+            self.timer.ccr1.write(|w| w.ccr().bits(period / 2));
+        }
+    }
+}
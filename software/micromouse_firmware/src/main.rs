@@ -24,11 +24,15 @@
 extern crate panic_halt;
 
 pub mod battery;
+#[cfg(feature = "buzzer")]
+pub mod buzzer;
 pub mod motors;
 pub mod system_test;
 pub mod time;
+pub mod trace;
 pub mod uart;
 pub mod vl6180x;
+pub mod watchdog;
 
 use cortex_m_rt::entry;
 use stm32f4xx_hal as stm32f4;
@@ -45,17 +49,24 @@ use embedded_hal::digital::v2::{InputPin, OutputPin, ToggleableOutputPin};
 use typenum::consts::U2048;
 
 use crate::battery::Battery;
+#[cfg(feature = "buzzer")]
+use crate::buzzer::Buzzer;
 use crate::time::Time;
+use crate::trace::Trace;
 
 use crate::uart::Uart;
+use crate::watchdog::Watchdog;
 
 use crate::motors::{Encoder, Motor};
 
 #[allow(unused_imports)]
 use micromouse_logic::config::{mouse_2019, mouse_2020};
 
-use micromouse_logic::comms::{DebugMsg, DebugPacket};
+use micromouse_logic::comms::{
+    crc16, DebugMsg, DebugPacket, DebugSelection, PROTOCOL_VERSION, SELECT_DEBUG_BYTE,
+};
 use micromouse_logic::fast::{Orientation, Vector, DIRECTION_PI_2};
+use micromouse_logic::hardware::battery::BATTERY;
 use micromouse_logic::mouse::Mouse;
 
 use crate::motors::left::{LeftEncoder, LeftMotor};
@@ -63,6 +74,10 @@ use crate::motors::right::{RightEncoder, RightMotor};
 use crate::vl6180x::VL6180x;
 use micromouse_logic::fast::motion_control::MotionHandlerDebug;
 
+/// How long the main loop can go without feeding the watchdog before the MCU resets. The loop
+/// normally goes around much faster than the 10ms control period, so this leaves plenty of room.
+const WATCHDOG_TIMEOUT_MS: u32 = 100;
+
 // Setup the master clock out
 pub fn mco2_setup(rcc: &stm32f405::RCC, gpioc: &stm32f405::GPIOC) {
     rcc.ahb1enr.write(|w| w.gpiocen().set_bit());
@@ -71,6 +86,15 @@ pub fn mco2_setup(rcc: &stm32f405::RCC, gpioc: &stm32f405::GPIOC) {
     gpioc.afrh.write(|w| w.afrh9().af0());
 }
 
+/// Serialize a packet to send over the uart, filling in the crc. Packets are cobs encoded and
+/// end in a 0 so the other end can find where each one starts.
+fn serialize_packet(mut packet: DebugPacket) -> Option<Vec<u8, U2048>> {
+    packet.crc = 0;
+    let unsealed = postcard::to_vec::<U2048, _>(&packet).ok()?;
+    packet.crc = crc16(&unsealed);
+    postcard::to_vec_cobs::<U2048, _>(&packet).ok()
+}
+
 pub fn do_mouse<RL, GL, BL, OL, LB, RB, I2C1, I2C2, I2C3>(
     mut time: Time,
     mut battery: Battery,
@@ -88,6 +112,9 @@ pub fn do_mouse<RL, GL, BL, OL, LB, RB, I2C1, I2C2, I2C3>(
     mut left_distance: VL6180x<I2C2>,
     mut right_distance: VL6180x<I2C3>,
     mut uart: Uart,
+    mut watchdog: Watchdog,
+    trace: &'static mut Trace,
+    #[cfg(feature = "buzzer")] mut buzzer: Buzzer,
 ) -> !
 where
     RL: OutputPin + ToggleableOutputPin,
@@ -102,6 +129,12 @@ where
 {
     let config = mouse_2020::MOUSE;
 
+    // Anything wrong in here would send infinities everywhere, so stop before moving at all
+    config
+        .mechanical
+        .validated()
+        .expect("Invalid mechanical config");
+
     let initial_orientation = Orientation {
         position: Vector {
             //x: 1260.0,
@@ -119,6 +152,10 @@ where
     let mut mouse: Option<Mouse> = None;
 
     let mut debugging = false;
+    let mut debug_selection = DebugSelection::default();
+
+    // The byte after a `SELECT_DEBUG_BYTE` is the selection, not another command
+    let mut selecting_debug = false;
 
     let mut start_time = None;
     let mut last_packet_time = last_time;
@@ -126,7 +163,19 @@ where
 
     let mut sensor_updating = 0;
 
+    let mut dump_index: Option<usize> = None;
+
+    // Only make noise when something changes, not every update it stays that way
+    #[cfg(feature = "buzzer")]
+    let mut was_aborted = false;
+    #[cfg(feature = "buzzer")]
+    let mut was_at_goal = false;
+    #[cfg(feature = "buzzer")]
+    let mut was_stalled = false;
+
     loop {
+        watchdog.feed();
+
         let now: u32 = time.now();
 
         match sensor_updating {
@@ -146,26 +195,76 @@ where
 
         if let Ok(byte) = uart.read_byte() {
             //blue_led.set_high().ok();
-            match byte {
-                0 => {}
-                1 => debugging = false,
-                2 => debugging = true,
-                3 => {
-                    mouse = None;
-                    start_time = None;
-                }
-                4 => {
-                    start_time = Some(now);
+            if selecting_debug {
+                debug_selection = DebugSelection::from_byte(byte);
+                selecting_debug = false;
+            } else {
+                match byte {
+                    0 => {}
+                    1 => debugging = false,
+                    2 => debugging = true,
+                    3 => {
+                        mouse = None;
+                        start_time = None;
+                    }
+                    4 => {
+                        if !battery.is_cut_off() {
+                            start_time = Some(now);
+                            trace.clear();
+                        }
+                    }
+                    6 => {
+                        debugging = false;
+                        dump_index = Some(0);
+                    }
+                    SELECT_DEBUG_BYTE => selecting_debug = true,
+                    _ => {}
                 }
-                _ => {}
             }
         } else {
             //blue_led.set_low().ok();
         }
 
+        // Send the recorded trace one record at a time whenever the uart has caught up
+        if let Some(index) = dump_index {
+            if uart.tx_len() == Ok(0) {
+                if let Some(record) = trace.get(index) {
+                    let mut msgs = Vec::new();
+                    msgs.push(DebugMsg::Trace(record)).ok();
+
+                    let packet = DebugPacket {
+                        version: PROTOCOL_VERSION,
+                        msgs,
+                        battery: record.telemetry.battery(),
+                        time: record.time,
+                        delta_time_sys: 0,
+                        delta_time_msg: 0,
+                        count: index as u16,
+                        tx_overflows: uart.tx_overflows(),
+                        crc: 0,
+                    };
+
+                    if let Some(bytes) = serialize_packet(packet) {
+                        uart.add_bytes(&bytes).ok();
+                    }
+
+                    dump_index = Some(index + 1);
+                } else {
+                    dump_index = None;
+                }
+            }
+        }
+
         if now - last_time >= 10 {
             green_led.toggle().ok();
 
+            // Stop the run for good before the pack browns out the MCU partway through
+            // a turn. The motors get turned off below since there is no mouse.
+            if battery.is_cut_off() {
+                mouse = None;
+                start_time = None;
+            }
+
             let debug = if let Some(mouse) = mouse.as_mut() {
                 let left_encoder_count = left_encoder.count();
                 let right_encoder_count = right_encoder.count();
@@ -176,16 +275,21 @@ where
                 let (left_power, right_power, debug) = mouse.update(
                     &config,
                     now,
-                    battery.raw(),
+                    battery.volts(),
                     left_encoder_count,
                     right_encoder_count,
                     left_distance_range,
                     front_distance_range,
                     right_distance_range,
+                    // There is no driver for the gyro yet
+                    None,
                 );
 
-                right_motor.change_power((right_power) as i32);
-                left_motor.change_power((left_power) as i32);
+                // Never trust the controller output directly, a glitch in the
+                // timing can make it huge
+                let motor_config = &config.motion_control.motor_control;
+                right_motor.change_power(motor_config.clamp_power(right_power));
+                left_motor.change_power(motor_config.clamp_power(left_power));
 
                 match debug.motion_control.handler {
                     Some(MotionHandlerDebug::Turn(_)) => blue_led.set_high().ok(),
@@ -198,6 +302,30 @@ where
                     orange_led.set_low().ok();
                 }
 
+                trace.record(&debug);
+
+                #[cfg(feature = "buzzer")]
+                {
+                    if mouse.aborted() && !was_aborted {
+                        buzzer.beep(1000, 50);
+                    }
+                    was_aborted = mouse.aborted();
+
+                    if mouse.stalled() && !was_stalled {
+                        buzzer.beep(500, 300);
+                    }
+                    was_stalled = mouse.stalled();
+
+                    if let Some(slow) = &debug.slow {
+                        if slow.at_goal && !was_at_goal {
+                            buzzer.beep(2000, 80);
+                            buzzer.beep(2500, 80);
+                            buzzer.beep(3000, 120);
+                        }
+                        was_at_goal = slow.at_goal;
+                    }
+                }
+
                 Some(debug)
             } else {
                 right_motor.change_power(0);
@@ -207,32 +335,25 @@ where
 
             if let Some(start_time) = start_time {
                 if now - start_time > 0 && debugging && uart.tx_len() == Ok(0) {
-                    let mut msgs = Vec::new();
-
-                    if let Some(debug) = debug {
-                        msgs.push(DebugMsg::Orientation(debug.orientation)).ok();
-                        msgs.push(DebugMsg::Hardware(debug.hardware)).ok();
-                        msgs.push(DebugMsg::Slow(debug.slow)).ok();
-                        msgs.push(DebugMsg::Localize(debug.localize)).ok();
-                        //msgs.push(DebugMsg::MotionQueue(debug.motion_queue)).ok();
-                        //msgs.push(DebugMsg::MotorControl(
-                        //debug.motion_control.motor_control,
-                        //))
-                        //.ok();
-                        //msgs.push(DebugMsg::MotionHandler(debug.motion_control.handler))
-                        //.ok();
-                    }
+                    let msgs = if let Some(debug) = debug {
+                        debug_selection.msgs(debug)
+                    } else {
+                        Vec::new()
+                    };
 
                     let packet = DebugPacket {
+                        version: PROTOCOL_VERSION,
                         msgs,
-                        battery: battery.raw(),
+                        battery: battery.volts(),
                         time: now,
                         delta_time_sys: now - last_time,
                         delta_time_msg: now - last_packet_time,
                         count: packet_count,
+                        tx_overflows: uart.tx_overflows(),
+                        crc: 0,
                     };
 
-                    if let Ok(bytes) = postcard::to_vec::<U2048, _>(&packet) {
+                    if let Some(bytes) = serialize_packet(packet) {
                         uart.add_bytes(&bytes).ok();
                         //orange_led.set_high().ok();
                     }
@@ -255,7 +376,10 @@ where
             }
 
             if let Ok(true) = left_button.is_low() {
-                start_time = Some(now);
+                if !battery.is_cut_off() {
+                    start_time = Some(now);
+                    trace.clear();
+                }
             }
 
             if let Ok(true) = right_button.is_low() {
@@ -263,7 +387,7 @@ where
                 start_time = None;
             }
 
-            if battery.is_dead() {
+            if battery.is_dead() || battery.is_cut_off() {
                 red_led.set_high().ok();
             } else {
                 red_led.set_low().ok();
@@ -273,11 +397,17 @@ where
         }
 
         battery.update(now);
+
+        #[cfg(feature = "buzzer")]
+        buzzer.update(now);
     }
 }
 
 #[entry]
 fn main() -> ! {
+    // Much too big for the stack, so it gets a place of its own
+    static mut TRACE: Trace = Trace::new();
+
     let p = stm32f4::stm32::Peripherals::take().unwrap();
     let _cp = stm32f405::CorePeripherals::take().unwrap();
 
@@ -292,7 +422,7 @@ fn main() -> ! {
 
     while time.now() < 10000 {}
 
-    let battery = Battery::setup(&p.RCC, &p.GPIOB, p.ADC1);
+    let battery = Battery::setup(&p.RCC, &p.GPIOB, p.ADC1, BATTERY);
 
     let mut uart = Uart::setup(&p.RCC, p.USART1, &p.GPIOA);
 
@@ -302,6 +432,9 @@ fn main() -> ! {
     let right_motor = RightMotor::setup(&p.RCC, p.TIM4, &p.GPIOB);
     let right_encoder = RightEncoder::setup(&p.RCC, &p.GPIOA, p.TIM5);
 
+    #[cfg(feature = "buzzer")]
+    let buzzer = Buzzer::setup(&p.RCC, p.TIM9, &p.GPIOA);
+
     // Init the hal things
     let rcc = p.RCC.constrain();
     let clocks = rcc
@@ -378,6 +511,10 @@ fn main() -> ! {
         let mut distance = vl6180x::VL6180x::new(i2c, 0x29);
         distance.init_private_registers();
         distance.init_default();
+
+        // The front sensor needs to see further down a corridor than the default ~100mm
+        distance.set_range_scaling(2);
+
         distance
     };
 
@@ -413,6 +550,9 @@ fn main() -> ! {
 
     uart.add_bytes(b"\n\nstart").ok();
 
+    // Started last so the slow setup above does not trip it
+    let watchdog = Watchdog::setup(p.IWDG, WATCHDOG_TIMEOUT_MS);
+
     do_mouse(
     //do_sensors(
     //do_echo(
@@ -433,5 +573,9 @@ fn main() -> ! {
         left_distance,
         right_distance,
         uart,
+        watchdog,
+        TRACE,
+        #[cfg(feature = "buzzer")]
+        buzzer,
     );
 }
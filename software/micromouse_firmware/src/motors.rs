@@ -8,4 +8,8 @@ pub trait Motor {
 pub trait Encoder {
     fn count(&self) -> i32;
     fn reset(&mut self);
+
+    /// The speed of the encoder in ticks per ms since the last time this was called. The first
+    /// call only starts tracking and gives 0.0.
+    fn velocity(&mut self, now: u32) -> f32;
 }
@@ -3,6 +3,7 @@ use core::i32;
 use stm32f4xx_hal::stm32 as stm32f405;
 
 use crate::motors::{Encoder, Motor};
+use micromouse_logic::hardware::encoder::EncoderVelocity;
 
 const FORWARD_DEADBAND: i32 = 0;
 const BACKWARD_DEADBAND: i32 = 0;
@@ -79,6 +80,7 @@ impl Motor for LeftMotor {
 
 pub struct LeftEncoder {
     timer: stm32f405::TIM2,
+    velocity: EncoderVelocity,
 }
 
 impl LeftEncoder {
@@ -105,7 +107,10 @@ impl LeftEncoder {
         timer.ccer.write(|w| w.cc1e().set_bit().cc2e().set_bit());
         timer.cr1.write(|w| w.cen().set_bit());
 
-        LeftEncoder { timer }
+        LeftEncoder {
+            timer,
+            velocity: EncoderVelocity::new(),
+        }
     }
 }
 
@@ -116,5 +121,11 @@ impl Encoder for LeftEncoder {
 
     fn reset(&mut self) {
         self.timer.cnt.write(|w| w.cnt().bits(0));
+        self.velocity.reset();
+    }
+
+    fn velocity(&mut self, now: u32) -> f32 {
+        let count = self.count();
+        self.velocity.update(count, now)
     }
 }
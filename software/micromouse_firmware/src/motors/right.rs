@@ -1,6 +1,7 @@
 use stm32f4xx_hal::stm32 as stm32f405;
 
 use crate::motors::{Encoder, Motor};
+use micromouse_logic::hardware::encoder::EncoderVelocity;
 
 const FORWARD_DEADBAND: i32 = 0;
 const BACKWARD_DEADBAND: i32 = 0;
@@ -77,6 +78,7 @@ impl Motor for RightMotor {
 
 pub struct RightEncoder {
     timer: stm32f405::TIM5,
+    velocity: EncoderVelocity,
 }
 
 impl RightEncoder {
@@ -110,7 +112,10 @@ impl RightEncoder {
         });
         timer.cr1.write(|w| w.cen().set_bit());
 
-        RightEncoder { timer }
+        RightEncoder {
+            timer,
+            velocity: EncoderVelocity::new(),
+        }
     }
 }
 
@@ -121,5 +126,11 @@ impl Encoder for RightEncoder {
 
     fn reset(&mut self) {
         self.timer.cnt.write(|w| w.cnt().bits(0));
+        self.velocity.reset();
+    }
+
+    fn velocity(&mut self, now: u32) -> f32 {
+        let count = self.count();
+        self.velocity.update(count, now)
     }
 }
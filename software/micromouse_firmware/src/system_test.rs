@@ -4,6 +4,11 @@ use core::str;
 use embedded_hal::blocking::i2c;
 use embedded_hal::digital::v2::{InputPin, OutputPin, ToggleableOutputPin};
 
+use micromouse_logic::config::mouse_2020;
+use micromouse_logic::hardware::system_test::{
+    write_mechanical_config, CalibrateKind, Calibration, CommandWord,
+};
+
 use crate::battery::Battery;
 use crate::motors::left::{LeftEncoder, LeftMotor};
 use crate::motors::right::{RightEncoder, RightMotor};
@@ -45,9 +50,10 @@ impl<M: Motor, E: Encoder> MotorCommand<M, E> {
         }
     }
 
-    pub fn report(&self, uart: &mut Uart, name: &str) {
+    pub fn report(&mut self, uart: &mut Uart, name: &str, now: u32) {
         if self.reporting {
-            write!(uart, "{}:{},", name, self.encoder.count()).ok();
+            let velocity = self.encoder.velocity(now);
+            write!(uart, "{}:{}:{},", name, self.encoder.count(), velocity).ok();
         }
     }
 }
@@ -60,8 +66,8 @@ pub fn do_system_test<RL, GL, BL, OL, LB, RB, I2C1, I2C2, I2C3>(
     _green_led: GL,
     _blue_led: BL,
     _orange_led: OL,
-    _left_button: LB,
-    _right_button: RB,
+    left_button: LB,
+    right_button: RB,
     left_motor: LeftMotor,
     right_motor: RightMotor,
     left_encoder: LeftEncoder,
@@ -82,6 +88,10 @@ where
     I2C2: i2c::Read + i2c::Write + i2c::WriteRead,
     I2C3: i2c::Read + i2c::Write + i2c::WriteRead,
 {
+    // The same config that `do_mouse` runs with
+    let mech = mouse_2020::MOUSE.mechanical;
+    let motors = mouse_2020::MOUSE.motion_control.motor_control;
+
     let mut time_report = false;
 
     let mut left_motor_command = MotorCommand {
@@ -100,15 +110,18 @@ where
     let mut right_distance_report = false;
     let mut front_distance_report = false;
 
+    let mut calibration: Option<Calibration> = None;
+
     let mut last_time = 0;
 
     loop {
         if let Some(buf) = uart.read_line().ok() {
             if let Some(line) = str::from_utf8(&buf).ok() {
                 let mut words = line.trim().split_whitespace();
+                let command = words.next();
 
-                match words.next() {
-                    Some("time") => match words.next() {
+                match command.and_then(CommandWord::parse) {
+                    Some(CommandWord::Time) => match words.next() {
                         Some("report") => match words.next() {
                             Some("on") => time_report = true,
                             Some("off") => time_report = false,
@@ -120,14 +133,14 @@ where
                             writeln!(uart, "Unknown command: {:?}", word).ok();
                         }
                     },
-                    Some("motor") => match words.next() {
+                    Some(CommandWord::Motor) => match words.next() {
                         Some("left") => left_motor_command.parse(&mut uart, words),
                         Some("right") => right_motor_command.parse(&mut uart, words),
                         word => {
                             writeln!(uart, "Unknown command: {:?}", word).ok();
                         }
                     },
-                    Some("distance") => match words.next() {
+                    Some(CommandWord::Distance) => match words.next() {
                         Some("left") => match words.next() {
                             Some("report") => match words.next() {
                                 Some("on") => left_distance_report = true,
@@ -168,20 +181,62 @@ where
                             writeln!(uart, "Unknown command: {:?}", word).ok();
                         }
                     },
-                    word => {
-                        writeln!(uart, "Unknown command: {:?}", word).ok();
+                    Some(CommandWord::Config) => {
+                        write_mechanical_config(&mut uart, &mech).ok();
+                    }
+                    Some(CommandWord::Calibrate) => match CalibrateKind::parse(words) {
+                        Ok((kind, amount)) => {
+                            calibration = Some(Calibration::new(
+                                &mech,
+                                &motors,
+                                kind,
+                                amount,
+                                left_motor_command.encoder.count(),
+                                right_motor_command.encoder.count(),
+                            ));
+                        }
+                        Err(e) => {
+                            writeln!(uart, "{}", e).ok();
+                        }
+                    },
+                    None => {
+                        writeln!(uart, "Unknown command: {:?}", command).ok();
                     }
                 }
             }
         }
 
         if time.now() - last_time >= 1 {
+            if let Some(c) = calibration {
+                let left = left_motor_command.encoder.count();
+                let right = right_motor_command.encoder.count();
+
+                let stop = left_button.is_low().unwrap_or(true)
+                    || right_button.is_low().unwrap_or(true);
+
+                if stop || c.done(left, right) {
+                    left_motor_command.motor.change_power(0);
+                    right_motor_command.motor.change_power(0);
+
+                    if stop {
+                        writeln!(uart, "calibrate stopped").ok();
+                    }
+
+                    c.report(&mut uart, left, right).ok();
+                    calibration = None;
+                } else {
+                    let (left_power, right_power) = c.powers(left, right);
+                    left_motor_command.motor.change_power(left_power);
+                    right_motor_command.motor.change_power(right_power);
+                }
+            }
+
             if time_report {
                 write!(uart, "T:{},", time.now()).ok();
             }
 
-            left_motor_command.report(&mut uart, "LM");
-            right_motor_command.report(&mut uart, "RM");
+            left_motor_command.report(&mut uart, "LM", time.now());
+            right_motor_command.report(&mut uart, "RM", time.now());
 
             if left_distance_report {
                 left_distance.update();
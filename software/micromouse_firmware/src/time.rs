@@ -1,13 +1,26 @@
-use stm32f4xx_hal::stm32 as stm32f405;
+use core::cell::{Cell, RefCell};
+
+use cortex_m::interrupt::Mutex;
+use cortex_m_rt_macros::interrupt as isr;
 
-static OVERFLOW_VALUE: u32 = 65535 / 4;
+use stm32f4xx_hal::stm32 as stm32f405;
+use stm32f4xx_hal::stm32::Interrupt as interrupt;
+use stm32f4xx_hal::stm32::NVIC;
 
-pub struct Time {
-    timer: stm32f405::TIM1,
-    last_time: u32,
-    overflows: u32,
+/// Convert the running microsecond count into the millisecond time `now()` reports
+#[inline(always)]
+fn us_to_ms(us: u64) -> u32 {
+    (us / 1000) as u32
 }
 
+static TIMER: Mutex<RefCell<Option<stm32f405::TIM1>>> = Mutex::new(RefCell::new(None));
+
+/// How many times the 16 bit counter has wrapped, counted in the update interrupt so the time
+/// stays right no matter how long it goes between reads
+static OVERFLOWS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+pub struct Time {}
+
 impl Time {
     pub fn setup(rcc: &stm32f405::RCC, timer: stm32f405::TIM1) -> Time {
         // Enable clock for timer 1
@@ -16,33 +29,67 @@ impl Time {
         // setup the timer
 
         // 16MHz ABP2
-        //timer.psc.write(|w| w.psc().bits(8000));
+        //timer.psc.write(|w| w.psc().bits(15));
 
-        // 84MHz ABP2
-        // Runs at 4 ticks every ms, so needs division later on
-        timer.psc.write(|w| w.psc().bits(42000));
+        // 84MHz ABP2, so the timer runs at 168MHz
+        // Divide down to 1 tick every us
+        timer.psc.write(|w| w.psc().bits(167));
+
+        // Count the full 16 bits so the counter wraps every 65.536ms. The update interrupt
+        // counts the wraps to extend it past that.
+        timer.arr.write(|w| w.arr().bits(0xffff));
+
+        // Only overflows raise the update interrupt, not loading the prescaler below
+        timer.cr1.modify(|_, w| w.urs().set_bit());
+
+        // Load the prescaler now instead of on the first overflow
+        timer.egr.write(|w| w.ug().set_bit());
+        timer.sr.modify(|_, w| w.uif().clear_bit());
+
+        timer.dier.modify(|_, w| w.uie().set_bit());
 
         timer.cr1.modify(|_, w| w.cen().set_bit());
         timer.cnt.write(|w| w.cnt().bits(0));
 
-        Time {
-            timer,
-            last_time: 0,
-            overflows: 0,
+        cortex_m::interrupt::free(|cs| TIMER.borrow(cs).replace(Some(timer)));
+
+        unsafe {
+            NVIC::unmask(interrupt::TIM1_UP_TIM10);
         }
+
+        Time {}
     }
 
     #[inline(always)]
-    pub fn now(&mut self) -> u32 {
-        let current_time = self.timer.cnt.read().cnt().bits() as u32 / 4;
+    fn elapsed_us(&mut self) -> u64 {
+        cortex_m::interrupt::free(|cs| {
+            if let Some(timer) = TIMER.borrow(cs).borrow().as_ref() {
+                let overflows = OVERFLOWS.borrow(cs).get();
+                let count = timer.cnt.read().cnt().bits();
 
-        if current_time < self.last_time {
-            self.overflows += 1;
-        }
+                // The counter can wrap while interrupts are off here, before the interrupt
+                // gets to count it. A small count means the wrap happened before it was read.
+                let pending = timer.sr.read().uif().bit() && count < 0x8000;
+                let overflows = overflows as u64 + pending as u64;
+
+                (overflows << 16) | count as u64
+            } else {
+                0
+            }
+        })
+    }
 
-        self.last_time = current_time;
+    /// The time since setup in ms
+    #[inline(always)]
+    pub fn now(&mut self) -> u32 {
+        us_to_ms(self.elapsed_us())
+    }
 
-        current_time + self.overflows * OVERFLOW_VALUE
+    /// The time since setup in us. This overflows every 2^32 us, or about 71.6 minutes,
+    /// so use `wrapping_sub` when finding the time between two readings.
+    #[inline(always)]
+    pub fn now_us(&mut self) -> u32 {
+        self.elapsed_us() as u32
     }
 
     pub fn delay(&mut self, msecs: u32) {
@@ -51,3 +98,17 @@ impl Time {
         while self.now() - start_time < msecs {}
     }
 }
+
+#[isr]
+fn TIM1_UP_TIM10() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(timer) = TIMER.borrow(cs).borrow().as_ref() {
+            if timer.sr.read().uif().bit() {
+                timer.sr.modify(|_, w| w.uif().clear_bit());
+
+                let overflows = OVERFLOWS.borrow(cs);
+                overflows.set(overflows.get().wrapping_add(1));
+            }
+        }
+    });
+}
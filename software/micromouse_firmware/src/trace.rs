@@ -0,0 +1,70 @@
+use micromouse_logic::comms::TraceRecord;
+use micromouse_logic::mouse::MouseDebug;
+
+/// Only record every this many updates. The mouse updates every 10ms, so this is 10 records a
+/// second.
+pub const TRACE_DECIMATION: usize = 10;
+
+/// How many records can be held at once, which is enough for a 4 minute run at
+/// `TRACE_DECIMATION`. Once full, the oldest records are overwritten.
+pub const TRACE_LEN: usize = 2400;
+
+/// Records a decimated trace of the mouse into RAM during a run, so it can be dumped over uart
+/// after the run when the link was too slow to stream it live.
+///
+/// This is tens of KB, so it needs to live in a `static` instead of on the stack.
+pub struct Trace {
+    records: [TraceRecord; TRACE_LEN],
+    start: usize,
+    len: usize,
+    skipped: usize,
+}
+
+impl Trace {
+    pub const fn new() -> Trace {
+        Trace {
+            records: [TraceRecord::EMPTY; TRACE_LEN],
+            start: 0,
+            len: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Forget all recorded records
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+        self.skipped = 0;
+    }
+
+    /// Record the debug, if enough updates have passed since the last one was recorded
+    pub fn record(&mut self, debug: &MouseDebug) {
+        if self.skipped > 0 {
+            self.skipped -= 1;
+            return;
+        }
+
+        self.skipped = TRACE_DECIMATION - 1;
+
+        if self.len < TRACE_LEN {
+            self.records[(self.start + self.len) % TRACE_LEN] = TraceRecord::from(debug);
+            self.len += 1;
+        } else {
+            self.records[self.start] = TraceRecord::from(debug);
+            self.start = (self.start + 1) % TRACE_LEN;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Get a record, with 0 being the oldest
+    pub fn get(&self, index: usize) -> Option<TraceRecord> {
+        if index < self.len {
+            Some(self.records[(self.start + index) % TRACE_LEN])
+        } else {
+            None
+        }
+    }
+}
@@ -10,8 +10,9 @@ use stm32f4xx_hal::stm32 as stm32f405;
 use stm32f4xx_hal::stm32::Interrupt as interrupt;
 use stm32f4xx_hal::stm32::NVIC;
 
+use micromouse_logic::hardware::uart::TxBuffer;
+
 pub const RX_BUFFER_LEN: usize = 64;
-pub const TX_BUFFER_LEN: usize = 2048;
 
 struct Buffer<T> {
     bytes: T,
@@ -26,11 +27,16 @@ static RX_BUF: Mutex<RefCell<Buffer<[u8; RX_BUFFER_LEN]>>> =
         len: 0,
     }));
 
-static TX_BUF: Mutex<RefCell<Buffer<[u8; TX_BUFFER_LEN]>>> =
-    Mutex::new(RefCell::new(Buffer {
-        bytes: [0; TX_BUFFER_LEN],
-        len: 0,
-    }));
+/// Bytes waiting to be sent, and whether one is already on its way out
+struct Tx {
+    buffer: TxBuffer,
+    sending: bool,
+}
+
+static TX: Mutex<RefCell<Tx>> = Mutex::new(RefCell::new(Tx {
+    buffer: TxBuffer::new(),
+    sending: false,
+}));
 
 #[derive(PartialEq)]
 pub enum TxError {
@@ -112,57 +118,44 @@ impl Uart {
         Uart {}
     }
 
-    fn add_byte(&mut self, c: u8) -> Result<(), TxError> {
+    pub fn add_bytes(&mut self, b: &[u8]) -> Result<(), TxError> {
         cortex_m::interrupt::free(|cs| {
-            if let Ok(mut buf) = TX_BUF.borrow(cs).try_borrow_mut() {
-                if buf.len < buf.bytes.len() {
-                    if buf.len == 0 {
-                        if let Some(uart) = UART.borrow(cs).borrow().as_ref() {
+            if let Ok(mut tx) = TX.borrow(cs).try_borrow_mut() {
+                // The bytes that do not fit are counted in the buffer
+                let result = tx.buffer.push(b).map_err(|_| TxError::BufferFull);
+
+                // Start sending if the interrupt is not already going through the buffer
+                if !tx.sending {
+                    if let Some(uart) = UART.borrow(cs).borrow().as_ref() {
+                        if let Some(c) = tx.buffer.pop() {
                             uart.dr.write(|w| w.dr().bits(c as u16));
-                            buf.len = 1;
-                            Ok(())
-                        } else {
-                            Err(TxError::Busy)
+                            tx.sending = true;
                         }
                     } else {
-                        for i in (1..buf.len).rev() {
-                            buf.bytes[i] = buf.bytes[i - 1];
-                        }
-                        buf.bytes[0] = c;
-                        buf.len += 1;
-
-                        Ok(())
+                        return Err(TxError::Busy);
                     }
-                } else {
-                    Err(TxError::BufferFull)
                 }
+
+                result
             } else {
                 Err(TxError::Busy)
             }
         })
     }
 
-    pub fn add_bytes(&mut self, b: &[u8]) -> Result<(), TxError> {
-        for &c in b {
-            self.add_byte(c)?;
-        }
-
-        Ok(())
-    }
-
     pub fn add_str(&mut self, s: &str) -> Result<(), TxError> {
-        for &c in s.as_bytes() {
-            self.add_byte(c)?;
-        }
+        self.add_bytes(s.as_bytes())
+    }
 
-        Ok(())
+    /// How many bytes have been dropped because the tx buffer was full
+    pub fn tx_overflows(&self) -> u32 {
+        cortex_m::interrupt::free(|cs| TX.borrow(cs).borrow().buffer.overflows())
     }
 
     pub fn clear_tx(&mut self) -> Result<(), TxError> {
         cortex_m::interrupt::free(|cs| {
-            if let Ok(mut buf) = TX_BUF.borrow(cs).try_borrow_mut() {
-                buf.len = 0;
-                buf.bytes = [0; TX_BUFFER_LEN];
+            if let Ok(mut tx) = TX.borrow(cs).try_borrow_mut() {
+                tx.buffer.clear();
                 Ok(())
             } else {
                 Err(TxError::Busy)
@@ -184,8 +177,9 @@ impl Uart {
 
     pub fn tx_len(&self) -> Result<usize, TxError> {
         cortex_m::interrupt::free(|cs| {
-            if let Ok(buf) = TX_BUF.borrow(cs).try_borrow().as_ref() {
-                Ok(buf.len)
+            if let Ok(tx) = TX.borrow(cs).try_borrow().as_ref() {
+                // The byte that is on its way out counts until it is done
+                Ok(tx.buffer.len() + tx.sending as usize)
             } else {
                 Err(TxError::Busy)
             }
@@ -280,17 +274,13 @@ fn USART1() {
             }
 
             if uart.sr.read().tc().bit() {
-                if let Ok(mut buf) = TX_BUF.borrow(cs).try_borrow_mut() {
-                    // decrement the byte we just send
-                    if buf.len > 0 {
-                        buf.len -= 1;
-                    }
-
-                    // send the next byte
-                    if buf.len > 0 {
-                        let index = buf.len - 1;
-                        uart.dr.write(|w| w.dr().bits(buf.bytes[index] as u16));
-                        buf.bytes[index] = 0;
+                if let Ok(mut tx) = TX.borrow(cs).try_borrow_mut() {
+                    // send the next byte, if there is one
+                    if let Some(c) = tx.buffer.pop() {
+                        uart.dr.write(|w| w.dr().bits(c as u16));
+                        tx.sending = true;
+                    } else {
+                        tx.sending = false;
                     }
                 }
 
@@ -1,10 +1,11 @@
 use embedded_hal::blocking::i2c;
+use micromouse_logic::hardware::vl6180x::{
+    range_interrupt_config, range_ready, range_to_reading, RangeScaling, SCALAR_VALUES,
+};
 use micromouse_logic::mouse::DistanceReading;
 
 pub const DEFAULT_ADDRESS: u8 = 0x29;
 
-const MAX_RANGE: u8 = 255;
-
 mod registers {
     #![allow(dead_code)]
     pub const IDENTIFICATION__MODEL_ID: u16 = 0x000;
@@ -71,8 +72,6 @@ mod registers {
     pub const FIRMWARE__RESULT_SCALER: u16 = 0x120;
     pub const I2C_SLAVE__DEVICE_ADDRESS: u16 = 0x212;
     pub const INTERLEAVED_MODE__ENABLE: u16 = 0x2A3;
-
-    pub const SCALAR_VALUES: [u16; 4] = [0, 253, 127, 84];
 }
 
 pub struct VL6180x<I2C>
@@ -187,9 +186,9 @@ where
             self.scaling = {
                 let s = self.read_u16(registers::RANGE_SCALER);
 
-                if s == registers::SCALAR_VALUES[3] {
+                if s == SCALAR_VALUES[3] {
                     3
-                } else if s == registers::SCALAR_VALUES[2] {
+                } else if s == SCALAR_VALUES[2] {
                     2
                 } else {
                     1
@@ -252,48 +251,34 @@ where
         self.write_u8(registers::INTERLEAVED_MODE__ENABLE, 0);
 
         // reset range scaling factor to 1x
-        self.set_scaling(1);
+        self.set_range_scaling(1);
     }
 
-    // Implemented using ST's VL6180X API as a reference (STSW-IMG003); see
-    // VL6180x_UpscaleSetScaling() in vl6180x_api.c.
-    fn set_scaling(&mut self, new_scaling: u8) {
-        // default value of SYSRANGE__CROSSTALK_VALID_HEIGHT
-        let default_crosstalk_valid_height = 20;
-
+    // Sets the range scaling factor to 1, 2, or 3. Higher scaling lets the sensor see further,
+    // but each step of the result is that many mm.
+    pub fn set_range_scaling(&mut self, new_scaling: u8) {
         // do nothing if scaling value is invalid
-        if new_scaling < 1 || new_scaling > 3 {
-            return;
-        }
-
-        self.scaling = new_scaling;
+        let scaling = match RangeScaling::new(new_scaling, self.ptp_offset) {
+            Some(scaling) => scaling,
+            None => return,
+        };
 
-        let scaling = self.scaling;
-        let ptp_offset = self.ptp_offset;
-        self.write_u16(
-            registers::RANGE_SCALER,
-            registers::SCALAR_VALUES[scaling as usize],
-        );
+        self.scaling = scaling.scaling;
 
-        // apply scaling on part-to-part offset
+        self.write_u16(registers::RANGE_SCALER, scaling.scaler);
         self.write_u8(
             registers::SYSRANGE__PART_TO_PART_RANGE_OFFSET,
-            ptp_offset / scaling,
+            scaling.ptp_offset,
         );
-
-        // apply scaling on CrossTalkValidHeight
         self.write_u8(
             registers::SYSRANGE__CROSSTALK_VALID_HEIGHT,
-            default_crosstalk_valid_height / scaling,
+            scaling.crosstalk_valid_height,
         );
 
-        // This function does not apply scaling to RANGE_IGNORE_VALID_HEIGHT.
-
-        // enable early convergence estimate only at 1x scaling
         let rce = self.read_u8(registers::SYSRANGE__RANGE_CHECK_ENABLES);
         self.write_u8(
             registers::SYSRANGE__RANGE_CHECK_ENABLES,
-            (rce & 0xFE) | (scaling == 1) as u8,
+            scaling.range_check_enables(rce),
         );
     }
 
@@ -342,21 +327,56 @@ where
         self.write_u8(registers::SYSRANGE__START, 0x01);
     }
 
+    // Configures GPIO1 to go low when a new range sample is ready, so the main loop can poll
+    // the pin instead of waiting on the sensor. The register sequence is:
+    //
+    // 1. SYSTEM__MODE_GPIO1 = 0x10: GPIO1 is the interrupt output, active low
+    // 2. SYSTEM__INTERRUPT_CONFIG_GPIO[2:0] = 4: interrupt on a new range sample, leaving the
+    //    ALS bits alone
+    // 3. SYSTEM__INTERRUPT_CLEAR = 0x07: clear anything that is already pending so the pin
+    //    starts high
+    //
+    // Once the pin goes low, call `take_reading` to get the sample and release the pin.
+    pub fn enable_gpio1_interrupt(&mut self) {
+        self.write_u8(registers::SYSTEM__MODE_GPIO1, 0x10);
+
+        let interrupt_config = self.read_u8(registers::SYSTEM__INTERRUPT_CONFIG_GPIO);
+        self.write_u8(
+            registers::SYSTEM__INTERRUPT_CONFIG_GPIO,
+            range_interrupt_config(interrupt_config),
+        );
+
+        self.write_u8(registers::SYSTEM__INTERRUPT_CLEAR, 0x07);
+    }
+
+    // Reads a range sample if the sensor has a new one ready, clears the interrupt, and starts
+    // the next one. Returns `None` without touching anything else if there is no new sample.
+    pub fn take_reading(&mut self) -> Option<DistanceReading> {
+        let status = self.read_u8(registers::RESULT__INTERRUPT_STATUS_GPIO);
+
+        if !range_ready(status) {
+            return None;
+        }
+
+        let range = self.read_u8(registers::RESULT__RANGE_VAL);
+        self.write_u8(registers::SYSTEM__INTERRUPT_CLEAR, 0x01);
+        self.start_ranging();
+
+        Some(range_to_reading(range, self.scaling))
+    }
+
     pub fn update(&mut self) {
-        if (self.read_u8(registers::RESULT__INTERRUPT_STATUS_GPIO) * 0x04) != 0 {
-            let range = self.read_u8(registers::RESULT__RANGE_VAL);
-            self.write_u8(registers::SYSTEM__INTERRUPT_CLEAR, 0x01);
-            self.start_ranging();
-
-            self.range = if range < MAX_RANGE {
-                Some(DistanceReading::InRange(range as f32))
-            } else {
-                Some(DistanceReading::OutOfRange)
-            };
+        if let Some(reading) = self.take_reading() {
+            self.range = Some(reading);
         }
     }
 
     pub fn range(&mut self) -> Option<DistanceReading> {
         self.range.take()
     }
+
+    // The range scaling factor that is currently set
+    pub fn scaling(&self) -> u8 {
+        self.scaling
+    }
 }
@@ -0,0 +1,67 @@
+//! The independent watchdog (IWDG), which resets the MCU if the main loop stops feeding it.
+//!
+//! The IWDG runs off of the LSI clock, so it keeps going even if the main clocks or a peripheral
+//! lock up. Once it is started, nothing but a reset can stop it.
+//!
+//! On reset, every GPIO goes back to a floating input and the timers stop, so the motor PWM
+//! outputs stop driving and the motors lose power. The mouse then goes through the normal
+//! startup, which keeps the motors at 0 until a new run is started.
+//!
+//! To check it by hand:
+//!
+//! 1. Flash the firmware as is and check that it gets through the startup pattern, which means
+//!    `Watchdog::setup` did not hang waiting on the LSI
+//! 2. Add an infinite loop to `do_mouse` that runs after a button press, like
+//!    `if let Ok(true) = left_button.is_low() { loop {} }`
+//! 3. Flash it, start a run with the wheels off the ground, and press the button
+//! 4. The wheels should stop within the timeout and the LEDs should go through the startup
+//!    pattern again
+//! 5. Take the loop back out, and check a normal run goes for longer than the timeout without
+//!    resetting
+
+use stm32f4xx_hal::stm32 as stm32f405;
+
+/// The LSI is about 32kHz, so dividing by 32 makes each tick of the reload value about 1ms
+const PRESCALER_DIV_32: u32 = 0b011;
+
+/// The reload register is 12 bits
+const MAX_RELOAD: u32 = 0xFFF;
+
+const KEY_UNLOCK: u32 = 0x5555;
+const KEY_FEED: u32 = 0xAAAA;
+const KEY_START: u32 = 0xCCCC;
+
+pub struct Watchdog {
+    iwdg: stm32f405::IWDG,
+}
+
+impl Watchdog {
+    /// Start the watchdog. If `feed` is not called for `timeout_ms`, the MCU resets. The LSI
+    /// is not very accurate, so the real timeout can be off by a bit.
+    pub fn setup(iwdg: stm32f405::IWDG, timeout_ms: u32) -> Watchdog {
+        let reload = timeout_ms.min(MAX_RELOAD).max(1);
+
+        // Starting the watchdog is also what turns on the LSI. The prescaler and reload
+        // registers can not be updated until the LSI is running, so waiting on them below would
+        // hang forever if it was not started first. Until the new values are in, it runs with
+        // the reset values, which time out after about 512ms.
+        iwdg.kr.write(|w| unsafe { w.bits(KEY_START) });
+
+        // The prescaler and reload registers are write protected until unlocked
+        iwdg.kr.write(|w| unsafe { w.bits(KEY_UNLOCK) });
+        iwdg.pr.write(|w| unsafe { w.bits(PRESCALER_DIV_32) });
+        iwdg.rlr.write(|w| unsafe { w.bits(reload) });
+
+        // Wait for the new values to make it into the LSI clock domain
+        while iwdg.sr.read().bits() != 0 {}
+
+        iwdg.kr.write(|w| unsafe { w.bits(KEY_FEED) });
+
+        Watchdog { iwdg }
+    }
+
+    /// Reset the countdown back to the timeout
+    pub fn feed(&mut self) {
+        self.iwdg.kr.write(|w| unsafe { w.bits(KEY_FEED) });
+    }
+}
@@ -4,12 +4,12 @@ use serde::Serialize;
 use typenum::consts::*;
 
 use crate::fast::motor_control::MotorControlDebug;
-use crate::fast::Orientation;
+use crate::fast::{Direction, Orientation, Vector};
 
 use crate::fast::localize::LocalizeDebug;
 use crate::fast::motion_control::MotionHandlerDebug;
 use crate::fast::motion_queue::MotionQueueDebug;
-use crate::mouse::{HardwareDebug, MouseConfig};
+use crate::mouse::{DistanceReading, HardwareDebug, MouseConfig, MouseDebug};
 use crate::slow::SlowDebug;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -23,6 +23,124 @@ pub enum MouseMsg {
     Config(MouseConfig),
 }
 
+/// The version of the `DebugPacket` layout. Bump this whenever `DebugPacket`, `DebugMsg` or
+/// anything they hold changes, so that old builds on the other end drop the packets instead of
+/// reading garbage out of them.
+pub const PROTOCOL_VERSION: u8 = 15;
+
+/// Command byte asking the mouse to change which `DebugMsg`s it sends. The next byte is the
+/// `DebugSelection` to use.
+pub const SELECT_DEBUG_BYTE: u8 = 7;
+
+/// Which `DebugMsg`s the mouse sends while debugging. Sending less lets the rest go out faster.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DebugSelection {
+    pub orientation: bool,
+    pub hardware: bool,
+    pub slow: bool,
+    pub localize: bool,
+    pub motion_queue: bool,
+    pub motor_control: bool,
+    pub motion_handler: bool,
+    pub telemetry: bool,
+}
+
+impl Default for DebugSelection {
+    /// The same messages the mouse sent before they could be selected, on every update
+    fn default() -> DebugSelection {
+        DebugSelection {
+            orientation: true,
+            hardware: true,
+            slow: true,
+            localize: true,
+            motion_queue: false,
+            motor_control: false,
+            motion_handler: false,
+            telemetry: false,
+        }
+    }
+}
+
+impl DebugSelection {
+    pub fn from_byte(byte: u8) -> DebugSelection {
+        DebugSelection {
+            orientation: byte & 0x01 != 0,
+            hardware: byte & 0x02 != 0,
+            slow: byte & 0x04 != 0,
+            localize: byte & 0x08 != 0,
+            motion_queue: byte & 0x10 != 0,
+            motor_control: byte & 0x20 != 0,
+            motion_handler: byte & 0x40 != 0,
+            telemetry: byte & 0x80 != 0,
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        [
+            self.orientation,
+            self.hardware,
+            self.slow,
+            self.localize,
+            self.motion_queue,
+            self.motor_control,
+            self.motion_handler,
+            self.telemetry,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |byte, (i, &selected)| byte | (selected as u8) << i)
+    }
+
+    /// The messages to send for one update of the mouse. Anything past the four that fit in a
+    /// packet is left out.
+    ///
+    /// With telemetry selected, the rest only go out when there is slow debug and telemetry goes
+    /// out the rest of the time, since the full debug is too big to send every update. Without
+    /// it, the rest go out on every update.
+    pub fn msgs(&self, debug: MouseDebug) -> Vec<DebugMsg, U4> {
+        let telemetry = Telemetry::from(&debug);
+        let mut msgs = Vec::new();
+
+        if !self.telemetry || debug.slow.is_some() {
+            if self.orientation {
+                msgs.push(DebugMsg::Orientation(debug.orientation)).ok();
+            }
+
+            if self.hardware {
+                msgs.push(DebugMsg::Hardware(debug.hardware)).ok();
+            }
+
+            if self.slow {
+                msgs.push(DebugMsg::Slow(debug.slow)).ok();
+            }
+
+            if self.localize {
+                msgs.push(DebugMsg::Localize(debug.localize)).ok();
+            }
+
+            if self.motion_queue {
+                msgs.push(DebugMsg::MotionQueue(debug.motion_queue)).ok();
+            }
+
+            if self.motor_control {
+                msgs.push(DebugMsg::MotorControl(debug.motion_control.motor_control))
+                    .ok();
+            }
+
+            if self.motion_handler {
+                msgs.push(DebugMsg::MotionHandler(debug.motion_control.handler))
+                    .ok();
+            }
+        }
+
+        if msgs.is_empty() && self.telemetry {
+            msgs.push(DebugMsg::Telemetry(telemetry)).ok();
+        }
+
+        msgs
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum DebugMsg {
     Hardware(HardwareDebug),
@@ -32,14 +150,356 @@ pub enum DebugMsg {
     MotorControl(MotorControlDebug),
     Localize(LocalizeDebug),
     Slow(Option<SlowDebug>),
+    Trace(TraceRecord),
+    Telemetry(Telemetry),
+}
+
+/// A compact snapshot of the mouse that is recorded on board during a run, to be sent after the
+/// run is over. Thousands of these are kept in RAM at once, so everything that can be is packed
+/// the same way as `Telemetry`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TraceRecord {
+    pub time: u32,
+    pub telemetry: Telemetry,
+    pub left_encoder: i32,
+    pub right_encoder: i32,
+}
+
+impl TraceRecord {
+    /// All zeros, so a buffer full of these can go in `.bss` instead of taking up flash
+    pub const EMPTY: TraceRecord = TraceRecord {
+        time: 0,
+        telemetry: Telemetry {
+            x: 0,
+            y: 0,
+            direction: 0,
+            battery: 0,
+            left_distance: 0,
+            front_distance: 0,
+            right_distance: 0,
+        },
+        left_encoder: 0,
+        right_encoder: 0,
+    };
+}
+
+impl From<&MouseDebug> for TraceRecord {
+    fn from(debug: &MouseDebug) -> TraceRecord {
+        TraceRecord {
+            time: debug.time,
+            telemetry: Telemetry::from(debug),
+            left_encoder: debug.hardware.left_encoder,
+            right_encoder: debug.hardware.right_encoder,
+        }
+    }
+}
+
+/// A distance in `Telemetry` for a sensor that did not read anything this time
+pub const TELEMETRY_NO_READING: u8 = 255;
+
+/// A distance in `Telemetry` for a sensor that read out of range
+pub const TELEMETRY_OUT_OF_RANGE: u8 = 254;
+
+/// Just enough about the mouse to follow it live, in few enough bytes that it can be sent every
+/// loop
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Telemetry {
+    /// The position, in mm
+    pub x: i16,
+    pub y: i16,
+
+    /// The direction, in milliradians
+    pub direction: u16,
+
+    /// The battery pack voltage, in millivolts
+    pub battery: u16,
+
+    /// The distance readings in mm, or one of `TELEMETRY_NO_READING` or `TELEMETRY_OUT_OF_RANGE`
+    pub left_distance: u8,
+    pub front_distance: u8,
+    pub right_distance: u8,
+}
+
+impl Telemetry {
+    fn quantize_distance(reading: Option<DistanceReading>) -> u8 {
+        match reading {
+            Some(DistanceReading::InRange(distance)) => {
+                distance.max(0.0).min(TELEMETRY_OUT_OF_RANGE as f32 - 1.0) as u8
+            }
+            Some(DistanceReading::OutOfRange) => TELEMETRY_OUT_OF_RANGE,
+            None => TELEMETRY_NO_READING,
+        }
+    }
+
+    fn distance_reading(distance: u8) -> Option<DistanceReading> {
+        match distance {
+            TELEMETRY_NO_READING => None,
+            TELEMETRY_OUT_OF_RANGE => Some(DistanceReading::OutOfRange),
+            distance => Some(DistanceReading::InRange(distance as f32)),
+        }
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        Orientation {
+            position: Vector {
+                x: self.x as f32,
+                y: self.y as f32,
+            },
+            direction: Direction::from(self.direction as f32 / 1000.0),
+        }
+    }
+
+    /// The battery pack voltage, in volts
+    pub fn battery(&self) -> f32 {
+        self.battery as f32 / 1000.0
+    }
+
+    pub fn left_distance(&self) -> Option<DistanceReading> {
+        Telemetry::distance_reading(self.left_distance)
+    }
+
+    pub fn front_distance(&self) -> Option<DistanceReading> {
+        Telemetry::distance_reading(self.front_distance)
+    }
+
+    pub fn right_distance(&self) -> Option<DistanceReading> {
+        Telemetry::distance_reading(self.right_distance)
+    }
+}
+
+impl From<&MouseDebug> for Telemetry {
+    fn from(debug: &MouseDebug) -> Telemetry {
+        Telemetry {
+            x: debug.orientation.position.x as i16,
+            y: debug.orientation.position.y as i16,
+            direction: (f32::from(debug.orientation.direction) * 1000.0) as u16,
+            battery: (debug.battery * 1000.0) as u16,
+            left_distance: Telemetry::quantize_distance(debug.hardware.left_distance),
+            front_distance: Telemetry::quantize_distance(debug.hardware.front_distance),
+            right_distance: Telemetry::quantize_distance(debug.hardware.right_distance),
+        }
+    }
+}
+
+#[cfg(test)]
+mod telemetry_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{Telemetry, TraceRecord, TELEMETRY_NO_READING, TELEMETRY_OUT_OF_RANGE};
+    use crate::fast::{Direction, Orientation, Vector};
+    use crate::mouse::{DistanceReading, MouseDebug};
+
+    #[test]
+    fn round_trip() {
+        let mut debug = MouseDebug::default();
+        debug.orientation = Orientation {
+            position: Vector { x: 1234.6, y: 90.2 },
+            direction: Direction::from(2.5),
+        };
+        debug.battery = 7.45;
+        debug.hardware.left_distance = Some(DistanceReading::InRange(43.7));
+        debug.hardware.front_distance = Some(DistanceReading::OutOfRange);
+        debug.hardware.right_distance = None;
+
+        let telemetry = Telemetry::from(&debug);
+        assert_eq!(telemetry.front_distance, TELEMETRY_OUT_OF_RANGE);
+        assert_eq!(telemetry.right_distance, TELEMETRY_NO_READING);
+
+        // Everything is within a unit of what it was
+        let orientation = telemetry.orientation();
+        assert!(orientation.position.distance_to(debug.orientation.position) < 1.5);
+        assert!((f32::from(orientation.direction) - 2.5).abs() < 0.001);
+        assert!((telemetry.battery() - 7.45).abs() < 0.001);
+        assert_eq!(
+            telemetry.left_distance(),
+            Some(DistanceReading::InRange(43.0))
+        );
+        assert_eq!(
+            telemetry.front_distance(),
+            Some(DistanceReading::OutOfRange)
+        );
+        assert_eq!(telemetry.right_distance(), None);
+    }
+
+    #[test]
+    fn trace_record() {
+        let mut debug = MouseDebug::default();
+        debug.time = 12345;
+        debug.battery = 7.45;
+        debug.hardware.left_encoder = -70000;
+        debug.hardware.right_encoder = 70000;
+
+        let record = TraceRecord::from(&debug);
+        assert_eq!(record.time, 12345);
+        assert_eq!(record.telemetry, Telemetry::from(&debug));
+        assert_eq!(record.left_encoder, -70000);
+        assert_eq!(record.right_encoder, 70000);
+
+        // Small enough to keep a whole run of them in RAM
+        assert!(core::mem::size_of::<TraceRecord>() <= 24);
+    }
+
+    #[test]
+    fn far_reading() {
+        let mut debug = MouseDebug::default();
+        debug.hardware.front_distance = Some(DistanceReading::InRange(1000.0));
+
+        // Too far to fit is still in range, just as far as it can go
+        assert_eq!(
+            Telemetry::from(&debug).front_distance(),
+            Some(DistanceReading::InRange(253.0))
+        );
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DebugPacket {
+    /// Always `PROTOCOL_VERSION`. This needs to stay first so it can be read without knowing the
+    /// layout of the rest of the packet.
+    pub version: u8,
     pub msgs: Vec<DebugMsg, U4>,
-    pub battery: u16,
+    /// The battery pack voltage, in volts
+    pub battery: f32,
     pub time: u32,
     pub delta_time_sys: u32,
     pub delta_time_msg: u32,
     pub count: u16,
+    /// How many bytes the mouse has had to drop because its uart buffer was full. Gaps in
+    /// the debug stream line up with this going up.
+    pub tx_overflows: u32,
+    /// The `crc16` of the packet serialized with this set to 0
+    pub crc: u16,
+}
+
+/// CRC-16/CCITT-FALSE, to catch packets that got corrupted over the serial line
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xffff, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod debug_selection_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{DebugMsg, DebugSelection};
+    use crate::mouse::MouseDebug;
+    use crate::slow::SlowDebug;
+
+    fn full_debug() -> MouseDebug {
+        MouseDebug {
+            slow: Some(SlowDebug::default()),
+            ..MouseDebug::default()
+        }
+    }
+
+    #[test]
+    fn decode_bits() {
+        let selection = DebugSelection::from_byte(0b1000_0011);
+
+        assert_eq!(
+            selection,
+            DebugSelection {
+                orientation: true,
+                hardware: true,
+                slow: false,
+                localize: false,
+                motion_queue: false,
+                motor_control: false,
+                motion_handler: false,
+                telemetry: true,
+            }
+        );
+    }
+
+    #[test]
+    fn byte_round_trip() {
+        for byte in 0..=255 {
+            assert_eq!(DebugSelection::from_byte(byte).to_byte(), byte);
+        }
+    }
+
+    #[test]
+    fn default_sends_full_debug() {
+        let msgs = DebugSelection::default().msgs(full_debug());
+
+        assert_eq!(msgs.len(), 4);
+        assert!(matches!(msgs[0], DebugMsg::Orientation(_)));
+        assert!(matches!(msgs[3], DebugMsg::Localize(_)));
+    }
+
+    #[test]
+    fn default_without_slow() {
+        let msgs = DebugSelection::default().msgs(MouseDebug::default());
+
+        assert_eq!(msgs.len(), 4);
+        assert!(matches!(msgs[0], DebugMsg::Orientation(_)));
+        assert!(matches!(msgs[2], DebugMsg::Slow(None)));
+    }
+
+    #[test]
+    fn telemetry_without_slow() {
+        let selection = DebugSelection {
+            telemetry: true,
+            ..DebugSelection::default()
+        };
+
+        let msgs = selection.msgs(MouseDebug::default());
+        assert_eq!(msgs.len(), 1);
+        assert!(matches!(msgs[0], DebugMsg::Telemetry(_)));
+
+        let msgs = selection.msgs(full_debug());
+        assert_eq!(msgs.len(), 4);
+        assert!(matches!(msgs[0], DebugMsg::Orientation(_)));
+    }
+
+    #[test]
+    fn telemetry_only() {
+        let msgs = DebugSelection::from_byte(0x80).msgs(full_debug());
+
+        assert_eq!(msgs.len(), 1);
+        assert!(matches!(msgs[0], DebugMsg::Telemetry(_)));
+    }
+
+    #[test]
+    fn nothing_selected() {
+        let msgs = DebugSelection::from_byte(0).msgs(full_debug());
+
+        assert_eq!(msgs.len(), 0);
+    }
+}
+
+#[cfg(test)]
+mod crc16_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::crc16;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(crc16(&[]), 0xffff);
+    }
+
+    #[test]
+    fn flipped_byte() {
+        assert_ne!(crc16(&[1, 2, 3, 4]), crc16(&[1, 2, 7, 4]));
+    }
 }
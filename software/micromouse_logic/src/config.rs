@@ -3,10 +3,15 @@ use core::f32;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::slow::goal::Goal;
 use crate::slow::map::MapConfig;
 use crate::slow::maze::MazeConfig;
+use crate::slow::navigate::NavigateConfig;
+use crate::slow::MazePosition;
 
 use crate::fast::localize::{LocalizeConfig, SideDistanceFilterConfig};
+use crate::fast::stall::StallConfig;
+use crate::fast::{Orientation, Vector, DIRECTION_0, DIRECTION_3_PI_2, DIRECTION_PI_2};
 use crate::slow::motion_plan::MotionPlanConfig;
 
 pub const MAZE: MazeConfig = MazeConfig {
@@ -18,24 +23,49 @@ pub const SIDE_FILTER: SideDistanceFilterConfig = SideDistanceFilterConfig {
     max_range: 100.0,
     max_delta2: 10.0,
     max_delta: 10.0,
+    use_median: false,
 };
 
 pub const LOCALIZE: LocalizeConfig = LocalizeConfig {
     left_side_filter: SIDE_FILTER,
     right_side_filter: SIDE_FILTER,
     front_max_range: 150.0,
+    max_correction: 0.0,
+    gyro_weight: 0.0,
+    slip_threshold: 20.0,
+    sensor_weight: 1.0,
     use_sensors: true,
 };
 
+/// The map only gets one set of readings for each cell it stops in, so walls are marked on the
+/// first one
 pub const MAP: MapConfig = MapConfig {
     front_threhold: 150.0,
     left_threshold: 100.0,
     right_threshold: 100.0,
+    confidence_threshold: 1,
+    diagonal_tolerance: 20.0,
 };
 
-pub const MOTION_PLAN: MotionPlanConfig = MotionPlanConfig { move_offset: 12.0 };
+pub const MOTION_PLAN: MotionPlanConfig = MotionPlanConfig {
+    move_offset: 12.0,
+    allow_diagonals: false,
+};
+
+/// Only reports stalls, since a wheel that is slow to get going could look stalled for a bit
+pub const STALL: StallConfig = StallConfig {
+    min_power: 1000,
+    max_ticks: 0,
+    time: 200,
+    stop_motors: false,
+};
+
+pub const NAVIGATE: NavigateConfig = NavigateConfig {
+    goal: Goal::Square(MazePosition { x: 7, y: 7 }),
+};
 
 pub mod sim {
+    use crate::fast::localize::LocalizeConfig;
     use crate::fast::motion_control::MotionControlConfig;
     use crate::fast::motor_control::{MotorControlConfig, PidfConfig};
     use crate::fast::path::PathHandlerConfig;
@@ -43,6 +73,13 @@ pub mod sim {
     use crate::mouse::MouseConfig;
     use core::f32::consts::FRAC_PI_8;
 
+    /// Move towards the sensors a little at a time instead of jumping after they drop out.
+    /// This has only been tuned in the simulation, so the real mice leave it off.
+    pub const LOCALIZE: LocalizeConfig = LocalizeConfig {
+        max_correction: 5.0,
+        ..super::LOCALIZE
+    };
+
     pub const PIDF: PidfConfig = PidfConfig {
         p: 0.0,
         i: 0.0,
@@ -57,6 +94,11 @@ pub mod sim {
             d: 0.0,
             offset_p: 0.02,
             velocity: 0.5,
+            max_accel: 0.002,
+            max_decel: 0.002,
+            exit_velocity: 0.5,
+            max_lateral_accel: 0.005,
+            max_curvature: 1.0 / 37.0,
         },
         turn: TurnHandlerConfig {
             rad_per_sec: 0.1,
@@ -64,12 +106,18 @@ pub mod sim {
             i: 0.0,
             d: 0.0,
             tolerance: 0.02,
+            quarter_turn_lead: 0.0,
+            half_turn_lead: 0.0,
+            max_angular_accel: 0.0,
         },
         motor_control: MotorControlConfig {
             left_pidf: PIDF,
             left_reverse: false,
             right_pidf: PIDF,
             right_reverse: false,
+            max_power: 10000,
+            nominal_volts: 0.0,
+            max_accel: 0.0,
         },
         stop_distance: 90.0,
     };
@@ -79,11 +127,17 @@ pub mod sim {
         maze: super::MAZE,
         map: super::MAP,
         motion_plan: super::MOTION_PLAN,
-        localize: super::LOCALIZE,
+        localize: LOCALIZE,
         motion_control: MOTION_CONTROL,
         front_sensor_abort: 50.0,
         left_sensor_abort: 20.0,
         right_sensor_abort: 20.0,
+        front_sensor_abort_done: 0.9,
+        plan_ahead_cells: 1,
+        navigate: super::NAVIGATE,
+        retrace_home: false,
+        localize_bias: 0,
+        stall: super::STALL,
     };
 
     pub const MOUSE_2019: MouseConfig = MouseConfig {
@@ -91,11 +145,17 @@ pub mod sim {
         maze: super::MAZE,
         map: super::MAP,
         motion_plan: super::MOTION_PLAN,
-        localize: super::LOCALIZE,
+        localize: LOCALIZE,
         motion_control: MOTION_CONTROL,
         front_sensor_abort: 50.0,
         left_sensor_abort: 20.0,
         right_sensor_abort: 20.0,
+        front_sensor_abort_done: 0.9,
+        plan_ahead_cells: 1,
+        navigate: super::NAVIGATE,
+        retrace_home: false,
+        localize_bias: 0,
+        stall: super::STALL,
     };
 }
 
@@ -147,6 +207,11 @@ pub mod mouse_2020 {
                 d: 0.0,
                 offset_p: 0.008,
                 velocity: 0.3,
+                max_accel: 0.002,
+                max_decel: 0.002,
+                exit_velocity: 0.3,
+                max_lateral_accel: 0.005,
+                max_curvature: 1.0 / 39.0,
             },
             turn: TurnHandlerConfig {
                 rad_per_sec: 0.05,
@@ -154,18 +219,30 @@ pub mod mouse_2020 {
                 i: 0.0,
                 d: 0.0,
                 tolerance: 0.02,
+                quarter_turn_lead: 0.0,
+                half_turn_lead: 0.0,
+                max_angular_accel: 0.0,
             },
             motor_control: MotorControlConfig {
                 left_pidf: PIDF,
                 left_reverse: true,
                 right_pidf: PIDF,
                 right_reverse: false,
+                max_power: 10000,
+                nominal_volts: 7.4,
+                max_accel: 0.0,
             },
             stop_distance: 90.0,
         },
         front_sensor_abort: 50.0,
         left_sensor_abort: 10.0,
         right_sensor_abort: 10.0,
+        front_sensor_abort_done: 0.9,
+        plan_ahead_cells: 1,
+        navigate: super::NAVIGATE,
+        retrace_home: false,
+        localize_bias: 0,
+        stall: super::STALL,
     };
 }
 
@@ -217,6 +294,11 @@ pub mod mouse_2019 {
                 d: 0.0,
                 offset_p: 0.01,
                 velocity: 0.2,
+                max_accel: 0.002,
+                max_decel: 0.002,
+                exit_velocity: 0.2,
+                max_lateral_accel: 0.005,
+                max_curvature: 1.0 / 37.0,
             },
             turn: TurnHandlerConfig {
                 rad_per_sec: 0.05,
@@ -224,18 +306,30 @@ pub mod mouse_2019 {
                 i: 0.0,
                 d: 0.0,
                 tolerance: 0.02,
+                quarter_turn_lead: 0.0,
+                half_turn_lead: 0.0,
+                max_angular_accel: 0.0,
             },
             motor_control: MotorControlConfig {
                 left_pidf: PIDF,
                 left_reverse: false,
                 right_pidf: PIDF,
                 right_reverse: false,
+                max_power: 10000,
+                nominal_volts: 7.4,
+                max_accel: 0.0,
             },
             stop_distance: 90.0,
         },
         front_sensor_abort: 50.0,
         left_sensor_abort: 20.0,
         right_sensor_abort: 20.0,
+        front_sensor_abort_done: 0.9,
+        plan_ahead_cells: 1,
+        navigate: super::NAVIGATE,
+        retrace_home: false,
+        localize_bias: 0,
+        stall: super::STALL,
     };
 }
 
@@ -281,7 +375,51 @@ pub struct MechanicalConfig {
     pub right_sensor_limit: f32,
 }
 
+/// Why a config can not be used
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ConfigError {
+    /// A field that has to be a finite number more than 0 was not. Holds the name of the field.
+    NotPositive(&'static str),
+
+    /// A field that can not be less than 0 was. Holds the name of the field.
+    Negative(&'static str),
+}
+
 impl MechanicalConfig {
+    /// Check that everything that gets divided by or has to be a real size is more than 0.
+    /// Otherwise, things like `ticks_per_mm` come out infinite or NaN and ruin everything that
+    /// uses them. The sensor limits can be 0 to ignore a sensor, but not less.
+    pub fn validated(self) -> Result<Self, ConfigError> {
+        let positive = [
+            ("wheel_diameter", self.wheel_diameter),
+            ("gearbox_ratio", self.gearbox_ratio),
+            ("ticks_per_rev", self.ticks_per_rev),
+            ("wheelbase", self.wheelbase),
+            ("width", self.width),
+            ("length", self.length),
+        ];
+
+        for &(name, value) in positive.iter() {
+            if !(value > 0.0 && value.is_finite()) {
+                return Err(ConfigError::NotPositive(name));
+            }
+        }
+
+        let non_negative = [
+            ("front_sensor_limit", self.front_sensor_limit),
+            ("left_sensor_limit", self.left_sensor_limit),
+            ("right_sensor_limit", self.right_sensor_limit),
+        ];
+
+        for &(name, value) in non_negative.iter() {
+            if value.is_nan() || value < 0.0 {
+                return Err(ConfigError::Negative(name));
+            }
+        }
+
+        Ok(self)
+    }
+
     pub fn ticks_per_mm(&self) -> f32 {
         (self.ticks_per_rev * self.gearbox_ratio)
             / (self.wheel_diameter * f32::consts::PI)
@@ -318,4 +456,205 @@ impl MechanicalConfig {
     pub fn rads_to_mm(&self, rads: f32) -> f32 {
         rads * self.mm_per_rad()
     }
+
+    /// Where the front sensor is and which way it points, relative to the center of the mouse
+    pub fn front_sensor(&self) -> Orientation {
+        Orientation {
+            position: Vector {
+                x: self.front_sensor_offset_x,
+                y: 0.0,
+            },
+            direction: DIRECTION_0,
+        }
+    }
+
+    /// Where the left sensor is and which way it points, relative to the center of the mouse
+    pub fn left_sensor(&self) -> Orientation {
+        Orientation {
+            position: Vector {
+                x: self.left_sensor_offset_x,
+                y: self.left_sensor_offset_y,
+            },
+            direction: DIRECTION_PI_2,
+        }
+    }
+
+    /// Where the right sensor is and which way it points, relative to the center of the mouse.
+    /// The offset is a distance, so it gets flipped to be on the right side.
+    pub fn right_sensor(&self) -> Orientation {
+        Orientation {
+            position: Vector {
+                x: self.right_sensor_offset_x,
+                y: -self.right_sensor_offset_y,
+            },
+            direction: DIRECTION_3_PI_2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod mechanical_config_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use pretty_assertions::assert_eq;
+
+    use super::mouse_2020::MECH;
+    use super::{mouse_2019, sim, ConfigError, MechanicalConfig};
+    use crate::fast::{Vector, DIRECTION_0, DIRECTION_3_PI_2, DIRECTION_PI_2};
+
+    #[test]
+    fn configs_are_valid() {
+        assert_eq!(MECH.validated(), Ok(MECH));
+        assert!(mouse_2019::MECH.validated().is_ok());
+        assert!(sim::MOUSE_2019.mechanical.validated().is_ok());
+        assert!(sim::MOUSE_2020.mechanical.validated().is_ok());
+    }
+
+    fn invalid(config: MechanicalConfig) -> Option<&'static str> {
+        match config.validated() {
+            Err(ConfigError::NotPositive(name)) => Some(name),
+            Err(ConfigError::Negative(name)) => Some(name),
+            Ok(_) => None,
+        }
+    }
+
+    #[test]
+    fn invalid_wheel_diameter() {
+        assert_eq!(
+            invalid(MechanicalConfig {
+                wheel_diameter: 0.0,
+                ..MECH
+            }),
+            Some("wheel_diameter")
+        );
+    }
+
+    #[test]
+    fn invalid_gearbox_ratio() {
+        assert_eq!(
+            invalid(MechanicalConfig {
+                gearbox_ratio: -1.0,
+                ..MECH
+            }),
+            Some("gearbox_ratio")
+        );
+    }
+
+    #[test]
+    fn invalid_ticks_per_rev() {
+        assert_eq!(
+            invalid(MechanicalConfig {
+                ticks_per_rev: 0.0,
+                ..MECH
+            }),
+            Some("ticks_per_rev")
+        );
+    }
+
+    #[test]
+    fn invalid_wheelbase() {
+        assert_eq!(
+            invalid(MechanicalConfig {
+                wheelbase: f32::NAN,
+                ..MECH
+            }),
+            Some("wheelbase")
+        );
+    }
+
+    #[test]
+    fn invalid_width() {
+        assert_eq!(
+            invalid(MechanicalConfig { width: 0.0, ..MECH }),
+            Some("width")
+        );
+    }
+
+    #[test]
+    fn invalid_length() {
+        assert_eq!(
+            invalid(MechanicalConfig {
+                length: f32::INFINITY,
+                ..MECH
+            }),
+            Some("length")
+        );
+    }
+
+    #[test]
+    fn invalid_sensor_limits() {
+        assert_eq!(
+            MechanicalConfig {
+                front_sensor_limit: -1.0,
+                ..MECH
+            }
+            .validated(),
+            Err(ConfigError::Negative("front_sensor_limit"))
+        );
+        assert_eq!(
+            invalid(MechanicalConfig {
+                left_sensor_limit: -1.0,
+                ..MECH
+            }),
+            Some("left_sensor_limit")
+        );
+        assert_eq!(
+            invalid(MechanicalConfig {
+                right_sensor_limit: f32::NAN,
+                ..MECH
+            }),
+            Some("right_sensor_limit")
+        );
+    }
+
+    #[test]
+    fn blind_sensor_is_valid() {
+        assert_eq!(
+            invalid(MechanicalConfig {
+                front_sensor_limit: 0.0,
+                ..MECH
+            }),
+            None
+        );
+    }
+
+    #[test]
+    fn front_sensor() {
+        let sensor = MECH.front_sensor();
+        assert_close2(
+            sensor.position,
+            Vector {
+                x: MECH.front_sensor_offset_x,
+                y: 0.0,
+            },
+        );
+        assert_eq!(sensor.direction, DIRECTION_0);
+    }
+
+    #[test]
+    fn left_sensor() {
+        let sensor = MECH.left_sensor();
+        assert_close2(
+            sensor.position,
+            Vector {
+                x: MECH.left_sensor_offset_x,
+                y: MECH.left_sensor_offset_y,
+            },
+        );
+        assert_eq!(sensor.direction, DIRECTION_PI_2);
+    }
+
+    #[test]
+    fn right_sensor() {
+        let sensor = MECH.right_sensor();
+        assert_close2(
+            sensor.position,
+            Vector {
+                x: MECH.right_sensor_offset_x,
+                y: -MECH.right_sensor_offset_y,
+            },
+        );
+        assert_eq!(sensor.direction, DIRECTION_3_PI_2);
+    }
 }
@@ -18,6 +18,78 @@ pub fn clamp(n: f32, min: f32, max: f32) -> f32 {
     }
 }
 
+/// Split the bezier curve with control `points` at `t` with de Casteljau's algorithm.
+///
+/// The control points for the curve before `t` are put into `first`, and the control points for
+/// the curve after `t` are put into `second`. `points` gets used as scratch space. All three must
+/// be the same length.
+fn de_casteljau(
+    points: &mut [Vector],
+    first: &mut [Vector],
+    second: &mut [Vector],
+    t: f32,
+) {
+    let n = points.len();
+
+    for level in 0..n {
+        first[level] = points[0];
+        second[n - 1 - level] = points[n - 1 - level];
+
+        for i in 0..(n - 1 - level) {
+            points[i] = points[i] + t * (points[i + 1] - points[i]);
+        }
+    }
+}
+
+/// The smallest axis-aligned box, as `(min, max)` corners, that holds all of `points`
+fn bounding_box_of(points: &[Vector]) -> (Vector, Vector) {
+    points
+        .iter()
+        .skip(1)
+        .fold((points[0], points[0]), |(min, max), p| {
+            (
+                Vector {
+                    x: min.x.min(p.x),
+                    y: min.y.min(p.y),
+                },
+                Vector {
+                    x: max.x.max(p.x),
+                    y: max.y.max(p.y),
+                },
+            )
+        })
+}
+
+/// The closest of `steps` evenly spaced points on `curve` to `m`, as `(t, point, distance)`
+///
+/// If `steps` is 0, this is the point at t=0.5
+fn coarse_closest_point<C: Curve + ?Sized>(
+    curve: &C,
+    m: Vector,
+    steps: u16,
+) -> (f32, Vector, f32) {
+    (0..steps)
+        // Compute the point and distance at each t
+        .map(|i| {
+            let t = i as f32 / steps as f32;
+            let p = curve.at(t);
+            let d = (m - p).magnitude();
+            (t, p, d)
+        })
+        // Find the closest point
+        .min_by(|&(_, _, d1), &(_, _, d2)| {
+            if d1 < d2 {
+                Ordering::Less
+            } else if d1 > d2 {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        // If steps was 0 and no point was found, start in the middle
+        .unwrap_or((0.5, curve.at(0.5), (m - curve.at(0.5)).magnitude()))
+}
+
 pub trait Curve {
     type Derivative: Curve;
 
@@ -37,14 +109,49 @@ pub trait Curve {
         (d1.x * d2.y - d2.x * d1.y) / (d1_magnitude * d1_magnitude * d1_magnitude)
     }
 
+    /// The length along the curve from t=0 to t=1
+    ///
+    /// By default, this integrates the magnitude of the derivative with the trapezoid rule over
+    /// `steps` pieces. More steps are more accurate, but slower.
+    fn arc_length(&self, steps: u16) -> f32 {
+        let steps = steps.max(1);
+        let derivative = self.derivative();
+        let h = 1.0 / steps as f32;
+
+        let ends =
+            (derivative.at(0.0).magnitude() + derivative.at(1.0).magnitude()) / 2.0;
+
+        let middle: f32 = (1..steps)
+            .map(|i| derivative.at(i as f32 * h).magnitude())
+            .sum();
+
+        (ends + middle) * h
+    }
+
+    /// An axis-aligned box that holds the curve from t=0 to t=1, as `(min, max)` corners
+    ///
+    /// By default, this samples the curve at 64 points and takes the box around them, so it may
+    /// cut off a little of the curve between samples. Curves that can find a box that is
+    /// guaranteed to hold them should override this.
+    fn bounding_box(&self) -> (Vector, Vector) {
+        const STEPS: u16 = 64;
+
+        let start = self.at(0.0);
+        (1..=STEPS)
+            .map(|i| self.at(i as f32 / STEPS as f32))
+            .fold((start, start), |(min, max), p| {
+                bounding_box_of(&[min, max, p])
+            })
+    }
+
     /// The closest point on the curve
     ///
     /// If `m` is past either end of the curve, the curve gets extended with a line tangent to the
     /// curve at that end and the closest point on that line is found. The returned `t` be greater
     /// than 1.0 if it is past the end, or less than 0.0 if it is before the start
     ///
-    /// By default, it does a binary search with default parameters,
-    /// but can be overridden if there is a better method
+    /// By default, it uses Newton's method, falling back to a binary search if that does not
+    /// converge, but can be overridden if there is a better method
     fn closest_point(&self, m: Vector) -> (f32, Vector) {
         // Check if the point is before the start
         let start_point = self.at(0.0);
@@ -78,7 +185,95 @@ pub trait Curve {
             return (1.1, p);
         }
 
-        self.closest_point_by_binary_search(m, 32, 0.000001)
+        self.closest_point_by_newton(m, 16, 0.000001, 8)
+            .unwrap_or_else(|| self.closest_point_by_binary_search(m, 32, 0.000001))
+    }
+
+    /// Use Newton's method to find the closest point on the curve.
+    ///
+    /// This starts from the closest of `steps` evenly spaced points, like the coarse search in
+    /// `closest_point_by_binary_search`, and usually only needs a few iterations from there. See
+    /// `closest_point_by_newton_from` for the rest.
+    ///
+    /// Returns a tuple of `(t, point)` for the closest point
+    fn closest_point_by_newton(
+        &self,
+        m: Vector,
+        steps: u16,
+        epsilon: f32,
+        max_iterations: u16,
+    ) -> Option<(f32, Vector)> {
+        let (t, _, _) = coarse_closest_point(self, m, steps);
+        self.closest_point_by_newton_from(m, t, epsilon, max_iterations)
+    }
+
+    /// Use Newton's method to find the closest point on the curve, starting at `t`.
+    ///
+    /// This finds the `t` where the derivative of the squared distance, `(C(t) - m)·C'(t)`, is
+    /// 0. It is done once `t` moves less than `epsilon` in an iteration. If that takes more than
+    /// `max_iterations`, or `t` ends up outside of 0.0 to 1.0, this gives up and returns `None`
+    /// so a slower search can be used instead.
+    ///
+    /// Returns a tuple of `(t, point)` for the closest point
+    fn closest_point_by_newton_from(
+        &self,
+        m: Vector,
+        mut t: f32,
+        epsilon: f32,
+        max_iterations: u16,
+    ) -> Option<(f32, Vector)> {
+        let derivative = self.derivative();
+        let second_derivative = derivative.derivative();
+
+        for _ in 0..max_iterations {
+            let offset = self.at(t) - m;
+            let d1 = derivative.at(t);
+            let d2 = second_derivative.at(t);
+
+            let slope = offset.dot(d1);
+            let curvature = d1.dot(d1) + offset.dot(d2);
+
+            // Newton's method would head towards a farthest point instead
+            if curvature <= 0.0 {
+                return None;
+            }
+
+            let next = t - slope / curvature;
+
+            if !(0.0..=1.0).contains(&next) {
+                return None;
+            }
+
+            if (next - t).abs() < epsilon {
+                return Some((next, self.at(next)));
+            }
+
+            t = next;
+        }
+
+        None
+    }
+
+    /// The closest point on the curve, starting the search at `t_hint`
+    ///
+    /// When `m` has only moved a little since the closest point was last found, the new one is
+    /// near the old one, so a few iterations of Newton's method from there are much cheaper than
+    /// searching the whole curve. If that does not converge, or finds a point farther from `m`
+    /// than the one at `t_hint`, this falls back to `closest_point`.
+    fn closest_point_near(&self, m: Vector, t_hint: f32) -> (f32, Vector) {
+        if (0.0..=1.0).contains(&t_hint) {
+            let hint_distance = self.at(t_hint).distance_to(m);
+
+            if let Some((t, p)) =
+                self.closest_point_by_newton_from(m, t_hint, 0.000001, 8)
+            {
+                if p.distance_to(m) <= hint_distance {
+                    return (t, p);
+                }
+            }
+        }
+
+        self.closest_point(m)
     }
 
     /// Do a binary search to find the closest point on the curve.
@@ -100,26 +295,7 @@ pub trait Curve {
         epsilon: f32,
     ) -> (f32, Vector) {
         // Do a coarse linear search to get a good starting point for the binary search
-        let mut current = (0..steps)
-            // Compute the point and distance at each t
-            .map(|i| {
-                let t = i as f32 / steps as f32;
-                let p = self.at(t);
-                let d = (m - p).magnitude();
-                (t, p, d)
-            })
-            // Find the closest point
-            .min_by(|&(_, _, d1), &(_, _, d2)| {
-                if d1 < d2 {
-                    Ordering::Less
-                } else if d1 > d2 {
-                    Ordering::Greater
-                } else {
-                    Ordering::Equal
-                }
-            })
-            // If steps was 0 and no point was found, start in the middle
-            .unwrap_or((0.5, self.at(0.5), (m - self.at(0.5)).magnitude()));
+        let mut current = coarse_closest_point(self, m, steps);
 
         let mut h = 1.0 / steps as f32;
 
@@ -234,7 +410,7 @@ mod arc_tests {
         assert_close2(d.center, Vector { x: 0.0, y: 0.0 });
         assert_close(d.radius, PI);
         assert_close(d.theta, FRAC_PI_2);
-        assert_close(f32::from(d.start_dir), f32::from(DIRECTION_3_PI_2))
+        assert_close_direction(d.start_dir, DIRECTION_3_PI_2)
     }
 
     #[test]
@@ -242,6 +418,11 @@ mod arc_tests {
         assert_close(A.curvature(0.5), 0.5);
     }
 
+    #[test]
+    fn arc_length() {
+        assert_close(A.arc_length(16), PI);
+    }
+
     #[test]
     fn closest_point() {
         let (t, p) = A.closest_point(Vector { x: 1.75, y: 0.25 });
@@ -299,9 +480,32 @@ impl Curve for Line {
         return 0.0;
     }
 
+    fn arc_length(&self, _steps: u16) -> f32 {
+        (self.end - self.start).magnitude()
+    }
+
+    fn bounding_box(&self) -> (Vector, Vector) {
+        bounding_box_of(&[self.start, self.end])
+    }
+
     fn closest_point(&self, m: Vector) -> (f32, Vector) {
-        let p = (m - self.start).project_onto(self.derivative());
-        let t = p.x / self.derivative().x;
+        let derivative = self.derivative();
+
+        // A line with no length is just a point, and has no direction to project onto
+        if derivative.dot(derivative) < f32::EPSILON {
+            return (0.0, self.start);
+        }
+
+        let p = (m - self.start).project_onto(derivative);
+
+        // Find t from whichever way the line goes more, so a vertical or horizontal line does not
+        // divide by 0
+        let t = if derivative.x.abs() >= derivative.y.abs() {
+            p.x / derivative.x
+        } else {
+            p.y / derivative.y
+        };
+
         (t, p + self.start)
     }
 }
@@ -312,7 +516,7 @@ mod line_tests {
     use crate::test::*;
 
     use super::super::Vector;
-    use super::{Curve, Line};
+    use super::{Bezier2, Curve, Line};
 
     const B: Line = Line {
         start: Vector { x: 0.0, y: 0.0 },
@@ -340,6 +544,57 @@ mod line_tests {
         assert_close(t, 0.5);
         assert_close2(p, Vector { x: 0.5, y: 0.5 });
     }
+
+    #[test]
+    fn arc_length() {
+        assert_close(B.arc_length(0), core::f32::consts::SQRT_2);
+    }
+
+    #[test]
+    fn closest_point_vertical() {
+        let vertical = Line {
+            start: Vector { x: 1.0, y: 0.0 },
+            end: Vector { x: 1.0, y: 2.0 },
+        };
+
+        let (t, p) = vertical.closest_point(Vector { x: 3.0, y: 0.5 });
+        assert_close(t, 0.25);
+        assert_close2(p, Vector { x: 1.0, y: 0.5 });
+    }
+
+    #[test]
+    fn closest_point_zero_length() {
+        let point = Line {
+            start: Vector { x: 1.0, y: 2.0 },
+            end: Vector { x: 1.0, y: 2.0 },
+        };
+
+        let (t, p) = point.closest_point(Vector { x: 3.0, y: 0.5 });
+        assert_close(t, 0.0);
+        assert_close2(p, Vector { x: 1.0, y: 2.0 });
+    }
+
+    #[test]
+    fn bounding_box() {
+        let backwards = Line {
+            start: Vector { x: 2.0, y: -1.0 },
+            end: Vector { x: -3.0, y: 4.0 },
+        };
+        let (min, max) = backwards.bounding_box();
+        assert_close2(min, Vector { x: -3.0, y: -1.0 });
+        assert_close2(max, Vector { x: 2.0, y: 4.0 });
+    }
+
+    #[test]
+    fn numeric_arc_length() {
+        // A straight bezier uses the default numeric arc length
+        let straight = Bezier2 {
+            start: B.start,
+            ctrl0: B.at(0.5),
+            end: B.end,
+        };
+        assert_close(straight.arc_length(16), B.arc_length(16));
+    }
 }
 
 pub struct Bezier2 {
@@ -464,6 +719,37 @@ impl Curve for Bezier3 {
             end: 3.0 * (self.end - self.ctrl1),
         }
     }
+
+    /// The box around the control points, since the curve always stays inside their hull
+    fn bounding_box(&self) -> (Vector, Vector) {
+        bounding_box_of(&[self.start, self.ctrl0, self.ctrl1, self.end])
+    }
+}
+
+impl Bezier3 {
+    /// Cut the curve at `t` into the part before and the part after
+    pub fn split_at(&self, t: f32) -> (Bezier3, Bezier3) {
+        let mut points = [self.start, self.ctrl0, self.ctrl1, self.end];
+        let mut first = [Vector::default(); 4];
+        let mut second = [Vector::default(); 4];
+
+        de_casteljau(&mut points, &mut first, &mut second, t);
+
+        (
+            Bezier3 {
+                start: first[0],
+                ctrl0: first[1],
+                ctrl1: first[2],
+                end: first[3],
+            },
+            Bezier3 {
+                start: second[0],
+                ctrl0: second[1],
+                ctrl1: second[2],
+                end: second[3],
+            },
+        )
+    }
 }
 
 #[cfg(test)]
@@ -564,6 +850,38 @@ mod bezier3_tests {
         assert_close2(p, Vector { x: 1.0, y: 2.0 });
     }
 
+    const INSIDE_POINTS: [Vector; 5] = [
+        Vector { x: 0.75, y: 0.25 },
+        Vector { x: 0.5, y: 0.5 },
+        Vector { x: 0.25, y: 0.1 },
+        Vector { x: 0.9, y: 0.75 },
+        Vector { x: 1.5, y: 0.5 },
+    ];
+
+    #[test]
+    fn closest_point_newton_matches_binary_search() {
+        for &m in INSIDE_POINTS.iter() {
+            let (newton_t, newton_p) =
+                B.closest_point_by_newton(m, 16, 0.000001, 8).unwrap();
+            let (binary_t, binary_p) = B.closest_point_by_binary_search(m, 32, 0.000001);
+            // The binary search compares distances, which barely change near the closest point,
+            // so it is only good to about this close
+            assert!((newton_t - binary_t).abs() < 0.001);
+            assert!(newton_p.distance_to(binary_p) < 0.001);
+            assert!(newton_p.distance_to(m) <= binary_p.distance_to(m) + 0.000001);
+        }
+    }
+
+    #[test]
+    fn closest_point_newton_iterations() {
+        for &m in INSIDE_POINTS.iter() {
+            let iterations = (1..=8)
+                .find(|&max| B.closest_point_by_newton(m, 16, 0.000001, max).is_some())
+                .unwrap();
+            assert!(iterations <= 4, "{:?} took {} iterations", m, iterations);
+        }
+    }
+
     // Observed in simulator when first testing
     #[test]
     fn closest_point_after_from_sim() {
@@ -599,6 +917,27 @@ mod bezier3_tests {
         )
     }
 
+    #[test]
+    fn split_at_continuous() {
+        let (first, second) = B.split_at(0.3);
+        assert_close2(first.at(0.0), B.at(0.0));
+        assert_close2(first.at(1.0), B.at(0.3));
+        assert_close2(second.at(0.0), B.at(0.3));
+        assert_close2(second.at(1.0), B.at(1.0));
+    }
+
+    #[test]
+    fn split_at_same_curve() {
+        let t = 0.3;
+        let (first, second) = B.split_at(t);
+
+        for i in 0..=10 {
+            let s = i as f32 / 10.0;
+            assert_close2(first.at(s), B.at(s * t));
+            assert_close2(second.at(s), B.at(t + s * (1.0 - t)));
+        }
+    }
+
     #[test]
     fn start_curvature() {
         assert_close(B.curvature(0.0), 1.333333);
@@ -691,4 +1030,152 @@ impl Curve for Bezier5 {
             end: 5.0 * (self.end - self.ctrl3),
         }
     }
+
+    /// The box around the control points, since the curve always stays inside their hull
+    fn bounding_box(&self) -> (Vector, Vector) {
+        bounding_box_of(&[
+            self.start, self.ctrl0, self.ctrl1, self.ctrl2, self.ctrl3, self.end,
+        ])
+    }
+}
+
+impl Bezier5 {
+    /// Cut the curve at `t` into the part before and the part after
+    pub fn split_at(&self, t: f32) -> (Bezier5, Bezier5) {
+        let mut points = [
+            self.start, self.ctrl0, self.ctrl1, self.ctrl2, self.ctrl3, self.end,
+        ];
+        let mut first = [Vector::default(); 6];
+        let mut second = [Vector::default(); 6];
+
+        de_casteljau(&mut points, &mut first, &mut second, t);
+
+        (
+            Bezier5 {
+                start: first[0],
+                ctrl0: first[1],
+                ctrl1: first[2],
+                ctrl2: first[3],
+                ctrl3: first[4],
+                end: first[5],
+            },
+            Bezier5 {
+                start: second[0],
+                ctrl0: second[1],
+                ctrl1: second[2],
+                ctrl2: second[3],
+                ctrl3: second[4],
+                end: second[5],
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod bezier5_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use core::cell::Cell;
+
+    use super::super::Vector;
+    use super::{Bezier4, Bezier5, Curve};
+
+    const B: Bezier5 = Bezier5 {
+        start: Vector { x: 0.0, y: 0.0 },
+        ctrl0: Vector { x: 0.5, y: 0.0 },
+        ctrl1: Vector { x: 1.0, y: 0.0 },
+        ctrl2: Vector { x: 1.0, y: 0.25 },
+        ctrl3: Vector { x: 1.0, y: 0.5 },
+        end: Vector { x: 1.0, y: 1.0 },
+    };
+
+    #[test]
+    fn bounding_box_holds_curve() {
+        let (min, max) = B.bounding_box();
+        assert_close2(min, Vector { x: 0.0, y: 0.0 });
+        assert_close2(max, Vector { x: 1.0, y: 1.0 });
+
+        for i in 0..=100 {
+            let p = B.at(i as f32 / 100.0);
+            assert!(p.x >= min.x && p.x <= max.x, "{:?} is outside", p);
+            assert!(p.y >= min.y && p.y <= max.y, "{:?} is outside", p);
+        }
+    }
+
+    /// Counts how many points get evaluated on the curve
+    struct Counted<'a> {
+        curve: Bezier5,
+        evaluations: &'a Cell<u32>,
+    }
+
+    impl<'a> Curve for Counted<'a> {
+        type Derivative = Bezier4;
+
+        fn at(&self, t: f32) -> Vector {
+            self.evaluations.set(self.evaluations.get() + 1);
+            self.curve.at(t)
+        }
+
+        fn derivative(&self) -> Bezier4 {
+            self.curve.derivative()
+        }
+    }
+
+    #[test]
+    fn closest_point_near_matches_cold_search() {
+        let evaluations = Cell::new(0);
+        let counted = Counted {
+            curve: B,
+            evaluations: &evaluations,
+        };
+
+        let mut t_hint = 0.0;
+        let mut cold_evaluations = 0;
+        let mut near_evaluations = 0;
+
+        // Drift along the curve a little off of it, like the mouse does between updates
+        for i in 2..19 {
+            let m = B.at(i as f32 / 20.0) + Vector { x: 0.01, y: -0.01 };
+
+            evaluations.set(0);
+            let (cold_t, _) = counted.closest_point(m);
+            cold_evaluations += evaluations.get();
+
+            evaluations.set(0);
+            let (near_t, _) = counted.closest_point_near(m, t_hint);
+            near_evaluations += evaluations.get();
+
+            assert!((cold_t - near_t).abs() < 0.0001, "{} != {}", cold_t, near_t);
+            t_hint = near_t;
+        }
+
+        assert!(
+            near_evaluations * 2 < cold_evaluations,
+            "{} near evaluations, {} cold evaluations",
+            near_evaluations,
+            cold_evaluations
+        );
+    }
+
+    #[test]
+    fn split_at_continuous() {
+        let (first, second) = B.split_at(0.6);
+        assert_close2(first.at(0.0), B.at(0.0));
+        assert_close2(first.at(1.0), B.at(0.6));
+        assert_close2(second.at(0.0), B.at(0.6));
+        assert_close2(second.at(1.0), B.at(1.0));
+    }
+
+    #[test]
+    fn split_at_same_curve() {
+        let t = 0.6;
+        let (first, second) = B.split_at(t);
+
+        for i in 0..=10 {
+            let s = i as f32 / 10.0;
+            assert_close2(first.at(s), B.at(s * t));
+            assert_close2(second.at(s), B.at(t + s * (1.0 - t)));
+        }
+    }
 }
@@ -1,3 +1,4 @@
+use core::cmp::Ordering;
 use core::f32::consts::FRAC_PI_8;
 
 use itertools::Itertools;
@@ -21,6 +22,7 @@ use super::{
     DIRECTION_PI_2,
 };
 use crate::fast::motion_queue::Motion;
+use crate::fast::path::ClosestPointHint;
 
 pub struct AverageFilter<N: ArrayLength<f32>> {
     values: Vec<f32, N>,
@@ -31,7 +33,36 @@ impl<N: ArrayLength<f32>> AverageFilter<N> {
         AverageFilter { values: Vec::new() }
     }
 
-    pub fn filter(&mut self, value: f32) -> f32 {
+    /// Throw out all of the values in the window
+    pub fn reset(&mut self) {
+        self.values = Vec::new();
+    }
+
+    /// The mean of the window, or `None` if there are no values in it yet
+    pub fn current(&self) -> Option<f32> {
+        self.values
+            .iter()
+            .sum1::<f32>()
+            .map(|sum| sum / self.values.len() as f32)
+    }
+
+    /// The variance of the window, or `None` if there are no values in it yet
+    pub fn variance(&self) -> Option<f32> {
+        self.current().map(|mean| {
+            self.values
+                .iter()
+                .map(|value| (value - mean) * (value - mean))
+                .sum::<f32>()
+                / self.values.len() as f32
+        })
+    }
+
+    /// How much of the window has been filled, from 0.0 to 1.0
+    pub fn fill(&self) -> f32 {
+        self.values.len() as f32 / self.values.capacity() as f32
+    }
+
+    fn push(&mut self, value: f32) {
         let len = self.values.len();
         if len >= self.values.capacity() {
             self.values.rotate_left(1);
@@ -39,11 +70,29 @@ impl<N: ArrayLength<f32>> AverageFilter<N> {
         } else {
             self.values.push(value).ok();
         }
+    }
 
-        if let Some(sum) = self.values.iter().sum1::<f32>() {
-            sum / self.values.len() as f32
-        } else {
+    /// Add a value and get the mean of the window
+    pub fn filter(&mut self, value: f32) -> f32 {
+        self.push(value);
+        self.current().unwrap_or(value)
+    }
+
+    /// Add a value and get the median of the window. With an even number of values, this is the
+    /// mean of the middle two.
+    pub fn filter_median(&mut self, value: f32) -> f32 {
+        self.push(value);
+
+        let mut sorted = self.values.clone();
+        sorted.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+        let len = sorted.len();
+        if len == 0 {
             value
+        } else if len % 2 == 1 {
+            sorted[len / 2]
+        } else {
+            (sorted[len / 2 - 1] + sorted[len / 2]) / 2.0
         }
     }
 }
@@ -91,8 +140,52 @@ mod test_average_filter {
             (2.0 + 3.0 + 4.0 + 5.0 + 6.0 + 7.0 + 8.0 + 9.0) / 8.0,
         );
     }
+
+    #[test]
+    fn current_before_any_push() {
+        let filter = AverageFilter::<U8>::new();
+        assert_eq!(filter.current(), None);
+    }
+
+    #[test]
+    fn reset_then_filter() {
+        let mut filter = AverageFilter::<U8>::new();
+
+        filter.filter(10.0);
+        filter.filter(20.0);
+        assert_close(filter.current().unwrap(), 15.0);
+
+        filter.reset();
+        assert_eq!(filter.current(), None);
+
+        assert_close(filter.filter(1.0), 1.0);
+        assert_close(filter.filter(2.0), (1.0 + 2.0) / 2.0);
+    }
+
+    #[test]
+    fn variance() {
+        let mut filter = AverageFilter::<U8>::new();
+        assert_eq!(filter.variance(), None);
+
+        filter.filter(1.0);
+        filter.filter(3.0);
+        assert_close(filter.variance().unwrap(), 1.0);
+        assert_close(filter.fill(), 2.0 / 8.0);
+    }
+
+    #[test]
+    fn median() {
+        let mut filter = AverageFilter::<U8>::new();
+
+        assert_close(filter.filter_median(3.0), 3.0);
+        assert_close(filter.filter_median(1.0), 2.0);
+        assert_close(filter.filter_median(2.0), 2.0);
+    }
 }
 
+/// How many readings the side distance filters average over unless told otherwise
+pub type FilterSize = U8;
+
 /// Configuration for a [SideDistanceFilter]
 #[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct SideDistanceFilterConfig {
@@ -104,21 +197,25 @@ pub struct SideDistanceFilterConfig {
 
     /// The max allowed change between the change in readings
     pub max_delta2: f32,
+
+    /// Use the median of the recent readings instead of the mean, so a single bad reading gets
+    /// thrown out instead of dragging the result
+    pub use_median: bool,
 }
 
 /// Filters a raw distance reading into something that makes sense
 ///
 ///  - Makes sure that the readings are within the max delta and second delta
-///  - Feeds through an averaging filter
+///  - Feeds through an averaging or median filter
 ///  - Offsets from the mechanical location of the sensor to the center of the mouse
-struct SideDistanceFilter {
-    average_filter: AverageFilter<U8>,
+struct SideDistanceFilter<N: ArrayLength<f32> = FilterSize> {
+    average_filter: AverageFilter<N>,
     last_raw: Option<f32>,
     last_delta: Option<f32>,
 }
 
-impl SideDistanceFilter {
-    pub fn new() -> SideDistanceFilter {
+impl<N: ArrayLength<f32>> SideDistanceFilter<N> {
+    pub fn new() -> SideDistanceFilter<N> {
         SideDistanceFilter {
             average_filter: AverageFilter::new(),
             last_raw: None,
@@ -157,10 +254,14 @@ impl SideDistanceFilter {
                 self.last_delta = delta;
 
                 if raw < config.max_range && stabilized {
-                    Some(self.average_filter.filter(raw))
+                    if config.use_median {
+                        Some(self.average_filter.filter_median(raw))
+                    } else {
+                        Some(self.average_filter.filter(raw))
+                    }
                 } else {
                     self.last_delta = None;
-                    self.average_filter = AverageFilter::new();
+                    self.average_filter.reset();
                     None
                 }
             }
@@ -168,11 +269,21 @@ impl SideDistanceFilter {
             DistanceReading::OutOfRange => {
                 self.last_raw = None;
                 self.last_delta = None;
-                self.average_filter = AverageFilter::new();
+                self.average_filter.reset();
                 None
             }
         }
     }
+
+    /// How much to trust the filtered distance, from 0.0 to 1.0. It starts at 0.0 when the
+    /// filter is reset, goes up as the window fills, and goes down when the readings in it are
+    /// spread out.
+    pub fn confidence(&self) -> f32 {
+        self.average_filter
+            .variance()
+            .map(|variance| self.average_filter.fill() / (1.0 + variance))
+            .unwrap_or(0.0)
+    }
 }
 
 #[cfg(test)]
@@ -183,16 +294,18 @@ mod side_distance_filter_test {
     use super::SideDistanceFilter;
     use super::SideDistanceFilterConfig;
     use crate::mouse::DistanceReading;
+    use typenum::{U16, U4, U8};
 
     const CONFIG: SideDistanceFilterConfig = SideDistanceFilterConfig {
         max_range: 100.0,
         max_delta: 10.0,
         max_delta2: 5.0,
+        use_median: false,
     };
 
     #[test]
     fn single_in_range() {
-        let mut filter = SideDistanceFilter::new();
+        let mut filter = SideDistanceFilter::<U8>::new();
         assert_eq!(
             filter.filter(&CONFIG, DistanceReading::InRange(1.0)),
             Some(1.0)
@@ -201,13 +314,13 @@ mod side_distance_filter_test {
 
     #[test]
     fn single_out_of_range() {
-        let mut filter = SideDistanceFilter::new();
+        let mut filter = SideDistanceFilter::<U8>::new();
         assert_eq!(filter.filter(&CONFIG, DistanceReading::OutOfRange), None)
     }
 
     #[test]
     fn two_in_range_out_of_range() {
-        let mut filter = SideDistanceFilter::new();
+        let mut filter = SideDistanceFilter::<U8>::new();
         assert_eq!(
             filter.filter(&CONFIG, DistanceReading::InRange(1.0)),
             Some(1.0)
@@ -217,7 +330,7 @@ mod side_distance_filter_test {
 
     #[test]
     fn out_of_range_is_none_and_clears_average_filter() {
-        let mut filter = SideDistanceFilter::new();
+        let mut filter = SideDistanceFilter::<U8>::new();
         assert_eq!(
             filter.filter(&CONFIG, DistanceReading::InRange(1.0)),
             Some(1.0)
@@ -231,7 +344,7 @@ mod side_distance_filter_test {
 
     #[test]
     fn delta_too_high_is_none_and_clears_average_filter() {
-        let mut filter = SideDistanceFilter::new();
+        let mut filter = SideDistanceFilter::<U8>::new();
         assert_eq!(
             filter.filter(&CONFIG, DistanceReading::InRange(1.0)),
             Some(1.0)
@@ -245,7 +358,7 @@ mod side_distance_filter_test {
 
     #[test]
     fn delta2_too_high_is_none_and_clears_average_filter() {
-        let mut filter = SideDistanceFilter::new();
+        let mut filter = SideDistanceFilter::<U8>::new();
         assert_eq!(
             filter.filter(&CONFIG, DistanceReading::InRange(1.0)),
             Some(1.0)
@@ -260,6 +373,90 @@ mod side_distance_filter_test {
             Some(3.0)
         );
     }
+
+    /// Seven good readings with one bad one in the middle that is still small enough to make it
+    /// past the delta checks
+    fn outlier(config: &SideDistanceFilterConfig) -> Option<f32> {
+        let mut filter = SideDistanceFilter::<U8>::new();
+
+        let mut last = None;
+        for &raw in [50.0, 50.0, 50.0, 58.0, 50.0, 50.0, 50.0, 50.0].iter() {
+            last = filter.filter(config, DistanceReading::InRange(raw));
+        }
+
+        last
+    }
+
+    #[test]
+    fn mean_is_affected_by_outlier() {
+        let config = SideDistanceFilterConfig {
+            max_delta2: 20.0,
+            ..CONFIG
+        };
+
+        assert_eq!(outlier(&config), Some(51.0));
+    }
+
+    #[test]
+    fn median_ignores_outlier() {
+        let config = SideDistanceFilterConfig {
+            max_delta2: 20.0,
+            use_median: true,
+            ..CONFIG
+        };
+
+        assert_eq!(outlier(&config), Some(50.0));
+    }
+
+    #[test]
+    fn confidence() {
+        let mut filter = SideDistanceFilter::<U8>::new();
+        assert_close(filter.confidence(), 0.0);
+
+        let mut last = 0.0;
+        for _ in 0..8 {
+            filter.filter(&CONFIG, DistanceReading::InRange(50.0));
+            assert!(filter.confidence() > last);
+            last = filter.confidence();
+        }
+        assert_close(last, 1.0);
+
+        filter.filter(&CONFIG, DistanceReading::OutOfRange);
+        assert_close(filter.confidence(), 0.0);
+    }
+
+    #[test]
+    fn noisy_readings_are_less_confident() {
+        let mut steady = SideDistanceFilter::<U8>::new();
+        let mut noisy = SideDistanceFilter::<U8>::new();
+
+        for i in 0..8 {
+            steady.filter(&CONFIG, DistanceReading::InRange(50.0));
+            noisy.filter(
+                &CONFIG,
+                DistanceReading::InRange(if i % 2 == 0 { 48.0 } else { 52.0 }),
+            );
+        }
+
+        assert!(noisy.confidence() < steady.confidence());
+    }
+
+    #[test]
+    fn window_sizes() {
+        let mut short = SideDistanceFilter::<U4>::new();
+        let mut long = SideDistanceFilter::<U16>::new();
+
+        let mut short_last = None;
+        let mut long_last = None;
+        for i in 0..16 {
+            let raw = DistanceReading::InRange(i as f32);
+            short_last = short.filter(&CONFIG, raw);
+            long_last = long.filter(&CONFIG, raw);
+        }
+
+        assert_eq!(short_last, Some((12.0 + 13.0 + 14.0 + 15.0) / 4.0));
+        assert_eq!(long_last, Some(15.0 / 2.0));
+    }
 }
 
 #[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
@@ -268,6 +465,17 @@ pub struct LocalizeConfig {
     pub left_side_filter: SideDistanceFilterConfig,
     pub right_side_filter: SideDistanceFilterConfig,
     pub front_max_range: f32,
+    /// The most the position can be moved towards the sensor estimate in one update. 0 turns
+    /// off the limit.
+    pub max_correction: f32,
+    /// How much to trust the gyro over the encoders for the heading, from 0.0 to 1.0
+    pub gyro_weight: f32,
+    /// How far the encoders and the front sensor can disagree on how far the mouse moved
+    /// between front readings before deciding that the wheels are slipping
+    pub slip_threshold: f32,
+    /// How much to trust the sensors over the encoders for the position, from 0.0 to 1.0. At
+    /// 1.0, the position is corrected all the way to the sensors, up to `max_correction`.
+    pub sensor_weight: f32,
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -275,6 +483,13 @@ pub struct LocalizeDebug {
     //pub maze: Maze,
     pub encoder_orientation: Orientation,
     pub sensor: Option<SensorDebug>,
+    /// The wheels are slipping, so the position was snapped to the sensors
+    pub slip: bool,
+
+    /// How much to trust the side sensors this update, from 0.0 to 1.0. This is 0.0 when the
+    /// sensors are not being used, when there were no side readings, or right after the side
+    /// filters were reset.
+    pub confidence: f32,
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -286,25 +501,153 @@ pub struct SensorDebug {
     pub center_offset: Option<f32>,
     pub maybe_x: Option<f32>,
     pub maybe_y: Option<f32>,
+    pub correction: f32,
+}
+
+/// Blend the heading from the encoders with the heading from the gyro. The gyro is used for fast
+/// changes, but the encoders keep it from drifting away with the gyro bias.
+fn fuse_heading(encoder: Direction, gyro: Direction, gyro_weight: f32) -> Direction {
+    Direction::from(
+        gyro_weight * f32::from(gyro) + (1.0 - gyro_weight) * encoder.centered_at(gyro),
+    )
+}
+
+#[cfg(test)]
+mod fuse_heading_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::fuse_heading;
+    use crate::fast::Direction;
+
+    #[test]
+    fn weighted() {
+        let direction = fuse_heading(Direction::from(1.0), Direction::from(2.0), 0.75);
+        assert_close(f32::from(direction), 1.75);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let direction = fuse_heading(Direction::from(-0.1), Direction::from(0.1), 0.5);
+        assert_close_direction(direction, Direction::from(0.0));
+    }
+}
+
+/// Blend the position from the encoders with the position from the sensors
+fn blend_position(encoder: Vector, sensor: Vector, sensor_weight: f32) -> Vector {
+    encoder * (1.0 - sensor_weight) + sensor * sensor_weight
+}
+
+#[cfg(test)]
+mod blend_position_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::blend_position;
+    use crate::fast::Vector;
+
+    #[test]
+    fn midpoint() {
+        let position = blend_position(
+            Vector { x: 10.0, y: 10.0 },
+            Vector { x: 20.0, y: 14.0 },
+            0.5,
+        );
+        assert_close2(position, Vector { x: 15.0, y: 12.0 });
+    }
+
+    #[test]
+    fn all_sensor() {
+        let position = blend_position(
+            Vector { x: 10.0, y: 10.0 },
+            Vector { x: 20.0, y: 14.0 },
+            1.0,
+        );
+        assert_close2(position, Vector { x: 20.0, y: 14.0 });
+    }
+}
+
+/// Move from `from` towards `to`, but not more than `max_step`. A `max_step` of 0 goes all the
+/// way to `to`.
+fn limit_correction(from: Vector, to: Vector, max_step: f32) -> Vector {
+    let correction = to - from;
+    let magnitude = correction.magnitude();
+
+    if max_step > 0.0 && magnitude > max_step {
+        from + correction * (max_step / magnitude)
+    } else {
+        to
+    }
 }
 
-pub struct Localize {
+#[cfg(test)]
+mod limit_correction_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::limit_correction;
+    use crate::fast::Vector;
+
+    #[test]
+    fn small_correction() {
+        let position = limit_correction(
+            Vector { x: 10.0, y: 10.0 },
+            Vector { x: 12.0, y: 10.0 },
+            5.0,
+        );
+        assert_close2(position, Vector { x: 12.0, y: 10.0 });
+    }
+
+    #[test]
+    fn large_correction() {
+        let position = limit_correction(
+            Vector { x: 10.0, y: 10.0 },
+            Vector { x: 40.0, y: 50.0 },
+            5.0,
+        );
+        assert_close2(position, Vector { x: 13.0, y: 14.0 });
+    }
+
+    #[test]
+    fn no_limit() {
+        let position = limit_correction(
+            Vector { x: 10.0, y: 10.0 },
+            Vector { x: 40.0, y: 50.0 },
+            0.0,
+        );
+        assert_close2(position, Vector { x: 40.0, y: 50.0 });
+    }
+}
+
+/// Keeps track of where the mouse is. `N` is how many readings the side distance filters
+/// average over.
+pub struct Localize<N: ArrayLength<f32> = FilterSize> {
     orientation: Orientation,
     left_encoder: i32,
     right_encoder: i32,
     raw_left_distance: Option<DistanceReading>,
     raw_right_distance: Option<DistanceReading>,
-    left_filter: SideDistanceFilter,
-    right_filter: SideDistanceFilter,
+    left_filter: SideDistanceFilter<N>,
+    right_filter: SideDistanceFilter<N>,
     last_direction_moved: Direction,
+
+    /// The heading from just the encoders, only kept separately while the gyro is being used
+    encoder_direction: Direction,
+
+    /// The front distance reading when the front wall was first seen, and how far forward the
+    /// encoders have moved since then
+    first_front_distance: Option<f32>,
+    travel_since_front: f32,
+
+    closest_point_hint: ClosestPointHint,
 }
 
-impl Localize {
+impl<N: ArrayLength<f32>> Localize<N> {
     pub fn new(
         orientation: Orientation,
         left_encoder: i32,
         right_encoder: i32,
-    ) -> Localize {
+    ) -> Localize<N> {
         Localize {
             orientation,
             left_encoder,
@@ -314,9 +657,18 @@ impl Localize {
             left_filter: SideDistanceFilter::new(),
             right_filter: SideDistanceFilter::new(),
             last_direction_moved: orientation.direction,
+            encoder_direction: orientation.direction,
+            first_front_distance: None,
+            travel_since_front: 0.0,
+            closest_point_hint: ClosestPointHint::default(),
         }
     }
 
+    /// Where the mouse was as of the last update
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
     pub fn update(
         &mut self,
         mech: &MechanicalConfig,
@@ -327,6 +679,8 @@ impl Localize {
         raw_left_distance: Option<DistanceReading>,
         raw_front_distance: Option<DistanceReading>,
         raw_right_distance: Option<DistanceReading>,
+        gyro_rate: Option<f32>,
+        delta_time: u32,
         motion: Option<Motion>,
         moves_completed: usize,
     ) -> (Orientation, LocalizeDebug) {
@@ -337,8 +691,37 @@ impl Localize {
             self.orientation
                 .update_from_encoders(&mech, delta_left, delta_right);
 
+        // The gyro rate is in radians per ms
+        let encoder_orientation = if let Some(gyro_rate) = gyro_rate {
+            self.encoder_direction +=
+                encoder_orientation.direction - self.orientation.direction;
+
+            let gyro_direction =
+                self.orientation.direction + gyro_rate * delta_time as f32;
+
+            Orientation {
+                position: encoder_orientation.position,
+                direction: fuse_heading(
+                    self.encoder_direction,
+                    gyro_direction,
+                    config.gyro_weight,
+                ),
+            }
+        } else {
+            encoder_orientation
+        };
+
+        self.travel_since_front += (encoder_orientation.position
+            - self.orientation.position)
+            .dot(encoder_orientation.direction.into_unit_vector());
+
+        let mut slip = false;
+        let mut confidence = 0.0;
+
         let (orientation, sensor_debug) = if let Some(Motion::Path(motion)) = motion {
-            let (t, _) = motion.closest_point(encoder_orientation.position);
+            let (t, _) = self
+                .closest_point_hint
+                .closest_point(&motion, encoder_orientation.position);
             let path_direction = motion.derivative(t).direction();
 
             const DIRECTION_WITHIN: f32 = FRAC_PI_8 / 2.0;
@@ -384,34 +767,59 @@ impl Localize {
                         (None, None)
                     };
 
+                // Going by whichever side is steadier, since one good wall is enough to center
+                // off of
+                let left_confidence = left_distance
+                    .map(|_| self.left_filter.confidence())
+                    .unwrap_or(0.0);
+                let right_confidence = right_distance
+                    .map(|_| self.right_filter.confidence())
+                    .unwrap_or(0.0);
+                confidence = left_confidence.max(right_confidence);
+
                 let front_distance = raw_front_distance
+                    .with_offset(mech.front_sensor_offset_x)
                     .value()
-                    .map(|d| d + mech.front_sensor_offset_x)
                     .filter(|&d| d < config.front_max_range);
 
-                // Calculate maze 'constants' for this location
-                let cell_center_x = (encoder_orientation.position.x / maze.cell_width)
-                    .floor()
-                    * maze.cell_width
-                    + maze.cell_width / 2.0;
+                // If the front wall barely got closer but the encoders moved a lot, a wheel is
+                // slipping and the encoders can't be trusted. This is compared over the whole
+                // time the wall is seen so that small slips add up.
+                if raw_front_distance.is_some() {
+                    match (self.first_front_distance, front_distance) {
+                        (Some(first), Some(front)) => {
+                            let sensor_travel = first - front;
+                            slip = (self.travel_since_front - sensor_travel).abs()
+                                > config.slip_threshold;
+
+                            // Start over from where the sensors put the mouse
+                            if slip {
+                                self.first_front_distance = Some(front);
+                                self.travel_since_front = 0.0;
+                            }
+                        }
+                        (None, Some(front)) => {
+                            self.first_front_distance = Some(front);
+                            self.travel_since_front = 0.0;
+                        }
+                        (_, None) => self.first_front_distance = None,
+                    }
+                }
 
-                let cell_center_y = (encoder_orientation.position.y / maze.cell_width)
-                    .floor()
-                    * maze.cell_width
-                    + maze.cell_width / 2.0;
+                // Calculate maze 'constants' for this location
+                let cell_center =
+                    maze.cell_center(maze.cell_index(encoder_orientation.position));
+                let cell_center_x = cell_center.x;
+                let cell_center_y = cell_center.y;
 
                 // Where the front sensor is
-                let front_sensor_position = encoder_orientation.position
-                    + mech.front_sensor_offset_x
-                        * encoder_orientation.direction.into_unit_vector();
-
-                let front_sensor_cell_center_x =
-                    (front_sensor_position.x / maze.cell_width).floor() * maze.cell_width
-                        + maze.cell_width / 2.0;
+                let front_sensor_position =
+                    encoder_orientation.offset(mech.front_sensor()).position;
 
-                let front_sensor_cell_center_y =
-                    (front_sensor_position.y / maze.cell_width).floor() * maze.cell_width
-                        + maze.cell_width / 2.0;
+                let front_sensor_cell_center =
+                    maze.cell_center(maze.cell_index(front_sensor_position));
+                let front_sensor_cell_center_x = front_sensor_cell_center.x;
+                let front_sensor_cell_center_y = front_sensor_cell_center.y;
 
                 // Where are we left/right within the cell?
                 let center_offset = match (left_distance, right_distance) {
@@ -469,11 +877,30 @@ impl Localize {
                     (None, None)
                 };
 
-                let position = Vector {
+                let sensor_position = Vector {
                     x: maybe_x.unwrap_or(encoder_orientation.position.x),
                     y: maybe_y.unwrap_or(encoder_orientation.position.y),
                 };
 
+                let blended_position = blend_position(
+                    encoder_orientation.position,
+                    sensor_position,
+                    config.sensor_weight,
+                );
+
+                // Don't snap to the sensor position, it can be far off of the encoders after
+                // the sensors drop out for a while. When slipping though, the encoders are
+                // what is far off.
+                let position = if slip {
+                    sensor_position
+                } else {
+                    limit_correction(
+                        encoder_orientation.position,
+                        blended_position,
+                        config.max_correction,
+                    )
+                };
+
                 let direction_moved = (position - self.orientation.position).direction();
 
                 let direction_moved_reset = !encoder_orientation
@@ -512,15 +939,18 @@ impl Localize {
                     center_offset,
                     maybe_x,
                     maybe_y,
+                    correction: encoder_orientation.position.distance_to(position),
                 };
 
                 (orientation, Some(sensor_debug))
             } else {
+                self.first_front_distance = None;
                 (encoder_orientation, None)
             }
         } else {
             self.left_filter = SideDistanceFilter::new();
             self.right_filter = SideDistanceFilter::new();
+            self.first_front_distance = None;
             (encoder_orientation, None)
         };
 
@@ -528,8 +958,16 @@ impl Localize {
             //maze: self.maze.clone(),
             encoder_orientation,
             sensor: sensor_debug,
+            slip,
+            confidence,
         };
 
+        // Without the gyro, or when the sensors fixed the heading, the encoder heading has
+        // nothing else to go off of
+        if gyro_rate.is_none() || orientation.direction != encoder_orientation.direction {
+            self.encoder_direction = orientation.direction;
+        }
+
         self.left_encoder = left_encoder;
         self.right_encoder = right_encoder;
         self.orientation = orientation;
@@ -537,3 +975,250 @@ impl Localize {
         (self.orientation, debug)
     }
 }
+
+#[cfg(test)]
+mod localize_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::{Localize, LocalizeConfig};
+    use crate::config::{LOCALIZE, MAZE};
+    use crate::fast::motion_queue::Motion;
+    use crate::fast::path::PathMotion;
+    use crate::fast::{Orientation, Vector, DIRECTION_0};
+    use crate::mouse::DistanceReading;
+
+    const MECH: crate::config::MechanicalConfig = crate::config::mouse_2019::MECH;
+
+    #[test]
+    fn gyro_bias_is_corrected() {
+        let config = LocalizeConfig {
+            use_sensors: false,
+            gyro_weight: 0.9,
+            ..LOCALIZE
+        };
+
+        let mut localize: Localize = Localize::new(
+            Orientation {
+                position: Vector { x: 90.0, y: 90.0 },
+                direction: DIRECTION_0,
+            },
+            0,
+            0,
+        );
+
+        // Sitting still, but the gyro thinks it is slowly turning
+        let bias = 0.0001;
+        let mut orientation = Orientation::default();
+        for _ in 0..1000 {
+            let (new_orientation, _) = localize.update(
+                &MECH,
+                &MAZE,
+                &config,
+                0,
+                0,
+                None,
+                None,
+                None,
+                Some(bias),
+                10,
+                None,
+                0,
+            );
+            orientation = new_orientation;
+        }
+
+        // Just integrating the gyro would be a full radian off. The encoders hold it to where
+        // the bias from each step balances out with the pull back.
+        let steady_state = config.gyro_weight * bias * 10.0 / (1.0 - config.gyro_weight);
+        assert_close(orientation.direction.centered_at(DIRECTION_0), steady_state);
+    }
+
+    #[test]
+    fn no_gyro_is_encoders() {
+        let start = Orientation {
+            position: Vector { x: 90.0, y: 90.0 },
+            direction: DIRECTION_0,
+        };
+
+        let mut localize: Localize = Localize::new(start, 0, 0);
+
+        let (orientation, _) = localize.update(
+            &MECH, &MAZE, &LOCALIZE, 10, 30, None, None, None, None, 10, None, 0,
+        );
+
+        assert_eq!(orientation, start.update_from_encoders(&MECH, 10, 30));
+    }
+
+    /// Drive a little further along a straight line east, seeing both side walls at `side`
+    fn side_update(
+        localize: &mut Localize,
+        config: &LocalizeConfig,
+        i: i32,
+        side: DistanceReading,
+    ) -> f32 {
+        let start = Vector { x: 90.0, y: 90.0 };
+        let motion = Motion::Path(PathMotion::line(start, Vector { x: 990.0, y: 90.0 }));
+        let ticks = MECH.mm_to_ticks(1.0) as i32;
+
+        let (_, debug) = localize.update(
+            &MECH,
+            &MAZE,
+            config,
+            ticks * i,
+            ticks * i,
+            Some(side),
+            None,
+            Some(side),
+            None,
+            10,
+            Some(motion),
+            0,
+        );
+
+        debug.confidence
+    }
+
+    #[test]
+    fn confidence_recovers_after_reset() {
+        let mut localize: Localize = Localize::new(
+            Orientation {
+                position: Vector { x: 90.0, y: 90.0 },
+                direction: DIRECTION_0,
+            },
+            0,
+            0,
+        );
+
+        let mut confidence = 0.0;
+        for i in 0..8 {
+            let next =
+                side_update(&mut localize, &LOCALIZE, i, DistanceReading::InRange(40.0));
+            assert!(next > confidence);
+            confidence = next;
+        }
+
+        assert_close(confidence, 1.0);
+
+        // Losing the walls throws out the filters
+        assert_close(
+            side_update(&mut localize, &LOCALIZE, 8, DistanceReading::OutOfRange),
+            0.0,
+        );
+
+        let recovered =
+            side_update(&mut localize, &LOCALIZE, 9, DistanceReading::InRange(40.0));
+        assert!(recovered > 0.0);
+        assert!(recovered < confidence);
+    }
+
+    #[test]
+    fn half_sensor_weight_is_midpoint() {
+        let config = LocalizeConfig {
+            sensor_weight: 0.5,
+            max_correction: 100.0,
+            ..LOCALIZE
+        };
+
+        let start = Orientation {
+            position: Vector { x: 90.0, y: 90.0 },
+            direction: DIRECTION_0,
+        };
+
+        let mut localize: Localize = Localize::new(start, 0, 0);
+        let motion = Motion::Path(PathMotion::line(
+            start.position,
+            Vector { x: 990.0, y: 90.0 },
+        ));
+
+        // Closer to the right wall, so the sensors put the mouse south of the encoders
+        let (orientation, debug) = localize.update(
+            &MECH,
+            &MAZE,
+            &config,
+            0,
+            0,
+            Some(DistanceReading::InRange(50.0)),
+            None,
+            Some(DistanceReading::InRange(30.0)),
+            None,
+            10,
+            Some(motion),
+            0,
+        );
+
+        let sensor_y = debug.sensor.unwrap().maybe_y.unwrap();
+        assert!(sensor_y < start.position.y);
+
+        assert_close(orientation.position.x, start.position.x);
+        assert_close(orientation.position.y, (start.position.y + sensor_y) / 2.0);
+    }
+
+    #[test]
+    fn no_confidence_without_sensors() {
+        let config = LocalizeConfig {
+            use_sensors: false,
+            ..LOCALIZE
+        };
+
+        let mut localize: Localize = Localize::new(
+            Orientation {
+                position: Vector { x: 90.0, y: 90.0 },
+                direction: DIRECTION_0,
+            },
+            0,
+            0,
+        );
+
+        for i in 0..8 {
+            assert_close(
+                side_update(&mut localize, &config, i, DistanceReading::InRange(40.0)),
+                0.0,
+            );
+        }
+    }
+
+    #[test]
+    fn slip_snaps_to_sensors() {
+        let start = Orientation {
+            position: Vector { x: 90.0, y: 90.0 },
+            direction: DIRECTION_0,
+        };
+
+        let mut localize: Localize = Localize::new(start, 0, 0);
+        let motion = Motion::Path(PathMotion::line(
+            start.position,
+            Vector { x: 270.0, y: 90.0 },
+        ));
+
+        // The wheels spin 5mm every update, but the front wall stays the same distance away
+        let ticks = MECH.mm_to_ticks(5.0) as i32;
+        let mut slipped = false;
+        let mut orientation = start;
+        for i in 1..10 {
+            let (new_orientation, debug) = localize.update(
+                &MECH,
+                &MAZE,
+                &LOCALIZE,
+                ticks * i,
+                ticks * i,
+                None,
+                Some(DistanceReading::InRange(50.0)),
+                None,
+                None,
+                10,
+                Some(motion),
+                0,
+            );
+
+            slipped |= debug.slip;
+            orientation = new_orientation;
+        }
+
+        assert!(slipped);
+
+        // Where the front sensor puts the mouse, instead of 45mm further along
+        let sensor_x = 90.0 + MAZE.center_to_wall() - 50.0 - MECH.front_sensor_offset_x;
+        assert_close(orientation.position.x, sensor_x);
+    }
+}
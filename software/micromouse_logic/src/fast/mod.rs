@@ -8,6 +8,7 @@ pub mod motion_control;
 pub mod motion_queue;
 pub mod motor_control;
 pub mod path;
+pub mod stall;
 pub mod turn;
 
 use core::f32::consts::{FRAC_PI_4, PI};
@@ -18,7 +19,7 @@ use libm::F32Ext;
 
 use crate::config::MechanicalConfig;
 use crate::slow::maze::MazeConfig;
-use crate::slow::{MazeDirection, MazeOrientation, MazePosition};
+use crate::slow::{MazeDirection, MazeOrientation};
 
 /// A 2d vector
 #[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
@@ -36,6 +37,26 @@ impl Vector {
         Direction::from(F32Ext::atan2(self.y, self.x))
     }
 
+    /// A vector in the same direction with a magnitude of 1, or a zero vector if this one has no
+    /// magnitude
+    pub fn normalized(&self) -> Vector {
+        let magnitude = self.magnitude();
+
+        if magnitude == 0.0 {
+            Vector { x: 0.0, y: 0.0 }
+        } else {
+            Vector {
+                x: self.x / magnitude,
+                y: self.y / magnitude,
+            }
+        }
+    }
+
+    /// The distance between `self` and `other` as points
+    pub fn distance_to(&self, other: Vector) -> f32 {
+        (other - *self).magnitude()
+    }
+
     pub fn cross(&self, v: Vector) -> f32 {
         self.x * v.y - v.x * self.y
     }
@@ -76,6 +97,11 @@ impl Vector {
             y: self.y + offset,
         }
     }
+
+    /// Checks if both components are within `eps` of the other vector's
+    pub fn approx_eq(&self, other: Vector, eps: f32) -> bool {
+        (self.x - other.x).abs() <= eps && (self.y - other.y).abs() <= eps
+    }
 }
 
 #[cfg(test)]
@@ -99,6 +125,13 @@ mod vector_tests {
         assert_close(f32::from(Vector { x: 1.0, y: 1.0 }.direction()), FRAC_PI_4);
     }
 
+    #[test]
+    fn approx_eq() {
+        let v = Vector { x: 1.0, y: 2.0 };
+        assert!(v.approx_eq(Vector { x: 1.001, y: 1.999 }, 0.01));
+        assert!(!v.approx_eq(Vector { x: 1.0, y: 2.1 }, 0.01));
+    }
+
     #[test]
     fn vector_dot_test_parallel() {
         assert_close(
@@ -154,6 +187,38 @@ mod vector_tests {
             Vector { x: 30.0, y: 32.0 },
         )
     }
+
+    #[test]
+    fn vector_normalized() {
+        assert_close2(
+            Vector { x: 3.0, y: 4.0 }.normalized(),
+            Vector { x: 0.6, y: 0.8 },
+        )
+    }
+
+    #[test]
+    fn vector_normalized_zero() {
+        assert_close2(
+            Vector { x: 0.0, y: 0.0 }.normalized(),
+            Vector { x: 0.0, y: 0.0 },
+        )
+    }
+
+    #[test]
+    fn vector_distance_to() {
+        assert_close(
+            Vector { x: 1.0, y: 2.0 }.distance_to(Vector { x: 4.0, y: 6.0 }),
+            5.0,
+        )
+    }
+
+    #[test]
+    fn vector_distance_to_self() {
+        assert_close(
+            Vector { x: 1.0, y: 2.0 }.distance_to(Vector { x: 1.0, y: 2.0 }),
+            0.0,
+        )
+    }
 }
 
 impl core::ops::Sub for Vector {
@@ -238,6 +303,17 @@ impl Direction {
     pub fn within(&self, other: Direction, within: f32) -> bool {
         (self.centered_at(other) - other.0).abs() < within
     }
+
+    /// Checks if this direction is within `eps` of the other direction, going the short way
+    /// around so that directions on either side of 0 can still be equal
+    pub fn approx_eq(&self, other: Direction, eps: f32) -> bool {
+        (self.centered_at(other) - other.0).abs() <= eps
+    }
+
+    /// The direction halfway between this one and the other, going the short way around
+    pub fn midpoint(self, other: Direction) -> Direction {
+        Direction::from((self.0 + other.centered_at(self)) / 2.0)
+    }
 }
 
 impl From<f32> for Direction {
@@ -315,6 +391,50 @@ pub const DIRECTION_PI_2: Direction = Direction(core::f32::consts::FRAC_PI_2);
 pub const DIRECTION_PI: Direction = Direction(core::f32::consts::PI);
 pub const DIRECTION_3_PI_2: Direction = Direction(3.0 * core::f32::consts::FRAC_PI_2);
 
+#[cfg(test)]
+mod direction_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use core::f32::consts::{FRAC_PI_4, PI};
+
+    use super::Direction;
+    use super::{DIRECTION_0, DIRECTION_PI_2};
+
+    #[test]
+    fn approx_eq_wrap() {
+        let almost_2_pi = Direction::from(2.0 * PI - 0.00001);
+        assert!(DIRECTION_0.approx_eq(almost_2_pi, 0.0001));
+        assert!(almost_2_pi.approx_eq(DIRECTION_0, 0.0001));
+        assert!(!DIRECTION_0.approx_eq(Direction::from(2.0 * PI - 0.1), 0.0001));
+    }
+
+    #[test]
+    fn approx_eq_not_wrapped() {
+        assert!(DIRECTION_PI_2.approx_eq(Direction::from(FRAC_PI_4 * 2.0), 0.0001));
+        assert!(!DIRECTION_PI_2.approx_eq(DIRECTION_0, 0.0001));
+    }
+
+    #[test]
+    fn midpoint() {
+        assert_close(f32::from(DIRECTION_0.midpoint(DIRECTION_PI_2)), FRAC_PI_4);
+    }
+
+    #[test]
+    fn midpoint_wrap() {
+        let midpoint = DIRECTION_0.midpoint(Direction::from(2.0 * PI - 0.1));
+        assert!(midpoint.within(DIRECTION_0, 0.1));
+        assert_close(f32::from(midpoint), 2.0 * PI - 0.05);
+    }
+
+    #[test]
+    fn midpoint_wrap_reversed() {
+        let midpoint = Direction::from(2.0 * PI - 0.1).midpoint(Direction::from(0.2));
+        assert!(midpoint.within(DIRECTION_0, 0.1));
+        assert_close(f32::from(midpoint), 0.05);
+    }
+}
+
 #[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Orientation {
     pub position: Vector,
@@ -358,6 +478,26 @@ impl Orientation {
         }
     }
 
+    /// The offset that undoes this one, so that `a.offset(b).offset(b.inverse())` is `a`
+    pub fn inverse(self) -> Orientation {
+        Orientation {
+            position: -1.0 * self.position.rotated(-self.direction),
+            direction: -self.direction,
+        }
+    }
+
+    /// Somewhere between this orientation at `t` = 0.0 and the other at `t` = 1.0. The position
+    /// moves in a straight line and the direction turns the short way around.
+    pub fn lerp(self, other: Orientation, t: f32) -> Orientation {
+        let start = f32::from(self.direction);
+        let end = other.direction.centered_at(self.direction);
+
+        Orientation {
+            position: self.position + t * (other.position - self.position),
+            direction: Direction::from(start + t * (end - start)),
+        }
+    }
+
     pub fn to_maze_orientation(self, maze_config: &MazeConfig) -> MazeOrientation {
         let maze_direction = if self.direction.within(DIRECTION_0, FRAC_PI_4) {
             MazeDirection::East
@@ -370,10 +510,7 @@ impl Orientation {
         };
 
         MazeOrientation {
-            position: MazePosition {
-                x: (self.position.x / maze_config.cell_width) as usize,
-                y: (self.position.y / maze_config.cell_width) as usize,
-            },
+            position: maze_config.cell_index(self.position),
             direction: maze_direction,
         }
     }
@@ -384,7 +521,7 @@ mod orientation_tests {
     #[allow(unused_imports)]
     use crate::test::*;
 
-    use super::{Orientation, Vector, DIRECTION_0, DIRECTION_PI_2};
+    use super::{Direction, Orientation, Vector, DIRECTION_0, DIRECTION_PI_2};
 
     #[test]
     fn offset() {
@@ -436,4 +573,88 @@ mod orientation_tests {
             f32::from(DIRECTION_PI_2),
         )
     }
+
+    /// Check that two orientations are the same, with some room for f32 rounding
+    fn assert_same(a: Orientation, b: Orientation) {
+        assert!(a.position.distance_to(b.position) < 0.001);
+        assert!(
+            (a.direction.centered_at(b.direction) - f32::from(b.direction)).abs() < 0.001
+        );
+    }
+
+    #[test]
+    fn inverse() {
+        let orientation = Orientation {
+            position: Vector { x: 30.0, y: 32.0 },
+            direction: DIRECTION_PI_2,
+        };
+
+        assert_same(
+            orientation.offset(orientation.inverse()),
+            Orientation::default(),
+        );
+        assert_same(
+            orientation.inverse().offset(orientation),
+            Orientation::default(),
+        );
+    }
+
+    #[test]
+    fn offset_then_inverse() {
+        let orientation = Orientation {
+            position: Vector {
+                x: 1260.0,
+                y: 1170.0,
+            },
+            direction: Direction::from(0.7),
+        };
+
+        let offset = Orientation {
+            position: Vector { x: 26.0, y: -32.0 },
+            direction: Direction::from(2.1),
+        };
+
+        assert_same(
+            orientation.offset(offset).offset(offset.inverse()),
+            orientation,
+        );
+    }
+
+    #[test]
+    fn lerp_ends() {
+        let start = Orientation {
+            position: Vector { x: 90.0, y: 90.0 },
+            direction: DIRECTION_0,
+        };
+
+        let end = Orientation {
+            position: Vector { x: 90.0, y: 270.0 },
+            direction: DIRECTION_PI_2,
+        };
+
+        assert_same(start.lerp(end, 0.0), start);
+        assert_same(start.lerp(end, 1.0), end);
+    }
+
+    #[test]
+    fn lerp_across_zero() {
+        let start = Orientation {
+            position: Vector { x: 0.0, y: 0.0 },
+            direction: Direction::from(-0.2),
+        };
+
+        let end = Orientation {
+            position: Vector { x: 10.0, y: 20.0 },
+            direction: Direction::from(0.4),
+        };
+
+        // Goes through 0 instead of all the way around through pi
+        assert_same(
+            start.lerp(end, 0.5),
+            Orientation {
+                position: Vector { x: 5.0, y: 10.0 },
+                direction: Direction::from(0.1),
+            },
+        );
+    }
 }
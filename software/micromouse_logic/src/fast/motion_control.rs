@@ -67,6 +67,7 @@ impl MotionControl {
         right_encoder: i32,
         motion: Option<Motion>,
         orientation: Orientation,
+        battery: Option<f32>,
     ) -> (i32, i32, MotionControlDebug) {
         let handler = self.handler.take();
 
@@ -121,6 +122,7 @@ impl MotionControl {
             right_encoder,
             left_target,
             right_target,
+            battery,
         );
 
         let debug = MotionControlDebug {
@@ -45,17 +45,34 @@ impl MotionQueue {
     }
 
     pub fn clear(&mut self) {
-        self.queue.clear();
+        // Vec::clear in heapless 0.5 indexes one past the end of the slice when dropping the
+        // last element, so replace the whole Vec instead
+        self.queue = Vec::new();
     }
 
+    /// Add motions to the queue. Like the queue, they are in reverse order, so the one to run
+    /// first is last.
+    ///
+    /// Returns how many more motions will fit. If they don't all fit, the ones that would be run
+    /// last are dropped so that the queue still starts from where the mouse is, and `Err` has how
+    /// many were dropped.
     pub fn add_motions(&mut self, motions: &[Motion]) -> Result<usize, usize> {
-        for (i, motion) in motions.iter().enumerate() {
-            if self.queue.push(*motion).is_err() {
-                return Err(i);
-            }
+        let dropped = motions.len().saturating_sub(self.remaining_capacity());
+
+        for motion in &motions[dropped..] {
+            self.queue.push(*motion).ok();
         }
 
-        Ok(MotionQueueSize::to_usize() - self.queue.len())
+        if dropped > 0 {
+            Err(dropped)
+        } else {
+            Ok(self.remaining_capacity())
+        }
+    }
+
+    /// How many more motions can be added before the queue is full
+    pub fn remaining_capacity(&self) -> usize {
+        MotionQueueSize::to_usize() - self.queue.len()
     }
 
     pub fn pop_completed(
@@ -85,9 +102,110 @@ impl MotionQueue {
         self.queue.last().cloned()
     }
 
+    /// All of the motions in the queue, in the order they will be run, starting with
+    /// `next_motion`
+    pub fn peek_all(&self) -> impl Iterator<Item = &Motion> + '_ {
+        self.queue.iter().rev()
+    }
+
     pub fn debug(&self) -> MotionQueueDebug {
         MotionQueueDebug {
             queue: self.queue.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod motion_queue_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{Motion, MotionQueue};
+    use crate::fast::turn::TurnMotion;
+    use crate::fast::{Direction, DIRECTION_0, DIRECTION_PI, DIRECTION_PI_2};
+
+    fn turn(target: Direction) -> Motion {
+        Motion::Turn(TurnMotion::new(DIRECTION_0, target))
+    }
+
+    #[test]
+    fn peek_all() {
+        let mut queue = MotionQueue::new();
+        let motions = [turn(DIRECTION_PI), turn(DIRECTION_PI_2), turn(DIRECTION_0)];
+
+        queue.add_motions(&motions).unwrap();
+
+        // The queue is a stack, so the last one added is run first
+        assert_eq!(queue.peek_all().next().cloned(), queue.next_motion());
+        assert!(queue.peek_all().eq(motions.iter().rev()));
+
+        // Peeking does not take anything out
+        assert_eq!(queue.motions_remaining(), 3);
+    }
+
+    #[test]
+    fn remaining_capacity() {
+        let mut queue = MotionQueue::new();
+        assert_eq!(queue.remaining_capacity(), 4);
+
+        assert_eq!(queue.add_motions(&[turn(DIRECTION_PI)]), Ok(3));
+        assert_eq!(queue.remaining_capacity(), 3);
+    }
+
+    #[test]
+    fn add_too_many() {
+        let mut queue = MotionQueue::new();
+        queue.add_motions(&[turn(DIRECTION_PI)]).unwrap();
+
+        let motions = [
+            turn(Direction::from(0.5)),
+            turn(Direction::from(0.4)),
+            turn(Direction::from(0.3)),
+            turn(Direction::from(0.2)),
+            turn(Direction::from(0.1)),
+        ];
+
+        assert_eq!(queue.add_motions(&motions), Err(2));
+        assert_eq!(queue.remaining_capacity(), 0);
+
+        // The ones that would have run last were dropped, so it still starts with the first
+        assert_eq!(queue.next_motion(), Some(turn(Direction::from(0.1))));
+        assert!(queue.peek_all().eq([
+            motions[4],
+            motions[3],
+            motions[2],
+            turn(DIRECTION_PI)
+        ]
+        .iter()));
+    }
+
+    #[test]
+    fn clear_full() {
+        let mut queue = MotionQueue::new();
+        queue
+            .add_motions(&[
+                turn(DIRECTION_PI),
+                turn(DIRECTION_PI_2),
+                turn(DIRECTION_0),
+                turn(DIRECTION_PI),
+            ])
+            .unwrap();
+
+        queue.clear();
+        assert_eq!(queue.motions_remaining(), 0);
+        assert_eq!(queue.next_motion(), None);
+
+        // It can be filled back up after clearing
+        assert_eq!(queue.add_motions(&[turn(DIRECTION_PI_2)]), Ok(3));
+        assert_eq!(queue.next_motion(), Some(turn(DIRECTION_PI_2)));
+    }
+
+    #[test]
+    fn peek_all_empty() {
+        let queue = MotionQueue::new();
+
+        assert_eq!(queue.peek_all().count(), 0);
+    }
+}
@@ -22,6 +22,86 @@ pub struct MotorControlConfig {
     pub left_reverse: bool,
     pub right_pidf: PidfConfig,
     pub right_reverse: bool,
+
+    /// The max absolute power that is allowed to be sent to either motor
+    pub max_power: i32,
+
+    /// The battery voltage the feedforward gains were tuned at. The feedforward is scaled up as
+    /// the battery drops below this so the mouse does not slow down over a run. 0 turns off the
+    /// scaling.
+    pub nominal_volts: f32,
+
+    /// How fast the target velocity of each wheel can change, in ticks/ms per ms. Steps in the
+    /// commanded velocity are ramped at this rate so the motors do not get a big jump in power.
+    /// 0 turns off the limit.
+    pub max_accel: f32,
+}
+
+impl MotorControlConfig {
+    /// Limit a motor power to be within `max_power` in either direction
+    pub fn clamp_power(&self, power: i32) -> i32 {
+        if power > self.max_power {
+            self.max_power
+        } else if power < -self.max_power {
+            -self.max_power
+        } else {
+            power
+        }
+    }
+
+    /// How much to scale the feedforward by for the battery being at `battery` volts
+    pub fn battery_scale(&self, battery: Option<f32>) -> f32 {
+        match battery {
+            Some(volts) if volts > 0.0 && self.nominal_volts > 0.0 => {
+                self.nominal_volts / volts
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// Move `current` towards `target`, but by no more than `max_accel` allows in `delta_time`
+    pub fn slew_target(&self, current: f64, target: f64, delta_time: u32) -> f64 {
+        if self.max_accel > 0.0 {
+            let max_change = self.max_accel as f64 * delta_time as f64;
+            current + (target - current).max(-max_change).min(max_change)
+        } else {
+            target
+        }
+    }
+}
+
+#[cfg(test)]
+mod clamp_power_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::MotorControlConfig;
+
+    const CONFIG: MotorControlConfig = MotorControlConfig {
+        left_pidf: crate::config::sim::PIDF,
+        left_reverse: false,
+        right_pidf: crate::config::sim::PIDF,
+        right_reverse: false,
+        max_power: 10000,
+        nominal_volts: 0.0,
+        max_accel: 0.0,
+    };
+
+    #[test]
+    fn within_limit() {
+        assert_eq!(CONFIG.clamp_power(5000), 5000);
+        assert_eq!(CONFIG.clamp_power(-5000), -5000);
+    }
+
+    #[test]
+    fn above_limit() {
+        assert_eq!(CONFIG.clamp_power(70000), 10000);
+    }
+
+    #[test]
+    fn below_limit() {
+        assert_eq!(CONFIG.clamp_power(-70000), -10000);
+    }
 }
 
 #[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
@@ -48,6 +128,10 @@ pub struct MotorControl {
     last_time: u32,
     last_left_encoder: i32,
     last_right_encoder: i32,
+
+    /// The targets after being limited by `max_accel`, in ticks/ms
+    left_target: f64,
+    right_target: f64,
 }
 
 // Good food in New Orleans according to my uncle
@@ -84,10 +168,13 @@ impl MotorControl {
             last_time: time,
             last_left_encoder: left_encoder,
             last_right_encoder: right_encoder,
+            left_target: 0.0,
+            right_target: 0.0,
         }
     }
 
-    /// Updates
+    /// Updates the PIDs and finds the power to send to each motor. `battery` is the current
+    /// battery voltage, if it is known, which is used to scale the feedforward.
     pub fn update(
         &mut self,
         config: &MotorControlConfig,
@@ -97,6 +184,7 @@ impl MotorControl {
         right_encoder: i32,
         target_left_velocity: f32,
         target_right_velocity: f32,
+        battery: Option<f32>,
     ) -> (i32, i32, MotorControlDebug) {
         self.left_pid.p_gain = config.left_pidf.p as f64;
         self.left_pid.i_gain = config.left_pidf.i as f64;
@@ -108,8 +196,16 @@ impl MotorControl {
 
         let delta_time = time - self.last_time;
 
-        let target_left_velocity = mech.mm_to_ticks(target_left_velocity) as f64;
-        let target_right_velocity = mech.mm_to_ticks(target_right_velocity) as f64;
+        let target_left_velocity = config.slew_target(
+            self.left_target,
+            mech.mm_to_ticks(target_left_velocity) as f64,
+            delta_time,
+        );
+        let target_right_velocity = config.slew_target(
+            self.right_target,
+            mech.mm_to_ticks(target_right_velocity) as f64,
+            delta_time,
+        );
 
         let delta_left = left_encoder - self.last_left_encoder;
         let delta_right = right_encoder - self.last_right_encoder;
@@ -117,20 +213,23 @@ impl MotorControl {
         let left_velocity = delta_left as f64 / delta_time as f64;
         let right_velocity = delta_right as f64 / delta_time as f64;
 
+        let battery_scale = config.battery_scale(battery) as f64;
+
         let (left_power, right_power) = if delta_time > 0 {
             self.left_pid.set_target(target_left_velocity);
             self.right_pid.set_target(target_right_velocity);
 
-            let mut left_power = (target_left_velocity * config.left_pidf.f as f64)
-                as i32
-                + self.left_pid.update(left_velocity, delta_time as f64) as i32;
+            let mut left_power =
+                (target_left_velocity * config.left_pidf.f as f64 * battery_scale) as i32
+                    + self.left_pid.update(left_velocity, delta_time as f64) as i32;
 
             if config.left_reverse {
                 left_power *= -1;
             }
 
-            let mut right_power = (target_right_velocity * config.right_pidf.f as f64)
-                as i32
+            let mut right_power = (target_right_velocity
+                * config.right_pidf.f as f64
+                * battery_scale) as i32
                 + self.right_pid.update(right_velocity, delta_time as f64) as i32;
 
             if config.right_reverse {
@@ -154,7 +253,94 @@ impl MotorControl {
         self.last_time = time;
         self.last_left_encoder = left_encoder;
         self.last_right_encoder = right_encoder;
+        self.left_target = target_left_velocity;
+        self.right_target = target_right_velocity;
 
         (left_power, right_power, debug)
     }
 }
+
+#[cfg(test)]
+mod motor_control_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::{MotorControl, MotorControlConfig, PidfConfig};
+
+    const MECH: crate::config::MechanicalConfig = crate::config::mouse_2019::MECH;
+
+    // Only feedforward, so the power is easy to predict
+    const PIDF: PidfConfig = PidfConfig {
+        p: 0.0,
+        i: 0.0,
+        d: 0.0,
+        f: 100.0,
+    };
+
+    const CONFIG: MotorControlConfig = MotorControlConfig {
+        left_pidf: PIDF,
+        left_reverse: false,
+        right_pidf: PIDF,
+        right_reverse: false,
+        max_power: 10000,
+        nominal_volts: 8.0,
+        max_accel: 0.0,
+    };
+
+    fn left_power(battery: Option<f32>) -> i32 {
+        let mut motor_control = MotorControl::new(&CONFIG, 0, 0, 0);
+        let (left, _, _) =
+            motor_control.update(&CONFIG, &MECH, 10, 0, 0, 0.5, 0.5, battery);
+        left
+    }
+
+    #[test]
+    fn no_battery() {
+        let target = MECH.mm_to_ticks(0.5) * PIDF.f;
+        assert_eq!(left_power(None), target as i32);
+        assert_eq!(left_power(Some(8.0)), target as i32);
+    }
+
+    #[test]
+    fn sagged_battery() {
+        // At half the voltage, it takes twice the power to go the same speed
+        assert_eq!(left_power(Some(4.0)), 2 * left_power(None));
+
+        let ratio = left_power(Some(7.0)) as f32 / left_power(None) as f32;
+        assert!((ratio - 8.0 / 7.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn scaling_off() {
+        let config = MotorControlConfig {
+            nominal_volts: 0.0,
+            ..CONFIG
+        };
+        assert_close(config.battery_scale(Some(4.0)), 1.0);
+    }
+
+    #[test]
+    fn accel_limited_target() {
+        let config = MotorControlConfig {
+            max_accel: 0.05,
+            ..CONFIG
+        };
+
+        let target = MECH.mm_to_ticks(1.0) as f64;
+        let mut motor_control = MotorControl::new(&config, 0, 0, 0);
+        let mut previous = 0.0;
+
+        for step in 1..=20 {
+            let (_, _, debug) =
+                motor_control.update(&config, &MECH, step * 10, 0, 0, 1.0, 1.0, None);
+
+            let change = debug.target_left_velocity - previous;
+            assert!(change >= 0.0);
+            assert!(change <= config.max_accel as f64 * 10.0 + 1e-6);
+            previous = debug.target_left_velocity;
+        }
+
+        // Gets there eventually, and does not overshoot
+        assert!((previous - target).abs() < 1e-6);
+    }
+}
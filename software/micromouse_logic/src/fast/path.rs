@@ -10,7 +10,9 @@ use serde::{Deserialize, Serialize};
 
 use pid_control::{Controller, PIDController};
 
-use super::{Direction, Orientation, Vector};
+use libm::F32Ext;
+
+use super::{Direction, Orientation, Vector, DIRECTION_PI};
 
 use super::curve::{Bezier5, Curve};
 use crate::config::MechanicalConfig;
@@ -29,6 +31,9 @@ use crate::config::MechanicalConfig;
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct PathMotion {
     bezier: Bezier5,
+
+    /// Drive along the path backwards, with the back of the mouse leading
+    reverse: bool,
 }
 
 impl PathMotion {
@@ -61,6 +66,7 @@ impl PathMotion {
                 ctrl3: center + (radius / 2.0) * end_v,
                 end: center + (radius + offset) * end_v,
             },
+            reverse: false,
         }
     }
 
@@ -76,14 +82,34 @@ impl PathMotion {
                 ctrl3: mid,
                 end,
             },
+            reverse: false,
         }
     }
 
+    /// The same path, but driven backwards. The mouse still goes from the start to the end, but
+    /// faces the other way.
+    pub fn reversed(self) -> PathMotion {
+        PathMotion {
+            reverse: true,
+            ..self
+        }
+    }
+
+    /// Whether the mouse drives this path backwards
+    pub fn is_reverse(&self) -> bool {
+        self.reverse
+    }
+
     /// Find the point on the segment closest to `m`
     pub fn closest_point(&self, m: Vector) -> (f32, Vector) {
         self.bezier.closest_point(m)
     }
 
+    /// Find the point on the segment closest to `m`, starting the search at `t_hint`
+    pub fn closest_point_near(&self, m: Vector, t_hint: f32) -> (f32, Vector) {
+        self.bezier.closest_point_near(m, t_hint)
+    }
+
     /// Derivative at `t`
     pub fn derivative(&self, t: f32) -> Vector {
         self.bezier.derivative().at(t)
@@ -102,6 +128,35 @@ impl PathMotion {
     pub fn end(&self) -> Vector {
         self.bezier.end
     }
+
+    /// The length along the path
+    pub fn arc_length(&self) -> f32 {
+        self.bezier.arc_length(32)
+    }
+}
+
+/// Where the closest point on a segment was last found, so the next search on the same segment can
+/// start there instead of searching the whole thing
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ClosestPointHint {
+    last: Option<(PathMotion, f32)>,
+}
+
+impl ClosestPointHint {
+    /// Find the point on `segment` closest to `m`, starting near the last one if it was on the
+    /// same segment
+    pub fn closest_point(&mut self, segment: &PathMotion, m: Vector) -> (f32, Vector) {
+        let (t, p) = match self.last {
+            Some((last, t_hint)) if last == *segment => {
+                segment.closest_point_near(m, t_hint)
+            }
+            _ => segment.closest_point(m),
+        };
+
+        self.last = Some((*segment, t));
+
+        (t, p)
+    }
 }
 
 // Adjust the curvature for the mouse not being on the path
@@ -205,6 +260,7 @@ pub struct PathHandlerDebug {
     pub adjust_direction: Option<Direction>,
     pub centered_direction: Option<f32>,
     pub adjust_curvature: Option<f32>,
+    pub velocity: Option<f32>,
 }
 
 #[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
@@ -213,13 +269,87 @@ pub struct PathHandlerConfig {
     pub i: f32,
     pub d: f32,
     pub offset_p: f32,
+
+    /// The top speed to drive at
     pub velocity: f32,
+
+    /// How fast the velocity can go up, in mm/ms per ms
+    pub max_accel: f32,
+
+    /// How fast the velocity can go down when slowing for the end of a segment, in mm/ms per ms
+    pub max_decel: f32,
+
+    /// How fast to be going at the end of each segment
+    pub exit_velocity: f32,
+
+    /// The most sideways acceleration allowed in a curve, in mm/ms per ms
+    pub max_lateral_accel: f32,
+
+    /// The tightest curve the mouse will try to follow, as 1 / the radius in mm. Around half the
+    /// wheelbase, the inside wheel would have to stop or go backwards. The path can be much
+    /// tighter than this right at the ends of a corner, which would otherwise slow the mouse down
+    /// to nearly nothing. 0.0 means no limit.
+    pub max_curvature: f32,
+}
+
+/// Keep the curvature of the path within `max_curvature`. A path that doesn't go anywhere at
+/// `t` has no curvature, so it is treated as straight.
+fn limit_curvature(config: &PathHandlerConfig, curvature: f32) -> f32 {
+    if curvature.is_nan() {
+        0.0
+    } else if config.max_curvature > 0.0 {
+        curvature
+            .max(-config.max_curvature)
+            .min(config.max_curvature)
+    } else {
+        curvature
+    }
+}
+
+/// The fastest the mouse can go around a curve without going over `max_lateral_accel`, but no
+/// faster than the cruise velocity
+fn curvature_velocity(config: &PathHandlerConfig, curvature: f32) -> f32 {
+    // Straight enough that it would take a huge speed to hit the limit
+    if curvature.abs() < 1e-6 {
+        config.velocity
+    } else {
+        config
+            .velocity
+            .min(F32Ext::sqrt(config.max_lateral_accel / curvature.abs()))
+    }
+}
+
+/// Find the velocity to drive at, ramping up from `previous` by at most `max_accel` and ramping
+/// down so that `exit_velocity` can be reached in the `remaining` distance with `max_decel`. It
+/// is also kept slow enough to make it around a curve with `curvature`.
+fn limit_velocity(
+    config: &PathHandlerConfig,
+    previous: f32,
+    delta_time: u32,
+    remaining: f32,
+    curvature: f32,
+) -> f32 {
+    let accel_limit = previous + config.max_accel * delta_time as f32;
+
+    let decel_limit = F32Ext::sqrt(
+        config.exit_velocity * config.exit_velocity
+            + 2.0 * config.max_decel * remaining.max(0.0),
+    );
+
+    curvature_velocity(config, curvature)
+        .min(accel_limit)
+        .min(decel_limit)
 }
 
 #[derive(Clone, Debug)]
 pub struct PathHandler {
     pub direction_pid: PIDController,
     pub time: u32,
+
+    /// The last velocity that was commanded
+    pub velocity: f32,
+
+    pub closest_point_hint: ClosestPointHint,
 }
 
 impl PathHandler {
@@ -228,6 +358,8 @@ impl PathHandler {
         PathHandler {
             direction_pid: pid,
             time,
+            velocity: 0.0,
+            closest_point_hint: ClosestPointHint::default(),
         }
     }
 
@@ -248,7 +380,9 @@ impl PathHandler {
         self.direction_pid.d_gain = config.d as f64;
 
         // Gather some info about the segment
-        let (t, p) = segment.closest_point(orientation.position);
+        let (t, p) = self
+            .closest_point_hint
+            .closest_point(&segment, orientation.position);
         debug.closest_point = Some((t, p));
         let v_tangent = segment.derivative(t);
         let v_m = orientation.position - p;
@@ -258,9 +392,14 @@ impl PathHandler {
             -v_m.magnitude()
         };
 
-        let tangent = v_tangent.direction();
+        // When backing up, the mouse points the opposite way that it is going along the path
+        let tangent = if segment.is_reverse() {
+            v_tangent.direction() + DIRECTION_PI
+        } else {
+            v_tangent.direction()
+        };
 
-        let path_curvature = segment.curvature(t);
+        let path_curvature = limit_curvature(config, segment.curvature(t));
 
         // If there was another segment, try to follow it
         // The curvature of the path where the mouse is
@@ -304,10 +443,29 @@ impl PathHandler {
             0.0
         };
 
-        let target_curvature = offset_curvature + adjust_curvature;
+        // The curvature is how much the mouse should turn per distance forward, so it flips when
+        // going backwards
+        let target_curvature = if segment.is_reverse() {
+            -(offset_curvature + adjust_curvature)
+        } else {
+            offset_curvature + adjust_curvature
+        };
+
+        // Close enough for slowing down, even though t is not spread evenly along the path
+        let remaining = (1.0 - t) * segment.arc_length();
+        let velocity =
+            limit_velocity(config, self.velocity, delta_time, remaining, path_curvature);
+        self.velocity = velocity;
+
+        let velocity = if segment.is_reverse() {
+            -velocity
+        } else {
+            velocity
+        };
+        debug.velocity = Some(velocity);
 
         let (target_left_velocity, target_right_velocity) =
-            curvature_to_left_right(mech, config.velocity, target_curvature);
+            curvature_to_left_right(mech, velocity, target_curvature);
 
         debug.distance_from = Some(distance);
         debug.tangent_direction = Some(tangent);
@@ -318,3 +476,223 @@ impl PathHandler {
         (target_left_velocity, target_right_velocity, debug)
     }
 }
+
+#[cfg(test)]
+mod path_handler_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::{
+        curvature_velocity, limit_curvature, limit_velocity, PathHandler,
+        PathHandlerConfig, PathMotion,
+    };
+    use crate::fast::{Direction, DIRECTION_0, DIRECTION_PI, DIRECTION_PI_2};
+    use crate::fast::{Orientation, Vector};
+
+    const MECH: crate::config::MechanicalConfig = crate::config::mouse_2019::MECH;
+
+    const CONFIG: PathHandlerConfig = PathHandlerConfig {
+        p: 0.1,
+        i: 0.0,
+        d: 0.0,
+        offset_p: 0.02,
+        velocity: 0.5,
+        max_accel: 0.01,
+        max_decel: 0.005,
+        exit_velocity: 0.1,
+        max_lateral_accel: 0.005,
+        max_curvature: 1.0 / 37.0,
+    };
+
+    #[test]
+    fn ramps_up() {
+        assert_close(limit_velocity(&CONFIG, 0.0, 10, 1000.0, 0.0), 0.1);
+        assert_close(limit_velocity(&CONFIG, 0.45, 10, 1000.0, 0.0), 0.5);
+    }
+
+    #[test]
+    fn ramps_down() {
+        assert_close(limit_velocity(&CONFIG, 0.5, 10, 0.0, 0.0), 0.1);
+        assert_close(limit_velocity(&CONFIG, 0.5, 10, 8.0, 0.0), 0.3);
+    }
+
+    #[test]
+    fn never_over_accel() {
+        let segment =
+            PathMotion::line(Vector { x: 0.0, y: 0.0 }, Vector { x: 200.0, y: 0.0 });
+
+        let mut handler = PathHandler::new(&CONFIG, 0);
+        let mut position = Vector { x: 0.0, y: 0.0 };
+        let mut previous = 0.0;
+
+        for time in 1..1000 {
+            let (left, right, _) = handler.update(
+                &CONFIG,
+                &MECH,
+                time,
+                Orientation {
+                    position,
+                    direction: DIRECTION_0,
+                },
+                segment,
+            );
+
+            let velocity = (left + right) / 2.0;
+            assert!(velocity <= previous + CONFIG.max_accel + 0.00001);
+
+            if position.x < 200.0 {
+                position.x += velocity;
+            }
+            previous = velocity;
+        }
+
+        // Stopped driving forward at the end of the segment
+        assert_close(previous, CONFIG.exit_velocity);
+    }
+
+    #[test]
+    fn straight_uses_cruise() {
+        let segment =
+            PathMotion::line(Vector { x: 0.0, y: 0.0 }, Vector { x: 200.0, y: 0.0 });
+
+        assert_close(
+            curvature_velocity(&CONFIG, segment.curvature(0.5)),
+            CONFIG.velocity,
+        );
+    }
+
+    #[test]
+    fn corner_uses_curvature() {
+        let segment = PathMotion::corner(
+            Vector { x: 90.0, y: 90.0 },
+            DIRECTION_0,
+            DIRECTION_PI_2,
+            45.0,
+            0.0,
+        );
+
+        let curvature = segment.curvature(0.5);
+        let velocity = curvature_velocity(&CONFIG, curvature);
+
+        assert!(velocity < CONFIG.velocity);
+        assert_close(
+            velocity * velocity * curvature.abs(),
+            CONFIG.max_lateral_accel,
+        );
+    }
+
+    #[test]
+    fn small_corner_curvature_is_limited() {
+        let segment = PathMotion::corner(
+            Vector { x: 90.0, y: 90.0 },
+            DIRECTION_0,
+            DIRECTION_PI_2,
+            2.0,
+            0.0,
+        );
+
+        // A corner this small is far tighter than the mouse can drive
+        assert!(segment.curvature(0.5) > CONFIG.max_curvature);
+
+        for i in 0..=100 {
+            let curvature = limit_curvature(&CONFIG, segment.curvature(i as f32 / 100.0));
+
+            assert!(curvature.is_finite());
+            assert!(curvature.abs() <= CONFIG.max_curvature);
+            assert!(curvature_velocity(&CONFIG, curvature) > 0.0);
+        }
+    }
+
+    #[test]
+    fn normal_corner_is_not_limited() {
+        let segment = PathMotion::corner(
+            Vector { x: 90.0, y: 90.0 },
+            DIRECTION_0,
+            DIRECTION_PI_2,
+            90.0,
+            12.0,
+        );
+
+        for i in 0..=100 {
+            let curvature = segment.curvature(i as f32 / 100.0);
+            assert_close(limit_curvature(&CONFIG, curvature), curvature);
+        }
+    }
+
+    #[test]
+    fn limit_curvature_edge_cases() {
+        assert_close(limit_curvature(&CONFIG, -1.0), -CONFIG.max_curvature);
+        assert_close(
+            limit_curvature(&CONFIG, core::f32::INFINITY),
+            CONFIG.max_curvature,
+        );
+        assert_close(limit_curvature(&CONFIG, core::f32::NAN), 0.0);
+
+        let unlimited = PathHandlerConfig {
+            max_curvature: 0.0,
+            ..CONFIG
+        };
+        assert_close(limit_curvature(&unlimited, 1.0), 1.0);
+    }
+
+    #[test]
+    fn reverse_line_drives_backwards() {
+        let segment =
+            PathMotion::line(Vector { x: 0.0, y: 0.0 }, Vector { x: 200.0, y: 0.0 })
+                .reversed();
+
+        let mut handler = PathHandler::new(&CONFIG, 0);
+        handler.velocity = CONFIG.velocity;
+
+        let (left, right, debug) = handler.update(
+            &CONFIG,
+            &MECH,
+            10,
+            Orientation {
+                position: Vector { x: 50.0, y: 0.0 },
+                direction: DIRECTION_PI,
+            },
+            segment,
+        );
+
+        assert!(left < 0.0);
+        assert!(right < 0.0);
+        assert_close(left, right);
+        assert_close(left, -CONFIG.velocity);
+
+        // The path goes east, but the mouse should be pointed west
+        assert_close(
+            f32::from(debug.tangent_direction.unwrap()),
+            f32::from(DIRECTION_PI),
+        );
+    }
+
+    #[test]
+    fn reverse_line_steers_back_to_path() {
+        let segment =
+            PathMotion::line(Vector { x: 0.0, y: 0.0 }, Vector { x: 1000.0, y: 0.0 })
+                .reversed();
+
+        let mut handler = PathHandler::new(&CONFIG, 0);
+        let mut orientation = Orientation {
+            position: Vector { x: 0.0, y: 10.0 },
+            direction: DIRECTION_PI,
+        };
+
+        for time in 1..500 {
+            let (left, right, _) =
+                handler.update(&CONFIG, &MECH, time, orientation, segment);
+
+            let forward = (left + right) / 2.0;
+            let turn = (right - left) / MECH.wheelbase;
+
+            orientation.direction = orientation.direction + Direction::from(turn);
+            orientation.position =
+                orientation.position + forward * orientation.direction.into_unit_vector();
+        }
+
+        assert!(orientation.position.x > 100.0);
+        assert!(orientation.position.y.abs() < 1.0);
+        assert!(orientation.direction.within(DIRECTION_PI, 0.1));
+    }
+}
@@ -0,0 +1,158 @@
+//! Notices when a wheel is being powered but is not turning, like when it is jammed against a
+//! wall
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StallConfig {
+    /// Motor powers smaller than this might not be enough to turn the wheel at all, so they
+    /// never count towards a stall
+    pub min_power: i32,
+
+    /// A wheel that moved this many ticks or fewer in total while it was powered did not move
+    pub max_ticks: i32,
+
+    /// How long in ms a wheel has to be powered without moving before it is stalled. 0 turns
+    /// off stall detection.
+    pub time: u32,
+
+    /// Turn off the motors while stalled
+    pub stop_motors: bool,
+}
+
+/// How long one wheel has been powered, and how far it went in that time
+#[derive(Debug, Copy, Clone, Default)]
+struct WheelStall {
+    time: u32,
+    ticks: i32,
+}
+
+impl WheelStall {
+    fn update(&mut self, config: &StallConfig, power: i32, delta: i32, delta_time: u32) {
+        if power.abs() < config.min_power {
+            *self = WheelStall::default();
+            return;
+        }
+
+        self.time = self.time.saturating_add(delta_time);
+        self.ticks = self.ticks.saturating_add(delta.abs());
+
+        // Moving far enough starts the wait over
+        if self.ticks > config.max_ticks {
+            *self = WheelStall::default();
+        }
+    }
+
+    fn stalled(&self, config: &StallConfig) -> bool {
+        self.time >= config.time
+    }
+}
+
+/// Keeps track of how long each wheel has been stalled for
+#[derive(Debug, Default)]
+pub struct StallDetector {
+    left: WheelStall,
+    right: WheelStall,
+}
+
+impl StallDetector {
+    pub fn new() -> StallDetector {
+        StallDetector::default()
+    }
+
+    /// Add how far each wheel moved since the last update, with the power that was driving it
+    /// over that time, and get whether either wheel is stalled
+    pub fn update(
+        &mut self,
+        config: &StallConfig,
+        left_power: i32,
+        right_power: i32,
+        delta_left: i32,
+        delta_right: i32,
+        delta_time: u32,
+    ) -> bool {
+        self.left.update(config, left_power, delta_left, delta_time);
+        self.right
+            .update(config, right_power, delta_right, delta_time);
+
+        self.stalled(config)
+    }
+
+    pub fn stalled(&self, config: &StallConfig) -> bool {
+        config.time > 0 && (self.left.stalled(config) || self.right.stalled(config))
+    }
+}
+
+#[cfg(test)]
+mod stall_detector_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::{StallConfig, StallDetector};
+
+    const CONFIG: StallConfig = StallConfig {
+        min_power: 1000,
+        max_ticks: 0,
+        time: 30,
+        stop_motors: true,
+    };
+
+    #[test]
+    fn stalls_after_time() {
+        let mut detector = StallDetector::new();
+
+        assert!(!detector.update(&CONFIG, 2000, 2000, 0, 5, 10));
+        assert!(!detector.update(&CONFIG, 2000, 2000, 0, 5, 10));
+        assert!(detector.update(&CONFIG, 2000, 2000, 0, 5, 10));
+    }
+
+    #[test]
+    fn update_rate_does_not_matter() {
+        let mut detector = StallDetector::new();
+
+        for _ in 0..29 {
+            assert!(!detector.update(&CONFIG, 2000, 2000, 0, 5, 1));
+        }
+        assert!(detector.update(&CONFIG, 2000, 2000, 0, 5, 1));
+    }
+
+    #[test]
+    fn slow_wheel_is_not_stalled() {
+        let mut detector = StallDetector::new();
+
+        // One tick every 20ms is slow, but still moving
+        for i in 0..20 {
+            assert!(!detector.update(&CONFIG, 2000, 2000, i % 2, 5, 10));
+        }
+    }
+
+    #[test]
+    fn moving_resets() {
+        let mut detector = StallDetector::new();
+
+        assert!(!detector.update(&CONFIG, 2000, 2000, 0, 5, 10));
+        assert!(!detector.update(&CONFIG, 2000, 2000, 0, 5, 10));
+        assert!(!detector.update(&CONFIG, 2000, 2000, 1, 5, 10));
+        assert!(!detector.update(&CONFIG, 2000, 2000, 0, 5, 10));
+    }
+
+    #[test]
+    fn unpowered_is_not_stalled() {
+        let mut detector = StallDetector::new();
+
+        for _ in 0..10 {
+            assert!(!detector.update(&CONFIG, 500, -500, 0, 0, 10));
+        }
+    }
+
+    #[test]
+    fn disabled() {
+        let config = StallConfig { time: 0, ..CONFIG };
+
+        let mut detector = StallDetector::new();
+
+        for _ in 0..10 {
+            assert!(!detector.update(&config, 2000, 2000, 0, 0, 10));
+        }
+    }
+}
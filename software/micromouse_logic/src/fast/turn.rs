@@ -4,7 +4,9 @@ use pid_control::{Controller, PIDController};
 
 use super::Direction;
 use crate::config::MechanicalConfig;
-use crate::fast::{Orientation, DIRECTION_PI_2};
+use crate::fast::{Orientation, DIRECTION_PI};
+
+use core::f32::consts::FRAC_PI_4;
 
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TurnDirection {
@@ -12,25 +14,121 @@ pub enum TurnDirection {
     Counterclockwise,
 }
 
+/// How close to a half turn, in radians, a turn has to be for both ways to be the same
+const HALF_TURN_TIE: f32 = 0.001;
+
+/// Which way to pick for a turn
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TurnChoice {
+    /// Whichever way is shortest, going counterclockwise when both ways are the same, like a
+    /// half turn
+    Shortest,
+    /// Always turn left, counterclockwise, even if it is the long way around
+    ForceLeft,
+    /// Always turn right, clockwise, even if it is the long way around
+    ForceRight,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TurnMotion {
+    start: Direction,
     target: Direction,
     direction: TurnDirection,
 }
 
 impl TurnMotion {
+    /// Turn from `current` to `target` whichever way is shortest
     pub fn new(current: Direction, target: Direction) -> TurnMotion {
-        let direction = if target - current < DIRECTION_PI_2 {
-            TurnDirection::Counterclockwise
+        TurnMotion::with_choice(current, target, TurnChoice::Shortest)
+    }
+
+    /// Turn from `current` to `target`, picking the way to go with `choice`
+    pub fn with_choice(
+        current: Direction,
+        target: Direction,
+        choice: TurnChoice,
+    ) -> TurnMotion {
+        let direction = match choice {
+            // The difference is wrapped to 0 - 2pi, so anything under pi is shorter going
+            // counterclockwise. Wrapping a half turn can leave it just past pi, which still
+            // counts as a tie.
+            TurnChoice::Shortest
+                if f32::from(target - current)
+                    < f32::from(DIRECTION_PI) + HALF_TURN_TIE =>
+            {
+                TurnDirection::Counterclockwise
+            }
+            TurnChoice::Shortest => TurnDirection::Clockwise,
+            TurnChoice::ForceLeft => TurnDirection::Counterclockwise,
+            TurnChoice::ForceRight => TurnDirection::Clockwise,
+        };
+
+        TurnMotion::with_direction(current, target, direction)
+    }
+
+    /// Turn from `current` to `target` going the way of `direction`, even if the other way is
+    /// shorter. This lets the mouse turn around away from a wall.
+    pub fn with_direction(
+        current: Direction,
+        target: Direction,
+        direction: TurnDirection,
+    ) -> TurnMotion {
+        TurnMotion {
+            start: current,
+            target,
+            direction,
+        }
+    }
+
+    /// Which way this turn goes
+    pub fn direction(&self) -> TurnDirection {
+        self.direction
+    }
+
+    /// How far this turn goes, in radians, in the direction of the turn
+    pub fn angle(&self) -> f32 {
+        match self.direction {
+            TurnDirection::Counterclockwise => f32::from(self.target - self.start),
+            TurnDirection::Clockwise => f32::from(self.start - self.target),
+        }
+    }
+
+    /// The direction halfway through the turn. Measuring the mouse around this instead of the
+    /// target makes it go the right way for turns of 180° or more.
+    pub fn midpoint(&self) -> Direction {
+        match self.direction {
+            TurnDirection::Counterclockwise => self.start + self.angle() / 2.0,
+            TurnDirection::Clockwise => self.start + -self.angle() / 2.0,
+        }
+    }
+
+    /// The direction at which the turn is considered done. This is short of the real target by
+    /// the lead angle for this kind of turn, so momentum can carry the mouse the rest of the way.
+    pub fn effective_target(&self, config: &TurnHandlerConfig) -> Direction {
+        let lead = if self.angle() > 3.0 * FRAC_PI_4 {
+            config.half_turn_lead
         } else {
-            TurnDirection::Clockwise
+            config.quarter_turn_lead
         };
 
-        TurnMotion { target, direction }
+        match self.direction {
+            TurnDirection::Counterclockwise => self.target + -lead,
+            TurnDirection::Clockwise => self.target + lead,
+        }
     }
 
+    /// Whether the mouse has turned at least as far as the effective target, give or take the
+    /// tolerance. Going past it still counts, since the turn handler keeps going to the real
+    /// target and would never come back to the effective one.
     pub fn done(&self, config: &TurnHandlerConfig, orientation: Orientation) -> bool {
-        orientation.direction.within(self.target, config.tolerance)
+        let midpoint = self.midpoint();
+        let current = orientation.direction.centered_at(midpoint);
+        let target = self.effective_target(config).centered_at(midpoint);
+
+        match self.direction {
+            TurnDirection::Counterclockwise => current > target - config.tolerance,
+            TurnDirection::Clockwise => current < target + config.tolerance,
+        }
     }
 }
 
@@ -41,16 +139,28 @@ pub struct TurnHandlerConfig {
     pub i: f32,
     pub d: f32,
     pub tolerance: f32,
+    /// How far before the target a quarter turn is considered done
+    pub quarter_turn_lead: f32,
+    /// How far before the target a half turn is considered done
+    pub half_turn_lead: f32,
+    /// How much the turn velocity can change each ms, in rad/ms/ms, so the turn eases in and out
+    /// instead of jerking the wheels. 0 turns off the limit.
+    pub max_angular_accel: f32,
 }
 
 #[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct TurnHandlerDebug {
+    /// The turn velocity the pid asked for
+    target_turn_velocity: f32,
+    /// The turn velocity actually used, after limiting the acceleration
     turn_velocity: f32,
+    effective_target: Direction,
 }
 
 pub struct TurnHandler {
     pid: PIDController,
     time: u32,
+    turn_velocity: f32,
 }
 
 impl TurnHandler {
@@ -58,7 +168,11 @@ impl TurnHandler {
         let mut pid =
             PIDController::new(config.p as f64, config.i as f64, config.d as f64);
         pid.set_limits(-config.rad_per_sec as f64, config.rad_per_sec as f64);
-        TurnHandler { pid, time }
+        TurnHandler {
+            pid,
+            time,
+            turn_velocity: 0.0,
+        }
     }
 
     pub fn update(
@@ -76,15 +190,29 @@ impl TurnHandler {
         self.pid.d_gain = config.d as f64;
         self.pid.set_limits(-0.005 as f64, 0.005 as f64);
 
-        let centered_direction = orientation.direction.centered_at(motion.target);
+        let midpoint = motion.midpoint();
+        let centered_direction = orientation.direction.centered_at(midpoint);
 
-        self.pid.set_target(f32::from(motion.target) as f64);
+        self.pid
+            .set_target(motion.target.centered_at(midpoint) as f64);
 
-        let turn_velocity = self
+        let target_turn_velocity = self
             .pid
             .update(f32::from(centered_direction) as f64, delta_time as f64)
             as f32;
 
+        let turn_velocity = if config.max_angular_accel > 0.0 {
+            let max_change = config.max_angular_accel * delta_time as f32;
+            let change = (target_turn_velocity - self.turn_velocity)
+                .max(-max_change)
+                .min(max_change);
+            self.turn_velocity + change
+        } else {
+            target_turn_velocity
+        };
+
+        self.turn_velocity = turn_velocity;
+
         let left_target = -mech.rads_to_mm(turn_velocity);
         let right_target = mech.rads_to_mm(turn_velocity);
 
@@ -93,7 +221,331 @@ impl TurnHandler {
         (
             left_target,
             right_target,
-            TurnHandlerDebug { turn_velocity },
+            TurnHandlerDebug {
+                target_turn_velocity,
+                turn_velocity,
+                effective_target: motion.effective_target(config),
+            },
         )
     }
 }
+
+#[cfg(test)]
+mod turn_motion_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use core::f32::consts::FRAC_PI_4;
+
+    use super::{TurnChoice, TurnDirection, TurnHandlerConfig, TurnMotion};
+    use crate::fast::{
+        Direction, Orientation, Vector, DIRECTION_0, DIRECTION_3_PI_2, DIRECTION_PI,
+        DIRECTION_PI_2,
+    };
+
+    const CONFIG: TurnHandlerConfig = TurnHandlerConfig {
+        rad_per_sec: 0.1,
+        p: 1.0,
+        i: 0.0,
+        d: 0.0,
+        tolerance: 0.02,
+        quarter_turn_lead: 0.1,
+        half_turn_lead: 0.2,
+        max_angular_accel: 0.0,
+    };
+
+    #[test]
+    fn quarter_turn_counterclockwise() {
+        let motion = TurnMotion::new(DIRECTION_0, DIRECTION_PI_2);
+        assert_close(
+            f32::from(motion.effective_target(&CONFIG)),
+            f32::from(DIRECTION_PI_2) - 0.1,
+        );
+    }
+
+    #[test]
+    fn quarter_turn_clockwise() {
+        let motion = TurnMotion::new(DIRECTION_PI_2, DIRECTION_0);
+        assert_close(f32::from(motion.effective_target(&CONFIG)), 0.1);
+    }
+
+    #[test]
+    fn half_turn() {
+        let motion = TurnMotion::new(Direction::from(0.1), DIRECTION_PI);
+        assert_close(
+            f32::from(motion.effective_target(&CONFIG)),
+            f32::from(DIRECTION_PI) - 0.2,
+        );
+    }
+
+    fn facing(direction: Direction) -> Orientation {
+        Orientation {
+            position: Vector { x: 0.0, y: 0.0 },
+            direction,
+        }
+    }
+
+    #[test]
+    fn done_at_effective_target() {
+        let motion = TurnMotion::new(DIRECTION_0, DIRECTION_PI_2);
+        let effective = f32::from(DIRECTION_PI_2) - 0.1;
+
+        assert!(!motion.done(&CONFIG, facing(DIRECTION_0)));
+        assert!(!motion.done(&CONFIG, facing(Direction::from(effective - 0.05))));
+        assert!(motion.done(&CONFIG, facing(Direction::from(effective - 0.01))));
+    }
+
+    #[test]
+    fn done_past_effective_target() {
+        // Going past the window still finishes the turn, either way around
+        let left = TurnMotion::new(DIRECTION_0, DIRECTION_PI_2);
+        assert!(left.done(&CONFIG, facing(DIRECTION_PI_2)));
+        assert!(left.done(&CONFIG, facing(Direction::from(2.0))));
+
+        let right = TurnMotion::new(DIRECTION_PI_2, DIRECTION_0);
+        assert!(!right.done(&CONFIG, facing(DIRECTION_PI_2)));
+        assert!(right.done(&CONFIG, facing(DIRECTION_0)));
+        assert!(right.done(&CONFIG, facing(Direction::from(-0.3))));
+    }
+
+    #[test]
+    fn forced_half_turn() {
+        // Both ways are as short for a half turn, so it can be made to go either way
+        let motion = TurnMotion::with_direction(
+            DIRECTION_0,
+            DIRECTION_PI,
+            TurnDirection::Counterclockwise,
+        );
+        assert_close(motion.angle(), f32::from(DIRECTION_PI));
+        assert_close_direction(motion.midpoint(), DIRECTION_PI_2);
+        assert_close(
+            f32::from(motion.effective_target(&CONFIG)),
+            f32::from(DIRECTION_PI) - 0.2,
+        );
+    }
+
+    #[test]
+    fn new_goes_the_short_way() {
+        // The turn direction decides when the turn is done, so it has to be the short way for
+        // every turn the mouse makes, not just quarter turns
+        for i in 0..8 {
+            let start = Direction::from(i as f32 * FRAC_PI_4);
+
+            for &angle in &[FRAC_PI_4, 2.0 * FRAC_PI_4, 3.0 * FRAC_PI_4] {
+                let left = TurnMotion::new(start, start + angle);
+                assert_eq!(left.direction(), TurnDirection::Counterclockwise);
+                assert_close(left.angle(), angle);
+
+                let right = TurnMotion::new(start, start + -angle);
+                assert_eq!(right.direction(), TurnDirection::Clockwise);
+                assert_close(right.angle(), angle);
+            }
+        }
+    }
+
+    #[test]
+    fn choices() {
+        let shortest = |current, target| {
+            TurnMotion::with_choice(current, target, TurnChoice::Shortest).direction()
+        };
+        assert_eq!(
+            shortest(DIRECTION_0, DIRECTION_PI_2),
+            TurnDirection::Counterclockwise
+        );
+        assert_eq!(
+            shortest(DIRECTION_PI_2, DIRECTION_0),
+            TurnDirection::Clockwise
+        );
+        // Both ways are the same for a half turn, no matter how it rounds
+        assert_eq!(
+            shortest(DIRECTION_0, DIRECTION_PI),
+            TurnDirection::Counterclockwise
+        );
+        assert_eq!(
+            shortest(DIRECTION_PI, DIRECTION_0),
+            TurnDirection::Counterclockwise
+        );
+        assert_eq!(
+            shortest(DIRECTION_PI_2, DIRECTION_3_PI_2),
+            TurnDirection::Counterclockwise
+        );
+
+        // Forcing goes the long way around if it has to
+        let left =
+            TurnMotion::with_choice(DIRECTION_PI_2, DIRECTION_0, TurnChoice::ForceLeft);
+        assert_eq!(left.direction(), TurnDirection::Counterclockwise);
+        assert_close(left.angle(), 3.0 * f32::from(DIRECTION_PI_2));
+
+        let right =
+            TurnMotion::with_choice(DIRECTION_0, DIRECTION_PI_2, TurnChoice::ForceRight);
+        assert_eq!(right.direction(), TurnDirection::Clockwise);
+        assert_close(right.angle(), 3.0 * f32::from(DIRECTION_PI_2));
+    }
+}
+
+#[cfg(test)]
+mod turn_handler_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::{TurnDirection, TurnHandler, TurnHandlerConfig, TurnMotion};
+    use crate::fast::{
+        Direction, Orientation, Vector, DIRECTION_0, DIRECTION_PI, DIRECTION_PI_2,
+    };
+
+    const MECH: crate::config::MechanicalConfig = crate::config::mouse_2019::MECH;
+
+    const CONFIG: TurnHandlerConfig = TurnHandlerConfig {
+        rad_per_sec: 0.1,
+        p: 1.0,
+        i: 0.0,
+        d: 0.0,
+        tolerance: 0.02,
+        quarter_turn_lead: 0.1,
+        half_turn_lead: 0.2,
+        max_angular_accel: 0.0,
+    };
+
+    /// The turn velocity on the first update, positive being counterclockwise
+    fn first_turn_velocity(motion: TurnMotion) -> f32 {
+        let mut handler = TurnHandler::new(&CONFIG, 0);
+        let (left, right, _) = handler.update(
+            &CONFIG,
+            &MECH,
+            10,
+            Orientation {
+                position: Vector { x: 0.0, y: 0.0 },
+                direction: DIRECTION_0,
+            },
+            motion,
+        );
+
+        assert_close(left, -right);
+        right
+    }
+
+    #[test]
+    fn forced_left() {
+        let motion = TurnMotion::with_direction(
+            DIRECTION_0,
+            DIRECTION_PI,
+            TurnDirection::Counterclockwise,
+        );
+        assert!(first_turn_velocity(motion) > 0.0);
+    }
+
+    #[test]
+    fn forced_right() {
+        let motion = TurnMotion::with_direction(
+            DIRECTION_0,
+            DIRECTION_PI,
+            TurnDirection::Clockwise,
+        );
+        assert!(first_turn_velocity(motion) < 0.0);
+    }
+
+    #[test]
+    fn limited_angular_accel() {
+        let config = TurnHandlerConfig {
+            max_angular_accel: 0.0001,
+            ..CONFIG
+        };
+
+        let motion = TurnMotion::new(DIRECTION_0, DIRECTION_PI_2);
+        let mut handler = TurnHandler::new(&config, 0);
+        let mut orientation = Orientation {
+            position: Vector { x: 0.0, y: 0.0 },
+            direction: DIRECTION_0,
+        };
+
+        let mut last_velocity = 0.0;
+        let mut limited = false;
+
+        for step in 1..=100 {
+            let (_, _, debug) =
+                handler.update(&config, &MECH, step * 10, orientation, motion);
+
+            let change = debug.turn_velocity - last_velocity;
+            assert!(change.abs() <= config.max_angular_accel * 10.0 + 1e-7);
+
+            if debug.turn_velocity != debug.target_turn_velocity {
+                limited = true;
+            }
+
+            orientation.direction += Direction::from(debug.turn_velocity * 10.0);
+            last_velocity = debug.turn_velocity;
+        }
+
+        // Make sure the limit actually did something
+        assert!(limited);
+    }
+
+    /// Run the turn handler on a mouse that turns exactly as fast as it is told to, until the
+    /// turn is done. Returns the heading it ended up at.
+    fn turn_until_done(config: &TurnHandlerConfig, motion: TurnMotion) -> Direction {
+        let mut handler = TurnHandler::new(config, 0);
+        let mut orientation = Orientation {
+            position: Vector { x: 0.0, y: 0.0 },
+            direction: motion.start,
+        };
+
+        for step in 1..=1000 {
+            if motion.done(config, orientation) {
+                return orientation.direction;
+            }
+
+            let (_, _, debug) =
+                handler.update(config, &MECH, step * 10, orientation, motion);
+            orientation.direction += Direction::from(debug.turn_velocity * 10.0);
+        }
+
+        panic!("Turn never finished, at {:?}", orientation.direction);
+    }
+
+    #[test]
+    fn finishes_with_lead() {
+        // Fast enough that each update turns further than the tolerance window is wide
+        let config = TurnHandlerConfig { p: 0.5, ..CONFIG };
+
+        for &motion in [
+            TurnMotion::new(DIRECTION_0, DIRECTION_PI_2),
+            TurnMotion::new(DIRECTION_PI_2, DIRECTION_0),
+            TurnMotion::with_direction(
+                DIRECTION_0,
+                DIRECTION_PI,
+                TurnDirection::Counterclockwise,
+            ),
+        ]
+        .iter()
+        {
+            let end = turn_until_done(&config, motion);
+
+            // It stops around the effective target, not the real one
+            let effective = motion.effective_target(&config);
+            assert!(end.within(effective, 0.1), "{:?} {:?}", end, effective);
+        }
+    }
+
+    #[test]
+    fn finishes_with_sim_config() {
+        // Each update turns about 0.05 rad, more than the whole tolerance window. This used to
+        // jump back and forth over the window without ever landing in it.
+        let config = crate::config::sim::MOUSE_2019.motion_control.turn;
+
+        for &motion in [
+            TurnMotion::new(DIRECTION_0, DIRECTION_PI_2),
+            TurnMotion::new(DIRECTION_PI_2, DIRECTION_0),
+            TurnMotion::new(Direction::from(0.01), DIRECTION_PI),
+        ]
+        .iter()
+        {
+            let end = turn_until_done(&config, motion);
+            assert!(
+                end.within(motion.target, 0.1),
+                "{:?} {:?}",
+                end,
+                motion.target
+            );
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Turning battery ADC readings into volts, and deciding when the pack is too low to run
+
+/// How to turn ADC readings into the actual voltage of the battery pack
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BatteryConfig {
+    /// The voltage that a full scale ADC reading is
+    pub adc_reference: f32,
+
+    /// The ADC reading at `adc_reference`
+    pub adc_max: u16,
+
+    /// How much the resistor divider between the pack and the ADC pin divides the voltage by,
+    /// (R1 + R2) / R2
+    pub divider_ratio: f32,
+
+    /// The pack is dead after it has been below this many volts for a while
+    pub dead_volts: f32,
+}
+
+/// The 12 bit ADC on the 3.3V rail with a 4:1 divider. The dead voltage is the same as the old
+/// raw threshold of 2100.
+pub const BATTERY: BatteryConfig = BatteryConfig {
+    adc_reference: 3.3,
+    adc_max: 4095,
+    divider_ratio: 4.0,
+    dead_volts: 6.77,
+};
+
+/// Below this many volts, the pack is about to brown out the MCU, so the motors get cut off for
+/// good. This is 3.2V per cell on the 2S pack, well under `dead_volts` so the LED warns first.
+///
+/// This can only really be checked on the mouse. With a bench supply in place of the pack and
+/// the wheels off the ground, start a run, then turn the supply down past this voltage. The
+/// motors should stop within `CUTOFF_TIME`, the red LED should stay on, and the run should not
+/// start again from either button or the remote until the mouse is reset, even after turning
+/// the supply back up.
+pub const CUTOFF_VOLTS: f32 = 6.4;
+
+/// How long the pack has to stay under `CUTOFF_VOLTS` before cutting off, in ms, so that the
+/// sag from the motors speeding up does not trip it
+pub const CUTOFF_TIME: u32 = 100;
+
+/// Latches once the pack voltage has been under `CUTOFF_VOLTS` for `CUTOFF_TIME`
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Cutoff {
+    below_since: Option<u32>,
+    tripped: bool,
+}
+
+impl Cutoff {
+    pub fn new() -> Cutoff {
+        Cutoff::default()
+    }
+
+    /// Add a voltage reading and get whether the motors are cut off
+    pub fn update(&mut self, volts: f32, now: u32) -> bool {
+        if volts < CUTOFF_VOLTS {
+            let below_since = *self.below_since.get_or_insert(now);
+            if now - below_since >= CUTOFF_TIME {
+                self.tripped = true;
+            }
+        } else {
+            self.below_since = None;
+        }
+
+        self.tripped
+    }
+
+    /// Whether the motors are cut off. Nothing but a reset clears this.
+    pub fn is_tripped(&self) -> bool {
+        self.tripped
+    }
+}
+
+/// Convert a raw ADC reading into the pack voltage
+pub fn raw_to_volts(config: &BatteryConfig, raw: u16) -> f32 {
+    raw as f32 / config.adc_max as f32 * config.adc_reference * config.divider_ratio
+}
+
+#[cfg(test)]
+mod battery_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::{
+        raw_to_volts, BatteryConfig, Cutoff, BATTERY, CUTOFF_TIME, CUTOFF_VOLTS,
+    };
+
+    #[test]
+    fn conversion() {
+        let config = BatteryConfig {
+            adc_reference: 3.0,
+            adc_max: 3000,
+            divider_ratio: 3.0,
+            dead_volts: 6.0,
+        };
+
+        assert_eq!(raw_to_volts(&config, 0), 0.0);
+        assert_close(raw_to_volts(&config, 1000), 3.0);
+        assert_close(raw_to_volts(&config, 3000), 9.0);
+    }
+
+    #[test]
+    fn old_dead_threshold() {
+        assert!((raw_to_volts(&BATTERY, 2100) - BATTERY.dead_volts).abs() < 0.01);
+    }
+
+    #[test]
+    fn cutoff_waits_for_sag() {
+        let mut cutoff = Cutoff::new();
+
+        assert!(!cutoff.update(CUTOFF_VOLTS - 0.5, 0));
+        assert!(!cutoff.update(CUTOFF_VOLTS - 0.5, CUTOFF_TIME - 1));
+
+        // Coming back up starts the wait over
+        assert!(!cutoff.update(CUTOFF_VOLTS + 0.5, CUTOFF_TIME));
+        assert!(!cutoff.update(CUTOFF_VOLTS - 0.5, CUTOFF_TIME + 1));
+        assert!(!cutoff.update(CUTOFF_VOLTS - 0.5, 2 * CUTOFF_TIME));
+    }
+
+    #[test]
+    fn cutoff_latches() {
+        let mut cutoff = Cutoff::new();
+
+        assert!(!cutoff.update(CUTOFF_VOLTS - 0.5, 0));
+        assert!(cutoff.update(CUTOFF_VOLTS - 0.5, CUTOFF_TIME));
+        assert!(cutoff.update(8.4, CUTOFF_TIME + 1000));
+        assert!(cutoff.is_tripped());
+    }
+
+    #[test]
+    fn cutoff_below_dead() {
+        assert!(CUTOFF_VOLTS < BATTERY.dead_volts);
+    }
+}
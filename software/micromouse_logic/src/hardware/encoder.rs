@@ -0,0 +1,57 @@
+//! Finding wheel speeds from encoder counts
+
+/// Keeps track of the last count and time of an encoder to find its velocity
+#[derive(Debug, Copy, Clone, Default)]
+pub struct EncoderVelocity {
+    last: Option<(i32, u32)>,
+}
+
+impl EncoderVelocity {
+    pub fn new() -> EncoderVelocity {
+        EncoderVelocity { last: None }
+    }
+
+    /// Ticks per ms since the last update
+    pub fn update(&mut self, count: i32, now: u32) -> f32 {
+        let velocity = match self.last {
+            Some((last_count, last_time)) if now > last_time => {
+                (count - last_count) as f32 / (now - last_time) as f32
+            }
+            _ => 0.0,
+        };
+
+        self.last = Some((count, now));
+
+        velocity
+    }
+
+    /// Forget the last count, like after the encoder is reset
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod encoder_velocity_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::EncoderVelocity;
+
+    #[test]
+    fn velocity() {
+        let mut velocity = EncoderVelocity::new();
+
+        assert_eq!(velocity.update(100, 1000), 0.0);
+        assert_eq!(velocity.update(150, 1010), 5.0);
+        assert_eq!(velocity.update(130, 1014), -5.0);
+
+        // No time has passed
+        assert_eq!(velocity.update(130, 1014), 0.0);
+
+        // Resetting the count does not look like a jump backwards
+        velocity.reset();
+        assert_eq!(velocity.update(0, 1020), 0.0);
+        assert_eq!(velocity.update(10, 1030), 1.0);
+    }
+}
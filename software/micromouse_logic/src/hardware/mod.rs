@@ -0,0 +1,11 @@
+//! The parts of the firmware drivers that do not touch the hardware
+//!
+//! The firmware only builds for the mouse, so anything in it that can be checked on a computer
+//! lives here instead
+
+pub mod battery;
+pub mod encoder;
+pub mod system_test;
+pub mod tone;
+pub mod uart;
+pub mod vl6180x;
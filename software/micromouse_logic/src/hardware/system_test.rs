@@ -0,0 +1,316 @@
+//! Parsing for the system test commands sent over UART
+
+use core::f32::consts::PI;
+use core::fmt::Write;
+
+use crate::config::MechanicalConfig;
+use crate::fast::motor_control::MotorControlConfig;
+
+/// The first word of a command, which decides what the rest of it is for
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CommandWord {
+    Time,
+    Motor,
+    Distance,
+    Config,
+    Calibrate,
+}
+
+impl CommandWord {
+    pub fn parse(word: &str) -> Option<CommandWord> {
+        match word {
+            "time" => Some(CommandWord::Time),
+            "motor" => Some(CommandWord::Motor),
+            "distance" => Some(CommandWord::Distance),
+            "config" => Some(CommandWord::Config),
+            "calibrate" => Some(CommandWord::Calibrate),
+            _ => None,
+        }
+    }
+}
+
+/// Write out each field of the mechanical config on its own line, to check that the firmware
+/// has the calibration that it is supposed to
+pub fn write_mechanical_config<W: Write>(
+    out: &mut W,
+    mech: &MechanicalConfig,
+) -> core::fmt::Result {
+    writeln!(out, "wheel_diameter: {}", mech.wheel_diameter)?;
+    writeln!(out, "gearbox_ratio: {}", mech.gearbox_ratio)?;
+    writeln!(out, "ticks_per_rev: {}", mech.ticks_per_rev)?;
+    writeln!(out, "wheelbase: {}", mech.wheelbase)?;
+    writeln!(out, "width: {}", mech.width)?;
+    writeln!(out, "length: {}", mech.length)?;
+    writeln!(out, "front_offset: {}", mech.front_offset)?;
+    writeln!(out, "front_sensor_offset_x: {}", mech.front_sensor_offset_x)?;
+    writeln!(out, "left_sensor_offset_x: {}", mech.left_sensor_offset_x)?;
+    writeln!(out, "left_sensor_offset_y: {}", mech.left_sensor_offset_y)?;
+    writeln!(out, "right_sensor_offset_x: {}", mech.right_sensor_offset_x)?;
+    writeln!(out, "right_sensor_offset_y: {}", mech.right_sensor_offset_y)?;
+    writeln!(out, "front_sensor_limit: {}", mech.front_sensor_limit)?;
+    writeln!(out, "left_sensor_limit: {}", mech.left_sensor_limit)?;
+    writeln!(out, "right_sensor_limit: {}", mech.right_sensor_limit)?;
+    Ok(())
+}
+
+/*
+ * Calibration
+ *
+ * These drive the mouse open loop and report how far the encoders went, so that the mechanical
+ * config can be worked out from a measurement instead of guessed. Pressing either button stops
+ * the motors right away.
+ *
+ * To find `ticks_per_rev` (through ticks per mm):
+ *  1. Set the mouse on a straight run with its back against a wall
+ *  2. Send `calibrate straight 900`
+ *  3. When it stops, measure how far it actually went in mm
+ *  4. ticks per mm is the average of the left and right counts / the measured distance, and
+ *     `ticks_per_rev` is that * pi * `wheel_diameter` / `gearbox_ratio`
+ *
+ * To find `wheelbase`, after `ticks_per_rev` is right:
+ *  1. Line the mouse up with a wall or a line on the floor
+ *  2. Send `calibrate spin 5`
+ *  3. When it stops, count how many turns it actually made, including the fraction
+ *  4. `wheelbase` is the average of the left and right counts / ticks per mm /
+ *     (pi * the measured turns)
+ *
+ * The mouse will not go exactly straight or spin exactly in place open loop, so use a few
+ * turns and a long distance so the error at the ends does not matter as much.
+ */
+
+/// Open loop power to calibrate with, slow enough that the wheels don't slip
+pub const CALIBRATE_POWER: i32 = 2000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CalibrateKind {
+    /// Both wheels forward
+    Straight,
+
+    /// Left wheel backward and right wheel forward, turning counterclockwise
+    Spin,
+}
+
+impl CalibrateKind {
+    /// Parse the rest of a `calibrate` command, `straight <mm>` or `spin <turns>`
+    pub fn parse<'a, I: Iterator<Item = &'a str>>(
+        mut words: I,
+    ) -> Result<(CalibrateKind, f32), &'static str> {
+        let kind = match words.next() {
+            Some("straight") => CalibrateKind::Straight,
+            Some("spin") => CalibrateKind::Spin,
+            _ => return Err("Expected straight or spin"),
+        };
+
+        match words.next().and_then(|word| word.parse::<f32>().ok()) {
+            Some(amount) if amount > 0.0 => Ok((kind, amount)),
+            _ => Err("Expected a positive number"),
+        }
+    }
+}
+
+/// A calibration run in progress
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Calibration {
+    kind: CalibrateKind,
+
+    /// How many ticks each wheel should go, by the current config
+    target_ticks: i32,
+
+    start_left: i32,
+    start_right: i32,
+
+    /// Whether each motor is wired backwards, the same as motor control uses
+    left_reverse: bool,
+    right_reverse: bool,
+}
+
+impl Calibration {
+    /// Start a calibration for `amount` mm straight or `amount` turns of spin from the current
+    /// encoder counts
+    pub fn new(
+        mech: &MechanicalConfig,
+        motors: &MotorControlConfig,
+        kind: CalibrateKind,
+        amount: f32,
+        left: i32,
+        right: i32,
+    ) -> Calibration {
+        let mm = match kind {
+            CalibrateKind::Straight => amount,
+            CalibrateKind::Spin => amount * PI * mech.wheelbase,
+        };
+
+        Calibration {
+            kind,
+            target_ticks: mech.mm_to_ticks(mm) as i32,
+            start_left: left,
+            start_right: right,
+            left_reverse: motors.left_reverse,
+            right_reverse: motors.right_reverse,
+        }
+    }
+
+    /// How far each wheel has gone since the start
+    pub fn counts(&self, left: i32, right: i32) -> (i32, i32) {
+        (left - self.start_left, right - self.start_right)
+    }
+
+    /// The power for each motor. Each one stops on its own once it has gone far enough.
+    pub fn powers(&self, left: i32, right: i32) -> (i32, i32) {
+        let (left_count, right_count) = self.counts(left, right);
+
+        let mut left_power = if left_count.abs() >= self.target_ticks {
+            0
+        } else if self.kind == CalibrateKind::Spin {
+            -CALIBRATE_POWER
+        } else {
+            CALIBRATE_POWER
+        };
+
+        let mut right_power = if right_count.abs() >= self.target_ticks {
+            0
+        } else {
+            CALIBRATE_POWER
+        };
+
+        if self.left_reverse {
+            left_power *= -1;
+        }
+
+        if self.right_reverse {
+            right_power *= -1;
+        }
+
+        (left_power, right_power)
+    }
+
+    pub fn done(&self, left: i32, right: i32) -> bool {
+        self.powers(left, right) == (0, 0)
+    }
+
+    pub fn report<W: Write>(
+        &self,
+        out: &mut W,
+        left: i32,
+        right: i32,
+    ) -> core::fmt::Result {
+        let (left_count, right_count) = self.counts(left, right);
+        writeln!(
+            out,
+            "calibrate {:?}: left {} right {} expected {}",
+            self.kind, left_count, right_count, self.target_ticks
+        )
+    }
+}
+
+#[cfg(test)]
+mod system_test_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use heapless::consts::U1024;
+    use heapless::String;
+
+    use crate::config::mouse_2020;
+    use crate::fast::motor_control::MotorControlConfig;
+
+    use super::{
+        write_mechanical_config, CalibrateKind, Calibration, CommandWord, CALIBRATE_POWER,
+    };
+
+    #[test]
+    fn parse_command_words() {
+        assert_eq!(CommandWord::parse("time"), Some(CommandWord::Time));
+        assert_eq!(CommandWord::parse("motor"), Some(CommandWord::Motor));
+        assert_eq!(CommandWord::parse("distance"), Some(CommandWord::Distance));
+        assert_eq!(CommandWord::parse("config"), Some(CommandWord::Config));
+        assert_eq!(
+            CommandWord::parse("calibrate"),
+            Some(CommandWord::Calibrate)
+        );
+        assert_eq!(CommandWord::parse("configure"), None);
+        assert_eq!(CommandWord::parse(""), None);
+    }
+
+    #[test]
+    fn config_lines() {
+        let mech = mouse_2020::MOUSE.mechanical;
+        let mut out: String<U1024> = String::new();
+
+        write_mechanical_config(&mut out, &mech).unwrap();
+
+        let mut lines = out.lines();
+        assert_eq!(lines.next(), Some("wheel_diameter: 32"));
+        assert!(out.contains("wheelbase: 78\n"));
+        assert!(out.contains("right_sensor_limit: "));
+        assert_eq!(out.lines().count(), 15);
+    }
+
+    #[test]
+    fn parse_calibrate() {
+        assert_eq!(
+            CalibrateKind::parse("straight 900".split_whitespace()),
+            Ok((CalibrateKind::Straight, 900.0))
+        );
+        assert_eq!(
+            CalibrateKind::parse("spin 2.5".split_whitespace()),
+            Ok((CalibrateKind::Spin, 2.5))
+        );
+        assert!(CalibrateKind::parse("spin".split_whitespace()).is_err());
+        assert!(CalibrateKind::parse("spin -1".split_whitespace()).is_err());
+        assert!(CalibrateKind::parse("sideways 10".split_whitespace()).is_err());
+    }
+
+    /// Motors that are both wired the normal way around
+    const FORWARD: MotorControlConfig = MotorControlConfig {
+        left_reverse: false,
+        right_reverse: false,
+        ..mouse_2020::MOUSE.motion_control.motor_control
+    };
+
+    #[test]
+    fn straight_stops_each_wheel() {
+        let mech = mouse_2020::MOUSE.mechanical;
+        let c =
+            Calibration::new(&mech, &FORWARD, CalibrateKind::Straight, 180.0, 100, -50);
+
+        assert_eq!(c.target_ticks, mech.mm_to_ticks(180.0) as i32);
+        assert_eq!(c.powers(100, -50), (CALIBRATE_POWER, CALIBRATE_POWER));
+        assert_eq!(c.powers(100 + c.target_ticks, -50), (0, CALIBRATE_POWER));
+        assert!(c.done(100 + c.target_ticks, -50 + c.target_ticks));
+    }
+
+    #[test]
+    fn spin_turns_in_place() {
+        let mech = mouse_2020::MOUSE.mechanical;
+        let c = Calibration::new(&mech, &FORWARD, CalibrateKind::Spin, 1.0, 0, 0);
+
+        assert_eq!(
+            c.target_ticks,
+            mech.mm_to_ticks(core::f32::consts::PI * mech.wheelbase) as i32
+        );
+        assert_eq!(c.powers(0, 0), (-CALIBRATE_POWER, CALIBRATE_POWER));
+        assert!(c.done(-c.target_ticks, c.target_ticks));
+    }
+
+    #[test]
+    fn reversed_motor() {
+        let mech = mouse_2020::MOUSE.mechanical;
+        let motors = mouse_2020::MOUSE.motion_control.motor_control;
+        assert!(motors.left_reverse);
+        assert!(!motors.right_reverse);
+
+        let straight =
+            Calibration::new(&mech, &motors, CalibrateKind::Straight, 180.0, 0, 0);
+        assert_eq!(straight.powers(0, 0), (-CALIBRATE_POWER, CALIBRATE_POWER));
+
+        let spin = Calibration::new(&mech, &motors, CalibrateKind::Spin, 1.0, 0, 0);
+        assert_eq!(spin.powers(0, 0), (CALIBRATE_POWER, CALIBRATE_POWER));
+
+        // A stopped wheel stays stopped either way around
+        assert_eq!(
+            straight.powers(straight.target_ticks, 0),
+            (0, CALIBRATE_POWER)
+        );
+    }
+}
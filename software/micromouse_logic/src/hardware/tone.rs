@@ -0,0 +1,85 @@
+//! Playing tones one after another, without touching the buzzer itself
+
+use heapless::consts::U8;
+use heapless::spsc::Queue;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tone {
+    pub freq_hz: u16,
+    pub duration_ms: u16,
+}
+
+/// Tones waiting to be played, in order. This does not touch the hardware, it only says what
+/// frequency should be playing.
+pub struct ToneQueue {
+    tones: Queue<Tone, U8>,
+    playing: Option<(Tone, u32)>,
+}
+
+impl Default for ToneQueue {
+    fn default() -> ToneQueue {
+        ToneQueue::new()
+    }
+}
+
+impl ToneQueue {
+    pub fn new() -> ToneQueue {
+        ToneQueue {
+            tones: Queue::new(),
+            playing: None,
+        }
+    }
+
+    /// Add a tone to the end of the queue. If the queue is full, the tone is dropped.
+    pub fn push(&mut self, tone: Tone) {
+        self.tones.enqueue(tone).ok();
+    }
+
+    /// Move on to the next tone if the current one is done. Returns the new frequency to play,
+    /// with 0 for silence, only when it changes.
+    pub fn update(&mut self, now: u32) -> Option<u16> {
+        match self.playing {
+            Some((tone, start)) if now - start < tone.duration_ms as u32 => None,
+            playing => {
+                if let Some(tone) = self.tones.dequeue() {
+                    self.playing = Some((tone, now));
+                    Some(tone.freq_hz)
+                } else {
+                    self.playing = None;
+                    playing.map(|_| 0)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tone_queue_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::{Tone, ToneQueue};
+
+    #[test]
+    fn plays_in_order() {
+        let mut queue = ToneQueue::new();
+
+        assert_eq!(queue.update(0), None);
+
+        queue.push(Tone {
+            freq_hz: 2000,
+            duration_ms: 100,
+        });
+        queue.push(Tone {
+            freq_hz: 3000,
+            duration_ms: 50,
+        });
+
+        assert_eq!(queue.update(10), Some(2000));
+        assert_eq!(queue.update(109), None);
+        assert_eq!(queue.update(110), Some(3000));
+        assert_eq!(queue.update(150), None);
+        assert_eq!(queue.update(160), Some(0));
+        assert_eq!(queue.update(200), None);
+    }
+}
@@ -0,0 +1,141 @@
+//! The buffer that holds bytes waiting to go out over UART
+
+pub const TX_BUFFER_LEN: usize = 2048;
+
+/// A ring buffer of bytes to send, which counts the bytes that did not fit instead of blocking
+pub struct TxBuffer {
+    bytes: [u8; TX_BUFFER_LEN],
+    start: usize,
+    len: usize,
+
+    /// Bytes that could not be added because the buffer was full
+    overflows: u32,
+}
+
+impl Default for TxBuffer {
+    fn default() -> TxBuffer {
+        TxBuffer::new()
+    }
+}
+
+impl TxBuffer {
+    pub const fn new() -> TxBuffer {
+        TxBuffer {
+            bytes: [0; TX_BUFFER_LEN],
+            start: 0,
+            len: 0,
+            overflows: 0,
+        }
+    }
+
+    /// Add as many bytes as fit to the end of the buffer. The rest are dropped and counted, and
+    /// returned in `Err` if there were any.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<(), usize> {
+        for (i, &byte) in bytes.iter().enumerate() {
+            if self.len == TX_BUFFER_LEN {
+                let dropped = bytes.len() - i;
+                self.overflows = self.overflows.wrapping_add(dropped as u32);
+                return Err(dropped);
+            }
+
+            self.bytes[(self.start + self.len) % TX_BUFFER_LEN] = byte;
+            self.len += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Take the next byte to send off the front of the buffer
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            None
+        } else {
+            let byte = self.bytes[self.start];
+            self.start = (self.start + 1) % TX_BUFFER_LEN;
+            self.len -= 1;
+            Some(byte)
+        }
+    }
+
+    /// Drop everything waiting to be sent. This does not count as an overflow.
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// How many bytes have been dropped because the buffer was full
+    pub fn overflows(&self) -> u32 {
+        self.overflows
+    }
+}
+
+#[cfg(test)]
+mod uart_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::{TxBuffer, TX_BUFFER_LEN};
+
+    #[test]
+    fn in_order() {
+        let mut buffer = TxBuffer::new();
+
+        assert_eq!(buffer.push(b"abc"), Ok(()));
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.pop(), Some(b'a'));
+        assert_eq!(buffer.push(b"d"), Ok(()));
+        assert_eq!(buffer.pop(), Some(b'b'));
+        assert_eq!(buffer.pop(), Some(b'c'));
+        assert_eq!(buffer.pop(), Some(b'd'));
+        assert_eq!(buffer.pop(), None);
+        assert_eq!(buffer.overflows(), 0);
+    }
+
+    #[test]
+    fn overflow_counts_dropped_bytes() {
+        let mut buffer = TxBuffer::new();
+
+        assert_eq!(buffer.push(&[1; TX_BUFFER_LEN - 2]), Ok(()));
+        assert_eq!(buffer.push(&[2; 5]), Err(3));
+        assert_eq!(buffer.len(), TX_BUFFER_LEN);
+        assert_eq!(buffer.overflows(), 3);
+
+        assert_eq!(buffer.push(&[3]), Err(1));
+        assert_eq!(buffer.overflows(), 4);
+
+        // The bytes that did fit still go out, in order
+        for _ in 0..TX_BUFFER_LEN - 2 {
+            assert_eq!(buffer.pop(), Some(1));
+        }
+        assert_eq!(buffer.pop(), Some(2));
+        assert_eq!(buffer.pop(), Some(2));
+        assert_eq!(buffer.pop(), None);
+
+        // Clearing drops the bytes but keeps the count
+        assert_eq!(buffer.push(b"abc"), Ok(()));
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.overflows(), 4);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut buffer = TxBuffer::new();
+
+        for i in 0..3 * TX_BUFFER_LEN {
+            assert_eq!(buffer.push(&[i as u8]), Ok(()));
+            assert_eq!(buffer.pop(), Some(i as u8));
+        }
+
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.overflows(), 0);
+    }
+}
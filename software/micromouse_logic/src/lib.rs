@@ -3,6 +3,7 @@
 pub mod comms;
 pub mod config;
 pub mod fast;
+pub mod hardware;
 pub mod mouse;
 pub mod slow;
 
@@ -2,24 +2,33 @@ use core::f32;
 
 use serde::{Deserialize, Serialize};
 
+use heapless::Vec;
+use typenum::U256;
+
 use crate::config::MechanicalConfig;
 
 use crate::fast::localize::{Localize, LocalizeConfig, LocalizeDebug};
-use crate::fast::motion_queue::{Motion, MotionQueue, MotionQueueDebug};
+use crate::fast::motion_queue::{
+    Motion, MotionQueue, MotionQueueBuffer, MotionQueueDebug,
+};
 use crate::fast::{Direction, Orientation, Vector};
 
 use crate::fast::motion_control::{
     MotionControl, MotionControlConfig, MotionControlDebug,
 };
 use crate::fast::path::PathMotion;
+use crate::fast::stall::{StallConfig, StallDetector};
+use crate::slow::goal::{flood_costs, flood_distances, Goal};
+use crate::slow::home::HomePath;
+use crate::slow::localize_quality::LocalizeQuality;
 use crate::slow::map::{Map, MapConfig};
-use crate::slow::maze::{Maze, MazeConfig};
-use crate::slow::motion_plan::{motion_plan, MotionPlanConfig};
-use crate::slow::navigate::TwelvePartitionNavigate;
-use crate::slow::{MazeDirection, MazeOrientation, SlowDebug};
+use crate::slow::maze::{Maze, MazeConfig, Wall, HEIGHT, WIDTH};
+use crate::slow::motion_plan::{motion_plan, try_motion_plan, MotionPlanConfig};
+use crate::slow::navigate::{FloodFillNavigate, NavigateConfig, TwelvePartitionNavigate};
+use crate::slow::{MazeDirection, MazeOrientation, MazePosition, SlowDebug};
 use core::cmp::Ordering;
 
-#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct HardwareDebug {
     pub left_encoder: i32,
     pub right_encoder: i32,
@@ -28,6 +37,37 @@ pub struct HardwareDebug {
     pub right_distance: Option<DistanceReading>,
 }
 
+/// What the mouse is trying to do in a run
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum RunPhase {
+    /// Exploring the maze on the way to the goal
+    #[default]
+    Search,
+
+    /// Heading back to the start after reaching the goal, still mapping on the way
+    Return,
+
+    /// Going straight to the goal on the map that was found, without changing it
+    Speed,
+}
+
+impl RunPhase {
+    /// The phase to be in after getting to `position`. A search is over once it gets to the goal,
+    /// and a return once it gets back to `start`. A speed run is the last phase.
+    pub fn next(
+        self,
+        goal: &Goal,
+        start: MazePosition,
+        position: MazePosition,
+    ) -> RunPhase {
+        match self {
+            RunPhase::Search if goal.contains(position) => RunPhase::Return,
+            RunPhase::Return if position == start => RunPhase::Speed,
+            phase => phase,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct MouseDebug {
     pub hardware: HardwareDebug,
@@ -37,7 +77,9 @@ pub struct MouseDebug {
     pub motion_control: MotionControlDebug,
     pub motion_queue: MotionQueueDebug,
     pub slow: Option<SlowDebug>,
-    pub battery: u16,
+
+    /// The battery pack voltage, in volts
+    pub battery: f32,
     pub time: u32,
     pub delta_time: u32,
 }
@@ -53,10 +95,27 @@ pub struct MouseConfig {
     pub front_sensor_abort: f32,
     pub left_sensor_abort: f32,
     pub right_sensor_abort: f32,
+    /// Once the closest point `t` on the current path motion is past this, a close front wall
+    /// no longer aborts it
+    pub front_sensor_abort_done: f32,
+    /// The most cells to plan motions for at once. Fewer are planned if that many do not fit in
+    /// the motion queue.
+    pub plan_ahead_cells: usize,
+    pub navigate: NavigateConfig,
+    /// When returning to the start, retrace the cells driven through on the way to the goal
+    /// instead of navigating
+    pub retrace_home: bool,
+    /// How many extra cells worth of cost to give routes through cells that the mouse has not
+    /// localized well in, so a speed run stays well localized. 0 turns this off.
+    pub localize_bias: u8,
+    pub stall: StallConfig,
 }
 
 pub trait ContainsDistanceReading {
     fn value(self) -> Option<f32>;
+
+    /// Move the reading by `offset` if there is one and it is in range
+    fn with_offset(self, offset: f32) -> Self;
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
@@ -68,6 +127,21 @@ pub enum DistanceReading {
     OutOfRange,
 }
 
+impl DistanceReading {
+    /// Change the value with `f` if it is in range. An out of range reading stays out of range.
+    pub fn map_in_range<F: FnOnce(f32) -> f32>(self, f: F) -> DistanceReading {
+        match self {
+            DistanceReading::InRange(value) => DistanceReading::InRange(f(value)),
+            DistanceReading::OutOfRange => DistanceReading::OutOfRange,
+        }
+    }
+
+    /// Move the reading by `offset`, like from the sensor to the center of the mouse
+    pub fn with_offset(self, offset: f32) -> DistanceReading {
+        self.map_in_range(|value| value + offset)
+    }
+}
+
 impl PartialEq<f32> for DistanceReading {
     fn eq(&self, other: &f32) -> bool {
         match self {
@@ -114,6 +188,10 @@ impl ContainsDistanceReading for Option<DistanceReading> {
             None
         }
     }
+
+    fn with_offset(self, offset: f32) -> Option<DistanceReading> {
+        self.map(|reading| reading.with_offset(offset))
+    }
 }
 
 pub struct Mouse {
@@ -124,6 +202,34 @@ pub struct Mouse {
     motion_queue: MotionQueue,
     motion_control: MotionControl,
     moves_completed: usize,
+    home_path: HomePath,
+    localize_quality: LocalizeQuality,
+
+    /// The cell the mouse started in, to return to after the search
+    start: MazePosition,
+    phase: RunPhase,
+
+    /// The encoder counts as of the last update
+    left_encoder: i32,
+    right_encoder: i32,
+
+    /// How far each wheel has turned since the run started, in mm
+    left_odometry: f32,
+    right_odometry: f32,
+
+    stall: StallDetector,
+
+    /// Whether the queued motions were thrown out last update because a sensor saw a wall too
+    /// close
+    aborted: bool,
+
+    /// Whether a wheel has been powered without turning for a while, like when it is jammed
+    /// against a wall
+    stalled: bool,
+
+    /// The motor powers that motion control asked for last update
+    left_power: i32,
+    right_power: i32,
 }
 
 impl Mouse {
@@ -134,6 +240,8 @@ impl Mouse {
         left_encoder: i32,
         right_encoder: i32,
     ) -> Mouse {
+        let start = orientation.to_maze_orientation(&config.maze).position;
+
         Mouse {
             last_time: time,
             map: Map::new(),
@@ -148,6 +256,19 @@ impl Mouse {
             ),
             motion_queue: MotionQueue::new(),
             moves_completed: 0,
+            home_path: HomePath::new(start),
+            localize_quality: LocalizeQuality::new(),
+            start,
+            phase: RunPhase::Search,
+            left_encoder,
+            right_encoder,
+            left_odometry: 0.0,
+            right_odometry: 0.0,
+            stall: StallDetector::new(),
+            aborted: false,
+            stalled: false,
+            left_power: 0,
+            right_power: 0,
         }
     }
 
@@ -155,15 +276,34 @@ impl Mouse {
         &mut self,
         config: &MouseConfig,
         time: u32,
-        battery: u16,
+        battery: f32,
         left_encoder: i32,
         right_encoder: i32,
         left_distance: Option<DistanceReading>,
         front_distance: Option<DistanceReading>,
         right_distance: Option<DistanceReading>,
+        gyro_rate: Option<f32>,
     ) -> (i32, i32, MouseDebug) {
         let delta_time = time - self.last_time;
 
+        let delta_left = left_encoder - self.left_encoder;
+        let delta_right = right_encoder - self.right_encoder;
+
+        self.left_odometry += config.mechanical.ticks_to_mm(delta_left.abs() as f32);
+        self.right_odometry += config.mechanical.ticks_to_mm(delta_right.abs() as f32);
+        self.left_encoder = left_encoder;
+        self.right_encoder = right_encoder;
+
+        // The wheels moved this much with the power from last update
+        let stalled = self.stall.update(
+            &config.stall,
+            self.left_power,
+            self.right_power,
+            delta_left,
+            delta_right,
+            delta_time,
+        );
+
         let (orientation, localize_debug) = self.localize.update(
             &config.mechanical,
             &config.maze,
@@ -173,10 +313,23 @@ impl Mouse {
             left_distance,
             front_distance,
             right_distance,
+            gyro_rate,
+            delta_time,
             self.motion_queue.next_motion(),
             self.moves_completed,
         );
 
+        let corrected = localize_debug
+            .sensor
+            .as_ref()
+            .map(|sensor| sensor.maybe_x.is_some() || sensor.maybe_y.is_some())
+            .unwrap_or(false);
+
+        self.localize_quality.record(
+            orientation.to_maze_orientation(&config.maze).position,
+            corrected,
+        );
+
         let (motion_going_forward, motion_going_left, motion_going_right) =
             match self.motion_queue.next_motion() {
                 Some(Motion::Path(path_motion)) => {
@@ -214,9 +367,22 @@ impl Mouse {
                 _ => (false, false, false),
             };
 
+        // A move that is nearly done is most likely driving up to the wall the front sensor sees
+        let motion_nearly_done = match self.motion_queue.next_motion() {
+            Some(Motion::Path(path_motion)) => {
+                path_motion.closest_point(orientation.position).0
+                    >= config.front_sensor_abort_done
+            }
+            _ => false,
+        };
+
         let abort_front = front_distance
             .value()
-            .map(|d| motion_going_forward && d < config.front_sensor_abort)
+            .map(|d| {
+                motion_going_forward
+                    && !motion_nearly_done
+                    && d < config.front_sensor_abort
+            })
             .unwrap_or(false);
 
         let abort_left = left_distance
@@ -245,33 +411,104 @@ impl Mouse {
                 &config.mechanical,
                 &config.maze,
                 &config.map,
-                orientation.to_maze_orientation(&config.maze),
+                orientation,
                 left_distance,
                 front_distance,
                 right_distance,
             );
 
             if let Some(move_options) = move_options {
-                let (next_direction, navigate_debug) = self.navigate.navigate(
-                    orientation.to_maze_orientation(&config.maze),
-                    move_options,
+                let maze_orientation = orientation.to_maze_orientation(&config.maze);
+                let at_goal = config.navigate.goal.contains(maze_orientation.position);
+
+                self.home_path.visit(maze_orientation.position);
+
+                let phase = self.phase.next(
+                    &config.navigate.goal,
+                    self.start,
+                    maze_orientation.position,
                 );
 
-                let path = motion_plan(
-                    &config.motion_plan,
-                    &config.maze,
-                    orientation,
-                    &[next_direction],
+                if phase == RunPhase::Speed && self.phase != RunPhase::Speed {
+                    self.map.freeze();
+                }
+
+                self.phase = phase;
+
+                let navigate_config = match self.phase {
+                    RunPhase::Return => NavigateConfig {
+                        goal: Goal::Cell(self.start),
+                    },
+                    _ => config.navigate,
+                };
+
+                let (navigate_direction, navigate_debug) = self.navigate.navigate(
+                    &navigate_config,
+                    maze_orientation,
+                    move_options,
                 );
 
-                self.motion_queue.add_motions(&path).ok();
+                // After the search, flood fill on the map is sure to get there eventually
+                let navigate_direction = match self.phase {
+                    RunPhase::Search => navigate_direction,
+                    _ => {
+                        FloodFillNavigate::from_maze(*self.map.maze())
+                            .navigate(&navigate_config, maze_orientation, move_options)
+                            .0
+                    }
+                };
+
+                // A speed run follows the whole route to the goal on the map, as long as the
+                // sensors agree that the first step is open. Otherwise, only go one cell at a
+                // time so the next cell can be mapped before deciding where to go. There is
+                // nowhere left to go once a speed run gets to the goal.
+                let speed_route = match self.phase {
+                    RunPhase::Speed => self.speed_route(config, maze_orientation),
+                    _ => Vec::new(),
+                };
+
+                let speed_route_open = speed_route
+                    .first()
+                    .filter(|&&direction| {
+                        move_options.allows(maze_orientation.direction, direction)
+                    })
+                    .is_some();
+
+                let directions = match self.phase {
+                    RunPhase::Speed if speed_route_open => speed_route,
+                    RunPhase::Speed if at_goal => Vec::new(),
+                    RunPhase::Return if config.retrace_home => {
+                        self.home_path.retrace().take(1).collect()
+                    }
+                    _ => {
+                        let mut directions = Vec::new();
+                        directions.push(navigate_direction).ok();
+                        directions
+                    }
+                };
+
+                let next_direction = directions.first().copied();
+
+                let dropped_motions = if next_direction.is_some() {
+                    let (path, dropped) =
+                        self.plan_motions(config, orientation, &directions);
+
+                    // The plan was made to fit, so this only drops anything if a single cell
+                    // does not fit either
+                    dropped + self.motion_queue.add_motions(&path).err().unwrap_or(0)
+                } else {
+                    0
+                };
 
                 // TODO: Get the move options and map debug out even if they are None
                 Some(SlowDebug {
                     map: map_debug,
                     move_options,
                     navigate: navigate_debug,
-                    next_direction,
+                    next_direction: next_direction.unwrap_or(navigate_direction),
+                    at_goal,
+                    phase: self.phase,
+                    dropped_motions,
                 })
             } else {
                 None
@@ -288,8 +525,20 @@ impl Mouse {
             right_encoder,
             self.motion_queue.next_motion(),
             orientation,
+            Some(battery),
         );
 
+        // Keep track of what motion control wants even while the motors are stopped, so a
+        // stall lasts until it stops asking to move
+        self.left_power = left_power;
+        self.right_power = right_power;
+
+        let (left_power, right_power) = if stalled && config.stall.stop_motors {
+            (0, 0)
+        } else {
+            (left_power, right_power)
+        };
+
         let hardware_debug = HardwareDebug {
             left_encoder,
             right_encoder,
@@ -311,10 +560,136 @@ impl Mouse {
             delta_time,
         };
 
+        self.aborted = abort_moves;
+        self.stalled = stalled;
         self.last_time = time;
 
         (left_power, right_power, debug)
     }
+
+    /// Plan motions for as many of `directions` as fit in the motion queue, up to
+    /// `plan_ahead_cells` of them. Any that are left over are planned once the mouse gets there.
+    ///
+    /// A single cell is always planned, even if it does not fit. Then it also gives how many
+    /// motions did not even fit in a `MotionQueueBuffer`.
+    fn plan_motions(
+        &self,
+        config: &MouseConfig,
+        orientation: Orientation,
+        directions: &[MazeDirection],
+    ) -> (MotionQueueBuffer, usize) {
+        let capacity = self.motion_queue.remaining_capacity();
+        let mut cells = config.plan_ahead_cells.min(directions.len()).max(1);
+
+        loop {
+            let plan = try_motion_plan(
+                &config.motion_plan,
+                &config.maze,
+                self.map.maze(),
+                orientation,
+                &directions[..cells],
+            );
+
+            match plan {
+                Ok(path) if path.len() <= capacity => return (path, 0),
+                _ if cells > 1 => cells -= 1,
+                Ok(path) => return (path, 0),
+                Err(dropped) => {
+                    let path = motion_plan(
+                        &config.motion_plan,
+                        &config.maze,
+                        self.map.maze(),
+                        orientation,
+                        &directions[..cells],
+                    );
+
+                    return (path, dropped);
+                }
+            }
+        }
+    }
+
+    /// How far the left and right wheels have turned since the run started, in mm. Going
+    /// backwards counts too, so these only ever go up.
+    pub fn aborted(&self) -> bool {
+        self.aborted
+    }
+
+    pub fn stalled(&self) -> bool {
+        self.stalled
+    }
+
+    pub fn phase(&self) -> RunPhase {
+        self.phase
+    }
+
+    /// The way back to the start over the cells visited so far
+    pub fn home_path(&self) -> &HomePath {
+        &self.home_path
+    }
+
+    pub fn localize_quality(&self) -> &LocalizeQuality {
+        &self.localize_quality
+    }
+
+    pub fn odometry(&self) -> (f32, f32) {
+        (self.left_odometry, self.right_odometry)
+    }
+
+    /// Whether the mouse is in one of the goal cells, as of the last update
+    pub fn at_goal(&self, config: &MouseConfig) -> bool {
+        let position = self
+            .localize
+            .orientation()
+            .to_maze_orientation(&config.maze)
+            .position;
+
+        config.navigate.goal.contains(position)
+    }
+
+    /// How much extra it costs a speed run to go through a cell. If `localize_bias` is set,
+    /// cells that have not localized well cost more.
+    fn route_penalty(&self, config: &MouseConfig, position: MazePosition) -> u8 {
+        if config.localize_bias > 0 {
+            self.localize_quality
+                .penalty(position, config.localize_bias)
+        } else {
+            0
+        }
+    }
+
+    /// The walls a speed run can route through. Walls that were never seen might be there, so
+    /// they are treated as closed.
+    fn speed_maze(&self) -> Maze {
+        self.map.maze().fill_unknown(Wall::Closed)
+    }
+
+    /// How much it costs to get to the goal from each cell, for a speed run to route by
+    pub fn route_costs(&self, config: &MouseConfig) -> [[u8; HEIGHT]; WIDTH] {
+        let maze = self.speed_maze();
+
+        if config.localize_bias > 0 {
+            flood_costs(&maze, &config.navigate.goal, |position| {
+                self.route_penalty(config, position)
+            })
+        } else {
+            flood_distances(&maze, &config.navigate.goal)
+        }
+    }
+
+    /// The whole route a speed run takes from `orientation` to the goal, following
+    /// `route_costs`
+    fn speed_route(
+        &self,
+        config: &MouseConfig,
+        orientation: MazeOrientation,
+    ) -> Vec<MazeDirection, U256> {
+        FloodFillNavigate::from_maze(self.speed_maze()).plan_route_weighted(
+            &config.navigate,
+            orientation,
+            |position| self.route_penalty(config, position),
+        )
+    }
 }
 
 pub struct TestMouse {}
@@ -338,3 +713,515 @@ impl TestMouse {
         }
     }
 }
+
+#[cfg(test)]
+mod distance_reading_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{ContainsDistanceReading, DistanceReading};
+
+    #[test]
+    fn in_range_offset() {
+        assert_eq!(
+            DistanceReading::InRange(30.0).with_offset(40.0),
+            DistanceReading::InRange(70.0)
+        );
+        assert_eq!(
+            Some(DistanceReading::InRange(30.0))
+                .with_offset(40.0)
+                .value(),
+            Some(70.0)
+        );
+    }
+
+    #[test]
+    fn out_of_range_offset() {
+        assert_eq!(
+            DistanceReading::OutOfRange.with_offset(40.0),
+            DistanceReading::OutOfRange
+        );
+        assert_eq!(
+            Some(DistanceReading::OutOfRange).with_offset(40.0),
+            Some(DistanceReading::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn none_offset() {
+        let reading: Option<DistanceReading> = None;
+        assert_eq!(reading.with_offset(40.0), None);
+    }
+
+    #[test]
+    fn map_in_range() {
+        assert_eq!(
+            DistanceReading::InRange(30.0).map_in_range(|value| value * 2.0),
+            DistanceReading::InRange(60.0)
+        );
+        assert_eq!(
+            DistanceReading::OutOfRange.map_in_range(|value| value * 2.0),
+            DistanceReading::OutOfRange
+        );
+    }
+}
+
+#[cfg(test)]
+mod mouse_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::{DistanceReading, Mouse, MouseConfig, RunPhase};
+    use crate::config::sim::MOUSE_2020;
+    use crate::fast::localize::LocalizeConfig;
+    use crate::fast::motion_queue::{Motion, MotionQueue};
+    use crate::fast::path::PathMotion;
+    use crate::fast::stall::StallConfig;
+    use crate::fast::{
+        Orientation, Vector, DIRECTION_0, DIRECTION_3_PI_2, DIRECTION_PI_2,
+    };
+    use crate::slow::maze::{Maze, Wall, WallIndex};
+    use crate::slow::motion_plan::try_motion_plan;
+    use crate::slow::{MazeDirection, MazeOrientation, MazePosition};
+
+    /// Drive east along a long line with a wall 30mm in front of the sensor, and report whether
+    /// the move was aborted
+    fn aborted_at(x: f32) -> bool {
+        let config = MOUSE_2020;
+        let orientation = Orientation {
+            position: Vector { x, y: 90.0 },
+            direction: DIRECTION_0,
+        };
+
+        let mut mouse = Mouse::new(&config, orientation, 0, 0, 0);
+        mouse
+            .motion_queue
+            .add_motions(&[Motion::Path(PathMotion::line(
+                Vector { x: 0.0, y: 90.0 },
+                Vector { x: 2100.0, y: 90.0 },
+            ))])
+            .unwrap();
+
+        mouse.update(
+            &config,
+            10,
+            4.0,
+            0,
+            0,
+            None,
+            Some(DistanceReading::InRange(30.0)),
+            None,
+            None,
+        );
+
+        mouse.aborted()
+    }
+
+    /// Go through `positions` one after another, starting in a search
+    fn phases(positions: &[(usize, usize)]) -> impl Iterator<Item = RunPhase> + '_ {
+        let goal = MOUSE_2020.navigate.goal;
+        let start = MazePosition { x: 0, y: 0 };
+
+        positions
+            .iter()
+            .scan(RunPhase::Search, move |phase, &(x, y)| {
+                *phase = phase.next(&goal, start, MazePosition { x, y });
+                Some(*phase)
+            })
+    }
+
+    #[test]
+    fn search_return_speed() {
+        let positions = [
+            (0, 0),
+            (0, 7),
+            (7, 7),
+            (7, 6),
+            (0, 6),
+            (0, 0),
+            (0, 7),
+            (7, 7),
+            (0, 0),
+        ];
+
+        let expected = [
+            RunPhase::Search,
+            RunPhase::Search,
+            RunPhase::Return,
+            RunPhase::Return,
+            RunPhase::Return,
+            RunPhase::Speed,
+            RunPhase::Speed,
+            RunPhase::Speed,
+            RunPhase::Speed,
+        ];
+
+        assert!(phases(&positions).eq(expected.iter().copied()));
+    }
+
+    #[test]
+    fn start_does_not_end_search() {
+        assert!(phases(&[(0, 0), (0, 1), (0, 0)]).all(|phase| phase == RunPhase::Search));
+    }
+
+    #[test]
+    fn at_goal_in_center() {
+        let config = MOUSE_2020;
+
+        let start = Orientation {
+            position: Vector { x: 90.0, y: 90.0 },
+            direction: DIRECTION_0,
+        };
+        assert!(!Mouse::new(&config, start, 0, 0, 0).at_goal(&config));
+
+        let center = Orientation {
+            position: Vector {
+                x: 7.0 * 180.0 + 90.0,
+                y: 8.0 * 180.0 + 90.0,
+            },
+            direction: DIRECTION_0,
+        };
+        assert!(Mouse::new(&config, center, 0, 0, 0).at_goal(&config));
+    }
+
+    #[test]
+    fn close_wall_aborts() {
+        assert!(aborted_at(1010.0));
+    }
+
+    #[test]
+    fn close_wall_nearly_done_does_not_abort() {
+        assert!(!aborted_at(1910.0));
+    }
+
+    #[test]
+    fn localize_bias_changes_speed_route() {
+        let start = Orientation {
+            position: Vector { x: 90.0, y: 90.0 },
+            direction: DIRECTION_PI_2,
+        };
+        let maze_start = MazeOrientation {
+            position: MazePosition { x: 0, y: 0 },
+            direction: MazeDirection::North,
+        };
+
+        let mut mouse = Mouse::new(&MOUSE_2020, start, 0, 0, 0);
+        mouse.map.import(&Maze::new(Wall::Open));
+
+        // The sensors only ever corrected along the south and east of the way to the goal
+        for x in 0..8 {
+            mouse
+                .localize_quality
+                .record(MazePosition { x, y: 0 }, true);
+        }
+        for y in 0..8 {
+            mouse
+                .localize_quality
+                .record(MazePosition { x: 7, y }, true);
+        }
+
+        // Without the bias, the straightest route goes north first
+        let route = mouse.speed_route(&MOUSE_2020, maze_start);
+        assert_eq!(route.len(), 14);
+        assert_eq!(route[0], MazeDirection::North);
+
+        let config = MouseConfig {
+            localize_bias: 10,
+            ..MOUSE_2020
+        };
+
+        let route = mouse.speed_route(&config, maze_start);
+        assert_eq!(route.len(), 14);
+        assert!(route[..7]
+            .iter()
+            .all(|&direction| direction == MazeDirection::East));
+        assert!(route[7..]
+            .iter()
+            .all(|&direction| direction == MazeDirection::North));
+    }
+
+    #[test]
+    fn corridor_walls_are_mapped() {
+        // Only the map uses the sensors, so the encoders alone say where the mouse is
+        let config = MouseConfig {
+            localize: LocalizeConfig {
+                use_sensors: false,
+                ..MOUSE_2020.localize
+            },
+            ..MOUSE_2020
+        };
+
+        let start = Orientation {
+            position: Vector { x: 270.0, y: 90.0 },
+            direction: DIRECTION_PI_2,
+        };
+
+        let mut mouse = Mouse::new(&config, start, 0, 0, 0);
+
+        // Walls close on both sides, and nothing in front
+        let side = Some(DistanceReading::InRange(40.0));
+        let front = Some(DistanceReading::OutOfRange);
+
+        // Drive straight north 2mm at a time until it gets to the fourth cell
+        let step = config.mechanical.mm_to_ticks(2.0) as i32;
+        let mut time = 0;
+        let mut ticks = 0;
+        loop {
+            time += 10;
+            ticks += step;
+
+            let (_, _, debug) =
+                mouse.update(&config, time, 8.0, ticks, ticks, side, front, side, None);
+
+            if debug.maze_orientation.position.y == 3 {
+                break;
+            }
+
+            assert!(time < 10_000, "never got down the corridor");
+        }
+
+        for y in 0..3 {
+            for &direction in [MazeDirection::West, MazeDirection::East].iter() {
+                let index = WallIndex::from_maze_orientation(MazeOrientation {
+                    position: MazePosition { x: 1, y },
+                    direction,
+                });
+
+                assert_eq!(mouse.map.maze().get_wall(index), Some(&Wall::Closed));
+            }
+        }
+    }
+
+    #[test]
+    fn speed_route_avoids_unknown_walls() {
+        let start = Orientation {
+            position: Vector { x: 90.0, y: 90.0 },
+            direction: DIRECTION_PI_2,
+        };
+        let maze_start = MazeOrientation {
+            position: MazePosition { x: 0, y: 0 },
+            direction: MazeDirection::North,
+        };
+
+        // The wall straight ahead was never seen, so it might be there
+        let mut maze = Maze::new(Wall::Open);
+        maze.set_wall(WallIndex::from_maze_orientation(maze_start), Wall::Unknown)
+            .unwrap();
+
+        let mut mouse = Mouse::new(&MOUSE_2020, start, 0, 0, 0);
+        mouse.map.import(&maze);
+
+        let route = mouse.speed_route(&MOUSE_2020, maze_start);
+        assert_eq!(route.len(), 14);
+        assert_eq!(route[0], MazeDirection::East);
+    }
+
+    #[test]
+    fn blocked_speed_run_navigates() {
+        let start = Orientation {
+            position: Vector { x: 90.0, y: 90.0 },
+            direction: DIRECTION_PI_2,
+        };
+
+        let mut mouse = Mouse::new(&MOUSE_2020, start, 0, 0, 0);
+        mouse.map.import(&Maze::new(Wall::Open));
+        mouse.map.freeze();
+        mouse.phase = RunPhase::Speed;
+
+        // The map says it is open straight ahead, but there is a wall there
+        let (_, _, debug) = mouse.update(
+            &MOUSE_2020,
+            10,
+            8.0,
+            0,
+            0,
+            Some(DistanceReading::InRange(40.0)),
+            Some(DistanceReading::InRange(30.0)),
+            Some(DistanceReading::OutOfRange),
+            None,
+        );
+
+        let slow = debug.slow.unwrap();
+        assert_eq!(slow.next_direction, MazeDirection::East);
+        assert_eq!(mouse.motion_queue.motions_remaining(), 2);
+    }
+
+    #[test]
+    fn return_avoids_dead_end() {
+        // In cell (1, 1) facing south, towards the start
+        let start = Orientation {
+            position: Vector { x: 270.0, y: 270.0 },
+            direction: DIRECTION_3_PI_2,
+        };
+
+        // Cell (1, 0) is a dead end, so the way home is around through (0, 1)
+        let mut maze = Maze::new(Wall::Open);
+        for &direction in [MazeDirection::West, MazeDirection::East].iter() {
+            let index = WallIndex::from_maze_orientation(MazeOrientation {
+                position: MazePosition { x: 1, y: 0 },
+                direction,
+            });
+            maze.set_wall(index, Wall::Closed).unwrap();
+        }
+
+        let mut mouse = Mouse::new(&MOUSE_2020, start, 0, 0, 0);
+        mouse.map.import(&maze);
+        mouse.start = MazePosition { x: 0, y: 0 };
+        mouse.phase = RunPhase::Return;
+
+        let (_, _, debug) = mouse.update(
+            &MOUSE_2020,
+            10,
+            8.0,
+            0,
+            0,
+            Some(DistanceReading::OutOfRange),
+            Some(DistanceReading::OutOfRange),
+            Some(DistanceReading::OutOfRange),
+            None,
+        );
+
+        assert_eq!(debug.slow.unwrap().next_direction, MazeDirection::West);
+    }
+
+    #[test]
+    fn long_plan_is_shortened() {
+        let config = MouseConfig {
+            plan_ahead_cells: 8,
+            ..MOUSE_2020
+        };
+
+        let start = Orientation {
+            position: Vector { x: 90.0, y: 90.0 },
+            direction: DIRECTION_PI_2,
+        };
+
+        let mut mouse = Mouse::new(&config, start, 0, 0, 0);
+        mouse.map.import(&Maze::new(Wall::Open));
+        mouse.map.freeze();
+        mouse.phase = RunPhase::Speed;
+
+        let open = Some(DistanceReading::OutOfRange);
+        let (_, _, debug) = mouse.update(&config, 10, 8.0, 0, 0, open, open, open, None);
+
+        let route = mouse.speed_route(&config, debug.maze_orientation);
+        let plan = |cells: usize| {
+            try_motion_plan(
+                &config.motion_plan,
+                &config.maze,
+                &Maze::new(Wall::Open),
+                debug.orientation,
+                &route[..cells],
+            )
+        };
+
+        // All 8 cells do not fit, but 3 of them just fill up the queue
+        assert!(plan(8).is_err());
+        let expected = plan(3).unwrap();
+        assert_eq!(expected.len(), 4);
+
+        let mut queue = MotionQueue::new();
+        queue.add_motions(&expected).unwrap();
+
+        assert_eq!(debug.slow.map(|slow| slow.dropped_motions), Some(0));
+        assert_eq!(mouse.motion_queue.debug(), queue.debug());
+
+        // The next plan starts from the whole horizon again
+        mouse.motion_queue.clear();
+        assert_eq!(
+            mouse.plan_motions(&config, debug.orientation, &route[..2]),
+            (plan(2).unwrap(), 0)
+        );
+    }
+
+    #[test]
+    fn odometry() {
+        let config = MOUSE_2020;
+        let mech = config.mechanical;
+
+        let start = Orientation {
+            position: Vector { x: 90.0, y: 90.0 },
+            direction: DIRECTION_0,
+        };
+
+        let mut mouse = Mouse::new(&config, start, 0, 100, -100);
+        assert_eq!(mouse.odometry(), (0.0, 0.0));
+
+        let ticks = mech.mm_to_ticks(10.0) as i32;
+        let encoders = [
+            (100 + ticks, -100 + ticks),
+            (100 + 2 * ticks, -100 + 2 * ticks),
+            // Backing up the left wheel still adds to how far it went
+            (100 + ticks, -100 + 3 * ticks),
+        ];
+
+        for (i, &(left, right)) in encoders.iter().enumerate() {
+            mouse.update(
+                &config,
+                10 * (i as u32 + 1),
+                8.0,
+                left,
+                right,
+                None,
+                None,
+                None,
+                None,
+            );
+        }
+
+        let (left, right) = mouse.odometry();
+        assert_close(left, mech.ticks_to_mm(3.0 * ticks as f32));
+        assert_close(right, mech.ticks_to_mm(3.0 * ticks as f32));
+    }
+
+    #[test]
+    fn stalled_wheels() {
+        let config = MouseConfig {
+            stall: StallConfig {
+                min_power: 1,
+                max_ticks: 0,
+                time: 50,
+                stop_motors: true,
+            },
+            ..MOUSE_2020
+        };
+
+        let start = Orientation {
+            position: Vector { x: 90.0, y: 90.0 },
+            direction: DIRECTION_0,
+        };
+
+        let mut mouse = Mouse::new(&config, start, 0, 0, 0);
+        mouse
+            .motion_queue
+            .add_motions(&[Motion::Path(PathMotion::line(
+                start.position,
+                Vector { x: 1890.0, y: 90.0 },
+            ))])
+            .unwrap();
+
+        // The motors are told to go, but the encoders never move
+        let mut powered_at = None;
+        let mut stalled_at = None;
+        for i in 1..30 {
+            let (left, right, _) =
+                mouse.update(&config, i * 10, 8.0, 0, 0, None, None, None, None);
+
+            if stalled_at.is_none() && (left != 0 || right != 0) {
+                powered_at.get_or_insert(i);
+            }
+
+            if mouse.stalled() {
+                stalled_at.get_or_insert(i);
+                assert_eq!((left, right), (0, 0));
+            }
+        }
+
+        // The power from one update is checked against how far the wheels went by the next, and
+        // the updates are 10ms apart
+        let powered_at = powered_at.unwrap();
+        assert_eq!(stalled_at, Some(powered_at + config.stall.time / 10));
+    }
+}
@@ -0,0 +1,299 @@
+//! A* search for a route through the maze that can trade a few extra cells for fewer turns
+//!
+//! Flood fill always finds one of the shortest routes, but a route with a couple more cells and
+//! long straightaways is often faster to drive than a short one that zig-zags.
+
+use heapless::Vec;
+use typenum::U256;
+
+use crate::slow::maze::{Maze, HEIGHT, WIDTH};
+use crate::slow::{MazeDirection, MazePosition};
+
+/// The cell the mouse was in before going `direction` to get to `position`
+fn previous_cell(position: MazePosition, direction: MazeDirection) -> MazePosition {
+    match direction {
+        MazeDirection::North => MazePosition {
+            x: position.x,
+            y: position.y - 1,
+        },
+        MazeDirection::South => MazePosition {
+            x: position.x,
+            y: position.y + 1,
+        },
+        MazeDirection::East => MazePosition {
+            x: position.x - 1,
+            y: position.y,
+        },
+        MazeDirection::West => MazePosition {
+            x: position.x + 1,
+            y: position.y,
+        },
+    }
+}
+
+/// The fewest cells it could possibly take to get from `position` to any of the `goal` cells
+fn heuristic(position: MazePosition, goal: &[MazePosition]) -> f32 {
+    goal.iter()
+        .map(|cell| cell.x.abs_diff(position.x) + cell.y.abs_diff(position.y))
+        .min()
+        .unwrap_or(0) as f32
+}
+
+/// Find the cheapest route from `start` to any of the `goal` cells. Every cell costs 1, and every
+/// time the route changes direction costs another `turn_cost`, so a higher `turn_cost` prefers
+/// longer straightaways over a shorter route. The first step is never counted as a turn.
+///
+/// Walls that are not known yet are treated as open, the same as flood fill. Returns `None` if
+/// none of the goal cells can be reached, and an empty route if `start` is already in the goal.
+pub fn astar(
+    maze: &Maze,
+    start: MazePosition,
+    goal: &[MazePosition],
+    turn_cost: f32,
+) -> Option<Vec<MazeDirection, U256>> {
+    if goal.contains(&start) {
+        return Some(Vec::new());
+    }
+
+    // Whether the next step is a turn depends on which way the mouse went to get into a cell, so
+    // each cell gets a separate state for each direction it can be entered from
+    let mut cost = [[[f32::INFINITY; 4]; HEIGHT]; WIDTH];
+    let mut open = [[[false; 4]; HEIGHT]; WIDTH];
+    let mut closed = [[[false; 4]; HEIGHT]; WIDTH];
+
+    // The direction the mouse entered the previous cell from, or `None` if that was the start
+    let mut came_from: [[[Option<MazeDirection>; 4]; HEIGHT]; WIDTH] =
+        [[[None; 4]; HEIGHT]; WIDTH];
+
+    for &(direction, next) in maze.neighbors(start, true).iter() {
+        cost[next.x][next.y][direction.to_index()] = 1.0;
+        open[next.x][next.y][direction.to_index()] = true;
+    }
+
+    loop {
+        // The open state that looks like it will be the cheapest all the way to the goal. There
+        // are few enough states in a maze that searching them all is fast enough.
+        let mut best: Option<(MazePosition, MazeDirection, f32)> = None;
+
+        for x in 0..WIDTH {
+            for y in 0..HEIGHT {
+                for &direction in MazeDirection::ALL.iter() {
+                    if !open[x][y][direction.to_index()] {
+                        continue;
+                    }
+
+                    let position = MazePosition { x, y };
+                    let estimate =
+                        cost[x][y][direction.to_index()] + heuristic(position, goal);
+
+                    match best {
+                        Some((_, _, best_estimate)) if best_estimate <= estimate => {}
+                        _ => best = Some((position, direction, estimate)),
+                    }
+                }
+            }
+        }
+
+        let (position, direction, _) = best?;
+        let index = direction.to_index();
+
+        open[position.x][position.y][index] = false;
+        closed[position.x][position.y][index] = true;
+
+        if goal.contains(&position) {
+            return Some(reconstruct(&came_from, position, direction));
+        }
+
+        for &(next_direction, next) in maze.neighbors(position, true).iter() {
+            // Going right back is never part of the cheapest route
+            if next_direction == direction.opposite() {
+                continue;
+            }
+
+            let next_index = next_direction.to_index();
+
+            if closed[next.x][next.y][next_index] {
+                continue;
+            }
+
+            let step_cost = if next_direction == direction {
+                1.0
+            } else {
+                1.0 + turn_cost
+            };
+
+            let next_cost = cost[position.x][position.y][index] + step_cost;
+
+            if next_cost < cost[next.x][next.y][next_index] {
+                cost[next.x][next.y][next_index] = next_cost;
+                came_from[next.x][next.y][next_index] = Some(direction);
+                open[next.x][next.y][next_index] = true;
+            }
+        }
+    }
+}
+
+/// Follow `came_from` back from the end of the route to the start
+fn reconstruct(
+    came_from: &[[[Option<MazeDirection>; 4]; HEIGHT]; WIDTH],
+    end: MazePosition,
+    end_direction: MazeDirection,
+) -> Vec<MazeDirection, U256> {
+    let mut route: Vec<MazeDirection, U256> = Vec::new();
+    let mut position = end;
+    let mut direction = Some(end_direction);
+
+    while let Some(current) = direction {
+        if route.push(current).is_err() {
+            break;
+        }
+
+        direction = came_from[position.x][position.y][current.to_index()];
+        position = previous_cell(position, current);
+    }
+
+    route.reverse();
+    route
+}
+
+#[cfg(test)]
+mod astar_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use pretty_assertions::assert_eq;
+
+    use super::astar;
+    use crate::slow::goal::Goal;
+    use crate::slow::maze::{Maze, Wall, WallIndex};
+    use crate::slow::navigate::{FloodFillNavigate, NavigateConfig};
+    use crate::slow::{MazeDirection, MazeOrientation, MazePosition};
+
+    const START: MazePosition = MazePosition { x: 0, y: 0 };
+    const GOAL: MazePosition = MazePosition { x: 4, y: 4 };
+
+    /// Open the walls along a path through the maze
+    fn carve(maze: &mut Maze, start: MazePosition, directions: &[MazeDirection]) {
+        let mut position = start;
+
+        for &direction in directions {
+            maze.set_wall(
+                WallIndex::from_maze_orientation(MazeOrientation {
+                    position,
+                    direction,
+                }),
+                Wall::Open,
+            )
+            .unwrap();
+
+            position = match direction {
+                MazeDirection::North => MazePosition {
+                    x: position.x,
+                    y: position.y + 1,
+                },
+                MazeDirection::South => MazePosition {
+                    x: position.x,
+                    y: position.y - 1,
+                },
+                MazeDirection::East => MazePosition {
+                    x: position.x + 1,
+                    y: position.y,
+                },
+                MazeDirection::West => MazePosition {
+                    x: position.x - 1,
+                    y: position.y,
+                },
+            };
+        }
+    }
+
+    /// Two ways from the start to `GOAL`: a short staircase with a turn in every cell, and a
+    /// longer way around with only two turns
+    fn staircase_maze() -> Maze {
+        use MazeDirection::*;
+
+        let mut maze = Maze::new(Wall::Closed);
+        carve(
+            &mut maze,
+            START,
+            &[East, North, East, North, East, North, East, North],
+        );
+        carve(
+            &mut maze,
+            START,
+            &[
+                North, North, North, North, North, North, East, East, East, East, South,
+                South,
+            ],
+        );
+
+        maze
+    }
+
+    fn turns(route: &[MazeDirection]) -> usize {
+        route.windows(2).filter(|pair| pair[0] != pair[1]).count()
+    }
+
+    #[test]
+    fn straights_beat_staircase() {
+        let maze = staircase_maze();
+
+        let flood_route = FloodFillNavigate::from_maze(maze).plan_route(
+            &NavigateConfig {
+                goal: Goal::Cell(GOAL),
+            },
+            MazeOrientation {
+                position: START,
+                direction: MazeDirection::North,
+            },
+        );
+
+        let route = astar(&maze, START, &[GOAL], 1.0).unwrap();
+
+        // Flood fill takes the shortest way, even though it turns every cell
+        assert_eq!(flood_route.len(), 8);
+        assert_eq!(turns(&flood_route), 7);
+
+        assert_eq!(route.len(), 12);
+        assert_eq!(turns(&route), 2);
+    }
+
+    #[test]
+    fn no_turn_cost_is_shortest() {
+        let maze = staircase_maze();
+
+        let route = astar(&maze, START, &[GOAL], 0.0).unwrap();
+
+        assert_eq!(route.len(), 8);
+    }
+
+    #[test]
+    fn open_maze_one_turn() {
+        let maze = Maze::new(Wall::Open);
+        let goal = Goal::default()
+            .cells()
+            .collect::<heapless::Vec<_, typenum::U4>>();
+
+        let route = astar(&maze, START, &goal, 1.0).unwrap();
+
+        assert_eq!(route.len(), 14);
+        assert_eq!(turns(&route), 1);
+    }
+
+    #[test]
+    fn start_in_goal() {
+        let maze = Maze::new(Wall::Open);
+
+        assert_eq!(
+            astar(&maze, GOAL, &[GOAL], 1.0).map(|route| route.len()),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn unreachable() {
+        let maze = Maze::new(Wall::Closed);
+
+        assert_eq!(astar(&maze, START, &[GOAL], 1.0), None);
+    }
+}
@@ -0,0 +1,352 @@
+//! Where in the maze the mouse is trying to get to
+
+use serde::{Deserialize, Serialize};
+
+use super::maze::{Maze, Wall, HEIGHT, WIDTH};
+use super::MazePosition;
+
+/// The shape of the goal region. Different contests define the goal differently, so this can be
+/// a single cell, the center 2x2, or a line of cells.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Goal {
+    /// Just one cell
+    Cell(MazePosition),
+
+    /// A 2x2 block of cells, with this cell in the south west corner
+    Square(MazePosition),
+
+    /// A straight line of cells from the first to the second, inclusive. They need to share
+    /// either an x or a y.
+    Line(MazePosition, MazePosition),
+
+    /// Any set of up to 8 cells
+    Cells([Option<MazePosition>; 8]),
+}
+
+impl Default for Goal {
+    fn default() -> Goal {
+        Goal::Square(MazePosition { x: 7, y: 7 })
+    }
+}
+
+impl Goal {
+    /// Whether a cell is part of the goal
+    pub fn contains(&self, position: MazePosition) -> bool {
+        match *self {
+            Goal::Cell(cell) => position == cell,
+            Goal::Square(corner) => {
+                position.x >= corner.x
+                    && position.x <= corner.x + 1
+                    && position.y >= corner.y
+                    && position.y <= corner.y + 1
+            }
+            Goal::Line(start, end) => {
+                position.x >= start.x.min(end.x)
+                    && position.x <= start.x.max(end.x)
+                    && position.y >= start.y.min(end.y)
+                    && position.y <= start.y.max(end.y)
+            }
+            Goal::Cells(cells) => cells.contains(&Some(position)),
+        }
+    }
+
+    /// The south west and north east corners of the smallest rectangle that holds the whole goal
+    pub fn bounds(&self) -> (MazePosition, MazePosition) {
+        self.cells().fold(
+            (
+                MazePosition {
+                    x: WIDTH - 1,
+                    y: HEIGHT - 1,
+                },
+                MazePosition { x: 0, y: 0 },
+            ),
+            |(min, max), cell| {
+                (
+                    MazePosition {
+                        x: min.x.min(cell.x),
+                        y: min.y.min(cell.y),
+                    },
+                    MazePosition {
+                        x: max.x.max(cell.x),
+                        y: max.y.max(cell.y),
+                    },
+                )
+            },
+        )
+    }
+
+    /// All of the cells in the maze that are part of the goal
+    pub fn cells(&self) -> impl Iterator<Item = MazePosition> + '_ {
+        (0..WIDTH)
+            .flat_map(|x| (0..HEIGHT).map(move |y| MazePosition { x, y }))
+            .filter(move |&position| self.contains(position))
+    }
+}
+
+/// Find how many cells away each cell is from the closest goal cell, flooding out from all of the
+/// goal cells at once. Walls that are not known are treated as open. Cells that cannot reach the
+/// goal are 255.
+pub fn flood_distances<const W: usize, const H: usize>(
+    maze: &Maze<W, H>,
+    goal: &Goal,
+) -> [[u8; H]; W] {
+    let mut distances = [[255u8; H]; W];
+
+    for (x, column) in distances.iter_mut().enumerate() {
+        for (y, distance) in column.iter_mut().enumerate() {
+            if goal.contains(MazePosition { x, y }) {
+                *distance = 0;
+            }
+        }
+    }
+
+    // Flood out one step at a time from the cells that were reached in the last step. This goes
+    // over the whole maze for each step instead of keeping a queue, since the queue would need to
+    // be as big as the maze.
+    let mut distance = 0;
+    let mut changed = true;
+    while changed && distance < 254 {
+        changed = false;
+
+        for x in 0..W {
+            for y in 0..H {
+                if distances[x][y] != distance {
+                    continue;
+                }
+
+                let (north, south, east, west) = maze.get_cell(x, y);
+
+                // The outside walls are always closed, so these will not go out of the maze
+                let neighbors = [
+                    (north, x, y + 1),
+                    (south, x, y.wrapping_sub(1)),
+                    (east, x + 1, y),
+                    (west, x.wrapping_sub(1), y),
+                ];
+
+                for &(wall, nx, ny) in neighbors.iter() {
+                    if wall != Wall::Closed && distances[nx][ny] == 255 {
+                        distances[nx][ny] = distance + 1;
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        distance += 1;
+    }
+
+    distances
+}
+
+/// Like `flood_distances`, but moving into a cell costs one plus the `penalty` for that cell
+/// instead of just one. This can be used to make some cells less attractive to route through.
+/// Costs saturate at 255, the same as unreachable cells.
+pub fn flood_costs<F, const W: usize, const H: usize>(
+    maze: &Maze<W, H>,
+    goal: &Goal,
+    penalty: F,
+) -> [[u8; H]; W]
+where
+    F: Fn(MazePosition) -> u8,
+{
+    let mut costs = [[255u8; H]; W];
+
+    for (x, column) in costs.iter_mut().enumerate() {
+        for (y, cost) in column.iter_mut().enumerate() {
+            if goal.contains(MazePosition { x, y }) {
+                *cost = 0;
+            }
+        }
+    }
+
+    // Keep relaxing every cell until nothing gets cheaper. Slow, but the maze is small.
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for x in 0..W {
+            for y in 0..H {
+                let (north, south, east, west) = maze.get_cell(x, y);
+
+                let neighbors = [
+                    (north, x, y + 1),
+                    (south, x, y.wrapping_sub(1)),
+                    (east, x + 1, y),
+                    (west, x.wrapping_sub(1), y),
+                ];
+
+                for &(wall, nx, ny) in neighbors.iter() {
+                    if wall != Wall::Closed {
+                        let cost = costs[nx][ny]
+                            .saturating_add(1)
+                            .saturating_add(penalty(MazePosition { x: nx, y: ny }));
+
+                        if cost < costs[x][y] {
+                            costs[x][y] = cost;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    costs
+}
+
+#[cfg(test)]
+mod goal_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{flood_costs, flood_distances, Goal};
+    use crate::slow::maze::{Maze, Wall, WallDirection, WallIndex};
+    use crate::slow::MazePosition;
+
+    #[test]
+    fn single_cell() {
+        let goal = Goal::Cell(MazePosition { x: 3, y: 4 });
+
+        assert!(goal.contains(MazePosition { x: 3, y: 4 }));
+        assert!(!goal.contains(MazePosition { x: 4, y: 4 }));
+        assert_eq!(goal.cells().count(), 1);
+
+        let distances = flood_distances(&Maze::new(Wall::Unknown), &goal);
+        assert_eq!(distances[3][4], 0);
+        assert_eq!(distances[0][0], 7);
+        assert_eq!(distances[15][15], 23);
+    }
+
+    #[test]
+    fn square() {
+        let goal = Goal::Square(MazePosition { x: 7, y: 7 });
+
+        assert!(goal.contains(MazePosition { x: 7, y: 7 }));
+        assert!(goal.contains(MazePosition { x: 8, y: 8 }));
+        assert!(!goal.contains(MazePosition { x: 9, y: 8 }));
+        assert_eq!(goal.cells().count(), 4);
+
+        let distances = flood_distances(&Maze::new(Wall::Open), &goal);
+        assert_eq!(distances[7][7], 0);
+        assert_eq!(distances[8][8], 0);
+        assert_eq!(distances[0][0], 14);
+        assert_eq!(distances[15][15], 14);
+    }
+
+    #[test]
+    fn line() {
+        let goal = Goal::Line(MazePosition { x: 2, y: 5 }, MazePosition { x: 2, y: 2 });
+
+        assert!(goal.contains(MazePosition { x: 2, y: 3 }));
+        assert!(!goal.contains(MazePosition { x: 3, y: 3 }));
+        assert_eq!(goal.cells().count(), 4);
+    }
+
+    #[test]
+    fn cells() {
+        let goal = Goal::Cells([
+            Some(MazePosition { x: 0, y: 0 }),
+            Some(MazePosition { x: 4, y: 9 }),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        ]);
+
+        assert!(goal.contains(MazePosition { x: 0, y: 0 }));
+        assert!(goal.contains(MazePosition { x: 4, y: 9 }));
+        assert!(!goal.contains(MazePosition { x: 4, y: 0 }));
+        assert_eq!(goal.cells().count(), 2);
+        assert_eq!(
+            goal.bounds(),
+            (MazePosition { x: 0, y: 0 }, MazePosition { x: 4, y: 9 })
+        );
+    }
+
+    #[test]
+    fn closed_walls() {
+        let goal = Goal::Cell(MazePosition { x: 0, y: 0 });
+
+        let distances = flood_distances(&Maze::new(Wall::Closed), &goal);
+        assert_eq!(distances[0][0], 0);
+        assert_eq!(distances[0][1], 255);
+        assert_eq!(distances[1][0], 255);
+    }
+
+    #[test]
+    fn costs_avoid_penalty() {
+        let goal = Goal::Cell(MazePosition { x: 2, y: 0 });
+
+        let costs = flood_costs(&Maze::new(Wall::Open), &goal, |position| {
+            if position == (MazePosition { x: 1, y: 0 }) {
+                5
+            } else {
+                0
+            }
+        });
+
+        // Going around the penalized cell is cheaper than going through it
+        assert_eq!(costs[2][0], 0);
+        assert_eq!(costs[1][0], 1);
+        assert_eq!(costs[0][0], 4);
+    }
+
+    #[test]
+    fn small_maze() {
+        let goal = Goal::Square(MazePosition { x: 3, y: 3 });
+
+        let distances = flood_distances(&Maze::<8, 8>::filled(Wall::Open), &goal);
+        assert_eq!(distances.len(), 8);
+        assert_eq!(distances[0].len(), 8);
+        assert_eq!(distances[3][3], 0);
+        assert_eq!(distances[4][4], 0);
+        assert_eq!(distances[0][0], 6);
+        assert_eq!(distances[7][7], 6);
+    }
+
+    #[test]
+    fn small_maze_walls() {
+        let goal = Goal::Cell(MazePosition { x: 7, y: 0 });
+
+        // A wall all the way across the bottom row, except for the far west end
+        let mut maze = Maze::<8, 8>::filled(Wall::Open);
+        for x in 1..8 {
+            maze.set_wall(
+                WallIndex {
+                    x,
+                    y: 1,
+                    direction: WallDirection::Horizontal,
+                },
+                Wall::Closed,
+            )
+            .unwrap();
+        }
+
+        let distances = flood_distances(&maze, &goal);
+        assert_eq!(distances[0][0], 7);
+        assert_eq!(distances[0][1], 8);
+        assert_eq!(distances[7][1], 15);
+
+        // The outside of the maze is the perimeter
+        assert_eq!(
+            maze.set_wall(
+                WallIndex {
+                    x: 2,
+                    y: 8,
+                    direction: WallDirection::Horizontal,
+                },
+                Wall::Open,
+            ),
+            Err(())
+        );
+        assert_eq!(
+            maze.get_cell(7, 7),
+            (Wall::Closed, Wall::Open, Wall::Closed, Wall::Open)
+        );
+    }
+}
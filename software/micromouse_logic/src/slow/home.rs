@@ -0,0 +1,103 @@
+//! Keeps track of a known-good way back to the start
+
+use heapless::Vec;
+use typenum::U256;
+
+use super::{MazeDirection, MazePosition};
+
+/// Enough to hold every cell in the maze once
+pub type HomePathSize = U256;
+
+/// The cells the mouse has actually driven through to get from the start to where it is now.
+///
+/// Whenever the mouse comes back to a cell it has already been in, everything after that cell is
+/// cut out, so this is always a path with no loops that is known to be driveable. Retracing it in
+/// reverse is a guaranteed way home, even when the map is not fully known.
+pub struct HomePath {
+    cells: Vec<MazePosition, HomePathSize>,
+}
+
+impl HomePath {
+    pub fn new(start: MazePosition) -> HomePath {
+        let mut cells = Vec::new();
+        cells.push(start).ok();
+        HomePath { cells }
+    }
+
+    /// Record that the mouse is in a cell
+    pub fn visit(&mut self, position: MazePosition) {
+        if let Some(index) = self.cells.iter().position(|&cell| cell == position) {
+            while self.cells.len() > index + 1 {
+                self.cells.pop();
+            }
+        } else {
+            // There can be no more cells than the maze has without a loop
+            self.cells.push(position).ok();
+        }
+    }
+
+    /// The directions to move in to get from the last visited cell back to the start
+    pub fn retrace(&self) -> impl Iterator<Item = MazeDirection> + '_ {
+        self.cells
+            .iter()
+            .rev()
+            .zip(self.cells.iter().rev().skip(1))
+            .map(|(from, to)| {
+                if to.x > from.x {
+                    MazeDirection::East
+                } else if to.x < from.x {
+                    MazeDirection::West
+                } else if to.y > from.y {
+                    MazeDirection::North
+                } else {
+                    MazeDirection::South
+                }
+            })
+    }
+
+    pub fn debug(&self) -> Vec<MazeDirection, HomePathSize> {
+        self.retrace().collect()
+    }
+}
+
+#[cfg(test)]
+mod home_path_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use pretty_assertions::assert_eq;
+
+    use super::HomePath;
+    use crate::slow::{MazeDirection, MazePosition};
+
+    #[test]
+    fn retrace_exploration() {
+        let mut home_path = HomePath::new(MazePosition { x: 0, y: 0 });
+
+        home_path.visit(MazePosition { x: 0, y: 1 });
+        home_path.visit(MazePosition { x: 1, y: 1 });
+
+        // Dead end, back out of it
+        home_path.visit(MazePosition { x: 1, y: 2 });
+        home_path.visit(MazePosition { x: 1, y: 1 });
+
+        home_path.visit(MazePosition { x: 2, y: 1 });
+
+        assert_eq!(
+            &home_path.debug()[..],
+            &[
+                MazeDirection::West,
+                MazeDirection::West,
+                MazeDirection::South
+            ]
+        );
+    }
+
+    #[test]
+    fn at_start() {
+        let mut home_path = HomePath::new(MazePosition { x: 0, y: 0 });
+        home_path.visit(MazePosition { x: 0, y: 0 });
+
+        assert_eq!(home_path.retrace().next(), None);
+    }
+}
@@ -0,0 +1,150 @@
+//! Keeps track of where in the maze the mouse has been able to localize well
+
+use super::maze::{HEIGHT, WIDTH};
+use super::MazePosition;
+
+/// How many times the mouse has been in each cell, and how many of those times the sensors
+/// corrected the position while it was there. Cells that get corrected on most visits are good
+/// places to route a fast run through to keep it localized.
+pub struct LocalizeQuality {
+    visits: [[u8; HEIGHT]; WIDTH],
+    corrected: [[u8; HEIGHT]; WIDTH],
+
+    /// The cell the mouse is in now, and whether it has been corrected in yet on this visit
+    current: Option<(MazePosition, bool)>,
+}
+
+impl Default for LocalizeQuality {
+    fn default() -> LocalizeQuality {
+        LocalizeQuality::new()
+    }
+}
+
+impl LocalizeQuality {
+    pub fn new() -> LocalizeQuality {
+        LocalizeQuality {
+            visits: [[0; HEIGHT]; WIDTH],
+            corrected: [[0; HEIGHT]; WIDTH],
+            current: None,
+        }
+    }
+
+    /// Record whether the sensors corrected the position this update. A visit only counts once
+    /// no matter how many updates it takes, so it starts over when the mouse changes cells.
+    pub fn record(&mut self, position: MazePosition, corrected: bool) {
+        if position.x >= WIDTH || position.y >= HEIGHT {
+            self.current = None;
+            return;
+        }
+
+        let corrected_before = match self.current {
+            Some((current, corrected_before)) if current == position => corrected_before,
+            _ => {
+                let visits = &mut self.visits[position.x][position.y];
+                *visits = visits.saturating_add(1);
+                false
+            }
+        };
+
+        if corrected && !corrected_before {
+            let count = &mut self.corrected[position.x][position.y];
+            *count = count.saturating_add(1);
+        }
+
+        self.current = Some((position, corrected_before || corrected));
+    }
+
+    /// How much of the time the position got corrected in a cell, from 0 for never, or never
+    /// visited, to 255 for every visit
+    pub fn score(&self, position: MazePosition) -> u8 {
+        if position.x >= WIDTH || position.y >= HEIGHT {
+            return 0;
+        }
+
+        let visits = self.visits[position.x][position.y] as u16;
+        let corrected = self.corrected[position.x][position.y] as u16;
+
+        (corrected.min(visits) * 255)
+            .checked_div(visits)
+            .unwrap_or(0) as u8
+    }
+
+    /// How much extra it should cost to route through a cell. Cells that have never been
+    /// corrected in cost the full `bias`, and it goes down to nothing for cells that are
+    /// corrected in on every visit.
+    pub fn penalty(&self, position: MazePosition, bias: u8) -> u8 {
+        let missing = 255 - self.score(position) as u16;
+        (missing * bias as u16 / 255) as u8
+    }
+}
+
+#[cfg(test)]
+mod localize_quality_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use pretty_assertions::assert_eq;
+
+    use super::LocalizeQuality;
+    use crate::slow::MazePosition;
+
+    #[test]
+    fn once_per_visit() {
+        let mut quality = LocalizeQuality::new();
+        let position = MazePosition { x: 1, y: 2 };
+        let other = MazePosition { x: 2, y: 2 };
+
+        // Lots of updates in one visit only count once
+        for _ in 0..300 {
+            quality.record(position, true);
+        }
+        assert_eq!(quality.score(position), 255);
+
+        // Then a visit with no corrections
+        quality.record(other, true);
+        quality.record(position, false);
+        quality.record(position, false);
+        assert_eq!(quality.score(position), 127);
+
+        assert_eq!(quality.score(other), 255);
+        assert_eq!(quality.score(MazePosition { x: 2, y: 1 }), 0);
+    }
+
+    #[test]
+    fn only_counts_corrections() {
+        let mut quality = LocalizeQuality::new();
+        let position = MazePosition { x: 1, y: 2 };
+
+        quality.record(position, false);
+        quality.record(position, false);
+
+        assert_eq!(quality.score(position), 0);
+    }
+
+    #[test]
+    fn outside_maze() {
+        let mut quality = LocalizeQuality::new();
+        let outside = MazePosition { x: 16, y: 3 };
+
+        quality.record(outside, true);
+
+        assert_eq!(quality.score(outside), 0);
+        assert_eq!(quality.penalty(outside, 10), 10);
+    }
+
+    #[test]
+    fn penalty() {
+        let mut quality = LocalizeQuality::new();
+        let good = MazePosition { x: 1, y: 2 };
+        let half = MazePosition { x: 1, y: 3 };
+
+        quality.record(good, true);
+        quality.record(half, true);
+        quality.record(good, true);
+        quality.record(half, false);
+
+        assert_eq!(quality.penalty(good, 10), 0);
+        assert_eq!(quality.penalty(half, 10), 5);
+        assert_eq!(quality.penalty(MazePosition { x: 0, y: 0 }, 10), 10);
+    }
+}
@@ -1,21 +1,99 @@
+use core::f32::consts::FRAC_PI_8;
+
 use serde::Deserialize;
 use serde::Serialize;
 
 use crate::config::MechanicalConfig;
+use crate::fast::Orientation;
 use crate::mouse::DistanceReading;
-use crate::slow::maze::{Maze, MazeConfig, Wall, WallIndex};
-use crate::slow::MazeOrientation;
+use crate::slow::maze::{
+    Maze, MazeConfig, MazeIndex, Wall, WallDirection, WallIndex, HEIGHT, WIDTH,
+};
+use crate::slow::{MazeDirection, MazeOrientation};
 
 #[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct MapConfig {
     pub front_threhold: f32,
     pub left_threshold: f32,
     pub right_threshold: f32,
+
+    /// How many more closed readings than open ones (or the other way around) a wall needs
+    /// before it is marked as closed (or open)
+    pub confidence_threshold: i8,
+
+    /// How close a side reading needs to be to where the closest wall should be to count as
+    /// seeing it when going diagonally, in mm
+    pub diagonal_tolerance: f32,
 }
 
 #[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct MapDebug {
     pub maze: Maze,
+    pub confidence: WallConfidence,
+}
+
+/// How sure the map is about each wall. Every reading that sees a wall adds one, and every reading
+/// that sees it open takes one away. The indexes line up with the walls in a `Maze`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WallConfidence {
+    horizontal_walls: [[i8; HEIGHT - 1]; WIDTH],
+    vertical_walls: [[i8; HEIGHT]; WIDTH - 1],
+}
+
+impl WallConfidence {
+    pub fn new() -> WallConfidence {
+        WallConfidence::default()
+    }
+
+    /// The confidence for a wall, or None for the outside of the maze
+    pub fn get(&self, index: WallIndex) -> Option<i8> {
+        match index.direction {
+            WallDirection::Horizontal if index.y > 0 => self
+                .horizontal_walls
+                .get(index.x)
+                .and_then(|walls| walls.get(index.y - 1)),
+            WallDirection::Vertical if index.x > 0 => self
+                .vertical_walls
+                .get(index.x - 1)
+                .and_then(|walls| walls.get(index.y)),
+            _ => None,
+        }
+        .copied()
+    }
+
+    fn get_mut(&mut self, index: WallIndex) -> Option<&mut i8> {
+        match index.direction {
+            WallDirection::Horizontal if index.y > 0 => self
+                .horizontal_walls
+                .get_mut(index.x)
+                .and_then(|walls| walls.get_mut(index.y - 1)),
+            WallDirection::Vertical if index.x > 0 => self
+                .vertical_walls
+                .get_mut(index.x - 1)
+                .and_then(|walls| walls.get_mut(index.y)),
+            _ => None,
+        }
+    }
+
+    /// Set the confidence for a wall. Does nothing for the outside of the maze.
+    pub fn set(&mut self, index: WallIndex, confidence: i8) {
+        if let Some(wall) = self.get_mut(index) {
+            *wall = confidence;
+        }
+    }
+
+    /// Add a reading of a wall, and get the new confidence for it
+    pub fn observe(&mut self, index: WallIndex, closed: bool) -> Option<i8> {
+        let confidence = self.get_mut(index)?;
+
+        *confidence = if closed {
+            confidence.saturating_add(1)
+        } else {
+            confidence.saturating_sub(1)
+        };
+
+        Some(*confidence)
+    }
 }
 
 #[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -25,37 +103,156 @@ pub struct MoveOptions {
     pub right: bool,
 }
 
+impl MoveOptions {
+    /// Whether the mouse can go `direction` when it is facing `facing`. Going back the way it
+    /// came is always open.
+    pub fn allows(&self, facing: MazeDirection, direction: MazeDirection) -> bool {
+        if direction == facing {
+            self.front
+        } else if direction == facing.left() {
+            self.left
+        } else if direction == facing.right() {
+            self.right
+        } else {
+            true
+        }
+    }
+}
+
+/// The wall a side sensor at `sensor` is looking at, if `reading` says it is there. Only the
+/// first wall the sensor could see counts, and posts are skipped since they are always there.
+fn side_wall(
+    maze: &MazeConfig,
+    config: &MapConfig,
+    sensor: Orientation,
+    reading: DistanceReading,
+) -> Option<WallIndex> {
+    let distance = match reading {
+        DistanceReading::InRange(distance) => distance,
+        DistanceReading::OutOfRange => return None,
+    };
+
+    let projection = maze.wall_projection(sensor).next()?;
+
+    match projection.maze_index {
+        MazeIndex::Wall(index)
+            if (projection.distance - distance).abs() < config.diagonal_tolerance =>
+        {
+            Some(index)
+        }
+        _ => None,
+    }
+}
+
 /// Figures out what the maze is. For now, it will just tell you what of the three walls around are
 /// open. Eventually, it will keep track of the entire maze.
 pub struct Map {
     maze: Maze,
+    confidence: WallConfidence,
     left_distance: Option<DistanceReading>,
     right_distance: Option<DistanceReading>,
     front_distance: Option<DistanceReading>,
+
+    /// Once frozen, readings no longer change any walls
+    frozen: bool,
 }
 
 impl Map {
     pub fn new() -> Map {
         Map {
             maze: Maze::new(Wall::Unknown),
+            confidence: WallConfidence::new(),
             left_distance: None,
             right_distance: None,
             front_distance: None,
+            frozen: false,
+        }
+    }
+
+    /// Stop learning walls, so the map stays as it is now. The move options are still found from
+    /// the readings.
+    pub fn freeze(&mut self) {
+        self.frozen = true;
+    }
+
+    /// The walls that have been mapped so far
+    pub fn maze(&self) -> &Maze {
+        &self.maze
+    }
+
+    /// Start from the walls in `maze`, like ones found in an earlier run. The known walls are
+    /// trusted completely, and the unknown ones are left to be found.
+    pub fn import(&mut self, maze: &Maze) {
+        let horizontal = (0..WIDTH).flat_map(|x| {
+            (1..HEIGHT).map(move |y| WallIndex {
+                x,
+                y,
+                direction: WallDirection::Horizontal,
+            })
+        });
+
+        let vertical = (1..WIDTH).flat_map(|x| {
+            (0..HEIGHT).map(move |y| WallIndex {
+                x,
+                y,
+                direction: WallDirection::Vertical,
+            })
+        });
+
+        for index in horizontal.chain(vertical) {
+            let wall = maze.get_wall(index).copied().unwrap_or(Wall::Unknown);
+
+            let confidence = match wall {
+                Wall::Closed => i8::MAX,
+                Wall::Open => i8::MIN,
+                Wall::Unknown => 0,
+            };
+
+            self.confidence.set(index, confidence);
+            self.maze.set_wall(index, wall).ok();
+        }
+    }
+
+    /// All of the walls that have been found so far
+    pub fn export(&self) -> Maze {
+        self.maze
+    }
+
+    /// Add a reading of a wall. It only gets marked as open or closed once there have been
+    /// enough readings one way, so a single bad reading does not change it.
+    fn observe(&mut self, config: &MapConfig, index: WallIndex, closed: bool) {
+        if self.frozen {
+            return;
+        }
+
+        if let Some(confidence) = self.confidence.observe(index, closed) {
+            let wall = if confidence >= config.confidence_threshold {
+                Wall::Closed
+            } else if confidence <= -config.confidence_threshold {
+                Wall::Open
+            } else {
+                Wall::Unknown
+            };
+
+            self.maze.set_wall(index, wall).ok();
         }
     }
 
     pub fn update(
         &mut self,
-        _mech: &MechanicalConfig,
-        _maze: &MazeConfig,
+        mech: &MechanicalConfig,
+        maze: &MazeConfig,
         config: &MapConfig,
-        maze_orientation: MazeOrientation,
+        orientation: Orientation,
         left_distance: Option<DistanceReading>,
         front_distance: Option<DistanceReading>,
         right_distance: Option<DistanceReading>,
     ) -> (Option<MoveOptions>, MapDebug) {
+        let maze_orientation = orientation.to_maze_orientation(maze);
+
         let debug = MapDebug {
-            maze: self.maze.clone(),
+            maze: self.maze,
+            confidence: self.confidence,
         };
 
         if left_distance != None {
@@ -84,35 +281,40 @@ impl Map {
                     right: right_distance >= config.right_threshold,
                 };
 
-                let front_index = WallIndex::from_maze_orientation(maze_orientation);
-                let front_wall = if options.front {
-                    Wall::Open
-                } else {
-                    Wall::Closed
-                };
-                self.maze.set_wall(front_index, front_wall);
-
-                let left_index = WallIndex::from_maze_orientation(MazeOrientation {
-                    direction: maze_orientation.direction.left(),
-                    ..maze_orientation
-                });
-                let left_wall = if options.left {
-                    Wall::Open
-                } else {
-                    Wall::Closed
-                };
-                self.maze.set_wall(left_index, left_wall);
-
-                let right_index = WallIndex::from_maze_orientation(MazeOrientation {
-                    direction: maze_orientation.direction.right(),
-                    ..maze_orientation
-                });
-                let right_wall = if options.right {
-                    Wall::Open
+                // Going diagonally towards a post, the side sensors look ahead at the walls on
+                // either side of it instead of the walls of this cell. Only trust them if both
+                // see a wall where it should be.
+                let diagonal = !orientation
+                    .direction
+                    .within(maze_orientation.direction.into_direction(), FRAC_PI_8);
+
+                if diagonal {
+                    let left_sensor = orientation.offset(mech.left_sensor());
+                    let right_sensor = orientation.offset(mech.right_sensor());
+
+                    if let (Some(left_index), Some(right_index)) = (
+                        side_wall(maze, config, left_sensor, left_distance),
+                        side_wall(maze, config, right_sensor, right_distance),
+                    ) {
+                        self.observe(config, left_index, true);
+                        self.observe(config, right_index, true);
+                    }
                 } else {
-                    Wall::Closed
-                };
-                self.maze.set_wall(right_index, right_wall);
+                    let front_index = WallIndex::from_maze_orientation(maze_orientation);
+                    self.observe(config, front_index, !options.front);
+
+                    let left_index = WallIndex::from_maze_orientation(MazeOrientation {
+                        direction: maze_orientation.direction.left(),
+                        ..maze_orientation
+                    });
+                    self.observe(config, left_index, !options.left);
+
+                    let right_index = WallIndex::from_maze_orientation(MazeOrientation {
+                        direction: maze_orientation.direction.right(),
+                        ..maze_orientation
+                    });
+                    self.observe(config, right_index, !options.right);
+                }
 
                 Some(options)
             } else {
@@ -122,3 +324,206 @@ impl Map {
         (move_options, debug)
     }
 }
+
+#[cfg(test)]
+mod map_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use super::{Map, MapConfig};
+    use crate::config::{MAP, MAZE};
+    use crate::fast::{Direction, Orientation, Vector, DIRECTION_PI_2};
+    use crate::mouse::DistanceReading;
+    use crate::slow::maze::{Maze, Wall, WallDirection, WallIndex};
+
+    const MECH: crate::config::MechanicalConfig = crate::config::mouse_2019::MECH;
+
+    /// In the middle of cell (1, 1) facing north
+    const ORIENTATION: Orientation = Orientation {
+        position: Vector { x: 270.0, y: 270.0 },
+        direction: DIRECTION_PI_2,
+    };
+
+    /// Needs a few readings to be sure of a wall, so the confidence has to add up
+    const CAREFUL: MapConfig = MapConfig {
+        confidence_threshold: 3,
+        ..MAP
+    };
+
+    fn front_index() -> WallIndex {
+        WallIndex::from_maze_orientation(ORIENTATION.to_maze_orientation(&MAZE))
+    }
+
+    /// Read the sensors once in cell (1, 1) facing north
+    fn read(map: &mut Map, config: &MapConfig, front: f32) {
+        map.update(
+            &MECH,
+            &MAZE,
+            config,
+            ORIENTATION,
+            Some(DistanceReading::InRange(200.0)),
+            Some(DistanceReading::InRange(front)),
+            Some(DistanceReading::InRange(200.0)),
+        );
+    }
+
+    fn front_wall(map: &Map) -> Wall {
+        *map.maze().get_wall(front_index()).unwrap()
+    }
+
+    #[test]
+    fn consistent_readings() {
+        let mut map = Map::new();
+
+        for _ in 0..CAREFUL.confidence_threshold - 1 {
+            read(&mut map, &CAREFUL, 50.0);
+            assert_eq!(front_wall(&map), Wall::Unknown);
+        }
+
+        read(&mut map, &CAREFUL, 50.0);
+        assert_eq!(front_wall(&map), Wall::Closed);
+    }
+
+    #[test]
+    fn frozen_ignores_readings() {
+        let mut map = Map::new();
+        map.freeze();
+
+        for _ in 0..10 {
+            read(&mut map, &MAP, 50.0);
+        }
+        assert_eq!(front_wall(&map), Wall::Unknown);
+    }
+
+    #[test]
+    fn spurious_reading() {
+        let mut map = Map::new();
+
+        for _ in 0..10 {
+            read(&mut map, &CAREFUL, 300.0);
+        }
+        assert_eq!(front_wall(&map), Wall::Open);
+
+        // One bad reading does not close it
+        read(&mut map, &CAREFUL, 50.0);
+        assert_eq!(front_wall(&map), Wall::Open);
+
+        let (_, debug) =
+            map.update(&MECH, &MAZE, &CAREFUL, ORIENTATION, None, None, None);
+        assert_eq!(debug.confidence.get(front_index()), Some(-9));
+    }
+
+    #[test]
+    fn threshold_of_one() {
+        let config = MapConfig {
+            confidence_threshold: 1,
+            ..MAP
+        };
+        let mut map = Map::new();
+
+        read(&mut map, &config, 50.0);
+        assert_eq!(front_wall(&map), Wall::Closed);
+    }
+
+    #[test]
+    fn export_import() {
+        let mut maze = Maze::new(Wall::Unknown);
+        maze.set_wall(
+            WallIndex {
+                x: 3,
+                y: 4,
+                direction: WallDirection::Horizontal,
+            },
+            Wall::Closed,
+        )
+        .unwrap();
+        maze.set_wall(
+            WallIndex {
+                x: 5,
+                y: 2,
+                direction: WallDirection::Vertical,
+            },
+            Wall::Open,
+        )
+        .unwrap();
+
+        let mut map = Map::new();
+        map.import(&maze);
+        let exported = map.export();
+        assert_eq!(exported, maze);
+
+        let mut resumed = Map::new();
+        resumed.import(&exported);
+        assert_eq!(resumed.maze(), &maze);
+
+        // The imported walls are not changed by a few readings, but unknown ones still are
+        for _ in 0..CAREFUL.confidence_threshold {
+            read(&mut resumed, &CAREFUL, 300.0);
+        }
+        assert_eq!(front_wall(&resumed), Wall::Open);
+        assert_eq!(
+            resumed.maze().get_wall(WallIndex {
+                x: 3,
+                y: 4,
+                direction: WallDirection::Horizontal,
+            }),
+            Some(&Wall::Closed)
+        );
+    }
+
+    /// Read the sensors once in the middle of cell (1, 1) facing north east, towards the post
+    /// at its north east corner
+    fn read_diagonal(map: &mut Map, left: DistanceReading, right: DistanceReading) {
+        map.update(
+            &MECH,
+            &MAZE,
+            &MAP,
+            Orientation {
+                position: Vector { x: 270.0, y: 270.0 },
+                direction: Direction::from(core::f32::consts::FRAC_PI_4),
+            },
+            Some(left),
+            Some(DistanceReading::OutOfRange),
+            Some(right),
+        );
+    }
+
+    #[test]
+    fn diagonal_walls() {
+        // The walls on either side of the post
+        let north = WallIndex {
+            x: 1,
+            y: 2,
+            direction: WallDirection::Horizontal,
+        };
+        let east = WallIndex {
+            x: 2,
+            y: 1,
+            direction: WallDirection::Vertical,
+        };
+
+        // The side sensors are about 60mm from those walls going diagonally
+        let mut map = Map::new();
+        for _ in 0..MAP.confidence_threshold {
+            read_diagonal(
+                &mut map,
+                DistanceReading::InRange(61.0),
+                DistanceReading::InRange(61.0),
+            );
+        }
+        assert_eq!(map.maze().get_wall(north), Some(&Wall::Closed));
+        assert_eq!(map.maze().get_wall(east), Some(&Wall::Closed));
+
+        // Both sides need to see a wall
+        let mut map = Map::new();
+        for _ in 0..MAP.confidence_threshold {
+            read_diagonal(
+                &mut map,
+                DistanceReading::InRange(61.0),
+                DistanceReading::OutOfRange,
+            );
+        }
+        assert_eq!(map.maze().get_wall(north), Some(&Wall::Unknown));
+        assert_eq!(map.maze().get_wall(east), Some(&Wall::Unknown));
+    }
+}
@@ -1,16 +1,26 @@
-use serde::Deserialize;
-use serde::Serialize;
+use core::fmt;
+
+use serde::de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeStruct, SerializeTuple};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use libm::F32Ext;
 
 use itertools::Itertools;
 
+use heapless::Vec;
+use typenum::U4;
+
 use crate::fast::{Orientation, Vector};
-use crate::slow::{MazeDirection, MazeOrientation};
+use crate::slow::{MazeDirection, MazeOrientation, MazePosition};
 
 pub const WIDTH: usize = 16;
 pub const HEIGHT: usize = 16;
 
+/// How much later than the wall a ray can hit a post and still count as hitting the post, in mm.
+/// Rays right at the corner of a post hit both faces at the same time, give or take rounding.
+const POST_EPSILON: f32 = 0.01;
+
 #[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
 pub struct MazeConfig {
     pub cell_width: f32,
@@ -22,6 +32,74 @@ impl MazeConfig {
         self.cell_width / 2.0 - self.wall_width / 2.0
     }
 
+    /// The cell that a position is in. Positions off the south or west edge of the maze are
+    /// counted as being in the first row or column.
+    pub fn cell_index(&self, position: Vector) -> MazePosition {
+        MazePosition {
+            x: (position.x / self.cell_width) as usize,
+            y: (position.y / self.cell_width) as usize,
+        }
+    }
+
+    /// The position of the center of a cell
+    pub fn cell_center(&self, position: MazePosition) -> Vector {
+        Vector {
+            x: (position.x as f32 + 0.5) * self.cell_width,
+            y: (position.y as f32 + 0.5) * self.cell_width,
+        }
+    }
+
+    /// Where a ray from `from` hits the post at maze coordinates (`x`, `y`). The post is a square
+    /// `wall_width` wide centered on the corner of the cells. Gives the distance to the hit,
+    /// where it hit, and which side of the post it hit, or None if the ray misses it.
+    pub fn post_intersection(
+        &self,
+        from: Orientation,
+        x: usize,
+        y: usize,
+    ) -> Option<(f32, Vector, WallDirection)> {
+        let center = Vector {
+            x: x as f32 * self.cell_width,
+            y: y as f32 * self.cell_width,
+        };
+        let half = self.wall_width / 2.0;
+        let direction_v = from.direction.into_unit_vector();
+
+        // Find where the ray goes in and out of the post along each axis. It hits the post if it
+        // goes in on both before it goes out of either.
+        let slab = |position: f32, direction: f32, center: f32| {
+            if direction == 0.0 {
+                if (position - center).abs() <= half {
+                    Some((f32::NEG_INFINITY, f32::INFINITY))
+                } else {
+                    None
+                }
+            } else {
+                let t0 = (center - half - position) / direction;
+                let t1 = (center + half - position) / direction;
+                Some((t0.min(t1), t0.max(t1)))
+            }
+        };
+
+        let (enter_x, exit_x) = slab(from.position.x, direction_v.x, center.x)?;
+        let (enter_y, exit_y) = slab(from.position.y, direction_v.y, center.y)?;
+
+        let enter = enter_x.max(enter_y);
+        let exit = exit_x.min(exit_y);
+
+        if enter <= exit && enter >= 0.0 {
+            let side = if enter_x > enter_y {
+                WallDirection::Vertical
+            } else {
+                WallDirection::Horizontal
+            };
+
+            Some((enter, from.position + enter * direction_v, side))
+        } else {
+            None
+        }
+    }
+
     /// Projects the `from` orientation onto the nearest wall or post, and gives the index of it
     pub fn wall_projection(
         &self,
@@ -50,28 +128,34 @@ impl MazeConfig {
             let wall_y = t * direction_v.y + from.position.y;
             let wall_index_y = (wall_y / self.cell_width) as usize;
 
-            // Figure out if we are looking at a wall or a post
-            let local_y = wall_y % self.cell_width;
-            let maze_index = if local_y < self.wall_width / 2.0 {
-                MazeIndex::Post(wall_index_x, wall_index_y)
-            } else if self.cell_width - local_y < self.wall_width / 2.0 {
-                MazeIndex::Post(wall_index_x, wall_index_y + 1)
+            // Figure out if we are looking at a wall or the post closest to it. If the ray only
+            // gets to the post after going through the wall, it is the wall.
+            let post_y = F32Ext::round(wall_y / self.cell_width) as usize;
+
+            if let Some((distance, hit_point, direction)) = self
+                .post_intersection(from, wall_index_x, post_y)
+                .filter(|&(distance, _, _)| distance <= t + POST_EPSILON)
+            {
+                MazeProjectionResult {
+                    maze_index: MazeIndex::Post(wall_index_x, post_y),
+                    hit_point,
+                    distance,
+                    direction,
+                }
             } else {
-                MazeIndex::Wall(WallIndex {
-                    x: wall_index_x,
-                    y: wall_index_y,
+                MazeProjectionResult {
+                    maze_index: MazeIndex::Wall(WallIndex {
+                        x: wall_index_x,
+                        y: wall_index_y,
+                        direction: WallDirection::Vertical,
+                    }),
+                    hit_point: Vector {
+                        x: wall_x,
+                        y: wall_y,
+                    },
+                    distance: t,
                     direction: WallDirection::Vertical,
-                })
-            };
-
-            MazeProjectionResult {
-                maze_index,
-                hit_point: Vector {
-                    x: wall_x,
-                    y: wall_y,
-                },
-                distance: t,
-                direction: WallDirection::Vertical,
+                }
             }
         });
 
@@ -92,28 +176,33 @@ impl MazeConfig {
             let wall_x = t * direction_v.x + from.position.x;
             let wall_index_x = (wall_x / self.cell_width) as usize;
 
-            // Figure out if we are looking at a wall or a post
-            let local_x = wall_x % self.cell_width;
-            let maze_index = if local_x < self.wall_width / 2.0 {
-                MazeIndex::Post(wall_index_x, wall_index_y)
-            } else if self.cell_width - local_x < self.wall_width / 2.0 {
-                MazeIndex::Post(wall_index_x + 1, wall_index_y)
+            // Figure out if we are looking at a wall or the post closest to it
+            let post_x = F32Ext::round(wall_x / self.cell_width) as usize;
+
+            if let Some((distance, hit_point, direction)) = self
+                .post_intersection(from, post_x, wall_index_y)
+                .filter(|&(distance, _, _)| distance <= t + POST_EPSILON)
+            {
+                MazeProjectionResult {
+                    maze_index: MazeIndex::Post(post_x, wall_index_y),
+                    hit_point,
+                    distance,
+                    direction,
+                }
             } else {
-                MazeIndex::Wall(WallIndex {
-                    x: wall_index_x,
-                    y: wall_index_y,
+                MazeProjectionResult {
+                    maze_index: MazeIndex::Wall(WallIndex {
+                        x: wall_index_x,
+                        y: wall_index_y,
+                        direction: WallDirection::Horizontal,
+                    }),
+                    hit_point: Vector {
+                        x: wall_x,
+                        y: wall_y,
+                    },
+                    distance: t,
                     direction: WallDirection::Horizontal,
-                })
-            };
-
-            MazeProjectionResult {
-                maze_index,
-                hit_point: Vector {
-                    x: wall_x,
-                    y: wall_y,
-                },
-                distance: t,
-                direction: WallDirection::Horizontal,
+                }
             }
         });
 
@@ -122,12 +211,60 @@ impl MazeConfig {
     }
 }
 
+#[cfg(test)]
+mod maze_config_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::config::MAZE;
+    use crate::fast::Vector;
+    use crate::slow::MazePosition;
+
+    #[test]
+    fn center_to_wall() {
+        assert_close(MAZE.center_to_wall(), 84.0);
+    }
+
+    #[test]
+    fn cell_center() {
+        let center = MAZE.cell_center(MazePosition { x: 2, y: 5 });
+        assert_close2(center, Vector { x: 450.0, y: 990.0 });
+        assert_eq!(MAZE.cell_index(center), MazePosition { x: 2, y: 5 });
+    }
+
+    #[test]
+    fn cell_index_boundaries() {
+        assert_eq!(
+            MAZE.cell_index(Vector { x: 0.0, y: 0.0 }),
+            MazePosition { x: 0, y: 0 }
+        );
+
+        // The line between two cells belongs to the cell to the north east of it
+        assert_eq!(
+            MAZE.cell_index(Vector { x: 180.0, y: 360.0 }),
+            MazePosition { x: 1, y: 2 }
+        );
+        assert_eq!(
+            MAZE.cell_index(Vector { x: 179.9, y: 359.9 }),
+            MazePosition { x: 0, y: 1 }
+        );
+
+        // Off the edge is clamped to the edge
+        assert_eq!(
+            MAZE.cell_index(Vector { x: -10.0, y: 90.0 }),
+            MazePosition { x: 0, y: 0 }
+        );
+    }
+}
+
 #[cfg(test)]
 mod wall_projection_tests {
     #[allow(unused_imports)]
     use crate::test::*;
 
-    use core::f32::consts::{FRAC_PI_8, PI};
+    use core::f32::consts::{FRAC_PI_2, FRAC_PI_4, FRAC_PI_8, PI, SQRT_2};
 
     use crate::config::MAZE;
     use crate::fast::{Direction, Orientation, Vector};
@@ -234,6 +371,38 @@ mod wall_projection_tests {
         );
         assert_close(result.distance, 219.50258);
     }
+
+    #[test]
+    fn wall_projection_post() {
+        // From the middle of cell (1, 1) straight at the post in its north east corner
+        let mouse = Orientation {
+            position: Vector { x: 270.0, y: 270.0 },
+            direction: Direction::from(FRAC_PI_4),
+        };
+
+        let result = MAZE.wall_projection(mouse).next().unwrap();
+        assert_eq!(result.maze_index, MazeIndex::Post(2, 2));
+
+        // It hits the corner of the post closest to the mouse. Far enough from the origin that
+        // f32 rounding is more than assert_close allows.
+        let face = 2.0 * 180.0 - 12.0 / 2.0;
+        assert!(result.hit_point.distance_to(Vector { x: face, y: face }) < 0.001);
+        assert!((result.distance - (face - 270.0) * SQRT_2).abs() < 0.001);
+    }
+
+    #[test]
+    fn wall_projection_post_side() {
+        // Going north along a wall line, the first thing in the way is the south side of a post
+        let mouse = Orientation {
+            position: Vector { x: 362.0, y: 270.0 },
+            direction: Direction::from(FRAC_PI_2),
+        };
+
+        let result = MAZE.wall_projection(mouse).next().unwrap();
+        assert_eq!(result.maze_index, MazeIndex::Post(2, 2));
+        assert_eq!(result.direction, WallDirection::Horizontal);
+        assert_close(result.distance, 84.0);
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -405,29 +574,41 @@ mod wall_index_test {
     }
 }
 
-/// Keeps track of all the walls in a maze
-#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
-pub struct Maze {
-    horizontal_walls: [[Wall; HEIGHT - 1]; WIDTH],
-    vertical_walls: [[Wall; HEIGHT]; WIDTH - 1],
+/// Keeps track of all the walls in a maze that is `W` cells wide and `H` cells tall. The
+/// perimeter is always closed, so it is not stored.
+///
+/// The last row of `horizontal_walls` and the last column of `vertical_walls` would be the
+/// perimeter, so they are always closed and can not be set. They are only there because array
+/// lengths can not be `H - 1` with const generics yet. They are left off when serializing, so
+/// the format is the same as when the maze was always 16x16.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Maze<const W: usize = WIDTH, const H: usize = HEIGHT> {
+    horizontal_walls: [[Wall; H]; W],
+    vertical_walls: [[Wall; H]; W],
 }
 
 impl Maze {
     pub fn new(wall: Wall) -> Maze {
-        Maze {
-            horizontal_walls: [[wall; HEIGHT - 1]; WIDTH],
-            vertical_walls: [[wall; HEIGHT]; WIDTH - 1],
-        }
+        Maze::filled(wall)
     }
 
     pub fn from_walls(
         horizontal_walls: [[Wall; HEIGHT - 1]; WIDTH],
         vertical_walls: [[Wall; HEIGHT]; WIDTH - 1],
     ) -> Maze {
-        Maze {
-            horizontal_walls,
-            vertical_walls,
+        let mut maze = Maze::new(Wall::Unknown);
+
+        for (maze_walls, walls) in maze
+            .horizontal_walls
+            .iter_mut()
+            .zip(horizontal_walls.iter())
+        {
+            maze_walls[..HEIGHT - 1].copy_from_slice(walls);
         }
+
+        maze.vertical_walls[..WIDTH - 1].copy_from_slice(&vertical_walls);
+
+        maze
     }
 
     /**
@@ -435,8 +616,7 @@ impl Maze {
      *  http://www.micromouseonline.com/2018/01/31/micromouse-maze-file-collection/
      */
     pub fn from_file(bytes: [u8; WIDTH * HEIGHT]) -> Maze {
-        let mut horizontal_walls = [[Wall::Unknown; HEIGHT - 1]; WIDTH];
-        let mut vertical_walls = [[Wall::Unknown; HEIGHT]; WIDTH - 1];
+        let mut maze = Maze::new(Wall::Unknown);
 
         for (i, byte) in bytes.iter().enumerate() {
             let y = i % WIDTH;
@@ -454,22 +634,72 @@ impl Maze {
             };
 
             if y < HEIGHT - 1 {
-                horizontal_walls[x][y] = north;
+                maze.horizontal_walls[x][y] = north;
             }
 
             if x < WIDTH - 1 {
-                vertical_walls[x][y] = east;
+                maze.vertical_walls[x][y] = east;
+            }
+        }
+
+        maze
+    }
+
+    /// Write the maze in the same format that `from_file` reads. Unknown walls are written as
+    /// open.
+    pub fn to_file(&self) -> [u8; WIDTH * HEIGHT] {
+        let mut bytes = [0; WIDTH * HEIGHT];
+
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            let y = i % WIDTH;
+            let x = i / WIDTH;
+
+            let (north, south, east, west) = self.get_cell(x, y);
+
+            for &(wall, bit) in
+                [(north, 0x01), (east, 0x02), (south, 0x04), (west, 0x08)].iter()
+            {
+                if wall == Wall::Closed {
+                    *byte |= bit;
+                }
             }
         }
 
-        Maze {
-            horizontal_walls,
-            vertical_walls,
+        bytes
+    }
+}
+
+impl<const W: usize, const H: usize> Maze<W, H> {
+    /// A maze of any size with every wall except the perimeter set to `wall`. `Maze::new` is the
+    /// same thing for the usual 16x16 maze.
+    pub fn filled(wall: Wall) -> Maze<W, H> {
+        let mut maze = Maze {
+            horizontal_walls: [[wall; H]; W],
+            vertical_walls: [[wall; H]; W],
+        };
+
+        // Keep the unused walls the same in every maze so they compare equal
+        for walls in maze.horizontal_walls.iter_mut() {
+            walls[H - 1] = Wall::Closed;
         }
+
+        maze.vertical_walls[W - 1] = [Wall::Closed; H];
+
+        maze
+    }
+
+    /// How many cells wide the maze is
+    pub fn width(&self) -> usize {
+        W
+    }
+
+    /// How many cells tall the maze is
+    pub fn height(&self) -> usize {
+        H
     }
 
     pub fn get_cell(&self, x: usize, y: usize) -> (Wall, Wall, Wall, Wall) {
-        let north_wall = if y >= HEIGHT - 1 {
+        let north_wall = if y >= H - 1 {
             Wall::Closed
         } else {
             self.horizontal_walls[x][y]
@@ -481,7 +711,7 @@ impl Maze {
             self.horizontal_walls[x][y - 1]
         };
 
-        let east_wall = if x >= WIDTH - 1 {
+        let east_wall = if x >= W - 1 {
             Wall::Closed
         } else {
             self.vertical_walls[x][y]
@@ -496,41 +726,623 @@ impl Maze {
         (north_wall, south_wall, east_wall, west_wall)
     }
 
-    pub fn set_wall(&mut self, index: WallIndex, wall: Wall) {
+    /// The cells that can be driven to from `pos`, along with the direction to get to each of
+    /// them. `unknown_open` decides whether walls that have not been seen yet count as open.
+    pub fn neighbors(
+        &self,
+        pos: MazePosition,
+        unknown_open: bool,
+    ) -> Vec<(MazeDirection, MazePosition), U4> {
+        let (north, south, east, west) = self.get_cell(pos.x, pos.y);
+
+        // The perimeter is always closed, so none of these go outside of the maze
+        let sides = [
+            (
+                MazeDirection::North,
+                north,
+                MazePosition {
+                    x: pos.x,
+                    y: pos.y + 1,
+                },
+            ),
+            (
+                MazeDirection::South,
+                south,
+                MazePosition {
+                    x: pos.x,
+                    y: pos.y.wrapping_sub(1),
+                },
+            ),
+            (
+                MazeDirection::East,
+                east,
+                MazePosition {
+                    x: pos.x + 1,
+                    y: pos.y,
+                },
+            ),
+            (
+                MazeDirection::West,
+                west,
+                MazePosition {
+                    x: pos.x.wrapping_sub(1),
+                    y: pos.y,
+                },
+            ),
+        ];
+
+        let mut neighbors = Vec::new();
+
+        for &(direction, wall, position) in sides.iter() {
+            let open = match wall {
+                Wall::Open => true,
+                Wall::Closed => false,
+                Wall::Unknown => unknown_open,
+            };
+
+            if open {
+                neighbors.push((direction, position)).ok();
+            }
+        }
+
+        neighbors
+    }
+
+    /// Set a single wall. Returns `Err` if the index is outside of the maze or on the perimeter.
+    #[allow(clippy::result_unit_err)]
+    pub fn set_wall(&mut self, index: WallIndex, wall: Wall) -> Result<(), ()> {
+        if let Some(maze_wall) = self.get_wall_mut(index) {
+            *maze_wall = wall;
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    pub fn get_wall(&self, index: WallIndex) -> Option<&Wall> {
         match index.direction {
             WallDirection::Horizontal => {
-                if index.y > 0 && index.y < 15 {
-                    self.horizontal_walls[index.x][index.y - 1] = wall
+                if index.y == 0 || index.y >= H {
+                    None
+                } else {
+                    self.horizontal_walls
+                        .get(index.x)
+                        .and_then(|walls| walls.get(index.y - 1))
                 }
             }
             WallDirection::Vertical => {
-                if index.x > 0 && index.x < 15 {
-                    self.vertical_walls[index.x - 1][index.y] = wall
+                if index.x == 0 || index.x >= W {
+                    None
+                } else {
+                    self.vertical_walls
+                        .get(index.x - 1)
+                        .and_then(|walls| walls.get(index.y))
                 }
             }
         }
     }
 
-    pub fn get_wall(&self, index: WallIndex) -> Option<&Wall> {
+    pub fn get_wall_mut(&mut self, index: WallIndex) -> Option<&mut Wall> {
         match index.direction {
             WallDirection::Horizontal => {
-                if index.y == 0 {
+                if index.y == 0 || index.y >= H {
                     None
                 } else {
                     self.horizontal_walls
-                        .get(index.x)
-                        .and_then(|walls| walls.get(index.y - 1))
+                        .get_mut(index.x)
+                        .and_then(|walls| walls.get_mut(index.y - 1))
                 }
             }
             WallDirection::Vertical => {
-                if index.x == 0 {
+                if index.x == 0 || index.x >= W {
                     None
                 } else {
                     self.vertical_walls
-                        .get(index.x - 1)
-                        .and_then(|walls| walls.get(index.y))
+                        .get_mut(index.x - 1)
+                        .and_then(|walls| walls.get_mut(index.y))
                 }
             }
         }
     }
+
+    /// Every wall that has not been seen yet. The perimeter is always known, so it is never
+    /// included.
+    pub fn unknown_walls(&self) -> impl Iterator<Item = WallIndex> + '_ {
+        let horizontal = (0..W).flat_map(|x| {
+            (1..H).map(move |y| WallIndex {
+                x,
+                y,
+                direction: WallDirection::Horizontal,
+            })
+        });
+
+        let vertical = (1..W).flat_map(|x| {
+            (0..H).map(move |y| WallIndex {
+                x,
+                y,
+                direction: WallDirection::Vertical,
+            })
+        });
+
+        horizontal
+            .chain(vertical)
+            .filter(move |&index| self.get_wall(index) == Some(&Wall::Unknown))
+    }
+
+    /// This maze with every wall that has not been seen yet set to `wall`
+    pub fn fill_unknown(&self, wall: Wall) -> Maze<W, H> {
+        let mut maze = *self;
+
+        for index in self.unknown_walls() {
+            maze.set_wall(index, wall).ok();
+        }
+
+        maze
+    }
+
+    /// How many walls have not been seen yet
+    pub fn unknown_count(&self) -> usize {
+        self.unknown_walls().count()
+    }
+
+    /// Whether every wall in the maze has been seen
+    pub fn is_fully_known(&self) -> bool {
+        self.unknown_walls().next().is_none()
+    }
+}
+
+impl<const W: usize, const H: usize> Default for Maze<W, H> {
+    fn default() -> Maze<W, H> {
+        Maze::filled(Wall::default())
+    }
+}
+
+impl<const W: usize, const H: usize> Serialize for Maze<W, H> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Maze", 2)?;
+        state.serialize_field(
+            "horizontal_walls",
+            &WallGrid {
+                columns: &self.horizontal_walls,
+                rows: H - 1,
+            },
+        )?;
+        state.serialize_field(
+            "vertical_walls",
+            &WallGrid {
+                columns: &self.vertical_walls[..W - 1],
+                rows: H,
+            },
+        )?;
+        state.end()
+    }
+}
+
+impl<'de, const W: usize, const H: usize> Deserialize<'de> for Maze<W, H> {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Maze<W, H>, D::Error> {
+        deserializer.deserialize_struct(
+            "Maze",
+            &["horizontal_walls", "vertical_walls"],
+            MazeVisitor::<W, H>,
+        )
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "snake_case")]
+enum MazeField {
+    HorizontalWalls,
+    VerticalWalls,
+}
+
+struct MazeVisitor<const W: usize, const H: usize>;
+
+impl<'de, const W: usize, const H: usize> Visitor<'de> for MazeVisitor<W, H> {
+    type Value = Maze<W, H>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("struct Maze")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Maze<W, H>, A::Error> {
+        let mut maze = Maze::<W, H>::default();
+
+        let horizontal = WallGridSeed {
+            columns: &mut maze.horizontal_walls,
+            rows: H - 1,
+        };
+        seq.next_element_seed(horizontal)?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+
+        let vertical = WallGridSeed {
+            columns: &mut maze.vertical_walls[..W - 1],
+            rows: H,
+        };
+        seq.next_element_seed(vertical)?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+
+        Ok(maze)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Maze<W, H>, A::Error> {
+        let mut maze = Maze::<W, H>::default();
+        let mut horizontal = false;
+        let mut vertical = false;
+
+        while let Some(field) = map.next_key()? {
+            match field {
+                MazeField::HorizontalWalls => {
+                    map.next_value_seed(WallGridSeed {
+                        columns: &mut maze.horizontal_walls,
+                        rows: H - 1,
+                    })?;
+                    horizontal = true;
+                }
+                MazeField::VerticalWalls => {
+                    map.next_value_seed(WallGridSeed {
+                        columns: &mut maze.vertical_walls[..W - 1],
+                        rows: H,
+                    })?;
+                    vertical = true;
+                }
+            }
+        }
+
+        if !horizontal {
+            Err(de::Error::missing_field("horizontal_walls"))
+        } else if !vertical {
+            Err(de::Error::missing_field("vertical_walls"))
+        } else {
+            Ok(maze)
+        }
+    }
+}
+
+/// The first `rows` walls of each column, serialized the same way as a nested array would be
+struct WallGrid<'a, const H: usize> {
+    columns: &'a [[Wall; H]],
+    rows: usize,
+}
+
+impl<'a, const H: usize> Serialize for WallGrid<'a, H> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut columns = serializer.serialize_tuple(self.columns.len())?;
+
+        for column in self.columns {
+            columns.serialize_element(&WallColumn(&column[..self.rows]))?;
+        }
+
+        columns.end()
+    }
+}
+
+struct WallColumn<'a>(&'a [Wall]);
+
+impl<'a> Serialize for WallColumn<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut walls = serializer.serialize_tuple(self.0.len())?;
+
+        for wall in self.0 {
+            walls.serialize_element(wall)?;
+        }
+
+        walls.end()
+    }
+}
+
+/// Reads a `WallGrid` back into the first `rows` walls of each column
+struct WallGridSeed<'a, const H: usize> {
+    columns: &'a mut [[Wall; H]],
+    rows: usize,
+}
+
+impl<'a, 'de, const H: usize> DeserializeSeed<'de> for WallGridSeed<'a, H> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        let len = self.columns.len();
+        deserializer.deserialize_tuple(len, self)
+    }
+}
+
+impl<'a, 'de, const H: usize> Visitor<'de> for WallGridSeed<'a, H> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "{} columns of {} walls",
+            self.columns.len(),
+            self.rows
+        )
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let rows = self.rows;
+
+        for (i, column) in self.columns.iter_mut().enumerate() {
+            seq.next_element_seed(WallColumnSeed(&mut column[..rows]))?
+                .ok_or_else(|| de::Error::invalid_length(i, &"a column of walls"))?;
+        }
+
+        Ok(())
+    }
+}
+
+struct WallColumnSeed<'a>(&'a mut [Wall]);
+
+impl<'a, 'de> DeserializeSeed<'de> for WallColumnSeed<'a> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        let len = self.0.len();
+        deserializer.deserialize_tuple(len, self)
+    }
+}
+
+impl<'a, 'de> Visitor<'de> for WallColumnSeed<'a> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{} walls", self.0.len())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        for (i, wall) in self.0.iter_mut().enumerate() {
+            *wall = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(i, &"a wall"))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod maze_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{Maze, Wall, WallDirection, WallIndex};
+    use crate::slow::{MazeDirection, MazePosition};
+
+    /// A 4x4 maze with a wall east of (1, 1), an unknown wall north of it, and the rest open
+    fn neighbors_maze() -> Maze<4, 4> {
+        let mut maze = Maze::<4, 4>::filled(Wall::Open);
+
+        maze.set_wall(
+            WallIndex {
+                x: 2,
+                y: 1,
+                direction: WallDirection::Vertical,
+            },
+            Wall::Closed,
+        )
+        .unwrap();
+
+        maze.set_wall(
+            WallIndex {
+                x: 1,
+                y: 2,
+                direction: WallDirection::Horizontal,
+            },
+            Wall::Unknown,
+        )
+        .unwrap();
+
+        maze
+    }
+
+    #[test]
+    fn neighbors_interior() {
+        let maze = neighbors_maze();
+        let pos = MazePosition { x: 1, y: 1 };
+
+        assert!(maze.neighbors(pos, false).iter().eq([
+            (MazeDirection::South, MazePosition { x: 1, y: 0 }),
+            (MazeDirection::West, MazePosition { x: 0, y: 1 }),
+        ]
+        .iter()));
+
+        assert!(maze.neighbors(pos, true).iter().eq([
+            (MazeDirection::North, MazePosition { x: 1, y: 2 }),
+            (MazeDirection::South, MazePosition { x: 1, y: 0 }),
+            (MazeDirection::West, MazePosition { x: 0, y: 1 }),
+        ]
+        .iter()));
+    }
+
+    #[test]
+    fn neighbors_edge() {
+        let maze = neighbors_maze();
+
+        // The perimeter is never open
+        assert!(maze
+            .neighbors(MazePosition { x: 0, y: 0 }, true)
+            .iter()
+            .eq([
+                (MazeDirection::North, MazePosition { x: 0, y: 1 }),
+                (MazeDirection::East, MazePosition { x: 1, y: 0 }),
+            ]
+            .iter()));
+
+        assert!(maze
+            .neighbors(MazePosition { x: 3, y: 2 }, true)
+            .iter()
+            .eq([
+                (MazeDirection::North, MazePosition { x: 3, y: 3 }),
+                (MazeDirection::South, MazePosition { x: 3, y: 1 }),
+                (MazeDirection::West, MazePosition { x: 2, y: 2 }),
+            ]
+            .iter()));
+    }
+
+    #[test]
+    fn set_wall() {
+        let mut maze = Maze::new(Wall::Unknown);
+        let index = WallIndex {
+            x: 3,
+            y: 15,
+            direction: WallDirection::Horizontal,
+        };
+
+        assert_eq!(maze.set_wall(index, Wall::Closed), Ok(()));
+        assert_eq!(maze.get_wall(index), Some(&Wall::Closed));
+
+        // Neighboring walls are not touched
+        assert_eq!(
+            maze.get_wall(WallIndex {
+                x: 3,
+                y: 14,
+                direction: WallDirection::Horizontal,
+            }),
+            Some(&Wall::Unknown)
+        );
+    }
+
+    #[test]
+    fn set_wall_perimeter() {
+        let mut maze = Maze::new(Wall::Unknown);
+
+        assert_eq!(
+            maze.set_wall(
+                WallIndex {
+                    x: 0,
+                    y: 4,
+                    direction: WallDirection::Vertical,
+                },
+                Wall::Open
+            ),
+            Err(())
+        );
+
+        assert_eq!(
+            maze.set_wall(
+                WallIndex {
+                    x: 4,
+                    y: 16,
+                    direction: WallDirection::Horizontal,
+                },
+                Wall::Open
+            ),
+            Err(())
+        );
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let maze = Maze::from_file(*include_bytes!(
+            "../../../micromouse_simulation/mazes/APEC2017.maz"
+        ));
+
+        assert_eq!(Maze::from_file(maze.to_file()), maze);
+    }
+
+    #[test]
+    fn new_maze_is_unknown() {
+        let maze = Maze::new(Wall::Unknown);
+
+        // Every wall except the perimeter
+        assert_eq!(maze.unknown_count(), 16 * 15 + 15 * 16);
+        assert!(!maze.is_fully_known());
+
+        for index in maze.unknown_walls() {
+            assert_eq!(maze.get_wall(index), Some(&Wall::Unknown));
+        }
+    }
+
+    #[test]
+    fn file_maze_is_known() {
+        let maze = Maze::from_file(*include_bytes!(
+            "../../../micromouse_simulation/mazes/APEC2017.maz"
+        ));
+
+        assert_eq!(maze.unknown_count(), 0);
+        assert!(maze.is_fully_known());
+        assert_eq!(maze.unknown_walls().next(), None);
+    }
+
+    #[test]
+    fn unknown_walls_left() {
+        let mut maze = Maze::<4, 4>::filled(Wall::Open);
+        assert!(maze.is_fully_known());
+
+        let index = WallIndex {
+            x: 2,
+            y: 3,
+            direction: WallDirection::Horizontal,
+        };
+        maze.set_wall(index, Wall::Unknown).unwrap();
+
+        assert_eq!(maze.unknown_count(), 1);
+        assert!(!maze.is_fully_known());
+        assert_eq!(maze.unknown_walls().next(), Some(index));
+    }
+
+    #[test]
+    fn file_unknown_is_open() {
+        let maze = Maze::new(Wall::Unknown);
+
+        assert_eq!(Maze::from_file(maze.to_file()), Maze::new(Wall::Open));
+    }
+
+    #[test]
+    fn small_maze() {
+        let mut maze = Maze::<8, 8>::filled(Wall::Unknown);
+        assert_eq!(maze.width(), 8);
+        assert_eq!(maze.height(), 8);
+
+        let index = WallIndex {
+            x: 7,
+            y: 7,
+            direction: WallDirection::Horizontal,
+        };
+        assert_eq!(maze.set_wall(index, Wall::Open), Ok(()));
+        assert_eq!(maze.get_wall(index), Some(&Wall::Open));
+
+        // The north and east edges are the perimeter, even though there would be more room in
+        // a 16x16 maze
+        for &index in [
+            WallIndex {
+                x: 7,
+                y: 8,
+                direction: WallDirection::Horizontal,
+            },
+            WallIndex {
+                x: 8,
+                y: 7,
+                direction: WallDirection::Vertical,
+            },
+        ]
+        .iter()
+        {
+            assert_eq!(maze.get_wall(index), None);
+            assert_eq!(maze.set_wall(index, Wall::Open), Err(()));
+        }
+
+        assert_eq!(
+            maze.get_cell(7, 7),
+            (Wall::Closed, Wall::Open, Wall::Closed, Wall::Unknown)
+        );
+    }
+
+    #[test]
+    fn get_wall_mut() {
+        let mut maze = Maze::new(Wall::Unknown);
+        let index = WallIndex {
+            x: 7,
+            y: 2,
+            direction: WallDirection::Vertical,
+        };
+
+        if let Some(wall) = maze.get_wall_mut(index) {
+            *wall = Wall::Open;
+        }
+
+        assert_eq!(maze.get_wall(index), Some(&Wall::Open));
+    }
 }
@@ -2,6 +2,10 @@
 //!
 //! Includes mapping and navigating
 
+pub mod astar;
+pub mod goal;
+pub mod home;
+pub mod localize_quality;
 pub mod map;
 pub mod maze;
 pub mod motion_plan;
@@ -13,6 +17,7 @@ use crate::fast::{
     Direction, Vector, DIRECTION_0, DIRECTION_3_PI_2, DIRECTION_PI, DIRECTION_PI_2,
 };
 
+use crate::mouse::RunPhase;
 use crate::slow::map::{MapDebug, MoveOptions};
 use crate::slow::navigate::TwelvePartitionNavigateDebug;
 use maze::MazeConfig;
@@ -23,6 +28,13 @@ pub struct SlowDebug {
     pub move_options: MoveOptions,
     pub navigate: TwelvePartitionNavigateDebug,
     pub next_direction: MazeDirection,
+    pub at_goal: bool,
+    pub phase: RunPhase,
+
+    /// How many of the motions planned this time did not fit in the motion queue and were
+    /// dropped. Plans are shortened to fit, so anything but 0 means a single cell takes more
+    /// motions than the queue can hold.
+    pub dropped_motions: usize,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
@@ -34,6 +46,29 @@ pub enum MazeDirection {
 }
 
 impl MazeDirection {
+    /// Every direction, in the same order as their indexes
+    pub const ALL: [MazeDirection; 4] = [
+        MazeDirection::North,
+        MazeDirection::South,
+        MazeDirection::East,
+        MazeDirection::West,
+    ];
+
+    /// A number from 0 to 3 for the direction, for looking things up in arrays
+    pub fn to_index(self) -> usize {
+        match self {
+            MazeDirection::North => 0,
+            MazeDirection::South => 1,
+            MazeDirection::East => 2,
+            MazeDirection::West => 3,
+        }
+    }
+
+    /// The direction for an index from `to_index`, or `None` if it is not 0 to 3
+    pub fn from_index(index: usize) -> Option<MazeDirection> {
+        MazeDirection::ALL.get(index).copied()
+    }
+
     pub fn into_direction(self) -> Direction {
         match self {
             MazeDirection::North => DIRECTION_PI_2,
@@ -77,6 +112,38 @@ impl Default for MazeDirection {
     }
 }
 
+#[cfg(test)]
+mod maze_direction_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use pretty_assertions::assert_eq;
+
+    use super::MazeDirection;
+
+    #[test]
+    fn index_round_trip() {
+        for &direction in MazeDirection::ALL.iter() {
+            assert_eq!(
+                MazeDirection::from_index(direction.to_index()),
+                Some(direction)
+            );
+        }
+    }
+
+    #[test]
+    fn all_in_index_order() {
+        for (index, direction) in MazeDirection::ALL.iter().enumerate() {
+            assert_eq!(direction.to_index(), index);
+        }
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        assert_eq!(MazeDirection::from_index(4), None);
+    }
+}
+
 #[derive(Debug, Copy, Clone, Default, PartialEq, Deserialize, Serialize)]
 pub struct MazePosition {
     pub x: usize,
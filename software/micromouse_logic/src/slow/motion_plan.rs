@@ -4,30 +4,81 @@ use heapless::Vec;
 
 use crate::fast::motion_queue::{Motion, MotionQueueBuffer};
 use crate::fast::path::PathMotion;
-use crate::fast::turn::TurnMotion;
+use crate::fast::turn::{TurnChoice, TurnMotion};
 
-use crate::fast::Orientation;
-use crate::slow::maze::MazeConfig;
-use crate::slow::MazeDirection;
+use crate::fast::{Direction, Orientation};
+use crate::slow::maze::{Maze, MazeConfig, Wall, WallIndex};
+use crate::slow::{MazeDirection, MazeOrientation};
 
 #[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct MotionPlanConfig {
     /// How much to offset the start of a move into the current cell and the end of a move into the
     /// next cell
     pub move_offset: f32,
+
+    /// Cut through zig-zags in the route with a single 45° line instead of a corner for every
+    /// cell
+    pub allow_diagonals: bool,
 }
 
+/// Plan the motions to move through each of `directions` in order. The walls in `maze` are used
+/// to turn around away from a wall.
+///
+/// If there are more motions than fit in a `MotionQueueBuffer`, the extra ones are dropped. Use
+/// `try_motion_plan` to find out if that happened.
 pub fn motion_plan(
     config: &MotionPlanConfig,
     maze_config: &MazeConfig,
+    maze: &Maze,
     orientation: Orientation,
     directions: &[MazeDirection],
 ) -> MotionQueueBuffer {
+    plan(config, maze_config, maze, orientation, directions).0
+}
+
+/// Plan the motions to move through each of `directions` in order. The walls in `maze` are used
+/// to turn around away from a wall.
+///
+/// Returns `Err` with the number of motions that did not fit in a `MotionQueueBuffer` instead of
+/// silently dropping them.
+pub fn try_motion_plan(
+    config: &MotionPlanConfig,
+    maze_config: &MazeConfig,
+    maze: &Maze,
+    orientation: Orientation,
+    directions: &[MazeDirection],
+) -> Result<MotionQueueBuffer, usize> {
+    let (out, dropped) = plan(config, maze_config, maze, orientation, directions);
+
+    if dropped > 0 {
+        Err(dropped)
+    } else {
+        Ok(out)
+    }
+}
+
+fn plan(
+    config: &MotionPlanConfig,
+    maze_config: &MazeConfig,
+    maze: &Maze,
+    orientation: Orientation,
+    directions: &[MazeDirection],
+) -> (MotionQueueBuffer, usize) {
     let mut out = Vec::new();
+    let mut dropped = 0;
+
+    let mut push = |motion: Motion| {
+        if out.push(motion).is_err() {
+            dropped += 1;
+        }
+    };
 
     let mut current_orientation = orientation;
+    let mut index = 0;
+
+    while let Some(&next_direction) = directions.get(index) {
+        index += 1;
 
-    for next_direction in directions.iter().copied() {
         let maze_orientation = current_orientation.to_maze_orientation(maze_config);
         let cell_center = maze_orientation.position.center_position(maze_config);
 
@@ -58,23 +109,83 @@ pub fn motion_plan(
             }
         };
 
-        if do_manual_turn || next_direction.opposite() == maze_orientation.direction {
-            out.push(Motion::Turn(TurnMotion::new(
-                orientation.direction,
-                next_direction.into_direction(),
-            )))
-            .ok();
-            out.push(Motion::Path(PathMotion::line(cell_center, end_position)))
-                .ok();
+        let diagonal_length = if config.allow_diagonals && !do_manual_turn {
+            zig_zag_length(maze_orientation.direction, &directions[index - 1..])
         } else {
-            out.push(Motion::Path(PathMotion::corner(
+            0
+        };
+
+        if diagonal_length > 0 {
+            // The midpoints of the edges crossed in a zig-zag all lie on a 45° line starting at
+            // the edge the mouse came into this cell through, so it can drive straight along it
+            let start_position = match maze_orientation.direction {
+                MazeDirection::North => {
+                    cell_center.offset_y(-maze_config.cell_width / 2.0)
+                }
+                MazeDirection::South => {
+                    cell_center.offset_y(maze_config.cell_width / 2.0)
+                }
+                MazeDirection::East => {
+                    cell_center.offset_x(-maze_config.cell_width / 2.0)
+                }
+                MazeDirection::West => cell_center.offset_x(maze_config.cell_width / 2.0),
+            };
+
+            // Each cell crossed moves half a cell in each of the two directions
+            let step = (maze_orientation
+                .direction
+                .into_direction()
+                .into_unit_vector()
+                + next_direction.into_direction().into_unit_vector())
+                * (maze_config.cell_width / 2.0);
+            let last_edge = start_position + step * diagonal_length as f32;
+
+            // Turn back onto the axis around the midpoint of the last edge crossed, so the mouse
+            // is heading the way it will leave the zig-zag by the time this move is done
+            let diagonal = step.direction();
+            let last_direction = directions[index + diagonal_length - 2].into_direction();
+            let exit_radius = maze_config.cell_width / 4.0;
+
+            push(Motion::Path(PathMotion::line(
+                start_position,
+                last_edge - exit_radius * diagonal.into_unit_vector(),
+            )));
+            push(Motion::Path(PathMotion::corner(
+                last_edge,
+                diagonal,
+                last_direction,
+                exit_radius,
+                0.0,
+            )));
+
+            current_orientation.direction = last_direction;
+            current_orientation.position =
+                last_edge + exit_radius * last_direction.into_unit_vector();
+            index += diagonal_length - 1;
+
+            continue;
+        }
+
+        let turn_around = next_direction.opposite() == maze_orientation.direction;
+
+        if do_manual_turn || turn_around {
+            let current = current_orientation.direction;
+            let target = next_direction.into_direction();
+
+            push(Motion::Turn(if turn_around {
+                u_turn(maze, maze_orientation, current, target)
+            } else {
+                TurnMotion::new(current, target)
+            }));
+            push(Motion::Path(PathMotion::line(cell_center, end_position)));
+        } else {
+            push(Motion::Path(PathMotion::corner(
                 cell_center,
                 maze_orientation.direction.into_direction(),
                 next_direction.into_direction(),
                 maze_config.cell_width / 2.0,
                 config.move_offset,
-            )))
-            .ok();
+            )));
         }
 
         current_orientation.direction = next_direction.into_direction();
@@ -84,7 +195,62 @@ pub fn motion_plan(
 
     out.reverse();
 
-    return out;
+    (out, dropped)
+}
+
+/// Turn around in place. If there is a wall on only one side, turn away from it so the front of
+/// the mouse does not swing into it. Otherwise, go whichever way is shortest.
+fn u_turn(
+    maze: &Maze,
+    maze_orientation: MazeOrientation,
+    current: Direction,
+    target: Direction,
+) -> TurnMotion {
+    let wall_to = |direction: MazeDirection| {
+        maze.get_wall(WallIndex::from_maze_orientation(MazeOrientation {
+            position: maze_orientation.position,
+            direction,
+        }))
+        // Only the outside of the maze is missing, which is always closed
+        .copied()
+        .unwrap_or(Wall::Closed)
+            == Wall::Closed
+    };
+
+    let left_closed = wall_to(maze_orientation.direction.left());
+    let right_closed = wall_to(maze_orientation.direction.right());
+
+    let choice = match (left_closed, right_closed) {
+        (true, false) => TurnChoice::ForceRight,
+        (false, true) => TurnChoice::ForceLeft,
+        _ => TurnChoice::Shortest,
+    };
+
+    TurnMotion::with_choice(current, target, choice)
+}
+
+/// How many of `directions` form a zig-zag that alternates between turning off of `heading` and
+/// going back to it, or 0 if there are not at least two of them. Two is the shortest zig-zag
+/// that is shorter to drive diagonally.
+fn zig_zag_length(heading: MazeDirection, directions: &[MazeDirection]) -> usize {
+    let turn = match directions.first() {
+        Some(&turn) if turn != heading && turn != heading.opposite() => turn,
+        _ => return 0,
+    };
+
+    let length = directions
+        .iter()
+        .enumerate()
+        .take_while(|&(i, &direction)| {
+            direction == if i % 2 == 0 { turn } else { heading }
+        })
+        .count();
+
+    if length >= 2 {
+        length
+    } else {
+        0
+    }
 }
 
 #[cfg(test)]
@@ -94,18 +260,22 @@ mod test_motion_plan {
 
     use pretty_assertions::assert_eq;
 
-    use super::motion_plan;
+    use super::{motion_plan, try_motion_plan};
     use crate::config::MAZE;
     use crate::config::MOTION_PLAN;
     use crate::fast::motion_queue::{Motion, MotionQueueBuffer};
     use crate::fast::path::PathMotion;
-    use crate::fast::turn::TurnMotion;
-    use crate::fast::{Orientation, Vector, DIRECTION_0, DIRECTION_PI};
+    use crate::fast::turn::{TurnDirection, TurnMotion};
+    use crate::fast::{Orientation, Vector, DIRECTION_0, DIRECTION_PI, DIRECTION_PI_2};
+    use crate::slow::maze::{Maze, Wall, WallDirection, WallIndex};
     use crate::slow::motion_plan::MotionPlanConfig;
     use crate::slow::MazeDirection;
     use heapless::Vec;
 
-    const CONFIG: MotionPlanConfig = MotionPlanConfig { move_offset: 12.0 };
+    const CONFIG: MotionPlanConfig = MotionPlanConfig {
+        move_offset: 12.0,
+        allow_diagonals: false,
+    };
 
     #[test]
     fn u_turn() {
@@ -122,14 +292,20 @@ mod test_motion_plan {
                 },
             )))
             .ok();
+        // The south wall is the outside of the maze, so it turns around to the north
         expected
-            .push(Motion::Turn(TurnMotion::new(DIRECTION_0, DIRECTION_PI)))
+            .push(Motion::Turn(TurnMotion::with_direction(
+                DIRECTION_0,
+                DIRECTION_PI,
+                TurnDirection::Counterclockwise,
+            )))
             .ok();
 
         assert_eq!(
             motion_plan(
                 &MOTION_PLAN,
                 &MAZE,
+                &Maze::new(Wall::Unknown),
                 Orientation {
                     position: Vector { x: 180.0, y: 90.0 },
                     direction: DIRECTION_0,
@@ -139,4 +315,191 @@ mod test_motion_plan {
             expected
         )
     }
+
+    /// The direction of the turn when turning around from east to west in cell (1, 1)
+    fn u_turn_direction(maze: &Maze) -> TurnDirection {
+        let motions = motion_plan(
+            &MOTION_PLAN,
+            &MAZE,
+            maze,
+            Orientation {
+                position: Vector {
+                    x: 180.0,
+                    y: 180.0 + 90.0,
+                },
+                direction: DIRECTION_0,
+            },
+            &[MazeDirection::West],
+        );
+
+        match motions[1] {
+            Motion::Turn(turn) => turn.direction(),
+            _ => panic!("Expected a turn"),
+        }
+    }
+
+    #[test]
+    fn u_turn_away_from_wall() {
+        let north_wall = WallIndex {
+            x: 1,
+            y: 2,
+            direction: WallDirection::Horizontal,
+        };
+        let south_wall = WallIndex {
+            x: 1,
+            y: 1,
+            direction: WallDirection::Horizontal,
+        };
+
+        let mut maze = Maze::new(Wall::Open);
+        maze.set_wall(north_wall, Wall::Closed).unwrap();
+        assert_eq!(u_turn_direction(&maze), TurnDirection::Clockwise);
+
+        let mut maze = Maze::new(Wall::Open);
+        maze.set_wall(south_wall, Wall::Closed).unwrap();
+        assert_eq!(u_turn_direction(&maze), TurnDirection::Counterclockwise);
+
+        // With nothing to avoid, it goes the shortest way
+        let maze = Maze::new(Wall::Open);
+        assert_eq!(
+            u_turn_direction(&maze),
+            TurnMotion::new(DIRECTION_0, DIRECTION_PI).direction()
+        );
+    }
+
+    #[test]
+    fn too_many_motions() {
+        assert_eq!(
+            try_motion_plan(
+                &MOTION_PLAN,
+                &MAZE,
+                &Maze::new(Wall::Unknown),
+                Orientation {
+                    position: Vector { x: 180.0, y: 90.0 },
+                    direction: DIRECTION_0,
+                },
+                &[
+                    MazeDirection::West,
+                    MazeDirection::East,
+                    MazeDirection::West
+                ]
+            ),
+            Err(2)
+        )
+    }
+
+    /// Whether any of the motions is a path that starts at about 45° off of the maze
+    fn has_diagonal(motions: &MotionQueueBuffer) -> bool {
+        motions.iter().any(|motion| match motion {
+            Motion::Path(path) => {
+                let derivative = path.derivative(0.0);
+                (derivative.x.abs() - derivative.y.abs()).abs()
+                    < 0.1 * derivative.magnitude()
+            }
+            _ => false,
+        })
+    }
+
+    #[test]
+    fn zig_zag() {
+        let directions = [
+            MazeDirection::East,
+            MazeDirection::North,
+            MazeDirection::East,
+            MazeDirection::North,
+        ];
+
+        // Driving north, just past the north edge of cell (0, 0)
+        let orientation = Orientation {
+            position: Vector {
+                x: 90.0,
+                y: 180.0 + CONFIG.move_offset,
+            },
+            direction: DIRECTION_PI_2,
+        };
+
+        let straight = motion_plan(
+            &CONFIG,
+            &MAZE,
+            &Maze::new(Wall::Unknown),
+            orientation,
+            &directions,
+        );
+        assert_eq!(straight.len(), 4);
+        assert!(!has_diagonal(&straight));
+
+        let diagonal = motion_plan(
+            &MotionPlanConfig {
+                allow_diagonals: true,
+                ..CONFIG
+            },
+            &MAZE,
+            &Maze::new(Wall::Unknown),
+            orientation,
+            &directions,
+        );
+        assert_eq!(diagonal.len(), 2);
+        assert!(has_diagonal(&diagonal));
+
+        match diagonal[0] {
+            Motion::Path(path) => {
+                // Far enough from the origin that f32 rounding is more than assert_close allows
+                assert!(path.end().distance_to(Vector { x: 450.0, y: 585.0 }) < 0.001);
+            }
+            _ => panic!("expected a path"),
+        }
+    }
+
+    #[test]
+    fn zig_zag_then_straight() {
+        let motions = motion_plan(
+            &MotionPlanConfig {
+                allow_diagonals: true,
+                ..CONFIG
+            },
+            &MAZE,
+            &Maze::new(Wall::Unknown),
+            Orientation {
+                position: Vector {
+                    x: 90.0,
+                    y: 180.0 + CONFIG.move_offset,
+                },
+                direction: DIRECTION_PI_2,
+            },
+            &[
+                MazeDirection::East,
+                MazeDirection::North,
+                MazeDirection::East,
+                MazeDirection::North,
+                MazeDirection::North,
+            ],
+        );
+
+        // The motions are in reverse order: the straight, the turn off of the diagonal, and
+        // the diagonal
+        assert_eq!(motions.len(), 3);
+
+        let paths: [PathMotion; 3] = [0, 1, 2].map(|i| match motions[i] {
+            Motion::Path(path) => path,
+            _ => panic!("expected a path"),
+        });
+
+        // Each motion starts heading the same way the one before it ended
+        for i in 0..2 {
+            let end = paths[i + 1].derivative(1.0).direction();
+            let start = paths[i].derivative(0.0).direction();
+            assert!(end.approx_eq(start, 0.001));
+        }
+
+        // Off of the diagonal facing north, and the straight stays in the middle of the column
+        assert!(paths[1]
+            .derivative(1.0)
+            .direction()
+            .approx_eq(DIRECTION_PI_2, 0.001));
+        assert!(
+            (paths[0].closest_point(Vector { x: 450.0, y: 630.0 }).1.x - 450.0).abs()
+                < 0.001
+        );
+        assert!((paths[0].end().x - 450.0).abs() < 0.001);
+    }
 }
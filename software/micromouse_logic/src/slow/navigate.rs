@@ -1,9 +1,13 @@
 use core::fmt::Debug;
 
+use heapless::Vec;
 use serde::{Deserialize, Serialize};
+use typenum::U256;
 
+use super::goal::{flood_costs, flood_distances, Goal};
 use super::map::MoveOptions;
-use super::{MazeDirection, MazeOrientation};
+use super::maze::{Maze, Wall, WallIndex, HEIGHT, WIDTH};
+use super::{MazeDirection, MazeOrientation, MazePosition};
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Move {
@@ -30,6 +34,12 @@ impl Move {
     }
 }
 
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NavigateConfig {
+    /// The cells to navigate to
+    pub goal: Goal,
+}
+
 const CENTER_LEFT: [Move; 3] = [Move::Forward, Move::Left, Move::Right];
 const CENTER_RIGHT: [Move; 3] = [Move::Forward, Move::Right, Move::Left];
 const LEFT: [Move; 3] = [Move::Left, Move::Forward, Move::Right];
@@ -37,52 +47,107 @@ const RIGHT: [Move; 3] = [Move::Right, Move::Forward, Move::Left];
 
 #[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct TwelvePartitionNavigateDebug {
-    cells: [[u8; 16]; 16],
+    cells: [[u8; HEIGHT]; WIDTH],
     next_move: Move,
     possibilities: [Move; 3],
+
+    /// The position was outside of the maze, so the closest cell in the maze was used instead
+    out_of_bounds: bool,
+
+    /// The fraction of the maze that has been visited
+    coverage: f32,
 }
 
-pub struct TwelvePartitionNavigate {
-    cells: [[u8; 16]; 16],
+pub struct TwelvePartitionNavigate<const W: usize = WIDTH, const H: usize = HEIGHT> {
+    cells: [[u8; H]; W],
 }
 
 impl TwelvePartitionNavigate {
     pub fn new() -> TwelvePartitionNavigate {
-        TwelvePartitionNavigate {
-            cells: [[0; 16]; 16],
-        }
+        TwelvePartitionNavigate::unvisited()
+    }
+
+    pub fn navigate(
+        &mut self,
+        config: &NavigateConfig,
+        orientation: MazeOrientation,
+        move_options: MoveOptions,
+    ) -> (MazeDirection, TwelvePartitionNavigateDebug) {
+        let (direction, next_move, possibilities, out_of_bounds) =
+            self.navigate_sized(config, orientation, move_options);
+
+        (
+            direction,
+            TwelvePartitionNavigateDebug {
+                cells: self.cells,
+                next_move,
+                possibilities,
+                out_of_bounds,
+                coverage: self.coverage(),
+            },
+        )
+    }
+}
+
+impl<const W: usize, const H: usize> TwelvePartitionNavigate<W, H> {
+    /// Start without any cells visited
+    pub fn unvisited() -> TwelvePartitionNavigate<W, H> {
+        TwelvePartitionNavigate { cells: [[0; H]; W] }
+    }
+
+    /// The fraction of cells in the maze that have been visited at least once, from 0 to 1
+    pub fn coverage(&self) -> f32 {
+        let visited = self
+            .cells
+            .iter()
+            .flatten()
+            .filter(|&&count| count > 0)
+            .count();
+
+        visited as f32 / (W * H) as f32
     }
 
     fn get_cell(&self, x: i32, y: i32) -> u8 {
-        if x >= 0 && x <= 15 && y >= 0 && y <= 15 {
+        if x >= 0 && x < W as i32 && y >= 0 && y < H as i32 {
             self.cells[x as usize][y as usize]
         } else {
             255
         }
     }
 
-    pub fn navigate(
+    /// Pick the direction to go next. This works on any size of maze, but only gives the debug
+    /// for the usual 16x16 maze, so it gives the move, the possible moves, and whether the
+    /// position was outside of the maze instead.
+    pub fn navigate_sized(
         &mut self,
+        config: &NavigateConfig,
         orientation: MazeOrientation,
         move_options: MoveOptions,
-    ) -> (MazeDirection, TwelvePartitionNavigateDebug) {
+    ) -> (MazeDirection, Move, [Move; 3], bool) {
         let x = orientation.position.x as i32;
         let y = orientation.position.y as i32;
+
+        // A bad reading can put the mouse outside of the maze. Carry on from the closest cell in
+        // the maze instead of giving up in the middle of a run.
         let ux = if x < 0 {
             0
-        } else if x > 15 {
-            15
+        } else if x >= W as i32 {
+            W as i32 - 1
         } else {
             x
         } as usize;
         let uy = if y < 0 {
             0
-        } else if y > 15 {
-            15
+        } else if y >= H as i32 {
+            H as i32 - 1
         } else {
             y
         } as usize;
 
+        let out_of_bounds = ux as i32 != x || uy as i32 != y;
+        let x = ux as i32;
+        let y = uy as i32;
+
         if self.cells[ux][uy] < 255 {
             self.cells[ux][uy] += 1;
         }
@@ -139,94 +204,110 @@ impl TwelvePartitionNavigate {
         }
         */
 
+        // The goal might not be the center 2x2, so split the maze up around wherever it is. If the
+        // goal is more than one cell wide, the columns that are not on the east side act like the
+        // west column of the center, and the same for rows.
+        let (goal_min, goal_max) = config.goal.bounds();
+        let min_x = goal_min.x as i32;
+        let min_y = goal_min.y as i32;
+        let max_x = goal_max.x as i32;
+        let max_y = goal_max.y as i32;
+
         let possibilities = match (x, y) {
-            (x, y) if x < 7 && y < 7 => match orientation.direction {
+            (x, y) if x < min_x && y < min_y => match orientation.direction {
                 MazeDirection::North => CENTER_RIGHT,
                 MazeDirection::South => LEFT,
                 MazeDirection::East => CENTER_LEFT,
                 MazeDirection::West => RIGHT,
             },
 
-            (x, y) if x > 8 && y < 7 => match orientation.direction {
+            (x, y) if x > max_x && y < min_y => match orientation.direction {
                 MazeDirection::North => CENTER_LEFT,
                 MazeDirection::South => RIGHT,
                 MazeDirection::East => LEFT,
                 MazeDirection::West => CENTER_RIGHT,
             },
 
-            (x, y) if x > 8 && y > 8 => match orientation.direction {
+            (x, y) if x > max_x && y > max_y => match orientation.direction {
                 MazeDirection::North => LEFT,
                 MazeDirection::South => CENTER_RIGHT,
                 MazeDirection::East => RIGHT,
                 MazeDirection::West => CENTER_LEFT,
             },
 
-            (x, y) if x < 7 && y > 8 => match orientation.direction {
+            (x, y) if x < min_x && y > max_y => match orientation.direction {
                 MazeDirection::North => RIGHT,
                 MazeDirection::South => CENTER_LEFT,
                 MazeDirection::East => CENTER_RIGHT,
                 MazeDirection::West => LEFT,
             },
 
-            (7, y) if y < 7 => match orientation.direction {
+            (x, y) if x >= min_x && x < max_x && y < min_y => match orientation.direction
+            {
                 MazeDirection::North => CENTER_RIGHT,
                 MazeDirection::South => LEFT,
                 MazeDirection::East => LEFT,
                 MazeDirection::West => RIGHT,
             },
 
-            (8, y) if y < 7 => match orientation.direction {
+            (x, y) if x == max_x && y < min_y => match orientation.direction {
                 MazeDirection::North => CENTER_LEFT,
                 MazeDirection::South => RIGHT,
                 MazeDirection::East => LEFT,
                 MazeDirection::West => RIGHT,
             },
 
-            (x, 7) if x > 8 => match orientation.direction {
+            (x, y) if y >= min_y && y < max_y && x > max_x => match orientation.direction
+            {
                 MazeDirection::North => LEFT,
                 MazeDirection::South => RIGHT,
                 MazeDirection::East => CENTER_RIGHT,
                 MazeDirection::West => LEFT,
             },
 
-            (x, 8) if x > 8 => match orientation.direction {
+            (x, y) if y == max_y && x > max_x => match orientation.direction {
                 MazeDirection::North => LEFT,
                 MazeDirection::South => RIGHT,
                 MazeDirection::East => CENTER_LEFT,
                 MazeDirection::West => RIGHT,
             },
 
-            (8, y) if y > 8 => match orientation.direction {
+            (x, y) if x == max_x && y > max_y => match orientation.direction {
                 MazeDirection::North => LEFT,
                 MazeDirection::South => CENTER_RIGHT,
                 MazeDirection::East => RIGHT,
                 MazeDirection::West => LEFT,
             },
 
-            (7, y) if y > 8 => match orientation.direction {
+            (x, y) if x >= min_x && x < max_x && y > max_y => match orientation.direction
+            {
                 MazeDirection::North => RIGHT,
                 MazeDirection::South => CENTER_LEFT,
                 MazeDirection::East => RIGHT,
                 MazeDirection::West => LEFT,
             },
 
-            (x, 8) if x < 7 => match orientation.direction {
+            (x, y) if y == max_y && x < min_x => match orientation.direction {
                 MazeDirection::North => RIGHT,
                 MazeDirection::South => LEFT,
                 MazeDirection::East => CENTER_RIGHT,
                 MazeDirection::West => LEFT,
             },
 
-            (x, 7) if x < 7 => match orientation.direction {
+            (x, y) if y >= min_y && y < max_y && x < min_x => match orientation.direction
+            {
                 MazeDirection::North => RIGHT,
                 MazeDirection::South => LEFT,
                 MazeDirection::East => CENTER_LEFT,
                 MazeDirection::West => RIGHT,
             },
 
-            (x, y) if x >= 7 && x <= 8 && y >= 7 && y <= 8 => CENTER_RIGHT,
+            (x, y) if x >= min_x && x <= max_x && y >= min_y && y <= max_y => {
+                CENTER_RIGHT
+            }
 
-            (_, _) => panic!("Invalid location!"),
+            // Every cell is covered above, but fall back to something that still moves
+            (_, _) => CENTER_RIGHT,
         };
 
         let mut next_move = Move::Backward;
@@ -264,14 +345,732 @@ impl TwelvePartitionNavigate {
 
         let direction = next_move.to_direction(orientation.direction);
 
+        (direction, next_move, possibilities, out_of_bounds)
+        //}
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FloodFillNavigateDebug {
+    distances: [[u8; HEIGHT]; WIDTH],
+    next_move: Move,
+
+    /// The cells that were walled off as dead ends before flooding
+    dead_ends: [[bool; HEIGHT]; WIDTH],
+}
+
+/// Navigates by flooding distances out from the goal through the walls that are known so far,
+/// and always moving to the neighboring cell that is closest to the goal
+pub struct FloodFillNavigate<const W: usize = WIDTH, const H: usize = HEIGHT> {
+    maze: Maze<W, H>,
+}
+
+impl Default for FloodFillNavigate {
+    fn default() -> FloodFillNavigate {
+        FloodFillNavigate::new()
+    }
+}
+
+impl FloodFillNavigate {
+    pub fn new() -> FloodFillNavigate {
+        FloodFillNavigate::from_maze(Maze::new(Wall::Unknown))
+    }
+
+    pub fn navigate(
+        &mut self,
+        config: &NavigateConfig,
+        orientation: MazeOrientation,
+        move_options: MoveOptions,
+    ) -> (MazeDirection, FloodFillNavigateDebug) {
+        let (direction, next_move, distances, dead_ends) =
+            self.navigate_sized(config, orientation, move_options);
+
         (
             direction,
-            TwelvePartitionNavigateDebug {
-                cells: self.cells,
+            FloodFillNavigateDebug {
+                distances,
                 next_move,
-                possibilities,
+                dead_ends,
             },
         )
-        //}
+    }
+}
+
+impl<const W: usize, const H: usize> FloodFillNavigate<W, H> {
+    /// Start with some walls already known
+    pub fn from_maze(maze: Maze<W, H>) -> FloodFillNavigate<W, H> {
+        FloodFillNavigate { maze }
+    }
+
+    /// The distance from a cell to the goal, or 255 if the cell is outside of the maze
+    fn get_distance(distances: &[[u8; H]; W], position: MazePosition) -> u8 {
+        distances
+            .get(position.x)
+            .and_then(|column| column.get(position.y))
+            .copied()
+            .unwrap_or(255)
+    }
+
+    /// The cell next to `position` in `direction`. This may be outside of the maze.
+    fn neighbor(position: MazePosition, direction: MazeDirection) -> MazePosition {
+        match direction {
+            MazeDirection::North => MazePosition {
+                x: position.x,
+                y: position.y + 1,
+            },
+            MazeDirection::South => MazePosition {
+                x: position.x,
+                y: position.y.wrapping_sub(1),
+            },
+            MazeDirection::East => MazePosition {
+                x: position.x + 1,
+                y: position.y,
+            },
+            MazeDirection::West => MazePosition {
+                x: position.x.wrapping_sub(1),
+                y: position.y,
+            },
+        }
+    }
+
+    /// Whether the wall on the `direction` side of a cell might be open
+    fn is_open(&self, position: MazePosition, direction: MazeDirection) -> bool {
+        let (north, south, east, west) = self.maze.get_cell(position.x, position.y);
+
+        let wall = match direction {
+            MazeDirection::North => north,
+            MazeDirection::South => south,
+            MazeDirection::East => east,
+            MazeDirection::West => west,
+        };
+
+        wall != Wall::Closed
+    }
+
+    /// The known maze with its dead ends walled off, and which cells those were
+    ///
+    /// A dead end is a cell with walls on three sides, so the only reason to go in is to come
+    /// right back out. Walling one off can make the cell leading to it a dead end too, so this
+    /// keeps going until a whole dead-end corridor is walled off. The goal and the cell at
+    /// `position` are never walled off, so there is always somewhere to flood to and from.
+    fn prune_dead_ends(
+        &self,
+        config: &NavigateConfig,
+        position: MazePosition,
+    ) -> (Maze<W, H>, [[bool; H]; W]) {
+        let mut maze = self.maze;
+        let mut dead_ends = [[false; H]; W];
+
+        let mut pruned_any = true;
+        while pruned_any {
+            pruned_any = false;
+
+            for (x, column) in dead_ends.iter_mut().enumerate() {
+                for (y, dead_end) in column.iter_mut().enumerate() {
+                    let cell = MazePosition { x, y };
+
+                    if *dead_end || cell == position || config.goal.contains(cell) {
+                        continue;
+                    }
+
+                    let (north, south, east, west) = maze.get_cell(x, y);
+
+                    let sides = [
+                        (north, MazeDirection::North),
+                        (south, MazeDirection::South),
+                        (east, MazeDirection::East),
+                        (west, MazeDirection::West),
+                    ];
+                    let mut open =
+                        sides.iter().filter(|&&(wall, _)| wall != Wall::Closed);
+
+                    if let (Some(&(_, direction)), None) = (open.next(), open.next()) {
+                        let index = WallIndex::from_maze_orientation(MazeOrientation {
+                            position: cell,
+                            direction,
+                        });
+
+                        // The only open side of a cell is never on the perimeter
+                        maze.set_wall(index, Wall::Closed).ok();
+                        *dead_end = true;
+                        pruned_any = true;
+                    }
+                }
+            }
+        }
+
+        (maze, dead_ends)
+    }
+
+    /// The whole route from the current cell to the goal through the walls known so far, found by
+    /// always stepping to a neighbor that is one closer. Going straight is preferred so the route
+    /// has long straightaways. The route never goes into a dead end. It is empty if the mouse is
+    /// already in the goal or the goal can not be reached.
+    pub fn plan_route(
+        &self,
+        config: &NavigateConfig,
+        orientation: MazeOrientation,
+    ) -> Vec<MazeDirection, U256> {
+        let (maze, _) = self.prune_dead_ends(config, orientation.position);
+        let distances = flood_distances(&maze, &config.goal);
+
+        self.follow_costs(&distances, orientation, |_| 0)
+    }
+
+    /// Like `plan_route`, but going into each cell costs one plus its `penalty` instead of just
+    /// one, so the route goes around cells with a high penalty if the way around is not too much
+    /// longer.
+    pub fn plan_route_weighted<F>(
+        &self,
+        config: &NavigateConfig,
+        orientation: MazeOrientation,
+        penalty: F,
+    ) -> Vec<MazeDirection, U256>
+    where
+        F: Fn(MazePosition) -> u8,
+    {
+        let (maze, _) = self.prune_dead_ends(config, orientation.position);
+        let costs = flood_costs(&maze, &config.goal, &penalty);
+
+        self.follow_costs(&costs, orientation, penalty)
+    }
+
+    /// Walk down `costs` from the current cell to the goal, always stepping into a neighbor whose
+    /// cost plus what it takes to get into it is the cost of the current cell
+    fn follow_costs<F>(
+        &self,
+        costs: &[[u8; H]; W],
+        orientation: MazeOrientation,
+        penalty: F,
+    ) -> Vec<MazeDirection, U256>
+    where
+        F: Fn(MazePosition) -> u8,
+    {
+        let mut route = Vec::new();
+        let mut position = orientation.position;
+        let mut direction = orientation.direction;
+        let mut cost = Self::get_distance(costs, position);
+
+        while cost != 0 && cost != 255 {
+            let next = [
+                direction,
+                direction.left(),
+                direction.right(),
+                direction.opposite(),
+            ]
+            .iter()
+            .copied()
+            .find(|&next_direction| {
+                let next_position = Self::neighbor(position, next_direction);
+
+                self.is_open(position, next_direction)
+                    && Self::get_distance(costs, next_position)
+                        .saturating_add(1)
+                        .saturating_add(penalty(next_position))
+                        == cost
+            });
+
+            // There is always a neighbor that fits on a flooded maze, but don't loop forever if
+            // there somehow isn't
+            let next_direction = match next {
+                Some(next_direction) => next_direction,
+                None => break,
+            };
+
+            if route.push(next_direction).is_err() {
+                break;
+            }
+
+            position = Self::neighbor(position, next_direction);
+            direction = next_direction;
+            cost = Self::get_distance(costs, position);
+        }
+
+        route
+    }
+
+    /// Remember the walls around the current cell and pick the direction to go next, staying out
+    /// of dead ends. This works on any size of maze, but only gives the debug for the usual 16x16
+    /// maze, so it gives the move, the distances, and the dead ends instead.
+    pub fn navigate_sized(
+        &mut self,
+        config: &NavigateConfig,
+        orientation: MazeOrientation,
+        move_options: MoveOptions,
+    ) -> (MazeDirection, Move, [[u8; H]; W], [[bool; H]; W]) {
+        // Remember the walls around this cell
+        for &(open, next_move) in [
+            (move_options.front, Move::Forward),
+            (move_options.left, Move::Left),
+            (move_options.right, Move::Right),
+        ]
+        .iter()
+        {
+            let index = WallIndex::from_maze_orientation(MazeOrientation {
+                position: orientation.position,
+                direction: next_move.to_direction(orientation.direction),
+            });
+
+            let wall = if open { Wall::Open } else { Wall::Closed };
+
+            // The perimeter can not be set, but it is always closed anyway
+            self.maze.set_wall(index, wall).ok();
+        }
+
+        let (maze, dead_ends) = self.prune_dead_ends(config, orientation.position);
+        let distances = flood_distances(&maze, &config.goal);
+
+        let behind_open =
+            self.is_open(orientation.position, orientation.direction.opposite());
+
+        // In order of preference if there is a tie
+        let next_move = [
+            (move_options.front, Move::Forward),
+            (move_options.left, Move::Left),
+            (move_options.right, Move::Right),
+            (behind_open, Move::Backward),
+        ]
+        .iter()
+        .filter(|&&(open, _)| open)
+        .map(|&(_, next_move)| next_move)
+        .fold(None, |best: Option<(Move, u8)>, next_move| {
+            let distance = Self::get_distance(
+                &distances,
+                Self::neighbor(
+                    orientation.position,
+                    next_move.to_direction(orientation.direction),
+                ),
+            );
+
+            match best {
+                Some((_, best_distance)) if best_distance <= distance => best,
+                _ => Some((next_move, distance)),
+            }
+        })
+        .map(|(next_move, _)| next_move)
+        .unwrap_or(Move::Backward);
+
+        (
+            next_move.to_direction(orientation.direction),
+            next_move,
+            distances,
+            dead_ends,
+        )
+    }
+}
+
+#[cfg(test)]
+mod flood_fill_navigate_tests {
+    #[allow(unused_imports)]
+    use crate::test::*;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{FloodFillNavigate, Move, NavigateConfig, TwelvePartitionNavigate};
+    use crate::slow::goal::{flood_distances, Goal};
+    use crate::slow::map::MoveOptions;
+    use crate::slow::maze::{Maze, Wall, WallDirection, WallIndex};
+    use crate::slow::{MazeDirection, MazeOrientation, MazePosition};
+
+    /// An open maze, except that the cell north of the start is a dead end
+    fn dead_end_maze() -> Maze {
+        let mut maze = Maze::new(Wall::Open);
+
+        maze.set_wall(
+            WallIndex {
+                x: 0,
+                y: 2,
+                direction: WallDirection::Horizontal,
+            },
+            Wall::Closed,
+        )
+        .unwrap();
+
+        maze.set_wall(
+            WallIndex {
+                x: 1,
+                y: 1,
+                direction: WallDirection::Vertical,
+            },
+            Wall::Closed,
+        )
+        .unwrap();
+
+        maze
+    }
+
+    const CONFIG: NavigateConfig = NavigateConfig {
+        goal: Goal::Square(MazePosition { x: 7, y: 7 }),
+    };
+
+    const START: MazeOrientation = MazeOrientation {
+        position: MazePosition { x: 0, y: 0 },
+        direction: MazeDirection::North,
+    };
+
+    const START_OPTIONS: MoveOptions = MoveOptions {
+        left: false,
+        front: true,
+        right: true,
+    };
+
+    #[test]
+    fn avoids_dead_end() {
+        let mut navigate = FloodFillNavigate::from_maze(dead_end_maze());
+
+        let (direction, debug) = navigate.navigate(&CONFIG, START, START_OPTIONS);
+
+        assert_eq!(direction, MazeDirection::East);
+        assert_eq!(debug.next_move, Move::Right);
+    }
+
+    /// An open maze, except that the cells north of the start are a long dead-end corridor
+    fn dead_end_corridor_maze() -> Maze {
+        let mut maze = Maze::new(Wall::Open);
+
+        for y in 1..=5 {
+            maze.set_wall(
+                WallIndex::from_maze_orientation(MazeOrientation {
+                    position: MazePosition { x: 0, y },
+                    direction: MazeDirection::East,
+                }),
+                Wall::Closed,
+            )
+            .unwrap();
+        }
+
+        maze.set_wall(
+            WallIndex::from_maze_orientation(MazeOrientation {
+                position: MazePosition { x: 0, y: 5 },
+                direction: MazeDirection::North,
+            }),
+            Wall::Closed,
+        )
+        .unwrap();
+
+        maze
+    }
+
+    #[test]
+    fn prunes_dead_end_corridor() {
+        let mut navigate = FloodFillNavigate::from_maze(dead_end_corridor_maze());
+
+        let (direction, debug) = navigate.navigate(&CONFIG, START, START_OPTIONS);
+
+        assert_eq!(direction, MazeDirection::East);
+        assert!(!debug.dead_ends[0][0]);
+        assert!((1..=5).all(|y| debug.dead_ends[0][y]));
+        assert!((1..=5).all(|y| debug.distances[0][y] == 255));
+        assert!(!debug.dead_ends[1][1]);
+
+        let route = navigate.plan_route(&CONFIG, START);
+        assert_eq!(route[0], MazeDirection::East);
+        assert!(CONFIG.goal.contains(follow(START.position, &route)));
+    }
+
+    #[test]
+    fn twelve_partition_wanders() {
+        let mut navigate = TwelvePartitionNavigate::new();
+
+        let (direction, _) = navigate.navigate(&CONFIG, START, START_OPTIONS);
+
+        assert_eq!(direction, MazeDirection::North);
+    }
+
+    #[test]
+    fn twelve_partition_coverage() {
+        let mut navigate = TwelvePartitionNavigate::new();
+        assert_eq!(navigate.coverage(), 0.0);
+
+        navigate.navigate(&CONFIG, START, START_OPTIONS);
+        assert_close(navigate.coverage(), 1.0 / 256.0);
+
+        // Coming back to the same cell does not count again
+        navigate.navigate(&CONFIG, START, START_OPTIONS);
+        assert_close(navigate.coverage(), 1.0 / 256.0);
+
+        let (_, debug) = navigate.navigate(
+            &CONFIG,
+            MazeOrientation {
+                position: MazePosition { x: 0, y: 1 },
+                direction: MazeDirection::North,
+            },
+            START_OPTIONS,
+        );
+        assert_close(navigate.coverage(), 2.0 / 256.0);
+        assert_close(debug.coverage, 2.0 / 256.0);
+    }
+
+    #[test]
+    fn twelve_partition_out_of_bounds() {
+        let mut navigate = TwelvePartitionNavigate::new();
+
+        let (direction, debug) = navigate.navigate(
+            &CONFIG,
+            MazeOrientation {
+                position: MazePosition { x: 27, y: 4 },
+                direction: MazeDirection::East,
+            },
+            MoveOptions {
+                left: true,
+                front: false,
+                right: true,
+            },
+        );
+
+        // Treated like the east edge, south of the goal, so it turns towards the goal
+        assert!(debug.out_of_bounds);
+        assert_eq!(debug.next_move, Move::Left);
+        assert_eq!(direction, MazeDirection::North);
+    }
+
+    #[test]
+    fn twelve_partition_small_maze() {
+        let mut navigate = TwelvePartitionNavigate::<4, 4>::unvisited();
+        let config = NavigateConfig {
+            goal: Goal::Square(MazePosition { x: 1, y: 1 }),
+        };
+
+        navigate.navigate_sized(&config, START, START_OPTIONS);
+        assert_close(navigate.coverage(), 1.0 / 16.0);
+
+        // Past the east edge of this maze, but would be inside of a 16x16 one
+        let (_, _, _, out_of_bounds) = navigate.navigate_sized(
+            &config,
+            MazeOrientation {
+                position: MazePosition { x: 5, y: 0 },
+                direction: MazeDirection::North,
+            },
+            START_OPTIONS,
+        );
+        assert!(out_of_bounds);
+        assert_close(navigate.coverage(), 2.0 / 16.0);
+    }
+
+    #[test]
+    fn straight_to_center() {
+        let mut navigate = FloodFillNavigate::from_maze(Maze::new(Wall::Open));
+
+        let (direction, debug) = navigate.navigate(
+            &CONFIG,
+            MazeOrientation {
+                position: MazePosition { x: 7, y: 4 },
+                direction: MazeDirection::North,
+            },
+            MoveOptions {
+                left: true,
+                front: true,
+                right: true,
+            },
+        );
+
+        assert_eq!(direction, MazeDirection::North);
+        assert_eq!(debug.distances[7][4], 3);
+    }
+
+    #[test]
+    fn learns_walls() {
+        let mut navigate = FloodFillNavigate::new();
+
+        let orientation = MazeOrientation {
+            position: MazePosition { x: 7, y: 6 },
+            direction: MazeDirection::North,
+        };
+
+        let (direction, _) = navigate.navigate(
+            &CONFIG,
+            orientation,
+            MoveOptions {
+                left: true,
+                front: false,
+                right: true,
+            },
+        );
+
+        assert_eq!(direction, MazeDirection::East);
+    }
+
+    #[test]
+    fn return_to_start() {
+        let config = NavigateConfig {
+            goal: Goal::Cell(MazePosition { x: 0, y: 0 }),
+        };
+
+        let mut navigate = FloodFillNavigate::from_maze(Maze::new(Wall::Open));
+
+        let (direction, debug) = navigate.navigate(
+            &config,
+            MazeOrientation {
+                position: MazePosition { x: 0, y: 3 },
+                direction: MazeDirection::East,
+            },
+            MoveOptions {
+                left: true,
+                front: true,
+                right: true,
+            },
+        );
+
+        assert_eq!(direction, MazeDirection::South);
+        assert_eq!(debug.distances[0][3], 3);
+        assert_eq!(debug.distances[0][0], 0);
+    }
+
+    #[test]
+    fn off_center_goal() {
+        let config = NavigateConfig {
+            goal: Goal::Cells([
+                Some(MazePosition { x: 12, y: 3 }),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            ]),
+        };
+
+        let mut navigate = FloodFillNavigate::from_maze(Maze::new(Wall::Open));
+
+        let (direction, debug) = navigate.navigate(
+            &config,
+            MazeOrientation {
+                position: MazePosition { x: 12, y: 8 },
+                direction: MazeDirection::North,
+            },
+            MoveOptions {
+                left: true,
+                front: true,
+                right: true,
+            },
+        );
+
+        assert_eq!(direction, MazeDirection::South);
+        assert_eq!(debug.distances[12][8], 5);
+    }
+
+    #[test]
+    fn twelve_partition_off_center_goal() {
+        let config = NavigateConfig {
+            goal: Goal::Cell(MazePosition { x: 12, y: 3 }),
+        };
+
+        let mut navigate = TwelvePartitionNavigate::new();
+
+        // South west of the goal going west, so it should turn right to head north. With the
+        // default goal this cell is south east of the goal and it would keep going west.
+        let (direction, _) = navigate.navigate(
+            &config,
+            MazeOrientation {
+                position: MazePosition { x: 10, y: 1 },
+                direction: MazeDirection::West,
+            },
+            MoveOptions {
+                left: true,
+                front: true,
+                right: true,
+            },
+        );
+
+        assert_eq!(direction, MazeDirection::North);
+    }
+
+    /// Follow a route from a cell and return where it ends up
+    fn follow(mut position: MazePosition, route: &[MazeDirection]) -> MazePosition {
+        for direction in route {
+            position = match direction {
+                MazeDirection::North => MazePosition {
+                    x: position.x,
+                    y: position.y + 1,
+                },
+                MazeDirection::South => MazePosition {
+                    x: position.x,
+                    y: position.y - 1,
+                },
+                MazeDirection::East => MazePosition {
+                    x: position.x + 1,
+                    y: position.y,
+                },
+                MazeDirection::West => MazePosition {
+                    x: position.x - 1,
+                    y: position.y,
+                },
+            };
+        }
+
+        position
+    }
+
+    #[test]
+    fn plan_route_reaches_goal() {
+        let maze = Maze::from_file(*include_bytes!(
+            "../../../micromouse_simulation/mazes/APEC2017.maz"
+        ));
+        let navigate = FloodFillNavigate::from_maze(maze);
+
+        let route = navigate.plan_route(&CONFIG, START);
+        let distances = flood_distances(&maze, &CONFIG.goal);
+
+        assert_eq!(route.len(), distances[0][0] as usize);
+        assert!(CONFIG.goal.contains(follow(START.position, &route)));
+    }
+
+    #[test]
+    fn plan_route_prefers_straight() {
+        let navigate = FloodFillNavigate::from_maze(Maze::new(Wall::Open));
+
+        let route = navigate.plan_route(&CONFIG, START);
+
+        assert_eq!(route.len(), 14);
+        assert!(route[..7]
+            .iter()
+            .all(|&direction| direction == MazeDirection::North));
+        assert!(route[7..]
+            .iter()
+            .all(|&direction| direction == MazeDirection::East));
+    }
+
+    #[test]
+    fn plan_route_weighted_avoids_penalty() {
+        let navigate = FloodFillNavigate::from_maze(Maze::new(Wall::Open));
+
+        // Going north first would be straight, but the west column costs a lot more
+        let route = navigate.plan_route_weighted(&CONFIG, START, |position| {
+            if position.x == 0 {
+                10
+            } else {
+                0
+            }
+        });
+
+        assert_eq!(route.len(), 14);
+        assert_eq!(route[0], MazeDirection::East);
+        assert!(CONFIG.goal.contains(follow(START.position, &route)));
+    }
+
+    #[test]
+    fn plan_route_weighted_no_penalty() {
+        let navigate = FloodFillNavigate::from_maze(Maze::new(Wall::Open));
+
+        assert_eq!(
+            navigate.plan_route_weighted(&CONFIG, START, |_| 0),
+            navigate.plan_route(&CONFIG, START)
+        );
+    }
+
+    #[test]
+    fn plan_route_at_goal() {
+        let navigate = FloodFillNavigate::new();
+
+        let route = navigate.plan_route(
+            &CONFIG,
+            MazeOrientation {
+                position: MazePosition { x: 8, y: 7 },
+                direction: MazeDirection::West,
+            },
+        );
+
+        assert_eq!(route.len(), 0);
     }
 }
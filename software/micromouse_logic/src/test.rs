@@ -1,6 +1,6 @@
 //! Various functions to help writing tests
 
-use crate::fast::Vector;
+use crate::fast::{Direction, Vector};
 
 const MAX_DELTA: f32 = 0.00002;
 
@@ -34,13 +34,23 @@ pub fn assert_close(left: f32, right: f32) {
 
 /// Assert that the two float vectors are close enough to be equal
 pub fn assert_close2(left: Vector, right: Vector) {
-    let delta0 = (left.x - right.x).abs();
-    let delta1 = (left.y - right.y).abs();
     assert!(
-        delta0 <= MAX_DELTA && delta1 <= MAX_DELTA,
+        left.approx_eq(right, MAX_DELTA),
         "\nleft: {:?}\nright: {:?}\ndelta: {:?}\n",
         left,
         right,
-        (delta0, delta1),
+        ((left.x - right.x).abs(), (left.y - right.y).abs()),
+    );
+}
+
+/// Assert that the two directions are close enough to be equal, even if they are on opposite
+/// sides of 0
+pub fn assert_close_direction(left: Direction, right: Direction) {
+    assert!(
+        left.approx_eq(right, MAX_DELTA),
+        "\nleft: {:?}\nright: {:?}\ndelta: {:?}\n",
+        left,
+        right,
+        left.centered_at(right) - f32::from(right),
     );
 }
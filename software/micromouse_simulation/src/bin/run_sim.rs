@@ -1,49 +1,73 @@
 use std::env;
-use std::fs::File;
-use std::io::Read;
-use std::io::Write;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
 use std::process::exit;
 use std::time::{Duration, Instant};
 
 use typenum::consts::U2048;
 
-use micromouse_logic::comms::{DebugMsg, DebugPacket};
+use micromouse_logic::comms::{crc16, DebugMsg, DebugPacket, PROTOCOL_VERSION};
 use micromouse_logic::config::sim::MOUSE_2019;
-use micromouse_logic::fast::{Orientation, Vector, DIRECTION_PI_2};
-use micromouse_logic::slow::maze::Maze;
-use micromouse_logic::slow::MazeOrientation;
-use micromouse_simulation::simulation::{Simulation, SimulationConfig};
+use micromouse_logic::config::MAZE;
+use micromouse_logic::slow::maze::{HEIGHT, WIDTH};
+use micromouse_logic::slow::{MazeDirection, MazeOrientation, MazePosition};
+use micromouse_simulation::record::SimulationRecorder;
+use micromouse_simulation::simulation::{
+    maze_from_bytes, MazeFileError, Simulation, SimulationConfig, DEFAULT_MAZE_FILE,
+};
 
 pub fn main() {
-    let args: Vec<_> = env::args().collect();
+    let mut args: Vec<_> = env::args().collect();
     println!("{:?}", args);
 
-    let maze_file_name = args.get(1).expect("No maze file provided");
-
-    println!("Using maze: {}", maze_file_name);
-
-    let mut maze_file = File::open(maze_file_name).expect("Could not open maze file");
-
-    let mut file_bytes = [0; 256];
-
-    maze_file.read_exact(&mut file_bytes).unwrap();
+    // `--record <file>` writes a recording of the run, which can be left off since it can be
+    // large. Anything else is the maze file.
+    let recording_file_name = match args.iter().position(|arg| arg == "--record") {
+        Some(i) if i + 1 < args.len() => Some(args.drain(i..i + 2).nth(1).unwrap()),
+        Some(_) => {
+            eprintln!("--record needs a file to write the recording to");
+            exit(1);
+        }
+        None => None,
+    };
 
-    let maze = Maze::from_file(file_bytes);
+    let maze = if let Some(maze_file_name) = args.get(1) {
+        println!("Using maze: {}", maze_file_name);
+
+        let file_bytes = fs::read(maze_file_name).expect("Could not read maze file");
+
+        match maze_from_bytes(&file_bytes) {
+            Ok(maze) => maze,
+            Err(MazeFileError::WrongLength(len)) => {
+                eprintln!(
+                    "Maze file is {} bytes, but it needs to be {} bytes",
+                    len,
+                    WIDTH * HEIGHT
+                );
+                exit(1);
+            }
+        }
+    } else {
+        println!("Using the default maze");
+        maze_from_bytes(DEFAULT_MAZE_FILE).unwrap()
+    };
 
     let config = SimulationConfig {
         mouse: MOUSE_2019,
         millis_per_step: 10,
         millis_per_sensor_update: 20,
-        initial_orientation: Orientation {
-            position: Vector {
-                x: 0.5 * 180.0,
-                y: 0.5 * 180.0,
-            },
-            direction: DIRECTION_PI_2,
-        },
+        initial_orientation: SimulationConfig::start_at_cell(
+            MazePosition { x: 0, y: 0 },
+            MazeDirection::North,
+            &MAZE,
+        ),
         max_wheel_accel: 1.0,
         max_speed: 1.0,
+        motor_tau: 0.0,
         maze,
+        sensor_noise_std: 0.0,
+        encoder_noise: false,
+        rng_seed: 1,
     };
 
     let mut simulation = Simulation::new(&config);
@@ -91,23 +115,46 @@ pub fn main() {
         ))
         .ok();
 
-        let packet = DebugPacket {
+        let mut packet = DebugPacket {
+            version: PROTOCOL_VERSION,
             msgs,
-            battery: 5000,
+            battery: 7.4,
             time: debug.mouse.time,
             delta_time_sys: config.millis_per_step,
             delta_time_msg: config.millis_per_step,
             count: count as u16,
+            tx_overflows: 0,
+            crc: 0,
         };
 
-        let bytes =
+        let unsealed =
             postcard::to_vec::<U2048, _>(&packet).expect("Could not serialize debug");
+        packet.crc = crc16(&unsealed);
+
+        let bytes = postcard::to_vec_cobs::<U2048, _>(&packet)
+            .expect("Could not serialize debug");
 
         outfile
             .write_all(&bytes)
             .expect("Could not write data to file");
     }
 
+    if let Some(recording_file_name) = recording_file_name {
+        println!("Recording to: {}", recording_file_name);
+
+        let recfile =
+            File::create(&recording_file_name).expect("Could not create recording file");
+        let mut recorder = SimulationRecorder::new(BufWriter::new(recfile));
+
+        for debug in debugs.iter() {
+            recorder
+                .record(debug)
+                .expect("Could not write debug to recording");
+        }
+
+        recorder.finish().expect("Could not write recording");
+    }
+
     if let Ok(ms) = result {
         println!("time: {} ms", ms);
     } else {
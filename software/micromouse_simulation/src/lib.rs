@@ -1,4 +1,6 @@
+pub mod record;
 pub mod remote;
+pub mod rng;
 pub mod simulation;
 
 use std::panic;
@@ -10,12 +12,15 @@ use console_error_panic_hook;
 #[allow(unused_imports)]
 use micromouse_logic::config::*;
 
+use simulation::MazeFileError;
 use simulation::Simulation;
 use simulation::SimulationConfig;
 
+use micromouse_logic::comms::DebugSelection;
 use micromouse_logic::config::sim::MOUSE_2019;
-use micromouse_logic::fast::{Orientation, Vector, DIRECTION_PI_2};
-use micromouse_logic::slow::maze::Maze;
+use micromouse_logic::slow::goal::{self, Goal};
+use micromouse_logic::slow::maze::{Maze, HEIGHT, WIDTH};
+use micromouse_logic::slow::{MazeDirection, MazePosition};
 use remote::Remote;
 use remote::RemoteConfig;
 
@@ -24,6 +29,25 @@ pub fn init_wasm() {
     panic::set_hook(Box::new(console_error_panic_hook::hook));
 }
 
+/// The slowest the simulation can be run compared to real time
+pub const MIN_TIME_SCALE: f32 = 0.1;
+
+/// The fastest the simulation can be run compared to real time
+pub const MAX_TIME_SCALE: f32 = 20.0;
+
+/// How long to wait between steps to run the simulation `time_scale` times faster than real
+/// time, in ms. The time scale is clamped to a range that the browser can keep up with.
+#[wasm_bindgen]
+pub fn step_interval(millis_per_step: u32, time_scale: f32) -> f32 {
+    let time_scale = if time_scale.is_nan() {
+        1.0
+    } else {
+        time_scale.max(MIN_TIME_SCALE).min(MAX_TIME_SCALE)
+    };
+
+    millis_per_step as f32 / time_scale
+}
+
 /// A wrapper for an actual Simulation that handles javascript
 /// type conversions
 #[wasm_bindgen]
@@ -52,10 +76,42 @@ impl JsSimulation {
         JsValue::from_serde(&debug).unwrap()
     }
 
+    /// Update the simulation `steps` times, but only serialize the debug from the last step.
+    /// The return is a SimulationDebug, or null if `steps` is 0.
+    pub fn step_many(&mut self, steps: u32) -> JsValue {
+        let debug = self.simulation.step_many(&self.config, steps);
+        JsValue::from_serde(&debug).unwrap()
+    }
+
     pub fn config(&mut self, config: JsValue) {
         self.config = config.into_serde().expect("Could not parse config");
     }
 
+    /// Start the run over from the beginning
+    pub fn reset(&mut self) {
+        self.simulation.reset(&self.config);
+    }
+
+    /// The maze being simulated, in the same `.maz` format that `set_maze` takes
+    pub fn maze_bytes(&self) -> Vec<u8> {
+        self.config.maze.to_file().to_vec()
+    }
+
+    /// Simulate a different maze from the contents of a `.maz` file, and start the run over in
+    /// it. Panics if the file is not a maze.
+    pub fn set_maze(&mut self, bytes: Vec<u8>) {
+        self.config.maze = match simulation::maze_from_bytes(&bytes) {
+            Ok(maze) => maze,
+            Err(MazeFileError::WrongLength(len)) => panic!(
+                "Maze file is {} bytes, but it needs to be {} bytes",
+                len,
+                WIDTH * HEIGHT
+            ),
+        };
+
+        self.reset();
+    }
+
     pub fn default_config() -> JsValue {
         /*
         let mut horizontal_walls = [[Wall::Unknown; maze::HEIGHT - 1]; maze::WIDTH];
@@ -103,28 +159,48 @@ impl JsSimulation {
 
         let maze = Maze::from_walls(horizontal_walls, vertical_walls);
         */
-        let bytes = include_bytes!("../mazes/APEC2017.maz");
-        let maze = Maze::from_file(*bytes);
+        let maze = simulation::maze_from_bytes(simulation::DEFAULT_MAZE_FILE).unwrap();
 
         JsValue::from_serde(&SimulationConfig {
             mouse: MOUSE_2019,
             millis_per_step: 10,
             millis_per_sensor_update: 20,
-            initial_orientation: Orientation {
-                position: Vector {
-                    x: 0.5 * 180.0,
-                    y: 0.5 * 180.0,
-                },
-                direction: DIRECTION_PI_2,
-            },
+            initial_orientation: SimulationConfig::start_at_cell(
+                MazePosition { x: 0, y: 0 },
+                MazeDirection::North,
+                &MAZE,
+            ),
             max_wheel_accel: 1.0,
             max_speed: 1.0,
+            motor_tau: 0.0,
             maze,
+            sensor_noise_std: 0.0,
+            encoder_noise: false,
+            rng_seed: 1,
         })
         .unwrap()
     }
 }
 
+/// Read every frame out of a recording made by a `SimulationRecorder`, so it can be looked
+/// through like a run that just happened.
+/// The return is a Result of a list of SimulationDebug, or a description of what was wrong with
+/// the recording.
+#[wasm_bindgen]
+pub fn read_recording(bytes: Vec<u8>) -> JsValue {
+    let debugs = record::read_recording(&bytes).map_err(|e| format!("{:?}", e));
+    JsValue::from_serde(&debugs).unwrap()
+}
+
+/// How many cells each cell of `maze` is from `goal`, the same as the navigator works them out.
+/// The mouse does not send these since they can be worked out from the map it does send.
+#[wasm_bindgen]
+pub fn flood_distances(maze: JsValue, goal: JsValue) -> JsValue {
+    let maze: Maze = maze.into_serde().expect("Could not parse maze");
+    let goal: Goal = goal.into_serde().expect("Could not parse goal");
+    JsValue::from_serde(&goal::flood_distances(&maze, &goal)).unwrap()
+}
+
 #[wasm_bindgen]
 pub struct JsRemote {
     remote: Remote,
@@ -145,7 +221,86 @@ impl JsRemote {
         JsValue::from_serde(&debugs).unwrap()
     }
 
+    pub fn debug_request(&self) -> Vec<u8> {
+        self.remote
+            .debug_request()
+            .map(|request| request.to_vec())
+            .unwrap_or_default()
+    }
+
     pub fn default_config() -> JsValue {
-        JsValue::from_serde(&RemoteConfig { mouse: MOUSE_2019 }).unwrap()
+        JsValue::from_serde(&RemoteConfig {
+            mouse: MOUSE_2019,
+            debug: DebugSelection::default(),
+        })
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod js_simulation_tests {
+    use micromouse_logic::config::sim::MOUSE_2019;
+    use micromouse_logic::fast::{Orientation, Vector, DIRECTION_PI_2};
+    use micromouse_logic::slow::maze::{Maze, Wall};
+
+    use super::{step_interval, JsSimulation};
+    use crate::simulation::{Simulation, SimulationConfig};
+
+    fn simulation() -> JsSimulation {
+        let config = SimulationConfig {
+            mouse: MOUSE_2019,
+            millis_per_step: 10,
+            millis_per_sensor_update: 20,
+            initial_orientation: Orientation {
+                position: Vector { x: 90.0, y: 90.0 },
+                direction: DIRECTION_PI_2,
+            },
+            max_wheel_accel: 1.0,
+            max_speed: 1.0,
+            motor_tau: 0.0,
+            maze: Maze::new(Wall::Open),
+            sensor_noise_std: 0.0,
+            encoder_noise: false,
+            rng_seed: 1,
+        };
+
+        JsSimulation {
+            simulation: Simulation::new(&config),
+            config,
+        }
+    }
+
+    #[test]
+    fn set_maze_round_trip() {
+        let mut simulation = simulation();
+        let bytes = include_bytes!("../mazes/APEC2017.maz").to_vec();
+
+        simulation.simulation.step_many(&simulation.config, 100);
+        simulation.set_maze(bytes.clone());
+
+        assert_eq!(simulation.maze_bytes(), bytes);
+
+        // The run starts over in the new maze
+        let first = simulation.simulation.update(&simulation.config);
+        assert_eq!(first.mouse.time, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Maze file is 10 bytes, but it needs to be 256 bytes")]
+    fn set_maze_wrong_length() {
+        simulation().set_maze(vec![0; 10]);
+    }
+
+    #[test]
+    fn time_scale() {
+        assert_eq!(step_interval(10, 1.0), 10.0);
+        assert_eq!(step_interval(10, 2.0), 5.0);
+        assert_eq!(step_interval(10, 0.5), 20.0);
+
+        // Out of range scales are clamped
+        assert_eq!(step_interval(10, 100.0), 0.5);
+        assert_eq!(step_interval(10, 0.0), 100.0);
+        assert_eq!(step_interval(10, -3.0), 100.0);
+        assert_eq!(step_interval(10, core::f32::NAN), 10.0);
     }
 }
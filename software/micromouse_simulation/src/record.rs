@@ -0,0 +1,272 @@
+//! Saving a simulation run to a file and playing it back later
+//!
+//! A recording is every `SimulationDebug` from the run, one after the other. Each one is
+//! serialized with postcard and written after its length as a little endian `u32`.
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+use postcard;
+
+use crate::simulation::SimulationDebug;
+
+/// The most bytes one serialized `SimulationDebug` can take
+pub const MAX_FRAME_SIZE: usize = 16384;
+
+#[derive(Debug)]
+pub enum RecordError {
+    /// Reading or writing the recording failed
+    Io(io::Error),
+
+    /// A frame could not be serialized or deserialized
+    Postcard(postcard::Error),
+
+    /// A frame said it was longer than `MAX_FRAME_SIZE`. Holds the length it said.
+    FrameTooLong(usize),
+
+    /// The recording ended partway through a frame
+    Truncated,
+}
+
+impl From<io::Error> for RecordError {
+    fn from(error: io::Error) -> RecordError {
+        RecordError::Io(error)
+    }
+}
+
+impl From<postcard::Error> for RecordError {
+    fn from(error: postcard::Error) -> RecordError {
+        RecordError::Postcard(error)
+    }
+}
+
+/// Writes each `SimulationDebug` it is given to a recording
+pub struct SimulationRecorder<W: Write> {
+    writer: W,
+    buf: Vec<u8>,
+    frames: usize,
+}
+
+impl<W: Write> SimulationRecorder<W> {
+    pub fn new(writer: W) -> SimulationRecorder<W> {
+        SimulationRecorder {
+            writer,
+            buf: vec![0; MAX_FRAME_SIZE],
+            frames: 0,
+        }
+    }
+
+    /// Add one step of the simulation to the end of the recording
+    pub fn record(&mut self, debug: &SimulationDebug) -> Result<(), RecordError> {
+        let bytes = postcard::to_slice(debug, &mut self.buf)?;
+
+        self.writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.writer.write_all(bytes)?;
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    /// How many frames have been recorded so far
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Flush anything that is left and get back the writer
+    pub fn finish(mut self) -> Result<W, RecordError> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads back the `SimulationDebug`s in a recording, in the order they were recorded
+pub struct SimulationPlayer<R: Read> {
+    reader: R,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> SimulationPlayer<R> {
+    pub fn new(reader: R) -> SimulationPlayer<R> {
+        SimulationPlayer {
+            reader,
+            buf: vec![0; MAX_FRAME_SIZE],
+            done: false,
+        }
+    }
+
+    /// Read the next frame, or `None` if the recording ended cleanly between frames
+    fn next_frame(&mut self) -> Result<Option<SimulationDebug>, RecordError> {
+        let mut len_bytes = [0u8; 4];
+
+        // Only the very first byte of a frame is allowed to be the end of the recording
+        match self.reader.read(&mut len_bytes[..1]) {
+            Ok(0) => return Ok(None),
+            Ok(_) => {}
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {
+                return self.next_frame();
+            }
+            Err(error) => return Err(error.into()),
+        }
+
+        read_exact(&mut self.reader, &mut len_bytes[1..])?;
+
+        let len = u32::from_le_bytes(len_bytes) as usize;
+        if len > MAX_FRAME_SIZE {
+            return Err(RecordError::FrameTooLong(len));
+        }
+
+        read_exact(&mut self.reader, &mut self.buf[..len])?;
+
+        Ok(Some(postcard::from_bytes(&self.buf[..len])?))
+    }
+}
+
+/// Read every frame out of a whole recording at once
+pub fn read_recording(bytes: &[u8]) -> Result<Vec<SimulationDebug>, RecordError> {
+    SimulationPlayer::new(bytes).collect()
+}
+
+/// Like `Read::read_exact`, but running out of bytes is a truncated recording
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), RecordError> {
+    reader.read_exact(buf).map_err(|error| {
+        if error.kind() == io::ErrorKind::UnexpectedEof {
+            RecordError::Truncated
+        } else {
+            RecordError::Io(error)
+        }
+    })
+}
+
+impl<R: Read> Iterator for SimulationPlayer<R> {
+    type Item = Result<SimulationDebug, RecordError>;
+
+    /// Stops after the end of the recording or the first error
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.next_frame() {
+            Ok(Some(debug)) => Some(Ok(debug)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod record_tests {
+    use micromouse_logic::config::sim::MOUSE_2019;
+    use micromouse_logic::fast::{Orientation, Vector, DIRECTION_PI_2};
+
+    use super::{read_recording, RecordError, SimulationPlayer, SimulationRecorder};
+    use crate::simulation::{
+        maze_from_bytes, Simulation, SimulationConfig, SimulationDebug, DEFAULT_MAZE_FILE,
+    };
+
+    fn record_run(steps: usize) -> (Vec<SimulationDebug>, Vec<u8>) {
+        let config = SimulationConfig {
+            mouse: MOUSE_2019,
+            millis_per_step: 10,
+            millis_per_sensor_update: 20,
+            initial_orientation: Orientation {
+                position: Vector { x: 90.0, y: 90.0 },
+                direction: DIRECTION_PI_2,
+            },
+            max_wheel_accel: 1.0,
+            max_speed: 1.0,
+            motor_tau: 0.0,
+            maze: maze_from_bytes(DEFAULT_MAZE_FILE).unwrap(),
+            sensor_noise_std: 0.0,
+            encoder_noise: false,
+            rng_seed: 1,
+        };
+
+        let mut simulation = Simulation::new(&config);
+        let mut recorder = SimulationRecorder::new(Vec::new());
+        let mut debugs = Vec::new();
+
+        for _ in 0..steps {
+            let debug = simulation.update(&config);
+            recorder.record(&debug).unwrap();
+            debugs.push(debug);
+        }
+
+        assert_eq!(recorder.frames(), steps);
+
+        (debugs, recorder.finish().unwrap())
+    }
+
+    #[test]
+    fn round_trip() {
+        let (debugs, bytes) = record_run(10);
+
+        let played = SimulationPlayer::new(bytes.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(played.len(), debugs.len());
+        for (played, debug) in played.iter().zip(debugs.iter()) {
+            assert_eq!(played.orientation, debug.orientation);
+            assert_eq!(played.left_encoder, debug.left_encoder);
+            assert_eq!(played.right_encoder, debug.right_encoder);
+        }
+
+        // The first steps have NaN velocities that never compare equal, so check that the whole
+        // frames came back the same by recording them again
+        let mut recorder = SimulationRecorder::new(Vec::new());
+        for debug in played.iter() {
+            recorder.record(debug).unwrap();
+        }
+
+        assert_eq!(recorder.finish().unwrap(), bytes);
+    }
+
+    #[test]
+    fn read_whole_recording() {
+        let (debugs, bytes) = record_run(5);
+
+        let played = read_recording(&bytes).unwrap();
+
+        assert_eq!(played.len(), debugs.len());
+        assert_eq!(
+            played.last().map(|debug| debug.mouse.time),
+            debugs.last().map(|debug| debug.mouse.time)
+        );
+
+        assert!(read_recording(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn empty_recording() {
+        let bytes: &[u8] = &[];
+
+        assert_eq!(SimulationPlayer::new(bytes).count(), 0);
+    }
+
+    #[test]
+    fn truncated_recording() {
+        let (_, mut bytes) = record_run(3);
+        bytes.pop();
+
+        let mut player = SimulationPlayer::new(bytes.as_slice());
+
+        assert!(player.next().unwrap().is_ok());
+        assert!(player.next().unwrap().is_ok());
+
+        match player.next() {
+            Some(Err(RecordError::Truncated)) => {}
+            other => panic!("Expected a truncated frame, got {:?}", other),
+        }
+
+        assert!(player.next().is_none());
+    }
+}
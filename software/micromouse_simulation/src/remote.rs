@@ -3,8 +3,12 @@ use postcard;
 use serde::Deserialize;
 use serde::Serialize;
 
+use micromouse_logic::comms::crc16;
 use micromouse_logic::comms::DebugMsg;
 use micromouse_logic::comms::DebugPacket;
+use micromouse_logic::comms::DebugSelection;
+use micromouse_logic::comms::PROTOCOL_VERSION;
+use micromouse_logic::comms::SELECT_DEBUG_BYTE;
 
 use micromouse_logic::mouse::MouseConfig;
 use micromouse_logic::mouse::MouseDebug;
@@ -12,6 +16,10 @@ use micromouse_logic::mouse::MouseDebug;
 #[derive(Clone, Default, Serialize, Deserialize)]
 pub struct RemoteConfig {
     pub mouse: MouseConfig,
+
+    /// Which messages to ask the mouse for. Configs saved before this existed get the default.
+    #[serde(default)]
+    pub debug: DebugSelection,
 }
 
 #[derive(Clone, Default, Serialize, Deserialize)]
@@ -20,11 +28,59 @@ pub struct RemoteDebug {
     delta_time_msg: u32,
     config: RemoteConfig,
     bytes: usize,
+    dropped_packets: usize,
+
+    /// Bytes the mouse reported dropping before they could be sent
+    tx_overflows: u32,
+
+    /// Packets that were dropped because they came from a different version of the protocol
+    unknown_version_packets: usize,
+
+    /// The version of the last packet that was not `PROTOCOL_VERSION`
+    unknown_version: Option<u8>,
+}
+
+/// Check that the crc in the packet matches the rest of it. The crc is cleared afterwards.
+fn check_crc(packet: &mut DebugPacket) -> bool {
+    let crc = packet.crc;
+    packet.crc = 0;
+
+    let mut buf = [0u8; 2048];
+    postcard::to_slice(packet, &mut buf)
+        .map(|bytes| crc16(bytes) == crc)
+        .unwrap_or(false)
+}
+
+/// The parts of a full `MouseDebug` that the mouse splits across `DebugMsg`s
+#[derive(Clone, Copy, Default)]
+struct FrameParts {
+    orientation: bool,
+    hardware: bool,
+    slow: bool,
+    localize: bool,
+}
+
+impl FrameParts {
+    fn any(&self) -> bool {
+        self.orientation || self.hardware || self.slow || self.localize
+    }
+
+    fn all(&self) -> bool {
+        self.orientation && self.hardware && self.slow && self.localize
+    }
+}
+
+/// A frame that has only gotten some of its parts so far
+struct PartialFrame {
+    count: u16,
+    mouse: MouseDebug,
+    parts: FrameParts,
 }
 
 pub struct Remote {
     debug: RemoteDebug,
     buf: Vec<u8>,
+    partial: Option<PartialFrame>,
 }
 
 impl Remote {
@@ -36,6 +92,7 @@ impl Remote {
         Remote {
             debug,
             buf: Vec::new(),
+            partial: None,
         }
     }
 
@@ -43,59 +100,428 @@ impl Remote {
         RemoteConfig::default()
     }
 
+    /// The bytes to send to the mouse to get the messages selected in the config. There are none
+    /// for the default selection, so a mouse that can't select messages is left alone unless a
+    /// different selection is asked for.
+    pub fn debug_request(&self) -> Option<[u8; 2]> {
+        if self.debug.config.debug == DebugSelection::default() {
+            None
+        } else {
+            Some([SELECT_DEBUG_BYTE, self.debug.config.debug.to_byte()])
+        }
+    }
+
     pub fn update(&mut self, bytes: &[u8]) -> Result<Vec<RemoteDebug>, String> {
         let mut debugs = Vec::new();
 
         for &byte in bytes {
-            self.buf.push(byte);
-            match postcard::take_from_bytes::<DebugPacket>(&self.buf) {
-                Ok((packet, remaining)) => {
-                    self.debug.bytes = self.buf.len() - remaining.len();
-                    self.buf = Vec::from(remaining.clone());
-
-                    self.debug.mouse.time = packet.time;
-                    self.debug.mouse.delta_time = packet.delta_time_sys;
-                    self.debug.delta_time_msg = packet.delta_time_msg;
-                    self.debug.mouse.battery = packet.battery;
-
-                    for msg in packet.msgs {
-                        match msg {
-                            DebugMsg::Hardware(hardware) => {
-                                self.debug.mouse.hardware = hardware
-                            }
-                            DebugMsg::Orientation(orientation) => {
-                                self.debug.mouse.orientation = orientation
-                            }
-                            DebugMsg::Slow(Some(slow)) => {
-                                self.debug.mouse.slow = Some(slow)
-                            }
-                            DebugMsg::Slow(None) => {}
-                            DebugMsg::MotorControl(motor_control) => {
-                                self.debug.mouse.motion_control.motor_control =
-                                    motor_control
-                            }
-                            DebugMsg::MotionHandler(handler) => {
-                                self.debug.mouse.motion_control.handler = handler
-                            }
-                            DebugMsg::MotionQueue(queue) => {
-                                self.debug.mouse.motion_queue = queue
-                            }
-                            DebugMsg::Localize(localize) => {
-                                self.debug.mouse.localize = localize
-                            }
-                        }
-                    }
+            // Every packet is cobs encoded and ends in a 0, so anything that gets garbled only
+            // ruins the one packet it is in
+            if byte != 0 {
+                self.buf.push(byte);
+                continue;
+            }
+
+            let mut frame = core::mem::replace(&mut self.buf, Vec::new());
+
+            if frame.is_empty() {
+                continue;
+            }
+
+            self.debug.bytes = frame.len() + 1;
+
+            // Check the version before reading the rest of the packet, since a different layout
+            // might still parse into something
+            match postcard::from_bytes_cobs::<u8>(&mut frame.clone()) {
+                Ok(PROTOCOL_VERSION) => {}
+                Ok(version) => {
+                    self.debug.dropped_packets += 1;
+                    self.debug.unknown_version_packets += 1;
+                    self.debug.unknown_version = Some(version);
+                    continue;
+                }
+                Err(_) => {
+                    self.debug.dropped_packets += 1;
+                    continue;
+                }
+            }
 
+            let mut packet = match postcard::from_bytes_cobs::<DebugPacket>(&mut frame) {
+                Ok(packet) => packet,
+                Err(_) => {
+                    self.debug.dropped_packets += 1;
+                    continue;
+                }
+            };
+
+            if !check_crc(&mut packet) {
+                self.debug.dropped_packets += 1;
+                continue;
+            }
+
+            // A frame that is still missing parts once the next one starts is as complete as
+            // it is going to get
+            if let Some(partial) = self.partial.take() {
+                if partial.count == packet.count {
+                    self.partial = Some(partial);
+                } else {
+                    self.debug.mouse = partial.mouse;
                     debugs.push(self.debug.clone());
                 }
-                Err(postcard::Error::DeserializeUnexpectedEnd) => {}
-                Err(e) => {
-                    self.buf = Vec::new();
-                    return Err(e.to_string());
+            }
+
+            // Anything the frame does not get is left over from the last one
+            let mut partial = self.partial.take().unwrap_or_else(|| PartialFrame {
+                count: packet.count,
+                mouse: self.debug.mouse.clone(),
+                parts: FrameParts::default(),
+            });
+
+            partial.mouse.time = packet.time;
+            partial.mouse.delta_time = packet.delta_time_sys;
+            partial.mouse.battery = packet.battery;
+            self.debug.delta_time_msg = packet.delta_time_msg;
+            self.debug.tx_overflows = packet.tx_overflows;
+
+            let mouse = &mut partial.mouse;
+            let parts = &mut partial.parts;
+
+            for msg in packet.msgs {
+                match msg {
+                    DebugMsg::Hardware(hardware) => {
+                        mouse.hardware = hardware;
+                        parts.hardware = true;
+                    }
+                    DebugMsg::Orientation(orientation) => {
+                        mouse.orientation = orientation;
+                        parts.orientation = true;
+                    }
+                    DebugMsg::Slow(slow) => {
+                        if let Some(slow) = slow {
+                            mouse.slow = Some(slow);
+                        }
+                        parts.slow = true;
+                    }
+                    DebugMsg::MotorControl(motor_control) => {
+                        mouse.motion_control.motor_control = motor_control
+                    }
+                    DebugMsg::MotionHandler(handler) => {
+                        mouse.motion_control.handler = handler
+                    }
+                    DebugMsg::MotionQueue(queue) => mouse.motion_queue = queue,
+                    DebugMsg::Localize(localize) => {
+                        mouse.localize = localize;
+                        parts.localize = true;
+                    }
+                    DebugMsg::Trace(record) => {
+                        let telemetry = record.telemetry;
+                        mouse.orientation = telemetry.orientation();
+                        mouse.battery = telemetry.battery();
+                        mouse.hardware.left_encoder = record.left_encoder;
+                        mouse.hardware.right_encoder = record.right_encoder;
+                        mouse.hardware.left_distance = telemetry.left_distance();
+                        mouse.hardware.front_distance = telemetry.front_distance();
+                        mouse.hardware.right_distance = telemetry.right_distance();
+                    }
+                    DebugMsg::Telemetry(telemetry) => {
+                        mouse.orientation = telemetry.orientation();
+                        mouse.battery = telemetry.battery();
+                        mouse.hardware.left_distance = telemetry.left_distance();
+                        mouse.hardware.front_distance = telemetry.front_distance();
+                        mouse.hardware.right_distance = telemetry.right_distance();
+                    }
                 }
             }
+
+            // Packets without any of the split up parts stand on their own
+            if partial.parts.all() || !partial.parts.any() {
+                self.debug.mouse = partial.mouse;
+                debugs.push(self.debug.clone());
+            } else {
+                self.partial = Some(partial);
+            }
         }
 
         Ok(debugs)
     }
 }
+
+#[cfg(test)]
+mod remote_tests {
+    use heapless::Vec;
+    use typenum::{U2048, U4};
+
+    use micromouse_logic::comms::{
+        crc16, DebugMsg, DebugPacket, DebugSelection, Telemetry, PROTOCOL_VERSION,
+        SELECT_DEBUG_BYTE,
+    };
+    use micromouse_logic::fast::localize::LocalizeDebug;
+    use micromouse_logic::fast::{Orientation, Vector};
+    use micromouse_logic::mouse::{DistanceReading, HardwareDebug, MouseDebug};
+    use micromouse_logic::slow::maze::{Maze, Wall, WallDirection, WallIndex};
+
+    use super::{Remote, RemoteConfig};
+
+    fn packet_bytes() -> Vec<u8, U2048> {
+        packet_bytes_version(PROTOCOL_VERSION)
+    }
+
+    fn packet_bytes_version(version: u8) -> Vec<u8, U2048> {
+        let mut msgs = Vec::new();
+        msgs.push(DebugMsg::Orientation(Orientation::default()))
+            .ok();
+        msgs.push(DebugMsg::Hardware(HardwareDebug::default())).ok();
+        msgs.push(DebugMsg::Slow(None)).ok();
+        msgs.push(DebugMsg::Localize(LocalizeDebug::default())).ok();
+
+        packet_bytes_msgs(version, 1, msgs)
+    }
+
+    fn packet_bytes_msgs(
+        version: u8,
+        count: u16,
+        msgs: Vec<DebugMsg, U4>,
+    ) -> Vec<u8, U2048> {
+        let mut packet = DebugPacket {
+            version,
+            msgs,
+            battery: 7.4,
+            time: 1234,
+            delta_time_sys: 10,
+            delta_time_msg: 10,
+            count,
+            tx_overflows: 7,
+            crc: 0,
+        };
+
+        let unsealed = postcard::to_vec::<U2048, _>(&packet).unwrap();
+        packet.crc = crc16(&unsealed);
+        postcard::to_vec_cobs(&packet).unwrap()
+    }
+
+    #[test]
+    fn good_packet() {
+        let mut remote = Remote::new(&RemoteConfig::default());
+        let debugs = remote.update(&packet_bytes()).unwrap();
+
+        assert_eq!(debugs.len(), 1);
+        assert_eq!(debugs[0].mouse.time, 1234);
+        assert_eq!(debugs[0].tx_overflows, 7);
+    }
+
+    #[test]
+    fn reassembles_split_frame() {
+        let orientation = Orientation {
+            position: Vector { x: 90.0, y: 180.0 },
+            ..Orientation::default()
+        };
+
+        let mut hardware = HardwareDebug::default();
+        hardware.front_distance = Some(DistanceReading::InRange(50.0));
+
+        let mut first = Vec::new();
+        first.push(DebugMsg::Orientation(orientation)).ok();
+        first.push(DebugMsg::Hardware(hardware)).ok();
+
+        let mut second = Vec::new();
+        second.push(DebugMsg::Slow(None)).ok();
+        second
+            .push(DebugMsg::Localize(LocalizeDebug::default()))
+            .ok();
+
+        let mut remote = Remote::new(&RemoteConfig::default());
+
+        // Nothing comes out until the rest of the frame shows up
+        let debugs = remote
+            .update(&packet_bytes_msgs(PROTOCOL_VERSION, 5, first))
+            .unwrap();
+        assert_eq!(debugs.len(), 0);
+
+        let debugs = remote
+            .update(&packet_bytes_msgs(PROTOCOL_VERSION, 5, second))
+            .unwrap();
+        assert_eq!(debugs.len(), 1);
+        assert_eq!(debugs[0].mouse.orientation, orientation);
+        assert_eq!(debugs[0].mouse.hardware, hardware);
+    }
+
+    #[test]
+    fn incomplete_frame_keeps_last_parts() {
+        let mut hardware = HardwareDebug::default();
+        hardware.front_distance = Some(DistanceReading::InRange(50.0));
+
+        let mut full = Vec::new();
+        full.push(DebugMsg::Orientation(Orientation::default()))
+            .ok();
+        full.push(DebugMsg::Hardware(hardware)).ok();
+        full.push(DebugMsg::Slow(None)).ok();
+        full.push(DebugMsg::Localize(LocalizeDebug::default())).ok();
+
+        let orientation = Orientation {
+            position: Vector { x: 90.0, y: 180.0 },
+            ..Orientation::default()
+        };
+
+        // The rest of this one got lost
+        let mut partial = Vec::new();
+        partial.push(DebugMsg::Orientation(orientation)).ok();
+
+        let mut stream = std::vec::Vec::new();
+        stream.extend_from_slice(&packet_bytes_msgs(PROTOCOL_VERSION, 1, full));
+        stream.extend_from_slice(&packet_bytes_msgs(PROTOCOL_VERSION, 2, partial));
+        stream.extend_from_slice(&packet_bytes_msgs(PROTOCOL_VERSION, 3, Vec::new()));
+
+        let mut remote = Remote::new(&RemoteConfig::default());
+        let debugs = remote.update(&stream).unwrap();
+
+        assert_eq!(debugs.len(), 3);
+        assert_eq!(debugs[1].mouse.orientation, orientation);
+        assert_eq!(debugs[1].mouse.hardware, hardware);
+    }
+
+    #[test]
+    fn debug_request() {
+        let config = RemoteConfig {
+            debug: DebugSelection::from_byte(0x81),
+            ..RemoteConfig::default()
+        };
+
+        let remote = Remote::new(&config);
+        assert_eq!(remote.debug_request(), Some([SELECT_DEBUG_BYTE, 0x81]));
+    }
+
+    #[test]
+    fn default_debug_request() {
+        let remote = Remote::new(&RemoteConfig::default());
+        assert_eq!(remote.debug_request(), None);
+    }
+
+    #[test]
+    fn corrupted_packet() {
+        let mut bytes = packet_bytes();
+
+        // Either it no longer decodes or the crc catches it. Avoid making it a 0 or it would
+        // split the frame in two instead.
+        bytes[5] = if bytes[5] == 0x10 { 0x20 } else { 0x10 };
+
+        let mut remote = Remote::new(&RemoteConfig::default());
+        let debugs = remote.update(&bytes).unwrap();
+
+        assert_eq!(debugs.len(), 0);
+        assert_eq!(remote.debug.dropped_packets, 1);
+    }
+
+    #[test]
+    fn unknown_version() {
+        let mut remote = Remote::new(&RemoteConfig::default());
+        let debugs = remote
+            .update(&packet_bytes_version(PROTOCOL_VERSION + 1))
+            .unwrap();
+
+        assert_eq!(debugs.len(), 0);
+        assert_eq!(remote.debug.dropped_packets, 1);
+        assert_eq!(remote.debug.unknown_version_packets, 1);
+        assert_eq!(remote.debug.unknown_version, Some(PROTOCOL_VERSION + 1));
+
+        // Packets with the right version still get through afterwards
+        let debugs = remote.update(&packet_bytes()).unwrap();
+        assert_eq!(debugs.len(), 1);
+    }
+
+    #[test]
+    fn split_frames() {
+        let mut stream = std::vec::Vec::new();
+        stream.extend_from_slice(&packet_bytes());
+        stream.extend_from_slice(&packet_bytes());
+
+        for &split in [1, 3, 7, 13].iter() {
+            let mut remote = Remote::new(&RemoteConfig::default());
+
+            let count: usize = stream
+                .chunks(split)
+                .map(|chunk| remote.update(chunk).unwrap().len())
+                .sum();
+
+            assert_eq!(count, 2);
+        }
+    }
+
+    #[test]
+    fn recovers_after_partial_frame() {
+        let bytes = packet_bytes();
+
+        // The start of the stream was missed, so the first frame is only half there
+        let mut stream = std::vec::Vec::new();
+        stream.extend_from_slice(&bytes[bytes.len() / 2..]);
+        stream.extend_from_slice(&bytes);
+
+        let mut remote = Remote::new(&RemoteConfig::default());
+        let debugs = remote.update(&stream).unwrap();
+
+        assert_eq!(debugs.len(), 1);
+        assert_eq!(remote.debug.dropped_packets, 1);
+    }
+
+    #[test]
+    fn maze_round_trip() {
+        let mut maze = Maze::new(Wall::Unknown);
+        maze.set_wall(
+            WallIndex {
+                x: 3,
+                y: 15,
+                direction: WallDirection::Horizontal,
+            },
+            Wall::Closed,
+        )
+        .unwrap();
+
+        // Only the walls inside the perimeter are sent
+        let bytes = postcard::to_vec::<U2048, _>(&maze).unwrap();
+        assert_eq!(bytes.len(), 16 * 15 + 15 * 16);
+        assert_eq!(postcard::from_bytes::<Maze>(&bytes).unwrap(), maze);
+
+        let small = Maze::<8, 8>::filled(Wall::Open);
+        let bytes = postcard::to_vec::<U2048, _>(&small).unwrap();
+        assert_eq!(bytes.len(), 8 * 7 + 7 * 8);
+        assert_eq!(postcard::from_bytes::<Maze<8, 8>>(&bytes).unwrap(), small);
+    }
+
+    #[test]
+    fn telemetry_is_small() {
+        let mut debug = MouseDebug::default();
+        debug.hardware.left_distance = Some(DistanceReading::InRange(40.0));
+
+        let msg = DebugMsg::Telemetry(Telemetry::from(&debug));
+        let bytes = postcard::to_vec::<U2048, _>(&msg).unwrap();
+        assert!(bytes.len() <= 16);
+
+        // A whole packet with only telemetry in it
+        let mut msgs = Vec::new();
+        msgs.push(msg).ok();
+        let packet = DebugPacket {
+            version: PROTOCOL_VERSION,
+            msgs,
+            battery: 7.4,
+            time: 1234,
+            delta_time_sys: 10,
+            delta_time_msg: 10,
+            count: 1,
+            tx_overflows: 0,
+            crc: 0,
+        };
+        let bytes = postcard::to_vec_cobs::<U2048, _>(&packet).unwrap();
+        assert!(bytes.len() <= 40);
+
+        let mut remote = Remote::new(&RemoteConfig::default());
+        let mut packet = packet;
+        let unsealed = postcard::to_vec::<U2048, _>(&packet).unwrap();
+        packet.crc = crc16(&unsealed);
+        let debugs = remote
+            .update(&postcard::to_vec_cobs::<U2048, _>(&packet).unwrap())
+            .unwrap();
+        assert_eq!(
+            debugs[0].mouse.hardware.left_distance,
+            Some(DistanceReading::InRange(40.0))
+        );
+    }
+}
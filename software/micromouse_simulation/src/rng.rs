@@ -0,0 +1,41 @@
+//! A small random number generator so that runs of the simulation can be repeated exactly
+
+use std::f32::consts::PI;
+
+/// xorshift64*
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // xorshift gets stuck on 0 forever
+        let state = if seed == 0 {
+            0x9e37_79b9_7f4a_7c15
+        } else {
+            seed
+        };
+        Rng { state }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number evenly spread between 0.0 and 1.0, not including 1.0
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// A number from a normal distribution with a mean of 0.0 and standard deviation of `std`
+    pub fn next_gaussian(&mut self, std: f32) -> f32 {
+        // Box-Muller, which needs u1 to not be 0
+        let u1 = 1.0 - self.next_f32();
+        let u2 = self.next_f32();
+
+        (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos() * std
+    }
+}
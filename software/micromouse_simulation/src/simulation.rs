@@ -1,15 +1,19 @@
+use std::convert::TryFrom;
 use std::f32;
 
 use serde::Deserialize;
 use serde::Serialize;
 
-use micromouse_logic::fast::{
-    Orientation, Vector, DIRECTION_0, DIRECTION_3_PI_2, DIRECTION_PI_2,
-};
+use micromouse_logic::config::MechanicalConfig;
+use micromouse_logic::fast::{Orientation, Vector, DIRECTION_0};
 use micromouse_logic::mouse::{DistanceReading, Mouse, MouseConfig, MouseDebug};
 use micromouse_logic::slow::maze::{
-    Maze, MazeConfig, MazeIndex, MazeProjectionResult, Wall,
+    Maze, MazeConfig, MazeIndex, MazeProjectionResult, Wall, WallDirection, WallIndex,
+    HEIGHT, WIDTH,
 };
+use micromouse_logic::slow::{MazeDirection, MazePosition};
+
+use crate::rng::Rng;
 
 #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct SimulationDebug {
@@ -22,10 +26,19 @@ pub struct SimulationDebug {
     pub right_accel: f32,
     pub left_ground_speed: f32,
     pub right_ground_speed: f32,
+
+    /// How much further each wheel turned than the ground went by this step, in mm
+    pub left_slip: f32,
+    pub right_slip: f32,
+
     pub left_distance: Option<DistanceReading>,
     pub front_distance: Option<DistanceReading>,
     pub right_distance: Option<DistanceReading>,
     pub orientation: Orientation,
+
+    /// Whether the body of the mouse ran into a closed wall or post this step
+    pub collided: bool,
+
     pub config: SimulationConfig,
 }
 
@@ -36,17 +49,118 @@ pub struct SimulationConfig {
     pub millis_per_step: u32,
     pub millis_per_sensor_update: u32,
 
-    /// The max speed a wheel can accelerate by before slipping
+    /// The max a wheel can accelerate or slow down by before slipping, in mm/ms/ms
     pub max_wheel_accel: f32,
     pub max_speed: f32,
 
+    /// The time constant of the motors, in ms. The speed of a wheel closes this fraction of the
+    /// gap to the commanded speed every ms. 0.0 makes the wheels reach the commanded speed right
+    /// away.
+    pub motor_tau: f32,
+
     pub maze: Maze,
+
+    /// The standard deviation of the noise added to distance sensor readings, in mm
+    pub sensor_noise_std: f32,
+
+    /// Randomly drop or add a tick to the encoders, like real encoders do now and then
+    pub encoder_noise: bool,
+
+    /// Runs with the same seed get the same noise
+    pub rng_seed: u64,
 }
 
 impl SimulationConfig {
     pub fn sec_per_step(&self) -> f32 {
         self.millis_per_step as f32 / 1000.0
     }
+
+    /// The orientation of a mouse sitting in the center of `cell`, facing `facing`. Use this for
+    /// `initial_orientation` to start somewhere other than the usual corner.
+    pub fn start_at_cell(
+        cell: MazePosition,
+        facing: MazeDirection,
+        maze: &MazeConfig,
+    ) -> Orientation {
+        Orientation {
+            position: cell.center_position(maze),
+            direction: facing.into_direction(),
+        }
+    }
+}
+
+/// The maze to simulate when no other maze is given
+pub const DEFAULT_MAZE_FILE: &[u8] = include_bytes!("../mazes/APEC2017.maz");
+
+/// Why the contents of a maze file could not be turned into a maze
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MazeFileError {
+    /// The file needs exactly one byte for every cell, but it had this many
+    WrongLength(usize),
+}
+
+/// Read a maze from the contents of a `.maz` file, in the format that `Maze::from_file` reads
+pub fn maze_from_bytes(bytes: &[u8]) -> Result<Maze, MazeFileError> {
+    let cells = <[u8; WIDTH * HEIGHT]>::try_from(bytes)
+        .map_err(|_| MazeFileError::WrongLength(bytes.len()))?;
+
+    Ok(Maze::from_file(cells))
+}
+
+/// Move a wheel speed toward the speed the motor is being told to go, like a real motor that
+/// takes some time to speed up or slow down
+fn motor_lag(config: &SimulationConfig, speed: f32, commanded_speed: f32) -> f32 {
+    let fraction = if config.motor_tau > 0.0 {
+        (config.millis_per_step as f32 / config.motor_tau).min(1.0)
+    } else {
+        1.0
+    };
+
+    speed + (commanded_speed - speed) * fraction
+}
+
+/// Add some noise to a distance reading. Out of range readings are left alone.
+fn add_noise(rng: &mut Rng, std: f32, reading: DistanceReading) -> DistanceReading {
+    match reading {
+        DistanceReading::InRange(distance) if std != 0.0 => {
+            DistanceReading::InRange((distance + rng.next_gaussian(std)).max(0.0))
+        }
+        reading => reading,
+    }
+}
+
+/// The speed the ground goes by under a wheel. If the wheel speeds up or slows down faster than
+/// `max_wheel_accel`, it slips and the ground only changes speed as fast as `max_wheel_accel`.
+fn traction_limit(config: &SimulationConfig, ground_speed: f32, wheel_speed: f32) -> f32 {
+    let accel = (wheel_speed - ground_speed) / config.millis_per_step as f32;
+
+    if accel.abs() > config.max_wheel_accel {
+        ground_speed
+            + config.max_wheel_accel.copysign(accel) * config.millis_per_step as f32
+    } else {
+        wheel_speed
+    }
+}
+
+/// How likely an encoder that is turning is to drop or add a tick each step
+const ENCODER_MISCOUNT_CHANCE: f32 = 0.1;
+
+/// Maybe drop or add a tick to the change in an encoder. An encoder that is not turning never
+/// miscounts.
+fn add_encoder_noise(rng: &mut Rng, delta: i32) -> i32 {
+    if delta == 0 {
+        return delta;
+    }
+
+    let n = rng.next_f32();
+
+    if n < ENCODER_MISCOUNT_CHANCE / 2.0 {
+        delta - 1
+    } else if n < ENCODER_MISCOUNT_CHANCE {
+        delta + 1
+    } else {
+        delta
+    }
 }
 
 /// Find the closest closed wall
@@ -64,35 +178,162 @@ fn find_closed_wall(
     })
 }
 
+/// Whether a point is inside of a closed wall or post. Anything outside of the maze is counted
+/// as inside the perimeter wall.
+fn point_in_wall(config: &MazeConfig, maze: &Maze, point: Vector) -> bool {
+    let half_wall = config.wall_width / 2.0;
+    let maze_width = WIDTH as f32 * config.cell_width;
+    let maze_height = HEIGHT as f32 * config.cell_width;
+
+    if point.x < half_wall
+        || point.y < half_wall
+        || point.x > maze_width - half_wall
+        || point.y > maze_height - half_wall
+    {
+        return true;
+    }
+
+    // The closest grid lines, which is where the walls are centered
+    let line_x = (point.x / config.cell_width).round();
+    let line_y = (point.y / config.cell_width).round();
+
+    let near_vertical = (point.x - line_x * config.cell_width).abs() < half_wall;
+    let near_horizontal = (point.y - line_y * config.cell_width).abs() < half_wall;
+
+    let wall_index = if near_vertical && near_horizontal {
+        // Posts are always there
+        return true;
+    } else if near_vertical {
+        WallIndex {
+            x: line_x as usize,
+            y: (point.y / config.cell_width) as usize,
+            direction: WallDirection::Vertical,
+        }
+    } else if near_horizontal {
+        WallIndex {
+            x: (point.x / config.cell_width) as usize,
+            y: line_y as usize,
+            direction: WallDirection::Horizontal,
+        }
+    } else {
+        return false;
+    };
+
+    maze.get_wall(wall_index).unwrap_or(&Wall::Closed) == &Wall::Closed
+}
+
+/// Whether the body of the mouse overlaps with any closed walls or posts. This checks points
+/// around the outline of the body, spaced closer than the width of a wall so none get missed.
+fn body_collides(
+    mech: &MechanicalConfig,
+    maze_config: &MazeConfig,
+    maze: &Maze,
+    orientation: Orientation,
+) -> bool {
+    let front = mech.front_offset;
+    let back = mech.front_offset - mech.length;
+    let left = mech.width / 2.0;
+    let right = -mech.width / 2.0;
+
+    let corners = [
+        Vector { x: front, y: left },
+        Vector { x: front, y: right },
+        Vector { x: back, y: right },
+        Vector { x: back, y: left },
+    ];
+
+    let spacing = maze_config.wall_width / 2.0;
+
+    (0..corners.len()).any(|i| {
+        let start = corners[i];
+        let end = corners[(i + 1) % corners.len()];
+        let steps = ((end - start).magnitude() / spacing).ceil().max(1.0) as usize;
+
+        (0..steps).any(|step| {
+            let body_point = start + (end - start) * (step as f32 / steps as f32);
+            let point = orientation
+                .offset(Orientation {
+                    position: body_point,
+                    direction: DIRECTION_0,
+                })
+                .position;
+            point_in_wall(maze_config, maze, point)
+        })
+    })
+}
+
 pub struct Simulation {
     mouse: Mouse,
     orientation: Orientation,
+    last_left_wheel_speed: f32,
+    last_right_wheel_speed: f32,
     last_left_ground_speed: f32,
     last_right_ground_speed: f32,
     left_encoder: i32,
     right_encoder: i32,
     time: u32,
     last_sensor_update: u32,
+    rng: Rng,
 }
 
 impl Simulation {
     pub fn new(config: &SimulationConfig) -> Simulation {
+        config
+            .mouse
+            .mechanical
+            .validated()
+            .expect("Invalid mechanical config");
+
         Simulation {
             mouse: Mouse::new(&config.mouse, config.initial_orientation, 0, 0, 0),
             orientation: config.initial_orientation,
             left_encoder: 0,
             right_encoder: 0,
+            last_left_wheel_speed: 0.0,
+            last_right_wheel_speed: 0.0,
             last_left_ground_speed: 0.0,
             last_right_ground_speed: 0.0,
             time: 0,
             last_sensor_update: 0,
+            rng: Rng::new(config.rng_seed),
         }
     }
 
+    /// Put the simulation back to how it was when it was created, to start a new run
+    pub fn reset(&mut self, config: &SimulationConfig) {
+        self.mouse = Mouse::new(&config.mouse, config.initial_orientation, 0, 0, 0);
+        self.orientation = config.initial_orientation;
+        self.left_encoder = 0;
+        self.right_encoder = 0;
+        self.last_left_wheel_speed = 0.0;
+        self.last_right_wheel_speed = 0.0;
+        self.last_left_ground_speed = 0.0;
+        self.last_right_ground_speed = 0.0;
+        self.time = 0;
+        self.last_sensor_update = 0;
+        self.rng = Rng::new(config.rng_seed);
+    }
+
+    /// A random number between 0.0 and 1.0 from the simulation's seeded RNG, for anything that
+    /// needs to be random but still repeatable
+    pub fn next_f32(&mut self) -> f32 {
+        self.rng.next_f32()
+    }
+
     pub fn default_config() -> SimulationConfig {
         SimulationConfig::default()
     }
 
+    /// Run `steps` updates in a row and only give the debug from the last one, for skipping
+    /// ahead without looking at every step. None if `steps` is 0.
+    pub fn step_many(
+        &mut self,
+        config: &SimulationConfig,
+        steps: u32,
+    ) -> Option<SimulationDebug> {
+        (0..steps).map(|_| self.update(config)).last()
+    }
+
     pub fn update(&mut self, config: &SimulationConfig) -> SimulationDebug {
         let mech = config.mouse.mechanical;
 
@@ -102,14 +343,7 @@ impl Simulation {
                 let front_result = find_closed_wall(
                     &config.mouse.maze,
                     &config.maze,
-                    self.orientation.offset(Orientation {
-                        position: Vector {
-                            x: config.mouse.mechanical.front_sensor_offset_x,
-                            y: 0.0,
-                        },
-
-                        direction: DIRECTION_0,
-                    }),
+                    self.orientation.offset(mech.front_sensor()),
                 );
                 let front_distance = front_result
                     .filter(|result| result.distance < mech.front_sensor_limit as f32)
@@ -120,13 +354,7 @@ impl Simulation {
                 let left_result = find_closed_wall(
                     &config.mouse.maze,
                     &config.maze,
-                    self.orientation.offset(Orientation {
-                        position: Vector {
-                            x: config.mouse.mechanical.left_sensor_offset_x,
-                            y: config.mouse.mechanical.left_sensor_offset_y,
-                        },
-                        direction: DIRECTION_PI_2,
-                    }),
+                    self.orientation.offset(mech.left_sensor()),
                 );
                 let left_distance = left_result
                     .filter(|result| result.distance < mech.left_sensor_limit)
@@ -137,13 +365,7 @@ impl Simulation {
                 let right_result = find_closed_wall(
                     &config.mouse.maze,
                     &config.maze,
-                    self.orientation.offset(Orientation {
-                        position: Vector {
-                            x: config.mouse.mechanical.right_sensor_offset_x,
-                            y: -config.mouse.mechanical.right_sensor_offset_y,
-                        },
-                        direction: DIRECTION_3_PI_2,
-                    }),
+                    self.orientation.offset(mech.right_sensor()),
                 );
                 let right_distance = right_result
                     .filter(|result| result.distance < mech.right_sensor_limit as f32)
@@ -151,10 +373,11 @@ impl Simulation {
                         DistanceReading::InRange(result.distance)
                     });
 
+                let std = config.sensor_noise_std;
                 (
-                    Some(front_distance),
-                    Some(left_distance),
-                    Some(right_distance),
+                    Some(add_noise(&mut self.rng, std, front_distance)),
+                    Some(add_noise(&mut self.rng, std, left_distance)),
+                    Some(add_noise(&mut self.rng, std, right_distance)),
                 )
             } else {
                 (None, None, None)
@@ -164,12 +387,13 @@ impl Simulation {
         let (raw_left_power, raw_right_power, mouse_debug) = self.mouse.update(
             &config.mouse,
             self.time,
-            0,
+            0.0,
             self.left_encoder,
             self.right_encoder,
             left_distance,
             front_distance,
             right_distance,
+            None,
         );
 
         // Make sure the wheel powers are in range -1.0 to 1.0
@@ -191,8 +415,16 @@ impl Simulation {
         };
 
         // Update the state for the next run
-        let left_wheel_speed = left_power as f32 / 10000.0 * config.max_speed;
-        let right_wheel_speed = right_power as f32 / 10000.0 * config.max_speed;
+        let left_wheel_speed = motor_lag(
+            config,
+            self.last_left_wheel_speed,
+            left_power as f32 / 10000.0 * config.max_speed,
+        );
+        let right_wheel_speed = motor_lag(
+            config,
+            self.last_right_wheel_speed,
+            right_power as f32 / 10000.0 * config.max_speed,
+        );
 
         let delta_left_wheel = config
             .mouse
@@ -206,22 +438,24 @@ impl Simulation {
             .mm_to_ticks(right_wheel_speed * (config.millis_per_step as f32))
             as i32;
 
+        let (delta_left_wheel, delta_right_wheel) = if config.encoder_noise {
+            (
+                add_encoder_noise(&mut self.rng, delta_left_wheel),
+                add_encoder_noise(&mut self.rng, delta_right_wheel),
+            )
+        } else {
+            (delta_left_wheel, delta_right_wheel)
+        };
+
         let left_accel = (left_wheel_speed - self.last_left_ground_speed)
             / config.millis_per_step as f32;
         let right_accel = (right_wheel_speed - self.last_right_ground_speed)
             / config.millis_per_step as f32;
 
-        let left_ground_speed = if left_accel > config.max_wheel_accel {
-            self.last_left_ground_speed + config.max_wheel_accel
-        } else {
-            left_wheel_speed
-        };
-
-        let right_ground_speed = if right_accel > config.max_wheel_accel {
-            self.last_right_ground_speed + config.max_wheel_accel
-        } else {
-            right_wheel_speed
-        };
+        let left_ground_speed =
+            traction_limit(config, self.last_left_ground_speed, left_wheel_speed);
+        let right_ground_speed =
+            traction_limit(config, self.last_right_ground_speed, right_wheel_speed);
 
         let delta_left_ground = config
             .mouse
@@ -235,6 +469,32 @@ impl Simulation {
             .mm_to_ticks(right_ground_speed * (config.millis_per_step as f32))
             as i32;
 
+        let next_orientation = self.orientation.update_from_encoders(
+            &config.mouse.mechanical,
+            delta_left_ground,
+            delta_right_ground,
+        );
+
+        // The wheels keep turning, but a wall stops the mouse from going anywhere
+        let collided = body_collides(
+            &config.mouse.mechanical,
+            &config.mouse.maze,
+            &config.maze,
+            next_orientation,
+        );
+
+        let (left_ground_speed, right_ground_speed) = if collided {
+            (0.0, 0.0)
+        } else {
+            (left_ground_speed, right_ground_speed)
+        };
+
+        // The encoders count the wheels turning, even when the ground does not go by as far
+        let left_slip =
+            (left_wheel_speed - left_ground_speed) * config.millis_per_step as f32;
+        let right_slip =
+            (right_wheel_speed - right_ground_speed) * config.millis_per_step as f32;
+
         // Collect debug info from this run
         let debug = SimulationDebug {
             mouse: mouse_debug,
@@ -246,10 +506,13 @@ impl Simulation {
             right_accel,
             left_ground_speed,
             right_ground_speed,
+            left_slip,
+            right_slip,
             left_distance,
             front_distance,
             right_distance,
             orientation: self.orientation,
+            collided,
             config: config.clone(),
         };
 
@@ -257,14 +520,466 @@ impl Simulation {
         self.time += config.millis_per_step;
         self.left_encoder += delta_left_wheel;
         self.right_encoder += delta_right_wheel;
+        self.last_left_wheel_speed = left_wheel_speed;
+        self.last_right_wheel_speed = right_wheel_speed;
         self.last_left_ground_speed = left_ground_speed;
         self.last_right_ground_speed = right_ground_speed;
-        self.orientation = self.orientation.update_from_encoders(
-            &config.mouse.mechanical,
-            delta_left_ground,
-            delta_right_ground,
-        );
+
+        if !collided {
+            self.orientation = next_orientation;
+        }
 
         debug
     }
 }
+
+/// How a run from `run_to_goal` went
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunResult {
+    /// How many times the simulation was updated
+    pub steps: u32,
+
+    /// Whether the mouse made it to the goal before running out of steps
+    pub reached_goal: bool,
+
+    /// Whether the mouse ran into a wall at any point during the run
+    pub collided: bool,
+}
+
+/// Run a simulation until the mouse thinks it is in the goal, or until `max_steps` have run
+pub fn run_to_goal(config: &SimulationConfig, max_steps: u32) -> RunResult {
+    let mut simulation = Simulation::new(config);
+    let mut result = RunResult::default();
+
+    while result.steps < max_steps {
+        let debug = simulation.update(config);
+
+        result.steps += 1;
+        result.collided |= debug.collided;
+
+        if simulation.mouse.at_goal(&config.mouse) {
+            result.reached_goal = true;
+            break;
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod simulation_tests {
+    use micromouse_logic::config::sim::MOUSE_2019;
+    use micromouse_logic::config::MAZE;
+    use micromouse_logic::fast::{
+        Orientation, Vector, DIRECTION_0, DIRECTION_3_PI_2, DIRECTION_PI_2,
+    };
+    use micromouse_logic::slow::maze::{Maze, Wall};
+    use micromouse_logic::slow::{MazeDirection, MazePosition};
+
+    use micromouse_logic::mouse::DistanceReading;
+
+    use super::{
+        add_encoder_noise, add_noise, body_collides, maze_from_bytes, motor_lag,
+        run_to_goal, traction_limit, MazeFileError, Simulation, SimulationConfig,
+        DEFAULT_MAZE_FILE,
+    };
+    use crate::rng::Rng;
+
+    fn config() -> SimulationConfig {
+        SimulationConfig {
+            mouse: MOUSE_2019,
+            millis_per_step: 10,
+            millis_per_sensor_update: 20,
+            initial_orientation: Orientation {
+                position: Vector { x: 90.0, y: 90.0 },
+                direction: DIRECTION_PI_2,
+            },
+            max_wheel_accel: 1.0,
+            max_speed: 1.0,
+            motor_tau: 0.0,
+            maze: maze_from_bytes(DEFAULT_MAZE_FILE).unwrap(),
+            sensor_noise_std: 0.0,
+            encoder_noise: false,
+            rng_seed: 1,
+        }
+    }
+
+    #[test]
+    fn start_at_first_cell() {
+        let orientation = SimulationConfig::start_at_cell(
+            MazePosition { x: 0, y: 0 },
+            MazeDirection::North,
+            &MAZE,
+        );
+
+        assert_eq!(
+            orientation,
+            Orientation {
+                position: Vector { x: 90.0, y: 90.0 },
+                direction: DIRECTION_PI_2,
+            }
+        );
+    }
+
+    #[test]
+    fn start_at_other_cell() {
+        let orientation = SimulationConfig::start_at_cell(
+            MazePosition { x: 2, y: 1 },
+            MazeDirection::East,
+            &MAZE,
+        );
+
+        assert_eq!(
+            orientation,
+            Orientation {
+                position: Vector { x: 450.0, y: 270.0 },
+                direction: DIRECTION_0,
+            }
+        );
+    }
+
+    #[test]
+    fn reset_is_like_new() {
+        let config = config();
+
+        let mut simulation = Simulation::new(&config);
+        for _ in 0..100 {
+            simulation.update(&config);
+        }
+        simulation.reset(&config);
+
+        let mut fresh = Simulation::new(&config);
+
+        // The first step has no time passed, so some velocities are NaN and never equal
+        assert_eq!(
+            format!("{:?}", simulation.update(&config)),
+            format!("{:?}", fresh.update(&config))
+        );
+    }
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let config = config();
+
+        let mut first = Simulation::new(&config);
+        let mut second = Simulation::new(&config);
+        let mut other = Simulation::new(&SimulationConfig {
+            rng_seed: config.rng_seed + 1,
+            ..config.clone()
+        });
+
+        let first_sequence: Vec<f32> = (0..100).map(|_| first.next_f32()).collect();
+        let second_sequence: Vec<f32> = (0..100).map(|_| second.next_f32()).collect();
+        let other_sequence: Vec<f32> = (0..100).map(|_| other.next_f32()).collect();
+
+        assert_eq!(first_sequence, second_sequence);
+        assert_ne!(first_sequence, other_sequence);
+        assert!(first_sequence.iter().all(|&n| n >= 0.0 && n < 1.0));
+    }
+
+    #[test]
+    fn noise_is_repeatable() {
+        let reading = DistanceReading::InRange(50.0);
+
+        let mut first = Rng::new(1234);
+        let mut second = Rng::new(1234);
+
+        let mut any_noise = false;
+        for _ in 0..100 {
+            let noisy = add_noise(&mut first, 2.0, reading);
+            assert_eq!(noisy, add_noise(&mut second, 2.0, reading));
+            any_noise |= noisy != reading;
+        }
+
+        assert!(any_noise);
+    }
+
+    #[test]
+    fn no_noise() {
+        let mut rng = Rng::new(1234);
+
+        let reading = DistanceReading::InRange(50.0);
+        assert_eq!(add_noise(&mut rng, 0.0, reading), reading);
+
+        let reading = DistanceReading::OutOfRange;
+        assert_eq!(add_noise(&mut rng, 2.0, reading), reading);
+    }
+
+    #[test]
+    fn noisy_runs_are_repeatable() {
+        let config = SimulationConfig {
+            sensor_noise_std: 2.0,
+            encoder_noise: false,
+            ..config()
+        };
+
+        let mut first = Simulation::new(&config);
+        let mut second = Simulation::new(&config);
+
+        for _ in 0..50 {
+            let first_debug = first.update(&config);
+            let second_debug = second.update(&config);
+
+            assert_eq!(first_debug.front_distance, second_debug.front_distance);
+            assert_eq!(first_debug.left_distance, second_debug.left_distance);
+            assert_eq!(first_debug.right_distance, second_debug.right_distance);
+        }
+    }
+
+    #[test]
+    fn encoder_noise_is_repeatable() {
+        let mut first = Rng::new(1234);
+        let mut second = Rng::new(1234);
+
+        let mut any_noise = false;
+        for _ in 0..100 {
+            let noisy = add_encoder_noise(&mut first, 20);
+            assert_eq!(noisy, add_encoder_noise(&mut second, 20));
+            assert!((noisy - 20).abs() <= 1);
+            any_noise |= noisy != 20;
+        }
+
+        assert!(any_noise);
+
+        // A wheel that is not turning does not count anything
+        assert_eq!(add_encoder_noise(&mut first, 0), 0);
+    }
+
+    #[test]
+    fn encoder_noise_runs() {
+        let encoders = |config: &SimulationConfig| {
+            let mut simulation = Simulation::new(config);
+            (0..100)
+                .map(|_| {
+                    let debug = simulation.update(config);
+                    (debug.left_encoder, debug.right_encoder)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let quiet = config();
+        let noisy = SimulationConfig {
+            encoder_noise: true,
+            ..config()
+        };
+
+        // Without the noise the encoders are the same every time, and with it they are
+        // different but still the same every time for the same seed
+        assert_eq!(encoders(&quiet), encoders(&quiet));
+        assert_eq!(encoders(&noisy), encoders(&noisy));
+        assert_ne!(encoders(&quiet), encoders(&noisy));
+    }
+
+    #[test]
+    fn body_in_walls() {
+        let config = config();
+        let mech = &config.mouse.mechanical;
+        let maze = Maze::new(Wall::Open);
+
+        // In the middle of a cell
+        assert!(!body_collides(
+            mech,
+            &config.mouse.maze,
+            &maze,
+            Orientation {
+                position: Vector { x: 90.0, y: 90.0 },
+                direction: DIRECTION_PI_2,
+            }
+        ));
+
+        // Nose into the south perimeter wall
+        assert!(body_collides(
+            mech,
+            &config.mouse.maze,
+            &maze,
+            Orientation {
+                position: Vector {
+                    x: 90.0,
+                    y: mech.front_offset,
+                },
+                direction: DIRECTION_3_PI_2,
+            }
+        ));
+
+        // Driving between cells through an open wall, but a closed one stops it
+        let orientation = Orientation {
+            position: Vector { x: 90.0, y: 180.0 },
+            direction: DIRECTION_PI_2,
+        };
+        assert!(!body_collides(mech, &config.mouse.maze, &maze, orientation));
+        assert!(body_collides(
+            mech,
+            &config.mouse.maze,
+            &Maze::new(Wall::Closed),
+            orientation
+        ));
+    }
+
+    #[test]
+    fn drive_into_wall() {
+        let mut config = config();
+
+        // Blind the front sensor so the mouse can't see the wall and avoid it
+        config.mouse.mechanical.front_sensor_limit = 0.0;
+
+        let mut simulation = Simulation::new(&config);
+
+        // The mouse thinks it is in the start cell and will drive north into the next cell, but it
+        // is really in the top left cell facing the north perimeter wall
+        simulation.orientation = Orientation {
+            position: Vector {
+                x: 90.0,
+                y: 15.0 * 180.0 + 90.0,
+            },
+            direction: DIRECTION_PI_2,
+        };
+
+        let debug = (0..200)
+            .map(|_| simulation.update(&config))
+            .find(|debug| debug.collided)
+            .expect("the mouse never hit the wall");
+
+        // It stopped at the wall instead of going through it
+        assert!(
+            debug.orientation.position.y + config.mouse.mechanical.front_offset
+                < 16.0 * 180.0
+        );
+
+        let next = simulation.update(&config);
+        assert!(next.collided);
+        assert_eq!(next.orientation, debug.orientation);
+    }
+
+    #[test]
+    fn solves_apec2017() {
+        let config = config();
+
+        // 10 minutes, the length of a contest
+        let max_steps = 10 * 60 * 1000 / config.millis_per_step;
+
+        let result = run_to_goal(&config, max_steps);
+
+        assert!(result.reached_goal);
+        assert!(result.steps < max_steps);
+        assert!(!result.collided);
+    }
+
+    #[test]
+    fn motor_step_response() {
+        let config = SimulationConfig {
+            motor_tau: 100.0,
+            ..config()
+        };
+
+        // Each 10ms step closes 10% of the gap
+        let mut speed = 0.0;
+        for step in 1..=50 {
+            speed = motor_lag(&config, speed, 1.0);
+            assert!((speed - (1.0 - 0.9f32.powi(step))).abs() < 0.0001);
+        }
+
+        // After one time constant, it should be most of the way to 1 - 1/e
+        let mut speed = 0.0;
+        for _ in 0..10 {
+            speed = motor_lag(&config, speed, 1.0);
+        }
+        assert!((speed - (1.0 - (-1.0f32).exp())).abs() < 0.03);
+    }
+
+    #[test]
+    fn motor_no_lag() {
+        let no_lag = SimulationConfig {
+            motor_tau: 0.0,
+            ..config()
+        };
+        assert_eq!(motor_lag(&no_lag, 0.0, 1.0), 1.0);
+
+        // Smaller than a step is the same as no lag
+        let small_lag = SimulationConfig {
+            motor_tau: 0.1,
+            ..config()
+        };
+        assert_eq!(motor_lag(&small_lag, 0.0, 1.0), 1.0);
+    }
+
+    fn slippery() -> SimulationConfig {
+        SimulationConfig {
+            max_wheel_accel: 0.01,
+            ..config()
+        }
+    }
+
+    #[test]
+    fn traction_limits_speeding_up() {
+        // 10ms at 0.01mm/ms/ms
+        assert!((traction_limit(&slippery(), 0.0, 1.0) - 0.1).abs() < 0.0001);
+    }
+
+    #[test]
+    fn traction_limits_hard_braking() {
+        assert!((traction_limit(&slippery(), 1.0, 0.0) - 0.9).abs() < 0.0001);
+    }
+
+    #[test]
+    fn traction_limits_reverse() {
+        assert!((traction_limit(&slippery(), 0.0, -1.0) + 0.1).abs() < 0.0001);
+        assert!((traction_limit(&slippery(), -1.0, 0.0) + 0.9).abs() < 0.0001);
+    }
+
+    #[test]
+    fn traction_gentle_change() {
+        // Changing by less than the limit does not slip at all
+        assert_eq!(traction_limit(&slippery(), 0.5, 0.55), 0.55);
+        assert_eq!(traction_limit(&slippery(), 0.5, 0.45), 0.45);
+        assert_eq!(traction_limit(&slippery(), -0.5, -0.45), -0.45);
+    }
+
+    #[test]
+    fn slip_in_debug() {
+        let config = SimulationConfig {
+            max_wheel_accel: 0.001,
+            ..config()
+        };
+        let mut simulation = Simulation::new(&config);
+
+        // Starting from a stop, the wheels spin up faster than the ground can keep up
+        let slipped = (0..20)
+            .map(|_| simulation.update(&config))
+            .any(|debug| debug.left_slip > 0.0 || debug.right_slip > 0.0);
+
+        assert!(slipped);
+    }
+
+    #[test]
+    fn maze_file_length() {
+        assert!(maze_from_bytes(DEFAULT_MAZE_FILE).is_ok());
+
+        assert_eq!(
+            maze_from_bytes(&DEFAULT_MAZE_FILE[..100]),
+            Err(MazeFileError::WrongLength(100))
+        );
+
+        let mut long = DEFAULT_MAZE_FILE.to_vec();
+        long.push(0);
+        assert_eq!(maze_from_bytes(&long), Err(MazeFileError::WrongLength(257)));
+    }
+
+    #[test]
+    fn step_many_is_like_update() {
+        let config = config();
+
+        let mut one_at_a_time = Simulation::new(&config);
+        let mut last = None;
+        for _ in 0..50 {
+            last = Some(one_at_a_time.update(&config));
+        }
+
+        let mut many = Simulation::new(&config);
+        assert_eq!(many.step_many(&config, 50), last);
+
+        // Both keep going from the same place
+        assert_eq!(many.update(&config), one_at_a_time.update(&config));
+
+        assert_eq!(many.step_many(&config, 0), None);
+    }
+}
@@ -12,8 +12,7 @@ function ControlUi(parent, state) {
                 .style('width', '7em')
                 .oninput(function(){
                     if (state.state !== state.STATE_RUNNING && this.el.value >= 0 && this.el.value < state.debugs.length) {
-                        state.index = Number(this.el.value);
-                        state.update();
+                        state.seek(Number(this.el.value));
                     } else if (this.el.value < 0) {
                         this.value(0);
                     } else if (this.el.value >= state.debugs.length) {
@@ -33,6 +32,8 @@ function ControlUi(parent, state) {
                 .onupdate(function(state) {
                     if (state.state === state.STATE_RUNNING) {
                         this.value(state.debugs.length);
+                    } else if (state.index >= 0) {
+                        this.value(state.index);
                     }
                 })
         ]),
@@ -47,6 +48,33 @@ function ControlUi(parent, state) {
         ])
     ]);
 
+    // Drag through all of the frames so far, like scrubbing through a video
+    let scrub = input()
+        .type('range')
+        .style('width', '100%')
+        .min(0)
+        .max(0)
+        .value(0)
+        .oninput(function() {
+            if (state.state !== state.STATE_RUNNING) {
+                state.seek(Number(this.el.value));
+            }
+        })
+        .onupdate(function(state) {
+            this.disabled(state.state === state.STATE_RUNNING);
+            this.max(Math.max(state.debugs.length - 1, 0));
+            if (state.state === state.STATE_RUNNING || state.index < 0) {
+                this.value(state.debugs.length - 1);
+            } else {
+                this.value(state.index);
+            }
+        });
+
+    let step = button().classes('control button').text('Step').style('width', '4em').onclick(function() {
+        state.index = -1;
+        state.step();
+    });
+
     let root = card().title("Control").content([
             div().classes('field is-grouped').children([
                 button().classes('control button is-primary').text('Start').style('width', '4em').onclick(function () {
@@ -54,18 +82,47 @@ function ControlUi(parent, state) {
                         state.stop();
                         this.text('Start');
                         controls.disabled(false);
+                        step.disabled(false);
                     } else {
                         state.start();
                         state.index = -1;
                         this.text('Stop');
                         controls.disabled(true);
+                        step.disabled(true);
                     }
                 }),
+                step,
+                div().classes('control field has-addons').children([
+                    div().classes('control').children([
+                        button().classes('button is-static').text('Speed: '),
+                    ]),
+                    div().classes('control').children([
+                        input()
+                            .type('number')
+                            .classes('input')
+                            .style('text-align', 'right')
+                            .style('font-family', 'monospace')
+                            .style('width', '5em')
+                            .min(0.1)
+                            .max(20)
+                            .value(1)
+                            .oninput(function() {
+                                let scale = Number(this.el.value);
+                                if (scale > 0) {
+                                    state.time_scale(scale);
+                                }
+                            }),
+                    ]),
+                ]),
                 button().classes('control button is-danger').text('Reset').style('width', '4em').onclick(function() {
                     state.reset()
                 }),
+                button().classes('control button').text('Dump').style('width', '4em').onclick(function() {
+                    state.dump_trace()
+                }),
                 controls,
-            ])
+            ]),
+            div().classes('field').children([scrub]),
     ]);
 
     parent.append(root.el);
@@ -8,8 +8,15 @@ function MazeUi(parent, state) {
     const wall_err_color = '#ff0000';
     const mouse_int_color = '#00ff00';
     const mouse_ext_color = '#ff0000';
+    const front_ray_color = '#ff8800';
+    const left_ray_color = '#0088ff';
+    const right_ray_color = '#aa00ff';
 
     let zoom = 1;
+    let show_distances = false;
+
+    // How many steps back to draw the paths the mouse took. 0 turns them off.
+    let trail_length = 500;
 
     let maze = div();
     let content = div().children([
@@ -33,7 +40,31 @@ function MazeUi(parent, state) {
                         }
                         state.update();
                     }),
-            ])
+            ]),
+            div().classes('control').children([
+                button().classes('button is-static').text("Trail: "),
+            ]),
+            div().classes('control').children([
+                input()
+                    .type('number')
+                    .classes('input')
+                    .style('text-align', 'right')
+                    .style('font-family', 'monospace')
+                    .style('width', '6em')
+                    .min(0)
+                    .value(trail_length)
+                    .oninput(function() {
+                        trail_length = Math.max(0, Math.floor(Number(this.el.value)) || 0);
+                        state.update();
+                    }),
+            ]),
+            div().classes('control').children([
+                button().classes('button').text('Distances').onclick(function() {
+                    show_distances = !show_distances;
+                    this.el.classList.toggle('is-info', show_distances);
+                    state.update();
+                }),
+            ]),
         ]),
         maze,
     ]);
@@ -51,11 +82,20 @@ function MazeUi(parent, state) {
 
     let px_per_mm = 1;
 
+    // Mazes don't have to be 16x16, so go by the size of the maze being simulated
+    let maze_width = MAZE_WIDTH;
+    let maze_height = MAZE_HEIGHT;
+
     function redraw(config) {
 
+        if (config.maze) {
+            maze_width = config.maze.horizontal_walls.length;
+            maze_height = config.maze.vertical_walls[0].length;
+        }
+
         const maze_config = config.mouse.maze;
-        const maze_width_mm = MAZE_WIDTH * maze_config.cell_width + maze_config.wall_width;
-        const maze_height_mm = MAZE_HEIGHT * maze_config.cell_width + maze_config.wall_width;
+        const maze_width_mm = maze_width * maze_config.cell_width + maze_config.wall_width;
+        const maze_height_mm = maze_height * maze_config.cell_width + maze_config.wall_width;
 
         draw.size("100%");
 
@@ -79,21 +119,23 @@ function MazeUi(parent, state) {
         self.horizontal_walls = [];
         self.vertical_walls = [];
         self.cells = [];
-        for (let i = 0; i < MAZE_WIDTH + 1; i++) {
+        self.distances = [];
+        for (let i = 0; i < maze_width + 1; i++) {
             self.posts[i] = [];
             self.horizontal_walls[i] = [];
             self.vertical_walls[i] = [];
             self.cells[i] = [];
-            for (let j = 0; j < MAZE_HEIGHT + 1; j++) {
+            self.distances[i] = [];
+            for (let j = 0; j < maze_height + 1; j++) {
 
                 let post = maze.rect(maze_config.wall_width, maze_config.wall_width);
                 post.move(i * maze_config.cell_width - maze_config.wall_width/2.0, j * maze_config.cell_width - maze_config.wall_width/2.0);
                 self.posts[i][j] = post;
 
-                if (i < MAZE_WIDTH) {
+                if (i < maze_width) {
                     let wall_color = wall_err_color;
 
-                    if (j === 0 || j === MAZE_WIDTH) {
+                    if (j === 0 || j === maze_height) {
                         wall_color = wall_closed_color;
                     } else {
                         wall_color = wall_unknown_color;
@@ -105,10 +147,10 @@ function MazeUi(parent, state) {
                         .fill(wall_color);
                 }
 
-                if (j < MAZE_HEIGHT) {
+                if (j < maze_height) {
                     let wall_color = wall_err_color;
 
-                    if (i === 0 || i === MAZE_WIDTH) {
+                    if (i === 0 || i === maze_width) {
                         wall_color = wall_closed_color;
                     } else {
                         wall_color = wall_unknown_color;
@@ -120,7 +162,14 @@ function MazeUi(parent, state) {
                         .fill(wall_color);
                 }
 
-                if (i < MAZE_WIDTH && j < MAZE_HEIGHT) {
+                if (i < maze_width && j < maze_height) {
+                    // Flipped back over so the text is not upside down in the flipped world
+                    self.distances[i][j] = maze
+                        .plain('')
+                        .font({size: maze_config.cell_width / 3, anchor: 'middle'})
+                        .attr('dominant-baseline', 'middle')
+                        .matrix(1, 0, 0, -1, (i + 0.5) * maze_config.cell_width, (j + 0.5) * maze_config.cell_width);
+
                     self.cells[i][j] = maze
                         .rect(maze_config.cell_width - maze_config.wall_width, maze_config.cell_width - maze_config.wall_width)
                         .move(i * maze_config.cell_width + maze_config.wall_width / 2.0, j * maze_config.cell_width + maze_config.wall_width / 2.0)
@@ -140,6 +189,16 @@ function MazeUi(parent, state) {
         self.mouse_ext = world.group();
         self.mouse_ext.rect(mech.length, mech.width).fill(mouse_ext_color).translate(mech.front_offset - mech.length, -mech.width / 2);
 
+        // Where the sensors on the simulated mouse are looking, relative to the mouse
+        self.sensor_rays = world.group();
+        self.front_ray = self.sensor_rays.line(0, 0, 0, 0).stroke({color: front_ray_color, width: 2});
+        self.left_ray = self.sensor_rays.line(0, 0, 0, 0).stroke({color: left_ray_color, width: 2});
+        self.right_ray = self.sensor_rays.line(0, 0, 0, 0).stroke({color: right_ray_color, width: 2});
+
+        // Where the mouse actually went and where it thought it went
+        self.trail_ext = world.polyline([]).fill('none').stroke({color: mouse_ext_color, width: 2});
+        self.trail_int = world.polyline([]).fill('none').stroke({color: mouse_int_color, width: 2});
+
         self.path = world.path('').fill('none').stroke({color: '#0000ff', width: 2});
         self.path_closest = world.circle(20.0).fill({color: '#0000ff'});
     }
@@ -168,10 +227,10 @@ function MazeUi(parent, state) {
         world.scale(px_per_mm * zoom, px_per_mm * zoom);
 
         const maze = debug?.config?.maze || debug?.mouse?.slow?.map?.maze;
-        for (let i = 0; i < MAZE_WIDTH; i++) {
-            for (let j = 0; j < MAZE_HEIGHT; j++) {
+        for (let i = 0; i < maze_width; i++) {
+            for (let j = 0; j < maze_height; j++) {
                 if (maze) {
-                    if (j > 0 && i < MAZE_WIDTH) {
+                    if (j > 0 && i < maze_width) {
                         let wall = maze.horizontal_walls[i][j - 1];
                         if (wall === "Closed") {
                             self.horizontal_walls[i][j].fill(wall_closed_color);
@@ -184,7 +243,7 @@ function MazeUi(parent, state) {
                         }
                     }
 
-                    if (i > 0 && j < MAZE_HEIGHT) {
+                    if (i > 0 && j < maze_height) {
                         let wall = maze.vertical_walls[i - 1][j];
                         if (wall === "Closed") {
                             self.vertical_walls[i][j].fill(wall_closed_color);
@@ -199,9 +258,17 @@ function MazeUi(parent, state) {
                 }
 
                 if (debug.mouse.slow) {
-                    if (i < MAZE_WIDTH && j < MAZE_HEIGHT) {
+                    if (i < maze_width && j < maze_height) {
                         let count = debug.mouse.slow.navigate.cells[i][j];
                         self.cells[i][j].fill({opacity: count / 32})
+
+                        // Unreachable cells are left blank
+                        let distance = debug.mouse.slow.distances[i][j];
+                        if (show_distances && distance !== 255) {
+                            self.distances[i][j].plain(String(distance));
+                        } else {
+                            self.distances[i][j].plain('');
+                        }
                     }
                 }
             }
@@ -216,14 +283,28 @@ function MazeUi(parent, state) {
         if (debug.orientation) {
             let orientation_ext = debug.orientation;
             self.mouse_ext.rotate(orientation_ext.direction * 180 / Math.PI).translate(orientation_ext.position.x, orientation_ext.position.y);
+            self.sensor_rays.rotate(orientation_ext.direction * 180 / Math.PI).translate(orientation_ext.position.x, orientation_ext.position.y);
+        }
+
+        // The sensors only read every few steps, so leave the rays where they were in between
+        if (debug.config) {
+            let mech = debug.config.mouse.mechanical;
+
+            plot_ray(self.front_ray, debug.front_distance, mech.front_sensor_offset_x, 0, 1, 0, mech.front_sensor_limit);
+            plot_ray(self.left_ray, debug.left_distance, mech.left_sensor_offset_x, mech.left_sensor_offset_y, 0, 1, mech.left_sensor_limit);
+            plot_ray(self.right_ray, debug.right_distance, mech.right_sensor_offset_x, -mech.right_sensor_offset_y, 0, -1, mech.right_sensor_limit);
         }
 
         if (debug.mouse.motion_queue.queue && debug.mouse.motion_queue.queue.length > 0) {
-            let path_string = debug.mouse.motion_queue.queue.reduce(function(str, motion) {
+            // The queue is a stack, so go through it backwards to go in the order the motions
+            // will run. Turns happen in place wherever the motion before them ended.
+            let position = debug.mouse.orientation.position;
+            let path_string = debug.mouse.motion_queue.queue.slice().reverse().reduce(function(str, motion) {
                 if (motion.Path) {
+                    position = motion.Path.bezier.end;
                     return str + bezier6_path(motion.Path.bezier);
                 } else if (motion.Turn) {
-                    return str
+                    return str + turn_path(motion.Turn, position);
                 } else {
                     return str
                 }
@@ -242,9 +323,42 @@ function MazeUi(parent, state) {
         */
     }
 
+    // Draw a sensor ray from (x, y) on the mouse going in the (dx, dy) direction out to the
+    // distance it read, or out to the limit if it did not see anything
+    function plot_ray(ray, reading, x, y, dx, dy, limit) {
+        if (reading) {
+            let distance = limit;
+            if (typeof reading === 'object' && 'InRange' in reading) {
+                distance = reading.InRange;
+            }
+
+            ray.plot(x, y, x + dx * distance, y + dy * distance);
+        }
+    }
+
+    // Draw the last `trail_length` positions up to the debug being shown
+    function update_trails(state) {
+        let end = state.index < 0 ? state.debugs.length : state.index + 1;
+        let start = Math.max(0, end - trail_length);
+
+        let trail_ext = [];
+        let trail_int = [];
+        for (let i = start; i < end; i++) {
+            let debug = state.debugs[i];
+            if (debug.orientation) {
+                trail_ext.push([debug.orientation.position.x, debug.orientation.position.y]);
+            }
+            trail_int.push([debug.mouse.orientation.position.x, debug.mouse.orientation.position.y]);
+        }
+
+        self.trail_ext.plot(trail_ext);
+        self.trail_int.plot(trail_int);
+    }
+
     let oldconfig = null;
     let olddebug = null;
     let oldzoom = null;
+    let oldtrail_length = null;
 
     self.update = function (state) {
         if (state.debug()) {
@@ -254,15 +368,36 @@ function MazeUi(parent, state) {
                 redraw(config);
                 oldconfig = config;
             }
-            if (!_.isEqual(debug, olddebug) || oldzoom !== zoom) {
+            if (!_.isEqual(debug, olddebug) || oldzoom !== zoom || oldtrail_length !== trail_length) {
                 update(debug);
+                update_trails(state);
                 olddebug = debug;
                 oldzoom = zoom;
+                oldtrail_length = trail_length;
             }
         }
     }
 }
 
+// An arc around `center` going from the start to the target direction of a turn, the same way
+// around as the turn goes
+function turn_path(turn, center) {
+    const radius = 30.0;
+    const counterclockwise = turn.direction === "Counterclockwise";
+
+    let angle = counterclockwise ? turn.target - turn.start : turn.start - turn.target;
+    angle = ((angle % (2 * Math.PI)) + 2 * Math.PI) % (2 * Math.PI);
+
+    const start_x = center.x + radius * Math.cos(turn.start);
+    const start_y = center.y + radius * Math.sin(turn.start);
+    const end_x = center.x + radius * Math.cos(turn.target);
+    const end_y = center.y + radius * Math.sin(turn.target);
+
+    return " M " + start_x + " " + start_y
+        + " A " + radius + " " + radius + " 0 " + (angle > Math.PI ? 1 : 0) + " " + (counterclockwise ? 1 : 0)
+        + " " + end_x + " " + end_y;
+}
+
 function bezier6_path(b) {
     let str = " M " + b.start.x + " " + b.start.y;
     for (let n = 1; n < 10; n += 1) {
@@ -7,6 +7,7 @@ function SetupUi(parent, state) {
     let simulated_tab = li();
     let remote_tab = li();
     let dump_tab = li();
+    let recording_tab = li();
 
     let simulated = div().children([
         p().text("Default config"),
@@ -42,6 +43,24 @@ function SetupUi(parent, state) {
         ])
     ])
 
+    let recording_file_name = span().classes('file-name').text('No file');
+
+    let recording_file = input().type('file').classes('file-input').onchange(function() {
+        recording_file_name.text(this.el.files[0].name)
+    });
+
+    let recording = div().children([
+        div().classes('file has-name').children([
+            label().classes('file-label').children([
+                recording_file,
+                span().classes('file-cta').children([
+                    span().classes('file-label').text('Choose a recording...')
+                ])
+            ]),
+            recording_file_name,
+        ])
+    ])
+
     let content = div();
 
     let connect = a().text("Connect").onclick(function() {
@@ -51,6 +70,8 @@ function SetupUi(parent, state) {
             state.connect('remote', state.remote_config_default, {url: remote_url.el.value});
         } else if (selected_tab === 'dump') {
             state.connect('dump', state.remote_config_default, {file: dump_file.el.files[0]});
+        } else if (selected_tab === 'recording') {
+            state.connect('recording', state.simulation_config_default, {file: recording_file.el.files[0]});
         }
     });
 
@@ -64,10 +85,12 @@ function SetupUi(parent, state) {
                         if (selected_tab !== "simulated") {
                             remote.el.remove();
                             dump.el.remove();
+                            recording.el.remove();
                             content.el.append(simulated.el);
                             simulated_tab.classes("is-active");
                             remote_tab.remove_class("is-active");
                             dump_tab.remove_class('is-active');
+                            recording_tab.remove_class('is-active');
                             selected_tab = "simulated";
                         }
                     }),
@@ -77,10 +100,12 @@ function SetupUi(parent, state) {
                         if (selected_tab !== "remote") {
                             simulated.el.remove();
                             dump.el.remove();
+                            recording.el.remove();
                             content.el.append(remote.el);
                             remote_tab.classes("is-active");
                             simulated_tab.remove_class("is-active");
                             dump_tab.remove_class('is-active');
+                            recording_tab.remove_class('is-active');
                             selected_tab = "remote";
                         }
                     }),
@@ -90,13 +115,30 @@ function SetupUi(parent, state) {
                         if (selected_tab !== "dump") {
                             simulated.el.remove();
                             remote.el.remove();
+                            recording.el.remove();
                             content.el.append(dump.el);
                             dump_tab.classes("is-active");
                             simulated_tab.remove_class("is-active");
                             remote_tab.remove_class('is-active');
+                            recording_tab.remove_class('is-active');
                             selected_tab = "dump";
                         }
                     })
+                ]),
+                recording_tab.children([
+                    a().text("Recording").onclick(function() {
+                        if (selected_tab !== "recording") {
+                            simulated.el.remove();
+                            remote.el.remove();
+                            dump.el.remove();
+                            content.el.append(recording.el);
+                            recording_tab.classes("is-active");
+                            simulated_tab.remove_class("is-active");
+                            remote_tab.remove_class('is-active');
+                            dump_tab.remove_class('is-active');
+                            selected_tab = "recording";
+                        }
+                    })
                 ])
             ]),
         ]),
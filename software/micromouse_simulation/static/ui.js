@@ -35,6 +35,15 @@ function Simulation() {
         }
     };
 
+    // Look at an earlier frame instead of the latest one. This only needs the frames that
+    // are already here, so it works the same for a live run or a recording.
+    self.seek = function(index) {
+        if (index >= 0 && index < self.debugs.length) {
+            self.index = index;
+            self.update();
+        }
+    };
+
     self.graphs = [];
 
     self.connect = function(type, config, options) {
@@ -70,6 +79,20 @@ function Simulation() {
         self.running = false;
     };
 
+    self.step = function() {
+        worker.postMessage({
+            name: 'step',
+            data: null,
+        });
+    };
+
+    self.time_scale = function(scale) {
+        worker.postMessage({
+            name: 'time_scale',
+            data: scale,
+        });
+    };
+
     self.reset = function() {
         worker.postMessage({
             name: 'reset',
@@ -77,6 +100,13 @@ function Simulation() {
         })
     };
 
+    self.dump_trace = function() {
+        worker.postMessage({
+            name: 'dump_trace',
+            data: null,
+        })
+    };
+
     self.update = function() {
         requestAnimationFrame(do_update);
     };
@@ -7,12 +7,26 @@ importScripts('pkg/micromouse_simulation.js');
 
 console.log("imported scripts");
 
+// The mouse does not send the flood fill distances, so work them out from its map for the maze
+// view to show
+function with_distances(config, debug) {
+    if (debug.mouse.slow) {
+        debug.mouse.slow.distances = wasm_bindgen.flood_distances(
+            debug.mouse.slow.map.maze,
+            config.mouse.navigate.goal
+        );
+    }
+
+    return debug;
+}
+
 function Simulation(config, send) {
     let self = this;
 
     let simulation = new wasm_bindgen.JsSimulation(config);
 
     let interval_id = undefined;
+    let time_scale = 1.0;
 
     send({name: 'connected'});
 
@@ -22,9 +36,9 @@ function Simulation(config, send) {
                 let debug = simulation.update();
                 send({
                     name: 'debug',
-                    data: debug,
+                    data: with_distances(config, debug),
                 });
-            }, config.millis_per_step);
+            }, wasm_bindgen.step_interval(config.millis_per_step, time_scale));
         }
     };
 
@@ -35,8 +49,28 @@ function Simulation(config, send) {
         }
     };
 
+    // Run exactly one step, only while stopped so it does not race the interval
+    self.step = function() {
+        if (!interval_id) {
+            let debug = simulation.update();
+            send({
+                name: 'debug',
+                data: with_distances(config, debug),
+            });
+        }
+    };
+
+    // Restart the interval so a running simulation picks up the new speed right away
+    self.time_scale = function(scale) {
+        time_scale = scale;
+        if (interval_id) {
+            self.stop();
+            self.start();
+        }
+    };
+
     self.reset = function() {
-        simulation = new wasm_bindgen.JsSimulation(config);
+        simulation.reset();
     };
 
     self.config = function(c) {
@@ -44,6 +78,8 @@ function Simulation(config, send) {
         simulation.config(config);
     };
 
+    self.dump_trace = function() {};
+
     self.disconnect = function() { }
 }
 
@@ -61,6 +97,7 @@ function Remote(config, url, send) {
     const BYTE_STOP = 3;
     const BYTE_START = 4;
     const BYTE_RESET = 5;
+    const BYTE_DUMP_TRACE = 6;
 
     const STATE_CONNECTING = 'connecting';
     const STATE_OK = 'ok';
@@ -85,6 +122,10 @@ function Remote(config, url, send) {
 
     socket.onopen = function(event) {
         console.log("websocket open");
+        let debug_request = remote.debug_request();
+        if (debug_request.length > 0) {
+            socket.send(debug_request);
+        }
         send_byte(BYTE_START_DEBUG);
         state = STATE_OK;
         send({name: 'connected'});
@@ -102,7 +143,7 @@ function Remote(config, url, send) {
             if ("Ok" in result) {
                 let debugs = result["Ok"];
                 debugs.forEach(function(debug) {
-                    send({name: 'debug', data: debug});
+                    send({name: 'debug', data: with_distances(config, debug)});
                 });
             } else if ("Err" in result) {
                 console.log("comms error");
@@ -128,10 +169,19 @@ function Remote(config, url, send) {
         send_byte(BYTE_STOP);
     };
 
+    // The real mouse runs on its own clock, so it can't be stepped or sped up
+    self.step = function() {};
+
+    self.time_scale = function() {};
+
     self.reset = function() {
         send_byte(BYTE_RESET);
     };
 
+    self.dump_trace = function() {
+        send_byte(BYTE_DUMP_TRACE);
+    };
+
     self.config = function () {};
 
     self.disconnect = function() {
@@ -160,7 +210,7 @@ function Dump(config, file, send) {
             if ("Ok" in result) {
                 let debugs = result["Ok"];
                 debugs.forEach(function(debug) {
-                    send({name: 'debug', data: debug});
+                    send({name: 'debug', data: with_distances(config, debug)});
                 });
             } else if ("Err" in result) {
                 console.log('Error reading dump file: ' + result['Err']);
@@ -178,10 +228,53 @@ function Dump(config, file, send) {
 
     self.stop = function() {};
 
+    self.step = function() {};
+
+    self.time_scale = function() {};
+
+    self.reset = function() {};
+
+    self.config = function () {};
+
+    self.dump_trace = function() {};
+
+    self.disconnect = function() {};
+}
+
+// Plays back a recording from a SimulationRecorder. All of the frames are sent at once, and then
+// they can be looked through the same as a run that was just stopped.
+function Recording(config, file, send) {
+    let self = this;
+
+    file.arrayBuffer().then(function(buffer) {
+        let result = wasm_bindgen.read_recording(new Uint8Array(buffer));
+        if ("Ok" in result) {
+            let debugs = result["Ok"];
+            console.log("Read " + debugs.length + " frames from recording");
+            debugs.forEach(function(debug) {
+                send({name: 'debug', data: with_distances(config, debug)});
+            });
+        } else if ("Err" in result) {
+            console.log('Error reading recording: ' + result['Err']);
+        }
+    });
+
+    send({name: 'connected'});
+
+    self.start = function() {};
+
+    self.stop = function() {};
+
+    self.step = function() {};
+
+    self.time_scale = function() {};
+
     self.reset = function() {};
 
     self.config = function () {};
 
+    self.dump_trace = function() {};
+
     self.disconnect = function() {};
 }
 
@@ -209,6 +302,8 @@ async function init() {
                     handler = new Remote(msg.data.config, msg.data.options.url, function(m) { postMessage(m) });
                 } else if (msg.data.type === 'dump') {
                     handler = new Dump(msg.data.config, msg.data.options.file, function(m) { postMessage(m) });
+                } else if (msg.data.type === 'recording') {
+                    handler = new Recording(msg.data.config, msg.data.options.file, function(m) { postMessage(m) });
                 }
                 postMessage({name: 'connecting'});
             }
@@ -223,11 +318,17 @@ async function init() {
         } else if (msg.name === 'stop') {
             handler.stop();
             postMessage({name: 'stopped'});
+        } else if (msg.name === 'step') {
+            handler.step();
+        } else if (msg.name === 'time_scale') {
+            handler.time_scale(msg.data);
         } else if (msg.name === 'reset') {
             handler.reset();
             postMessage({name: 'reset'})
         } else if (msg.name === 'config') {
             handler.config(msg.data);
+        } else if (msg.name === 'dump_trace') {
+            handler.dump_trace();
         }
     };
 